@@ -4,7 +4,7 @@ extern crate test;
 
 use tbs::{
     blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
-    verify, Message,
+    verify, verify_batch, Message,
 };
 use test::Bencher;
 
@@ -22,7 +22,7 @@ fn bench_signing(bencher: &mut Bencher) {
     let (_bk, bmsg) = blind_message(msg);
     let (_pk, _pks, sks) = dealer_keygen(4, 5);
 
-    bencher.iter(|| sign_blinded_msg(bmsg, sks[0]));
+    bencher.iter(|| sign_blinded_msg(bmsg, &sks[0]));
 }
 
 #[bench]
@@ -32,7 +32,7 @@ fn bench_combine(bencher: &mut Bencher) {
     let (_pk, _pks, sks) = dealer_keygen(4, 5);
     let shares = sks
         .iter()
-        .map(|sk| sign_blinded_msg(bmsg, *sk))
+        .map(|sk| sign_blinded_msg(bmsg, sk))
         .enumerate()
         .collect::<Vec<_>>();
 
@@ -46,12 +46,12 @@ fn bench_unblind(bencher: &mut Bencher) {
     let (_pk, _pks, sks) = dealer_keygen(4, 5);
     let shares = sks
         .iter()
-        .map(|sk| sign_blinded_msg(bmsg, *sk))
+        .map(|sk| sign_blinded_msg(bmsg, sk))
         .enumerate()
         .collect::<Vec<_>>();
     let bsig = combine_valid_shares(shares, 4);
 
-    bencher.iter(|| unblind_signature(bk, bsig));
+    bencher.iter(|| unblind_signature(&bk, bsig));
 }
 
 #[bench]
@@ -61,11 +61,42 @@ fn bench_verify(bencher: &mut Bencher) {
     let (pk, _pks, sks) = dealer_keygen(4, 5);
     let shares = sks
         .iter()
-        .map(|sk| sign_blinded_msg(bmsg, *sk))
+        .map(|sk| sign_blinded_msg(bmsg, sk))
         .enumerate()
         .collect::<Vec<_>>();
     let bsig = combine_valid_shares(shares, 4);
-    let sig = unblind_signature(bk, bsig);
+    let sig = unblind_signature(&bk, bsig);
 
     bencher.iter(|| verify(msg, sig, pk));
 }
+
+fn signed_messages(count: usize) -> Vec<(Message, tbs::Signature, tbs::AggregatePublicKey)> {
+    let (pk, _pks, sks) = dealer_keygen(4, 5);
+    (0..count)
+        .map(|idx| {
+            let msg = Message::from_bytes(&idx.to_be_bytes());
+            let (bk, bmsg) = blind_message(msg);
+            let shares = sks
+                .iter()
+                .map(|sk| sign_blinded_msg(bmsg, sk))
+                .enumerate()
+                .collect::<Vec<_>>();
+            let sig = unblind_signature(&bk, combine_valid_shares(shares, 4));
+            (msg, sig, pk)
+        })
+        .collect()
+}
+
+#[bench]
+fn bench_verify_individually_64(bencher: &mut Bencher) {
+    let items = signed_messages(64);
+
+    bencher.iter(|| items.iter().all(|(msg, sig, pk)| verify(*msg, *sig, *pk)));
+}
+
+#[bench]
+fn bench_verify_batch_64(bencher: &mut Bencher) {
+    let items = signed_messages(64);
+
+    bencher.iter(|| verify_batch(items.iter().copied()));
+}
@@ -15,7 +15,12 @@ fn main() {
 
     println!("apk={}", to_hex(&pk));
     for (idx, (pk, sk)) in pks.iter().zip(sks.iter()).enumerate() {
-        println!("peer {}: pk={}; sk={}", idx, to_hex(&pk), to_hex(&sk));
+        println!(
+            "peer {}: pk={}; sk={}",
+            idx,
+            to_hex(&pk),
+            hex::encode(sk.to_secret_bytes())
+        );
     }
 }
 
@@ -1,11 +1,49 @@
+use bls12_381::hash_to_curve::{ExpandMsgXmd, HashToCurve};
+use bls12_381::G1Projective;
 use group::Group;
 use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
+use serde::{Deserialize, Serialize};
 use sha3::digest::generic_array::typenum::U32;
 use sha3::Digest;
 
 const HASH_TAG: &[u8] = b"TBS_BLS12-381_";
 
+/// Hash-to-curve suite of [RFC 9380](https://www.rfc-editor.org/rfc/rfc9380.html) used by
+/// [`MessageDerivation::V1HashToCurve`]
+pub const HASH_TO_CURVE_SUITE: &str = "BLS12381G1_XMD:SHA-256_SSWU_RO_";
+
+/// Defines how byte strings are mapped to the curve points that get blind signed. Signer and
+/// requester have to agree on it, so it is fixed for the lifetime of a key set.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum MessageDerivation {
+    /// Ad-hoc construction seeding a PRNG with a tagged SHA3-256 hash, see [`hash_bytes_to_curve`]
+    V0AdHoc,
+    /// The standardized [`HASH_TO_CURVE_SUITE`] with the given domain separation tag, see
+    /// [`hash_bytes_to_g1`]
+    V1HashToCurve { dst: Vec<u8> },
+}
+
+impl MessageDerivation {
+    /// Hash-to-curve message derivation with a domain separation tag unique to the application
+    /// identified by `app_id`, following the tag format recommended by RFC 9380 section 3.1.
+    ///
+    /// # Panics
+    /// * If `app_id` is so long that the resulting tag exceeds 255 bytes
+    pub fn hash_to_curve(app_id: &str) -> MessageDerivation {
+        let dst = format!("{}-V01-CS01-with-{}", app_id, HASH_TO_CURVE_SUITE).into_bytes();
+        assert!(dst.len() <= 255, "Domain separation tag too long");
+        MessageDerivation::V1HashToCurve { dst }
+    }
+}
+
+impl Default for MessageDerivation {
+    /// Defaults to the legacy mode so that existing key sets keep their meaning
+    fn default() -> Self {
+        MessageDerivation::V0AdHoc
+    }
+}
+
 pub fn hash_bytes_to_curve<G: Group>(data: &[u8]) -> G {
     let mut hash_engine = sha3::Sha3_256::new();
     hash_engine.update(HASH_TAG);
@@ -18,3 +56,46 @@ pub fn hash_to_curve<G: Group, H: Digest<OutputSize = U32>>(hash: H) -> G {
     let mut prng = ChaChaRng::from_seed(hash.finalize().into());
     G::random(&mut prng)
 }
+
+/// Hashes `data` to G1 using [`HASH_TO_CURVE_SUITE`] and the domain separation tag `dst`
+pub fn hash_bytes_to_g1(data: &[u8], dst: &[u8]) -> G1Projective {
+    <G1Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(data, dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::{hash_bytes_to_g1, HASH_TO_CURVE_SUITE};
+    use bls12_381::G1Affine;
+    use group::Curve;
+
+    /// Test vectors from RFC 9380, appendix J.9.1
+    #[test]
+    fn test_hash_to_g1_vectors() {
+        let dst = format!("QUUX-V01-CS02-with-{}", HASH_TO_CURVE_SUITE);
+        let vectors: [(&[u8], &str, &str); 3] = [
+            (
+                b"",
+                "052926add2207b76ca4fa57a8734416c8dc95e24501772c814278700eed6d1e4e8cf62d9c09db0fac349612b759e79a1",
+                "08ba738453bfed09cb546dbb0783dbb3a5f1f566ed67bb6be0e8c67e2e81a4cc68ee29813bb7994998f3eae0c9c6a265",
+            ),
+            (
+                b"abc",
+                "03567bc5ef9c690c2ab2ecdf6a96ef1c139cc0b2f284dca0a9a7943388a49a3aee664ba5379a7655d3c68900be2f6903",
+                "0b9c15f3fe6e5cf4211f346271d7b01c8f3b28be689c8429c85b67af215533311f0b8dfaaa154fa6b88176c229f2885d",
+            ),
+            (
+                b"abcdef0123456789",
+                "11e0b079dea29a68f0383ee94fed1b940995272407e3bb916bbf268c263ddd57a6a27200a784cbc248e84f357ce82d98",
+                "03a87ae2caf14e8ee52e51fa2ed8eefe80f02457004ba4d486d6aa1f517c0889501dc7413753f9599b099ebcbbd2d709",
+            ),
+        ];
+
+        for (msg, x, y) in vectors.iter() {
+            let mut uncompressed = [0u8; 96];
+            hex::decode_to_slice(format!("{}{}", x, y), &mut uncompressed).unwrap();
+            let expected = G1Affine::from_uncompressed(&uncompressed).unwrap();
+
+            assert_eq!(hash_bytes_to_g1(msg, dst.as_bytes()).to_affine(), expected);
+        }
+    }
+}
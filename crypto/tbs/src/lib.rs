@@ -3,9 +3,11 @@
 //! This library implements an ad-hoc threshold blind signature scheme based on BLS signatures using
 //! the (unrelated) BLS12-381 curve.
 
-use crate::hash::{hash_bytes_to_curve, hash_to_curve};
+use crate::hash::{hash_bytes_to_curve, hash_bytes_to_g1, hash_to_curve};
 use crate::poly::Poly;
-use bls12_381::{pairing, G1Affine, G1Projective, G2Affine, G2Projective};
+use bls12_381::{
+    multi_miller_loop, pairing, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt,
+};
 use ff::Field;
 use group::Curve;
 use rand::rngs::OsRng;
@@ -17,22 +19,27 @@ use sha3::Digest;
 pub use bls12_381::G1Affine as MessagePoint;
 pub use bls12_381::G2Affine as PubKeyPoint;
 pub use bls12_381::Scalar;
+pub use hash::MessageDerivation;
 
 pub mod hash;
 pub mod poly;
+pub mod reshare;
 mod serde_impl;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct PublicKeyShare(#[serde(with = "serde_impl::g2")] pub G2Affine);
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub struct SecretKeyShare(#[serde(with = "serde_impl::scalar")] pub Scalar);
+/// Key share of a signer. It isn't `Serialize`, configs that persist it opt in explicitly using
+/// `#[serde(with = "tbs::serde_secret")]`.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct SecretKeyShare(Scalar);
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct AggregatePublicKey(#[serde(with = "serde_impl::g2")] pub G2Affine);
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub struct BlindingKey(#[serde(with = "serde_impl::scalar")] pub Scalar);
+/// Key a message was blinded with, needed to unblind its signature
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct BlindingKey(Scalar);
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct BlindedMessage(#[serde(with = "serde_impl::g1")] pub G1Affine);
@@ -60,10 +67,21 @@ impl FromRandom for Scalar {
 }
 
 impl Message {
+    /// Maps `msg` to a curve point using [`MessageDerivation::V0AdHoc`]
     pub fn from_bytes(msg: &[u8]) -> Message {
         Message(hash_bytes_to_curve::<G1Projective>(msg).to_affine())
     }
 
+    /// Maps `msg` to a curve point as defined by `derivation`
+    pub fn derive(msg: &[u8], derivation: &MessageDerivation) -> Message {
+        match derivation {
+            MessageDerivation::V0AdHoc => Message::from_bytes(msg),
+            MessageDerivation::V1HashToCurve { dst } => {
+                Message(hash_bytes_to_g1(msg, dst).to_affine())
+            }
+        }
+    }
+
     /// **IMPORTANT**: `from_bytes` includes a tag in the hash, this doesn't
     pub fn from_hash(hash: impl Digest<OutputSize = U32>) -> Message {
         Message(hash_to_curve::<G1Projective, _>(hash).to_affine())
@@ -101,8 +119,36 @@ point_impl!(Signature);
 point_impl!(BlindedSignature);
 point_impl!(BlindedSignatureShare);
 
+/// Secret scalars never show up in `Debug` output. They are `Copy` so that holders can keep them in
+/// a wrapper that erases them when dropped.
+macro_rules! secret_impl {
+    ($type:ident) => {
+        impl std::fmt::Debug for $type {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(concat!(stringify!($type), "(<redacted>)"))
+            }
+        }
+
+        impl $type {
+            /// Encodes the secret scalar, the only way to get it out of the key. The returned bytes
+            /// aren't erased, they should only be written where the key is meant to be stored.
+            pub fn to_secret_bytes(&self) -> [u8; 32] {
+                self.0.to_bytes()
+            }
+
+            /// Decodes a key encoded using `to_secret_bytes`, `None` if the bytes aren't a scalar
+            pub fn from_secret_bytes(bytes: &[u8; 32]) -> Option<$type> {
+                Option::from(Scalar::from_bytes(bytes)).map($type)
+            }
+        }
+    };
+}
+
+secret_impl!(SecretKeyShare);
+secret_impl!(BlindingKey);
+
 impl SecretKeyShare {
-    pub fn to_pub_key_share(self) -> PublicKeyShare {
+    pub fn to_pub_key_share(&self) -> PublicKeyShare {
         PublicKeyShare((G2Projective::generator() * self.0).to_affine())
     }
 }
@@ -112,6 +158,27 @@ impl BlindingKey {
         // TODO: fix rand incompatibities
         BlindingKey(Scalar::random(OsRng))
     }
+
+    /// Blinds `msg` the same way [`blind_message`] did when it returned this blinding key, which
+    /// allows recomputing blinded messages instead of storing them
+    pub fn blind(&self, msg: Message) -> BlindedMessage {
+        BlindedMessage((msg.0 * self.0).to_affine())
+    }
+}
+
+/// Serializes secret key shares, to be used with `#[serde(with = "tbs::serde_secret")]` where
+/// persisting them is intended
+pub mod serde_secret {
+    use crate::{serde_impl, SecretKeyShare};
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(sk: &SecretKeyShare, s: S) -> Result<S::Ok, S::Error> {
+        serde_impl::scalar::serialize(&sk.0, s)
+    }
+
+    pub fn deserialize<'d, D: Deserializer<'d>>(d: D) -> Result<SecretKeyShare, D::Error> {
+        serde_impl::scalar::deserialize(d).map(SecretKeyShare)
+    }
 }
 
 /// * `threshold`: how many signature shares are needed to produce a signature
@@ -140,17 +207,13 @@ pub fn dealer_keygen(
 }
 
 pub fn blind_message(msg: Message) -> (BlindingKey, BlindedMessage) {
-    let mut rng = OsRng;
-    let blinding_key = Scalar::random(&mut rng);
-    let blinded_msg = msg.0 * blinding_key;
+    let blinding_key = BlindingKey::random();
+    let blinded_msg = blinding_key.blind(msg);
 
-    (
-        BlindingKey(blinding_key),
-        BlindedMessage(blinded_msg.to_affine()),
-    )
+    (blinding_key, blinded_msg)
 }
 
-pub fn sign_blinded_msg(msg: BlindedMessage, sks: SecretKeyShare) -> BlindedSignatureShare {
+pub fn sign_blinded_msg(msg: BlindedMessage, sks: &SecretKeyShare) -> BlindedSignatureShare {
     let sig = msg.0 * sks.0;
     BlindedSignatureShare(sig.to_affine())
 }
@@ -177,7 +240,7 @@ where
     BlindedSignature(bsig.to_affine())
 }
 
-pub fn unblind_signature(blinding_key: BlindingKey, blinded_sig: BlindedSignature) -> Signature {
+pub fn unblind_signature(blinding_key: &BlindingKey, blinded_sig: BlindedSignature) -> Signature {
     let sig = blinded_sig.0 * blinding_key.0.invert().unwrap();
     Signature(sig.to_affine())
 }
@@ -186,6 +249,48 @@ pub fn verify(msg: Message, sig: Signature, pk: AggregatePublicKey) -> bool {
     pairing(&msg.0, &pk.0) == pairing(&sig.0, &G2Affine::generator())
 }
 
+/// Verifies many signatures at once using a single multi-pairing, which is considerably cheaper
+/// than verifying them one by one. Returns `false` if any of the signatures is invalid, without
+/// telling which one.
+///
+/// Every signature is weighted with a random coefficient, so invalid signatures can't cancel each
+/// other out. Messages signed under the same key are summed up before pairing, so the cost mostly
+/// depends on the number of distinct keys.
+pub fn verify_batch<I>(items: I) -> bool
+where
+    I: IntoIterator<Item = (Message, Signature, AggregatePublicKey)>,
+{
+    let mut rng = OsRng;
+    let mut sig_sum = G1Projective::identity();
+    let mut msg_sums: Vec<(AggregatePublicKey, G1Projective)> = Vec::new();
+    for (msg, sig, pk) in items {
+        // 128 bit coefficients are sufficient to make forgeries improbable
+        let coefficient = Scalar::from_raw([rng.next_u64(), rng.next_u64(), 0, 0]);
+        sig_sum += sig.0 * coefficient;
+
+        let weighted_msg = msg.0 * coefficient;
+        match msg_sums.iter_mut().find(|(key, _)| *key == pk) {
+            Some((_, msg_sum)) => *msg_sum += weighted_msg,
+            None => msg_sums.push((pk, weighted_msg)),
+        }
+    }
+
+    // Checks e(sig_sum, g2) == Π e(msg_sum, pk) as Π e(msg_sum, pk) * e(-sig_sum, g2) == 1
+    let g1_points = msg_sums
+        .iter()
+        .map(|(_, msg_sum)| msg_sum.to_affine())
+        .chain(std::iter::once((-sig_sum).to_affine()))
+        .collect::<Vec<_>>();
+    let g2_points = msg_sums
+        .iter()
+        .map(|(pk, _)| G2Prepared::from(pk.0))
+        .chain(std::iter::once(G2Prepared::from(G2Affine::generator())))
+        .collect::<Vec<_>>();
+    let terms = g1_points.iter().zip(g2_points.iter()).collect::<Vec<_>>();
+
+    multi_miller_loop(&terms).final_exponentiation() == Gt::identity()
+}
+
 pub fn verify_blind_share(
     msg: BlindedMessage,
     sig: BlindedSignatureShare,
@@ -214,11 +319,26 @@ impl Aggregatable for Vec<PublicKeyShare> {
     }
 }
 
+/// Aggregates public key shares that are indexed explicitly, e.g. because the key holders' indices
+/// aren't contiguous
+impl Aggregatable for Vec<(usize, PublicKeyShare)> {
+    type Aggregate = AggregatePublicKey;
+
+    fn aggregate(&self, threshold: usize) -> Self::Aggregate {
+        let elements = self
+            .iter()
+            .map(|(idx, PublicKeyShare(pk))| (Scalar::from((*idx as u64) + 1), pk.into()))
+            .take(threshold);
+        let pk: G2Projective = poly::interpolate_zero(elements);
+        AggregatePublicKey(pk.to_affine())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
-        verify, Aggregatable, Message,
+        verify, verify_batch, Aggregatable, Message, SecretKeyShare,
     };
 
     #[test]
@@ -230,6 +350,16 @@ mod tests {
         assert_eq!(pka, pk);
     }
 
+    #[test]
+    fn test_secret_bytes() {
+        let (_pk, _pks, sks) = dealer_keygen(1, 1);
+        let bytes = sks[0].to_secret_bytes();
+        assert_eq!(SecretKeyShare::from_secret_bytes(&bytes), Some(sks[0]));
+
+        // Larger than the group order
+        assert_eq!(SecretKeyShare::from_secret_bytes(&[0xff; 32]), None);
+    }
+
     #[test]
     fn test_roundtrip() {
         let msg = Message::from_bytes(b"Hello World!");
@@ -242,12 +372,12 @@ mod tests {
         let mut sigs = sks
             .iter()
             .enumerate()
-            .map(|(idx, sk)| (idx, sign_blinded_msg(bmsg, *sk)))
+            .map(|(idx, sk)| (idx, sign_blinded_msg(bmsg, sk)))
             .collect::<Vec<_>>();
 
         // All sig shards available
         let bsig = combine_valid_shares(sigs.clone().into_iter(), threshold);
-        let sig = unblind_signature(bkey, bsig);
+        let sig = unblind_signature(&bkey, bsig);
         assert!(verify(msg, sig, pk));
 
         // Missing sig shards
@@ -255,14 +385,49 @@ mod tests {
             sigs.pop();
         }
         let bsig = combine_valid_shares(sigs.clone().into_iter(), threshold);
-        let sig = unblind_signature(bkey, bsig);
+        let sig = unblind_signature(&bkey, bsig);
         assert!(verify(msg, sig, pk));
 
         let new_order = [9, 5, 4, 7, 8, 6, 0, 1, 3, 2];
 
         let shuffle_sigs = new_order.iter().map(|idx| sigs[*idx]);
         let bsig = combine_valid_shares(shuffle_sigs, threshold);
-        let sig = unblind_signature(bkey, bsig);
+        let sig = unblind_signature(&bkey, bsig);
         assert!(verify(msg, sig, pk));
     }
+
+    #[test]
+    fn test_verify_batch() {
+        let (pk_a, _pks, sks_a) = dealer_keygen(1, 1);
+        let (pk_b, _pks, sks_b) = dealer_keygen(1, 1);
+
+        let mut items = (0..6u8)
+            .map(|idx| {
+                let msg = Message::from_bytes(&[idx]);
+                let (bkey, bmsg) = blind_message(msg);
+                let (pk, sk) = if idx % 2 == 0 {
+                    (pk_a, &sks_a[0])
+                } else {
+                    (pk_b, &sks_b[0])
+                };
+                let bsig = combine_valid_shares(vec![(0, sign_blinded_msg(bmsg, sk))], 1);
+                (msg, unblind_signature(&bkey, bsig), pk)
+            })
+            .collect::<Vec<_>>();
+
+        assert!(verify_batch(vec![]));
+        assert!(verify_batch(items.clone()));
+
+        // Signature under the wrong key
+        let mut wrong_key = items.clone();
+        wrong_key[0].2 = pk_b;
+        assert!(!verify_batch(wrong_key));
+
+        // Swapped signatures are individually invalid but sum up to the same value
+        let (sig_0, sig_2) = (items[0].1, items[2].1);
+        items[0].1 = sig_2;
+        items[2].1 = sig_0;
+        assert!(!verify(items[0].0, items[0].1, items[0].2));
+        assert!(!verify_batch(items));
+    }
 }
@@ -28,6 +28,35 @@ where
         }
     }
 
+    /// Generates a random polynomial of `degree` that evaluates to `zero` at zero
+    pub fn random_with_zero(zero: G, degree: usize, rng: &mut impl RngCore) -> Self {
+        let mut poly = Self::random(degree, rng);
+        poly.coefficients[0] = zero;
+        poly
+    }
+}
+
+impl<G, S> Poly<G, S>
+where
+    G: Debug + MulAssign<S> + AddAssign<G> + Copy,
+    S: Copy,
+{
+    /// Constructs a polynomial from its coefficients, starting with the constant one
+    ///
+    /// # Panics
+    /// If no coefficients are supplied
+    pub fn from_coefficients(coefficients: Vec<G>) -> Self {
+        assert!(!coefficients.is_empty(), "Polynomial has no coefficients");
+        Poly {
+            coefficients,
+            _pd: PhantomData,
+        }
+    }
+
+    pub fn coefficients(&self) -> &[G] {
+        &self.coefficients
+    }
+
     pub fn evaluate(&self, x: impl Into<S>) -> G {
         let mut result = *self
             .coefficients
@@ -45,7 +74,7 @@ where
 /// Interpolates the constant factor of a polynomial defined by the points supplied in `elements`.
 ///
 /// # Panics
-/// If less than 2 points are supplied.
+/// If no points are supplied.
 pub fn interpolate_zero<G, S>(elements: impl Iterator<Item = (S, G)> + Clone) -> G
 where
     G: Copy + Mul<S, Output = G> + Add<G, Output = G>,
@@ -65,8 +94,7 @@ where
                     None
                 }
             })
-            .reduce(|a, b| a * b)
-            .expect("Elements may not be empty!")
+            .fold(S::one(), |a, b| a * b)
     };
 
     elements
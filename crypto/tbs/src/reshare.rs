@@ -0,0 +1,247 @@
+//! # Proactive Resharing
+//!
+//! Moves a shared secret key to a new set of key holders and a new threshold without changing the
+//! aggregate public key. Every old key holder splits its key share into sub-shares using a random
+//! polynomial of the new threshold's degree whose constant term is its key share, sends one
+//! sub-share to every new key holder and publishes a commitment to the polynomial. Each new key
+//! holder verifies its sub-shares against the commitments and interpolates its new key share from
+//! them. Since the old key shares interpolate to the old secret key, so do the new ones.
+//!
+//! All new key holders have to combine sub-shares from the same set of old key holders, otherwise
+//! their new key shares don't belong to the same key. That set needs to contain at least as many
+//! old key holders as the old threshold.
+
+use crate::poly::{interpolate_zero, Poly};
+use crate::{serde_impl, AggregatePublicKey, PublicKeyShare, SecretKeyShare};
+use bls12_381::{G2Affine, G2Projective, Scalar};
+use group::Curve;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+/// Commitment to the polynomial an old key holder used to split its key share, published so that
+/// the new key holders can verify their sub-shares
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReshareCommitment(Vec<CoefficientCommitment>);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+struct CoefficientCommitment(#[serde(with = "serde_impl::g2")] G2Affine);
+
+/// Splits `share` into one sub-share per new key holder.
+///
+/// * `threshold`: how many of the new key shares are needed to produce a signature
+/// * `keys`: indices of the new key holders, which don't need to be contiguous. Returns pairs of
+///   these indices and the sub-share meant for the respective key holder.
+pub fn deal_reshare(
+    share: &SecretKeyShare,
+    threshold: usize,
+    keys: &[usize],
+) -> (ReshareCommitment, Vec<(usize, SecretKeyShare)>) {
+    let mut rng = OsRng; // FIXME: pass rng
+    let poly = Poly::<Scalar, Scalar>::random_with_zero(share.0, threshold - 1, &mut rng);
+
+    let commitment = poly
+        .coefficients()
+        .iter()
+        .map(|coefficient| {
+            CoefficientCommitment((G2Projective::generator() * coefficient).to_affine())
+        })
+        .collect();
+    let sub_shares = keys
+        .iter()
+        .map(|&idx| (idx, SecretKeyShare(poly.evaluate((idx as u64) + 1))))
+        .collect();
+
+    (ReshareCommitment(commitment), sub_shares)
+}
+
+impl ReshareCommitment {
+    /// Public key share of the old key holder that dealt the sub-shares, has to be compared to the
+    /// known public key share of that key holder before accepting any of them
+    pub fn dealer_pub_key(&self) -> PublicKeyShare {
+        PublicKeyShare(self.0[0].0)
+    }
+
+    /// Number of new key shares needed to produce a signature
+    pub fn threshold(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Checks that `sub_share` was dealt to the new key holder with index `idx`
+    pub fn verify_sub_share(&self, idx: usize, sub_share: &SecretKeyShare) -> bool {
+        let expected = Poly::<G2Projective, Scalar>::from_coefficients(
+            self.0.iter().map(|c| G2Projective::from(c.0)).collect(),
+        )
+        .evaluate((idx as u64) + 1);
+        expected == G2Projective::generator() * sub_share.0
+    }
+}
+
+/// Combines the verified sub-shares dealt to one new key holder into its new key share.
+///
+/// * `sub_shares`: pairs of old key indices and the sub-share dealt by said key holder
+///
+/// # Panics
+/// If no sub-shares are supplied
+pub fn combine_sub_shares<I>(sub_shares: I) -> SecretKeyShare
+where
+    I: IntoIterator<Item = (usize, SecretKeyShare)>,
+    I::IntoIter: Clone,
+{
+    let points = sub_shares
+        .into_iter()
+        .map(|(idx, share)| (Scalar::from((idx as u64) + 1), share.0));
+    SecretKeyShare(interpolate_zero(points))
+}
+
+/// Computes the aggregate public key and the public key shares of the new key holders with the
+/// indices `keys` from the commitments of the same old key holders whose sub-shares were combined.
+/// The aggregate public key has to equal the old one, otherwise too few or inconsistent commitments
+/// were supplied.
+///
+/// * `commitments`: pairs of old key indices and the commitment published by said key holder
+///
+/// # Panics
+/// * If no commitments are supplied
+/// * If the commitments are for different thresholds
+pub fn combine_commitments<'a, I>(
+    commitments: I,
+    keys: &[usize],
+) -> (AggregatePublicKey, Vec<(usize, PublicKeyShare)>)
+where
+    I: IntoIterator<Item = (usize, &'a ReshareCommitment)>,
+{
+    let commitments = commitments.into_iter().collect::<Vec<_>>();
+    let threshold = commitments
+        .first()
+        .expect("No commitments supplied")
+        .1
+        .threshold();
+    assert!(
+        commitments.iter().all(|(_, c)| c.threshold() == threshold),
+        "Commitments for different thresholds"
+    );
+
+    let coefficients = (0..threshold)
+        .map(|coefficient| {
+            let points = commitments.iter().map(|(idx, commitment)| {
+                let x = Scalar::from((*idx as u64) + 1);
+                let y = G2Projective::from(commitment.0[coefficient].0);
+                (x, y)
+            });
+            interpolate_zero(points)
+        })
+        .collect::<Vec<G2Projective>>();
+    let poly = Poly::<G2Projective, Scalar>::from_coefficients(coefficients);
+
+    let pub_key = AggregatePublicKey(poly.evaluate(0u64).to_affine());
+    let pub_key_shares = keys
+        .iter()
+        .map(|&idx| {
+            let pub_key_share = PublicKeyShare(poly.evaluate((idx as u64) + 1).to_affine());
+            (idx, pub_key_share)
+        })
+        .collect();
+
+    (pub_key, pub_key_shares)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reshare::{combine_commitments, combine_sub_shares, deal_reshare};
+    use crate::{
+        blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
+        verify, Aggregatable, Message, SecretKeyShare,
+    };
+
+    #[test]
+    fn test_reshare() {
+        let (pk, pks, sks) = dealer_keygen(3, 4);
+
+        // Old key holders 1, 2 and 3 move the key to 6 key holders with threshold 4, whose indices
+        // aren't contiguous
+        let dealers = [1usize, 2, 3];
+        let new_keys = [0usize, 1, 2, 5, 7, 9];
+        let deals = dealers
+            .iter()
+            .map(|&idx| (idx, deal_reshare(&sks[idx], 4, &new_keys)))
+            .collect::<Vec<_>>();
+
+        for (idx, (commitment, sub_shares)) in &deals {
+            assert_eq!(commitment.dealer_pub_key(), pks[*idx]);
+            for (new_idx, sub_share) in sub_shares {
+                assert!(commitment.verify_sub_share(*new_idx, sub_share));
+                assert!(!commitment.verify_sub_share(new_idx + 1, sub_share));
+            }
+        }
+
+        let new_sks = (0..new_keys.len())
+            .map(|pos| {
+                let sk = combine_sub_shares(
+                    deals
+                        .iter()
+                        .map(|(idx, (_, sub_shares))| (*idx, sub_shares[pos].1)),
+                );
+                (new_keys[pos], sk)
+            })
+            .collect::<Vec<(usize, SecretKeyShare)>>();
+        let (new_pk, new_pks) =
+            combine_commitments(deals.iter().map(|(idx, (c, _))| (*idx, c)), &new_keys);
+        assert_eq!(new_pk, pk);
+        assert_eq!(
+            new_pks,
+            new_sks
+                .iter()
+                .map(|(idx, sk)| (*idx, sk.to_pub_key_share()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(new_pks.aggregate(4), pk);
+
+        // Any 4 of the new key shares produce signatures valid under the old key
+        let msg = Message::from_bytes(b"Hello World!");
+        let (bkey, bmsg) = blind_message(msg);
+        let sig_shares = new_sks
+            .iter()
+            .skip(2)
+            .map(|(idx, sk)| (*idx, sign_blinded_msg(bmsg, sk)));
+        let sig = unblind_signature(&bkey, combine_valid_shares(sig_shares, 4));
+        assert!(verify(msg, sig, pk));
+
+        // Too few dealers don't reproduce the key
+        let (wrong_pk, _) = combine_commitments(
+            deals.iter().take(2).map(|(idx, (c, _))| (*idx, c)),
+            &new_keys,
+        );
+        assert_ne!(wrong_pk, pk);
+    }
+
+    #[test]
+    fn test_reshare_single_dealer() {
+        // A single key holder with threshold 1 adds two key holders and raises the threshold
+        let (pk, _, sks) = dealer_keygen(1, 1);
+        let new_keys = [0usize, 1, 2];
+        let (commitment, sub_shares) = deal_reshare(&sks[0], 2, &new_keys);
+
+        let new_sks = sub_shares
+            .iter()
+            .map(|(idx, sub_share)| (*idx, combine_sub_shares(vec![(0, *sub_share)])))
+            .collect::<Vec<_>>();
+        let (new_pk, new_pks) = combine_commitments(vec![(0, &commitment)], &new_keys);
+        assert_eq!(new_pk, pk);
+        assert_eq!(new_pks.aggregate(2), pk);
+
+        // Two of the new key holders move the key back to a single key holder
+        let deals = [0usize, 2]
+            .iter()
+            .map(|&idx| (idx, deal_reshare(&new_sks[idx].1, 1, &[0])))
+            .collect::<Vec<_>>();
+        let sk = combine_sub_shares(
+            deals
+                .iter()
+                .map(|(idx, (_, sub_shares))| (*idx, sub_shares[0].1)),
+        );
+        let (new_pk, new_pks) =
+            combine_commitments(deals.iter().map(|(idx, (c, _))| (*idx, c)), &[0]);
+        assert_eq!(new_pk, pk);
+        assert_eq!(new_pks, vec![(0, sk.to_pub_key_share())]);
+    }
+}
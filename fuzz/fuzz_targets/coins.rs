@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use minimint_mint::tiered::coins::Coins;
+use minimint_mint::Coin;
+
+fuzz_target!(|data: &[u8]| {
+    minimint_fuzz::fuzz_roundtrip::<Coins<Coin>>(data);
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use minimint_ln::ContractOrOfferOutput;
+
+fuzz_target!(|data: &[u8]| {
+    minimint_fuzz::fuzz_roundtrip::<ContractOrOfferOutput>(data);
+});
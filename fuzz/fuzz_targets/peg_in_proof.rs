@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use minimint_wallet::txoproof::PegInProof;
+
+fuzz_target!(|data: &[u8]| {
+    minimint_fuzz::fuzz_roundtrip::<PegInProof>(data);
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use minimint::transaction::Transaction;
+
+fuzz_target!(|data: &[u8]| {
+    minimint_fuzz::fuzz_roundtrip::<Transaction>(data);
+});
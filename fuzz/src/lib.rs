@@ -0,0 +1,27 @@
+use minimint_api::encoding::{Decodable, Encodable};
+use std::fmt::Debug;
+use std::io::Cursor;
+
+/// Maximum number of bytes decoded per input, same order of magnitude as a network message
+const MAX_INPUT_LEN: u64 = 1024 * 1024;
+
+/// Decodes `data` as `T` and, if that succeeds, checks that encoding and decoding the result
+/// again yields the same value
+pub fn fuzz_roundtrip<T>(data: &[u8])
+where
+    T: Encodable + Decodable + PartialEq + Debug,
+{
+    let value = match T::consensus_decode_limited(Cursor::new(data), MAX_INPUT_LEN) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+
+    let mut bytes = Vec::new();
+    let len = value
+        .consensus_encode(&mut bytes)
+        .expect("writing to vec can't fail");
+    assert_eq!(len, bytes.len());
+
+    let decoded = T::consensus_decode(Cursor::new(&bytes)).expect("re-encoded value must decode");
+    assert_eq!(value, decoded);
+}
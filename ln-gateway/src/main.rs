@@ -2,6 +2,7 @@ use clightningrpc::lightningrpc::PayOptions;
 use clightningrpc::LightningRPC;
 use minimint::config::{load_from_file, ClientConfig};
 use minimint::modules::mint::tiered::coins::Coins;
+use minimint_api::db::sled_impl::SledDb;
 use minimint_api::OutPoint;
 use mint_client::mint::SpendableCoin;
 use mint_client::MintClient;
@@ -59,7 +60,7 @@ async fn pay_invoice(mut req: tide::Request<State>) -> tide::Result {
 
     debug!("Trying to reissue");
     let txid = mint_client
-        .reissue(pay_req.coins, &mut rng)
+        .reissue(pay_req.coins, None, &mut rng)
         .await
         .expect("error while starting reissuance");
     debug!("Fetching coins");
@@ -110,7 +111,7 @@ async fn main() -> tide::Result<()> {
         .open_tree("mint-client")
         .unwrap();
 
-    let client = MintClient::new(cfg.client, Arc::new(db), Default::default());
+    let client = MintClient::new(cfg.client, Arc::new(SledDb::new(db)), Default::default());
     let ln_client = LightningRPC::new(cfg.ln_socket);
 
     let state = State {
@@ -1,6 +1,9 @@
-use super::{DatabaseKeyPrefix, SerializableDatabaseValue};
+use super::overlay::OverlayDb;
+use super::{DatabaseKeyPrefix, RawDatabase, SerializableDatabaseValue};
 use crate::db::{DatabaseKey, DatabaseValue, DecodingError};
 use std::any::Any;
+use std::collections::BTreeMap;
+use std::ops::Bound;
 
 pub type DbBatch = Accumulator<BatchItem>;
 pub type BatchTx<'a> = AccumulatorTx<'a, BatchItem>;
@@ -8,15 +11,15 @@ pub type BatchTx<'a> = AccumulatorTx<'a, BatchItem>;
 #[derive(Debug)]
 /// Database key-value pair
 pub struct Element {
-    pub key: Box<dyn DatabaseKeyPrefix + Send>,
-    pub value: Box<dyn SerializableDatabaseValue + Send>,
+    pub key: Box<dyn DatabaseKeyPrefix + Send + Sync>,
+    pub value: Box<dyn SerializableDatabaseValue + Send + Sync>,
 }
 
 pub type UpdateFunction =
-    Box<dyn Fn(&[u8], Option<&[u8]>) -> Result<Option<Vec<u8>>, DecodingError> + Send>;
+    Box<dyn Fn(&[u8], Option<&[u8]>) -> Result<Option<Vec<u8>>, DecodingError> + Send + Sync>;
 
 pub struct Update {
-    pub key: Box<dyn DatabaseKeyPrefix + Send>,
+    pub key: Box<dyn DatabaseKeyPrefix + Send + Sync>,
     pub updater: UpdateFunction,
 }
 
@@ -28,9 +31,9 @@ pub enum BatchItem {
     /// Insets new element, even if it already exists
     InsertElement(Element),
     /// Deletes element, errors if it doesn't exist
-    DeleteElement(Box<dyn DatabaseKeyPrefix + Send>),
+    DeleteElement(Box<dyn DatabaseKeyPrefix + Send + Sync>),
     /// Deletes element, does nothing if it doesn't exist
-    MaybeDeleteElement(Box<dyn DatabaseKeyPrefix + Send>),
+    MaybeDeleteElement(Box<dyn DatabaseKeyPrefix + Send + Sync>),
     /// Tries to update an element.
     ///
     /// If it doesn't exist already the update fn may decide if to do nothing or to insert a new
@@ -41,8 +44,8 @@ pub enum BatchItem {
 impl Element {
     pub fn new<K, V>(key: K, value: V) -> Element
     where
-        K: DatabaseKeyPrefix + Send + 'static,
-        V: SerializableDatabaseValue + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
+        V: SerializableDatabaseValue + Send + Sync + 'static,
     {
         Element {
             key: Box::new(key),
@@ -61,9 +64,22 @@ impl Element {
 ///
 /// Resetting happens automatically when a [`AccumulatorTx`] is dropped after going out of scope.
 /// To prevent this from happening [`AccumulatorTx::commit`] should be called before.
+///
+/// Items are indexed by the database key they refer to, so the pending items of a key can be
+/// looked up without scanning the whole batch (see [`OverlayDb`]).
 #[derive(Debug, Clone)]
 pub struct Accumulator<T> {
     buffer: Vec<T>,
+    /// Encoded key of every item in `buffer`
+    keys: Vec<Option<Vec<u8>>>,
+    /// Positions of the items in `buffer` by key, in the order they were appended
+    index: BTreeMap<Vec<u8>, Vec<usize>>,
+}
+
+/// Item of an [`Accumulator`] that may refer to a database key
+pub trait KeyedItem {
+    /// Returns the encoded database key the item refers to, if any
+    fn key_bytes(&self) -> Option<Vec<u8>>;
 }
 
 /// A transaction on an [`Accumulator`] that aborts by default when dropped unless `commit` is
@@ -76,7 +92,11 @@ pub struct AccumulatorTx<'a, T> {
 impl<T> Accumulator<T> {
     /// Construct a new, empty `Accumulator`
     pub fn new() -> Accumulator<T> {
-        Accumulator { buffer: Vec::new() }
+        Accumulator {
+            buffer: Vec::new(),
+            keys: Vec::new(),
+            index: BTreeMap::new(),
+        }
     }
 
     /// Start a new transaction. If the [`AccumulatorTx`] is dropped without [`AccumulatorTx::commit`]
@@ -99,6 +119,54 @@ impl<T> Accumulator<T> {
         f(&mut tx);
         tx.commit();
     }
+
+    /// Returns the items referring to `key` in the order they were appended
+    pub fn items_by_key<'a>(&'a self, key: &[u8]) -> impl Iterator<Item = &'a T> + 'a {
+        self.index
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(move |&pos| &self.buffer[pos])
+    }
+
+    /// Returns all keys starting with `key_prefix` that items refer to, ordered by key, together
+    /// with their items in the order they were appended
+    pub fn items_by_prefix<'a>(
+        &'a self,
+        key_prefix: &'a [u8],
+    ) -> impl Iterator<Item = (&'a [u8], Vec<&'a T>)> + 'a {
+        self.index
+            .range::<[u8], _>((Bound::Included(key_prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| key.starts_with(key_prefix))
+            .map(move |(key, positions)| {
+                let items = positions.iter().map(|&pos| &self.buffer[pos]).collect();
+                (key.as_slice(), items)
+            })
+    }
+
+    fn push(&mut self, item: T, key: Option<Vec<u8>>) {
+        if let Some(key) = &key {
+            self.index
+                .entry(key.clone())
+                .or_default()
+                .push(self.buffer.len());
+        }
+        self.buffer.push(item);
+        self.keys.push(key);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        while self.buffer.len() > len {
+            self.buffer.pop();
+            if let Some(key) = self.keys.pop().expect("every item has a key entry") {
+                let positions = self.index.get_mut(&key).expect("indexed on push");
+                positions.pop();
+                if positions.is_empty() {
+                    self.index.remove(&key);
+                }
+            }
+        }
+    }
 }
 
 impl<T> Default for Accumulator<T> {
@@ -113,16 +181,6 @@ impl<'a, T> AccumulatorTx<'a, T> {
         std::mem::forget(self);
     }
 
-    /// Append one `item` to the pending transaction
-    pub fn append(&mut self, item: T) {
-        self.batch.buffer.push(item);
-    }
-
-    /// Append multiple items to the pending transaction
-    pub fn append_from_iter(&mut self, iter: impl Iterator<Item = T>) {
-        self.batch.buffer.extend(iter);
-    }
-
     /// Start a sub-transaction which has the following behavior:
     ///  * Aborting the sub-transaction does not automatically abort the parent transaction but
     ///    only resets the parent transaction to the state when the sub-transaction was created.
@@ -141,22 +199,42 @@ impl<'a, T> AccumulatorTx<'a, T> {
         }
     }
 
+    /// Allocate space for items to avoid frequent reallocation
+    pub fn reserve(&mut self, items: usize) {
+        self.batch.buffer.reserve(items);
+        self.batch.keys.reserve(items);
+    }
+}
+
+impl<'a, T: KeyedItem> AccumulatorTx<'a, T> {
+    /// Append one `item` to the pending transaction
+    pub fn append(&mut self, item: T) {
+        let key = item.key_bytes();
+        self.batch.push(item, key);
+    }
+
+    /// Append multiple items to the pending transaction
+    pub fn append_from_iter(&mut self, iter: impl Iterator<Item = T>) {
+        for item in iter {
+            self.append(item);
+        }
+    }
+
     /// Currently the accumulator and transactions are not thread safe. Therefore one has to create
     /// at least one accumulator per thread when parallelizing. This function can be used to merge
     /// these with a minimal amount of allocations.
     pub fn append_from_accumulators(&mut self, iter: impl Iterator<Item = Accumulator<T>>) {
-        self.append_from_iter(iter.flat_map(|acc| acc.buffer))
-    }
-
-    /// Allocate space for items to avoid frequent reallocation
-    pub fn reserve(&mut self, items: usize) {
-        self.batch.buffer.reserve(items);
+        for acc in iter {
+            for (item, key) in acc.buffer.into_iter().zip(acc.keys) {
+                self.batch.push(item, key);
+            }
+        }
     }
 }
 
 impl<'a, T> Drop for AccumulatorTx<'a, T> {
     fn drop(&mut self) {
-        self.batch.buffer.truncate(self.checkpoint);
+        self.batch.truncate(self.checkpoint);
     }
 }
 
@@ -166,12 +244,24 @@ impl<T> From<Accumulator<T>> for Vec<T> {
     }
 }
 
+impl KeyedItem for BatchItem {
+    fn key_bytes(&self) -> Option<Vec<u8>> {
+        Some(match self {
+            BatchItem::InsertNewElement(element) | BatchItem::InsertElement(element) => {
+                element.key.to_bytes()
+            }
+            BatchItem::DeleteElement(key) | BatchItem::MaybeDeleteElement(key) => key.to_bytes(),
+            BatchItem::MaybeUpdate(update) => update.key.to_bytes(),
+        })
+    }
+}
+
 impl BatchItem {
     /// Construct a DB operation to insert a new element
     pub fn insert_new<K, V>(key: K, value: V) -> Self
     where
-        K: DatabaseKeyPrefix + Send + 'static,
-        V: SerializableDatabaseValue + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
+        V: SerializableDatabaseValue + Send + Sync + 'static,
     {
         BatchItem::InsertNewElement(Element::new(key, value))
     }
@@ -179,8 +269,8 @@ impl BatchItem {
     /// Construct a DB operation to insert a potentially already existing item
     pub fn insert<K, V>(key: K, value: V) -> Self
     where
-        K: DatabaseKeyPrefix + Send + 'static,
-        V: SerializableDatabaseValue + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
+        V: SerializableDatabaseValue + Send + Sync + 'static,
     {
         BatchItem::InsertElement(Element::new(key, value))
     }
@@ -188,7 +278,7 @@ impl BatchItem {
     /// Construct a DB operation to delete an existing element
     pub fn delete<K>(key: K) -> Self
     where
-        K: DatabaseKeyPrefix + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
     {
         BatchItem::DeleteElement(Box::new(key))
     }
@@ -196,7 +286,7 @@ impl BatchItem {
     /// Construct a DB operation to delete a potentially absent element
     pub fn maybe_delete<K>(key: K) -> Self
     where
-        K: DatabaseKeyPrefix + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
     {
         BatchItem::MaybeDeleteElement(Box::new(key))
     }
@@ -204,8 +294,8 @@ impl BatchItem {
     /// Construct a DB operation to insert a potentially already existing item
     pub fn maybe_update<K, V, F>(key: K, update_fn: F) -> Self
     where
-        K: DatabaseKey + Send + 'static,
-        V: DatabaseValue + Send + 'static,
+        K: DatabaseKey + Send + Sync + 'static,
+        V: DatabaseValue + Send + Sync + 'static,
         F: Fn(K, Option<V>) -> Option<V> + Send + Sync + 'static,
     {
         let dyn_update_fn = Box::new(
@@ -228,8 +318,8 @@ impl<'a> AccumulatorTx<'a, BatchItem> {
     /// Append a DB operation to insert a new element
     pub fn append_insert_new<K, V>(&mut self, key: K, value: V)
     where
-        K: DatabaseKeyPrefix + Send + 'static,
-        V: SerializableDatabaseValue + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
+        V: SerializableDatabaseValue + Send + Sync + 'static,
     {
         self.append(BatchItem::insert_new(key, value))
     }
@@ -237,8 +327,8 @@ impl<'a> AccumulatorTx<'a, BatchItem> {
     /// Append a DB operation to insert a potentially already existing item
     pub fn append_insert<K, V>(&mut self, key: K, value: V)
     where
-        K: DatabaseKeyPrefix + Send + 'static,
-        V: SerializableDatabaseValue + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
+        V: SerializableDatabaseValue + Send + Sync + 'static,
     {
         self.append(BatchItem::insert(key, value))
     }
@@ -246,7 +336,7 @@ impl<'a> AccumulatorTx<'a, BatchItem> {
     /// Append a DB operation to delete an existing element
     pub fn append_delete<K>(&mut self, key: K)
     where
-        K: DatabaseKeyPrefix + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
     {
         self.append(BatchItem::delete(key))
     }
@@ -254,16 +344,22 @@ impl<'a> AccumulatorTx<'a, BatchItem> {
     /// Append a DB operation to delete a potentially absent element
     pub fn append_maybe_delete<K>(&mut self, key: K)
     where
-        K: DatabaseKeyPrefix + Send + 'static,
+        K: DatabaseKeyPrefix + Send + Sync + 'static,
     {
         self.append(BatchItem::maybe_delete(key))
     }
 
+    /// Returns a read-only view of `db` that reflects all DB operations accumulated so far as if
+    /// they were already applied
+    pub fn overlay<'b>(&'b self, db: &'b dyn RawDatabase) -> OverlayDb<'b> {
+        OverlayDb::new(db, &*self.batch)
+    }
+
     /// Append a DB operation to atomically update a potentially absent element (might create or ignore in that case)
     pub fn append_maybe_update<K, V, F>(&mut self, key: K, update_fn: F)
     where
-        K: DatabaseKey + Send + 'static,
-        V: DatabaseValue + Send + 'static,
+        K: DatabaseKey + Send + Sync + 'static,
+        V: DatabaseValue + Send + Sync + 'static,
         F: Fn(K, Option<V>) -> Option<V> + Send + Sync + 'static,
     {
         self.append(BatchItem::maybe_update(key, update_fn))
@@ -278,7 +374,13 @@ impl std::fmt::Debug for Update {
 
 #[cfg(test)]
 mod test {
-    use super::Accumulator;
+    use super::{Accumulator, KeyedItem};
+
+    impl KeyedItem for u8 {
+        fn key_bytes(&self) -> Option<Vec<u8>> {
+            Some(vec![*self % 2])
+        }
+    }
 
     #[test]
     fn test_transaction() {
@@ -310,6 +412,37 @@ mod test {
             tx.append(2);
             tx.append(3);
         }
+        assert_eq!(acc.items_by_key(&[1]).collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(acc.items_by_key(&[0]).count(), 0);
         assert_eq!(Vec::<u8>::from(acc), vec![1]);
     }
+
+    #[test]
+    fn test_index() {
+        let mut acc = Accumulator::<u8>::new();
+        acc.autocommit(|tx| tx.append_from_iter(1..=4));
+        {
+            let mut tx = acc.transaction();
+            tx.append(5);
+            let mut sub_tx = tx.subtransaction();
+            sub_tx.append(6);
+            sub_tx.commit();
+            tx.commit();
+        }
+        {
+            let mut tx = acc.transaction();
+            tx.append(8);
+            tx.append(7);
+        }
+
+        assert_eq!(acc.items_by_key(&[0]).collect::<Vec<_>>(), vec![&2, &4, &6]);
+        assert_eq!(acc.items_by_key(&[1]).collect::<Vec<_>>(), vec![&1, &3, &5]);
+        assert_eq!(
+            acc.items_by_prefix(&[])
+                .map(|(key, items)| (key.to_vec(), items.len()))
+                .collect::<Vec<_>>(),
+            vec![(vec![0], 3), (vec![1], 3)]
+        );
+        assert_eq!(acc.items_by_prefix(&[2]).count(), 0);
+    }
 }
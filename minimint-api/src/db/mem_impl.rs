@@ -24,6 +24,26 @@ impl MemDatabase {
             eprintln!("{}: {}", hex::encode(key), hex::encode(value));
         }
     }
+
+    /// Creates a database from the output of [`MemDatabase::dump_db`], which is useful to load
+    /// fixtures. Empty lines and lines starting with `#` are ignored.
+    pub fn from_dump(dump: &str) -> Result<MemDatabase, hex::FromHexError> {
+        let data = dump
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.splitn(2, ':');
+                let key = hex::decode(parts.next().expect("splitn yields at least one item"))?;
+                let value = hex::decode(parts.next().unwrap_or_default().trim())?;
+                Ok((key, value))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+        Ok(MemDatabase {
+            data: Arc::new(Mutex::new(data)),
+        })
+    }
 }
 
 impl RawDatabase for MemDatabase {
@@ -59,12 +79,14 @@ impl RawDatabase for MemDatabase {
 
     fn raw_apply_batch(&self, batch: DbBatch) -> Result<(), DatabaseError> {
         let batch: Vec<_> = batch.into();
+        // Hold the lock for the whole batch so snapshots never observe it partially applied
+        let mut data = self.data.lock().unwrap();
 
         for change in batch.iter() {
             match change {
                 BatchItem::InsertNewElement(element) => {
-                    if self
-                        .raw_insert_entry(&element.key.to_bytes(), element.value.to_bytes())?
+                    if data
+                        .insert(element.key.to_bytes(), element.value.to_bytes())
                         .is_some()
                     {
                         error!("Database replaced element! This should not happen!");
@@ -72,25 +94,23 @@ impl RawDatabase for MemDatabase {
                     }
                 }
                 BatchItem::InsertElement(element) => {
-                    self.raw_insert_entry(&element.key.to_bytes(), element.value.to_bytes())?;
+                    data.insert(element.key.to_bytes(), element.value.to_bytes());
                 }
                 BatchItem::DeleteElement(key) => {
-                    if self.raw_remove_entry(&key.to_bytes())?.is_none() {
+                    if data.remove(&key.to_bytes()).is_none() {
                         error!("Database deleted absent element! This should not happen!");
                         trace!("Problematic key: {:?}", key);
                     }
                 }
                 BatchItem::MaybeDeleteElement(key) => {
-                    self.raw_remove_entry(&key.to_bytes())?;
+                    data.remove(&key.to_bytes());
                 }
                 BatchItem::MaybeUpdate(update) => {
                     let key = update.key.to_bytes();
-                    let maybe_val = self.raw_get_value(&key)?;
-                    let maybe_val_ref = maybe_val.as_ref().map(|v| v.as_ref());
-                    let maybe_new_val = (update.updater)(&key, maybe_val_ref)?;
+                    let maybe_new_val = (update.updater)(&key, data.get(&key).map(Vec::as_slice))?;
 
                     if let Some(new_val) = maybe_new_val {
-                        self.raw_insert_entry(&key, new_val)?;
+                        data.insert(key, new_val);
                     }
                 }
             }
@@ -98,6 +118,16 @@ impl RawDatabase for MemDatabase {
 
         Ok(())
     }
+
+    fn raw_snapshot(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatabaseError> {
+        Ok(self
+            .data
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
 }
 
 struct MemDbIter {
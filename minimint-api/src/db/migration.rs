@@ -0,0 +1,278 @@
+use super::batch::{BatchTx, DbBatch};
+use super::registry::{decode_entry, DbPrefix};
+use super::{Database, DatabaseError, DatabaseKeyPrefixConst, RawDatabase};
+use crate::encoding::{Decodable, Encodable};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use thiserror::Error;
+use tracing::info;
+
+pub const DB_PREFIX_SCHEMA_VERSION: u8 = 0x00;
+
+/// Schema versions are stored in the server's as well as in the client's database
+pub const DB_PREFIXES: &[DbPrefix] = &[DbPrefix {
+    prefix: DB_PREFIX_SCHEMA_VERSION,
+    name: "Schema Versions",
+    key: "module name",
+    value: "schema version",
+    decode: decode_entry::<SchemaVersionKey, SchemaVersion>,
+}];
+
+/// Version of the database layout of one module
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    Serialize,
+    Encodable,
+    Decodable,
+)]
+pub struct SchemaVersion(pub u32);
+
+impl SchemaVersion {
+    pub fn increment(self) -> SchemaVersion {
+        SchemaVersion(self.0 + 1)
+    }
+}
+
+impl std::fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Schema version of the module with the given name
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
+pub struct SchemaVersionKey(pub String);
+
+impl DatabaseKeyPrefixConst for SchemaVersionKey {
+    const DB_PREFIX: u8 = DB_PREFIX_SCHEMA_VERSION;
+}
+
+/// Migrates the entries of a module from one schema version to the next one. The migration reads
+/// the old entries from the database and appends the necessary changes to the batch, which is
+/// applied atomically together with the version bump.
+pub type MigrationFn = fn(&dyn RawDatabase, &mut BatchTx) -> Result<(), DatabaseError>;
+
+/// Database schema of a module and the migrations leading to it from older versions
+pub struct ModuleMigrations {
+    module: &'static str,
+    version: SchemaVersion,
+    /// Migrations indexed by the version they migrate from
+    migrations: BTreeMap<SchemaVersion, MigrationFn>,
+}
+
+/// All modules' migrations that are run on start-up before using the database
+#[derive(Default)]
+pub struct MigrationRegistry {
+    modules: Vec<ModuleMigrations>,
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("Database of module {module} has version {found}, but only {supported} is supported")]
+    UnsupportedVersion {
+        module: &'static str,
+        found: SchemaVersion,
+        supported: SchemaVersion,
+    },
+    #[error("No migration of module {module} from version {from} registered")]
+    MissingMigration {
+        module: &'static str,
+        from: SchemaVersion,
+    },
+    #[error("Database error: {0}")]
+    DatabaseError(DatabaseError),
+}
+
+impl ModuleMigrations {
+    /// Creates the schema of `module` at `version`. Databases without a stored version are
+    /// assumed to be at version 0, the layout used before versioning was introduced.
+    pub fn new(module: &'static str, version: SchemaVersion) -> ModuleMigrations {
+        ModuleMigrations {
+            module,
+            version,
+            migrations: BTreeMap::new(),
+        }
+    }
+
+    /// Registers the migration from version `from` to the next one
+    ///
+    /// # Panics
+    /// * If there already is a migration from `from`
+    /// * If `from` isn't older than the schema version
+    pub fn with_migration(mut self, from: SchemaVersion, migration: MigrationFn) -> Self {
+        assert!(from < self.version, "Migration starts at current version");
+        assert!(
+            self.migrations.insert(from, migration).is_none(),
+            "Migration registered twice"
+        );
+        self
+    }
+
+    pub fn module(&self) -> &'static str {
+        self.module
+    }
+
+    pub fn version(&self) -> SchemaVersion {
+        self.version
+    }
+
+    /// Reads the version the database is currently at
+    pub fn stored_version(&self, db: &dyn RawDatabase) -> Result<SchemaVersion, MigrationError> {
+        Ok(db
+            .get_value(&SchemaVersionKey(self.module.to_owned()))?
+            .unwrap_or_default())
+    }
+
+    /// Migrates the module's entries in `db` to the current schema version. Each migration step is
+    /// applied atomically, so an interrupted upgrade is resumed from the last completed step.
+    pub fn apply(&self, db: &dyn RawDatabase) -> Result<(), MigrationError> {
+        let mut stored_version = self.stored_version(db)?;
+
+        if stored_version > self.version {
+            return Err(MigrationError::UnsupportedVersion {
+                module: self.module,
+                found: stored_version,
+                supported: self.version,
+            });
+        }
+
+        while stored_version < self.version {
+            let migration =
+                self.migrations
+                    .get(&stored_version)
+                    .ok_or(MigrationError::MissingMigration {
+                        module: self.module,
+                        from: stored_version,
+                    })?;
+            let next_version = stored_version.increment();
+
+            let mut batch = DbBatch::new();
+            let mut tx = batch.transaction();
+            migration(db, &mut tx)?;
+            tx.append_insert(SchemaVersionKey(self.module.to_owned()), next_version);
+            tx.commit();
+            db.apply_batch(batch)?;
+
+            info!(
+                "Migrated database of module {} from version {} to {}",
+                self.module, stored_version, next_version
+            );
+            stored_version = next_version;
+        }
+
+        Ok(())
+    }
+}
+
+impl MigrationRegistry {
+    pub fn new() -> MigrationRegistry {
+        Default::default()
+    }
+
+    /// Registers the schema of a module
+    ///
+    /// # Panics
+    /// * If a schema with the same module name was registered already
+    pub fn with_module(mut self, module: ModuleMigrations) -> Self {
+        assert!(
+            self.modules.iter().all(|m| m.module != module.module),
+            "Module {} registered twice",
+            module.module
+        );
+        self.modules.push(module);
+        self
+    }
+
+    /// Runs all outstanding migrations of all registered modules
+    pub fn apply(&self, db: &dyn RawDatabase) -> Result<(), MigrationError> {
+        self.modules.iter().try_for_each(|module| module.apply(db))
+    }
+}
+
+impl From<DatabaseError> for MigrationError {
+    fn from(e: DatabaseError) -> Self {
+        MigrationError::DatabaseError(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MigrationError, MigrationRegistry, ModuleMigrations, SchemaVersion};
+    use crate::db::batch::BatchTx;
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::tests::{TestKey, TestVal};
+    use crate::db::{Database, DatabaseError, DatabaseKeyPrefixConst, RawDatabase};
+    use crate::encoding::{Decodable, Encodable};
+
+    /// Test module that switched its values from `u32` to `u64` in version 1
+    const TEST_MODULE: &str = "test";
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct TestKeyPrefix;
+
+    impl DatabaseKeyPrefixConst for TestKeyPrefix {
+        const DB_PREFIX: u8 = 0x42;
+    }
+
+    fn migrate_v0(db: &dyn RawDatabase, batch: &mut BatchTx) -> Result<(), DatabaseError> {
+        for res in db.find_by_prefix::<_, TestKey, u32>(&TestKeyPrefix) {
+            let (key, old_value) = res?;
+            batch.append_insert(key, TestVal(old_value as u64));
+        }
+        Ok(())
+    }
+
+    fn test_migrations() -> MigrationRegistry {
+        MigrationRegistry::new().with_module(
+            ModuleMigrations::new(TEST_MODULE, SchemaVersion(1))
+                .with_migration(SchemaVersion(0), migrate_v0),
+        )
+    }
+
+    #[test]
+    fn test_migrate_fixture() {
+        let db = MemDatabase::from_dump(include_str!("fixtures/migration_v0.dump")).unwrap();
+        let db: &dyn RawDatabase = &db;
+        let module = ModuleMigrations::new(TEST_MODULE, SchemaVersion(1));
+        assert_eq!(module.stored_version(db).unwrap(), SchemaVersion(0));
+
+        test_migrations().apply(db).unwrap();
+
+        assert_eq!(module.stored_version(db).unwrap(), SchemaVersion(1));
+        assert_eq!(db.get_value(&TestKey(1)).unwrap(), Some(TestVal(42)));
+        assert_eq!(db.get_value(&TestKey(2)).unwrap(), Some(TestVal(1337)));
+
+        // Running the migrations again doesn't change anything
+        test_migrations().apply(db).unwrap();
+        assert_eq!(db.get_value(&TestKey(1)).unwrap(), Some(TestVal(42)));
+    }
+
+    #[test]
+    fn test_reject_newer_version() {
+        let db = MemDatabase::new();
+        let db: &dyn RawDatabase = &db;
+        test_migrations().apply(db).unwrap();
+
+        let old_schema = MigrationRegistry::new()
+            .with_module(ModuleMigrations::new(TEST_MODULE, SchemaVersion(0)));
+        assert!(matches!(
+            old_schema.apply(db),
+            Err(MigrationError::UnsupportedVersion { .. })
+        ));
+
+        let missing_migration = MigrationRegistry::new()
+            .with_module(ModuleMigrations::new(TEST_MODULE, SchemaVersion(2)));
+        assert!(matches!(
+            missing_migration.apply(db),
+            Err(MigrationError::MissingMigration { .. })
+        ));
+    }
+}
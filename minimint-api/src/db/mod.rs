@@ -9,7 +9,11 @@ use tracing::trace;
 
 pub mod batch;
 pub mod mem_impl;
+pub mod migration;
+pub mod overlay;
+pub mod registry;
 pub mod sled_impl;
+pub mod snapshot;
 
 pub trait DatabaseKeyPrefixConst {
     const DB_PREFIX: u8;
@@ -48,6 +52,11 @@ pub trait RawDatabase: Send + Sync {
     fn raw_find_by_prefix(&self, key_prefix: &[u8]) -> PrefixIter;
 
     fn raw_apply_batch(&self, batch: DbBatch) -> Result<(), DatabaseError>;
+
+    /// Returns all entries of the database ordered by key. The snapshot has to be consistent, i.e.
+    /// reflect either all or none of the changes of any batch applied concurrently. Backends that
+    /// can't isolate iteration from writes require the caller to have exclusive access.
+    fn raw_snapshot(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatabaseError>;
 }
 
 pub trait Database {
@@ -252,6 +261,8 @@ pub enum DecodingError {
     WrongPrefix { expected: u8, found: u8 },
     #[error("Key had a wrong length, expected {expected} but got {found}")]
     WrongLength { expected: usize, found: usize },
+    #[error("Entry isn't canonically encoded")]
+    NonCanonical,
     #[error("Other decoding error: {0}")]
     Other(Box<dyn Error + Send + 'static>),
 }
@@ -284,22 +295,25 @@ impl From<DecodingError> for DatabaseError {
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// Test suite every [`RawDatabase`] implementation should pass, exposed through the `test-utils`
+/// feature so backends living in other crates can run it too
+#[cfg(any(test, feature = "test-utils"))]
+pub mod tests {
     use super::{Database, RawDatabase};
     use crate::db::DatabaseKeyPrefixConst;
     use crate::encoding::{Decodable, Encodable};
+    use serde::Serialize;
     use std::sync::Arc;
 
-    #[derive(Debug, Encodable, Decodable)]
-    struct TestKey(u64);
+    #[derive(Debug, Serialize, Encodable, Decodable)]
+    pub struct TestKey(pub u64);
 
     impl DatabaseKeyPrefixConst for TestKey {
         const DB_PREFIX: u8 = 0x42;
     }
 
-    #[derive(Debug, Encodable, Decodable, Eq, PartialEq)]
-    struct TestVal(u64);
+    #[derive(Debug, Serialize, Encodable, Decodable, Eq, PartialEq)]
+    pub struct TestVal(pub u64);
 
     pub fn test_db_impl(db: Arc<dyn RawDatabase + 'static>) {
         assert!(db
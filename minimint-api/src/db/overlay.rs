@@ -0,0 +1,168 @@
+use super::batch::{BatchItem, DbBatch};
+use super::{DatabaseError, DecodingError, PrefixIter, RawDatabase};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Read-only view of a database with a batch of not yet applied [`BatchItem`]s on top of it
+///
+/// Reads return what the database would contain after applying the pending items, which allows
+/// modules to see the effects of earlier operations of the same epoch before the epoch's batch is
+/// written. Pending items are looked up by key, so reads don't get slower with the batch's size.
+/// Usually obtained through [`AccumulatorTx::overlay`](super::batch::AccumulatorTx::overlay).
+pub struct OverlayDb<'a> {
+    db: &'a dyn RawDatabase,
+    pending: &'a DbBatch,
+}
+
+#[derive(Debug, Error)]
+#[error("Overlay databases are read-only, writes have to be appended to the batch")]
+pub struct ReadOnlyOverlay;
+
+impl<'a> OverlayDb<'a> {
+    pub fn new(db: &'a dyn RawDatabase, pending: &'a DbBatch) -> OverlayDb<'a> {
+        OverlayDb { db, pending }
+    }
+
+    /// Returns all entries with `key_prefix` ordered by key
+    fn overlay_prefix(
+        &self,
+        key_prefix: &[u8],
+    ) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, DatabaseError> {
+        let mut entries = self
+            .db
+            .raw_find_by_prefix(key_prefix)
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+        for (key, items) in self.pending.items_by_prefix(key_prefix) {
+            let mut value = entries.remove(key);
+            for item in items {
+                apply_item(item, key, &mut value)?;
+            }
+            if let Some(value) = value {
+                entries.insert(key.to_vec(), value);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+impl<'a> RawDatabase for OverlayDb<'a> {
+    fn raw_insert_entry(
+        &self,
+        _key: &[u8],
+        _value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        Err(DatabaseError::DbError(Box::new(ReadOnlyOverlay)))
+    }
+
+    fn raw_get_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let mut value = self.db.raw_get_value(key)?;
+        for item in self.pending.items_by_key(key) {
+            apply_item(item, key, &mut value)?;
+        }
+        Ok(value)
+    }
+
+    fn raw_remove_entry(&self, _key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        Err(DatabaseError::DbError(Box::new(ReadOnlyOverlay)))
+    }
+
+    fn raw_find_by_prefix(&self, key_prefix: &[u8]) -> PrefixIter {
+        match self.overlay_prefix(key_prefix) {
+            Ok(entries) => Box::new(entries.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn raw_apply_batch(&self, _batch: DbBatch) -> Result<(), DatabaseError> {
+        Err(DatabaseError::DbError(Box::new(ReadOnlyOverlay)))
+    }
+
+    fn raw_snapshot(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatabaseError> {
+        Ok(self.overlay_prefix(&[])?.into_iter().collect())
+    }
+}
+
+/// Changes `value`, the current value stored under `key`, the same way applying `item` to the
+/// database would
+fn apply_item(
+    item: &BatchItem,
+    key: &[u8],
+    value: &mut Option<Vec<u8>>,
+) -> Result<(), DecodingError> {
+    match item {
+        BatchItem::InsertNewElement(element) | BatchItem::InsertElement(element) => {
+            *value = Some(element.value.to_bytes());
+        }
+        BatchItem::DeleteElement(_) | BatchItem::MaybeDeleteElement(_) => {
+            *value = None;
+        }
+        BatchItem::MaybeUpdate(update) => {
+            if let Some(new_value) = (update.updater)(key, value.as_deref())? {
+                *value = Some(new_value);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::batch::DbBatch;
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::tests::{TestKey, TestVal};
+    use crate::db::{Database, DatabaseKeyPrefixConst, RawDatabase};
+
+    #[test]
+    fn test_read_your_writes() {
+        let mem_db = MemDatabase::new();
+        let db: &dyn RawDatabase = &mem_db;
+        for i in 0..4 {
+            db.insert_entry(&TestKey(i), &TestVal(i)).unwrap();
+        }
+
+        let mut batch = DbBatch::new();
+        let mut tx = batch.transaction();
+        tx.append_insert_new(TestKey(4), TestVal(4));
+        tx.append_delete(TestKey(1));
+        tx.append_maybe_update(TestKey(2), |_, value: Option<TestVal>| {
+            value.map(|TestVal(v)| TestVal(v + 10))
+        });
+        tx.append_insert(TestKey(2), TestVal(20));
+        tx.append_maybe_update(TestKey(2), |_, value: Option<TestVal>| {
+            value.map(|TestVal(v)| TestVal(v + 10))
+        });
+
+        {
+            // Aborted sub-transactions don't show up
+            let mut sub_tx = tx.subtransaction();
+            sub_tx.append_insert_new(TestKey(5), TestVal(5));
+        }
+
+        {
+            let overlay = tx.overlay(db);
+            let overlay: &dyn RawDatabase = &overlay;
+            assert_eq!(overlay.get_value(&TestKey(0)).unwrap(), Some(TestVal(0)));
+            assert_eq!(overlay.get_value::<_, TestVal>(&TestKey(1)).unwrap(), None);
+            assert_eq!(overlay.get_value(&TestKey(2)).unwrap(), Some(TestVal(30)));
+            assert_eq!(overlay.get_value(&TestKey(4)).unwrap(), Some(TestVal(4)));
+            assert_eq!(overlay.get_value::<_, TestVal>(&TestKey(5)).unwrap(), None);
+            assert!(overlay.insert_entry(&TestKey(5), &TestVal(5)).is_err());
+
+            let entries = overlay
+                .raw_find_by_prefix(&[TestKey::DB_PREFIX])
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(entries.len(), 4);
+            assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+
+        // The underlying database is untouched until the batch is applied
+        assert_eq!(db.get_value(&TestKey(2)).unwrap(), Some(TestVal(2)));
+        tx.commit();
+        db.apply_batch(batch).unwrap();
+        assert_eq!(db.get_value(&TestKey(2)).unwrap(), Some(TestVal(30)));
+        assert_eq!(db.get_value::<_, TestVal>(&TestKey(1)).unwrap(), None);
+    }
+}
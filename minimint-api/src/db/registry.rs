@@ -0,0 +1,247 @@
+use super::{DatabaseKey, DatabaseValue, DecodingError};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Decodes a raw entry of a certain table, failing if it isn't a valid key-value pair of it
+pub type DecodeFn = fn(&[u8], &[u8]) -> Result<DecodedEntry, DecodingError>;
+
+/// Human readable representation of a database entry
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedEntry {
+    pub key: serde_json::Value,
+    pub value: serde_json::Value,
+}
+
+/// Description of a key prefix, i.e. a "table", a module uses in the database
+#[derive(Clone, Copy)]
+pub struct DbPrefix {
+    pub prefix: u8,
+    pub name: &'static str,
+    /// Human readable description of the key following the prefix
+    pub key: &'static str,
+    /// Human readable description of the value
+    pub value: &'static str,
+    /// Usually [`decode_entry`] instantiated with the table's key and value types
+    pub decode: DecodeFn,
+}
+
+/// Collection of the key prefixes used by all modules sharing a database. Every prefix may only
+/// be claimed by one module, which is verified by [`PrefixRegistry::check`] on start-up.
+#[derive(Clone, Default)]
+pub struct PrefixRegistry {
+    modules: Vec<(&'static str, &'static [DbPrefix])>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error(
+    "DB prefix {prefix:#04x} is used by {first} of {first_module} and {second} of {second_module}"
+)]
+pub struct PrefixCollision {
+    pub prefix: u8,
+    pub first_module: &'static str,
+    pub first: &'static str,
+    pub second_module: &'static str,
+    pub second: &'static str,
+}
+
+impl PrefixRegistry {
+    pub fn new() -> PrefixRegistry {
+        Default::default()
+    }
+
+    /// Registers the `prefixes` used by `module`
+    pub fn with_module(mut self, module: &'static str, prefixes: &'static [DbPrefix]) -> Self {
+        self.modules.push((module, prefixes));
+        self
+    }
+
+    /// Adds all modules of `other`, e.g. to check that the client's prefixes don't overlap with
+    /// the server's ones
+    pub fn with_registry(mut self, other: PrefixRegistry) -> Self {
+        self.modules.extend(other.modules);
+        self
+    }
+
+    /// Returns an error for the first prefix that is used twice
+    pub fn check(&self) -> Result<(), PrefixCollision> {
+        let mut used = BTreeMap::<u8, (&'static str, &'static str)>::new();
+        for (module, prefix) in self.prefixes() {
+            if let Some((first_module, first)) = used.insert(prefix.prefix, (module, prefix.name)) {
+                return Err(PrefixCollision {
+                    prefix: prefix.prefix,
+                    first_module,
+                    first,
+                    second_module: module,
+                    second: prefix.name,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up the module and table description a prefix belongs to
+    pub fn find(&self, prefix: u8) -> Option<(&'static str, &'static DbPrefix)> {
+        self.prefixes().find(|(_, p)| p.prefix == prefix)
+    }
+
+    /// Renders one markdown table per module listing its prefixes
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::new();
+        for (module, prefixes) in &self.modules {
+            markdown.push_str(&format!("### {}\n\n", module));
+            markdown.push_str("| Name | Prefix | Key | Value |\n");
+            markdown.push_str("|------|--------|-----|-------|\n");
+            for prefix in prefixes.iter() {
+                markdown.push_str(&format!(
+                    "| {} | `{:#04x}` | {} | {} |\n",
+                    prefix.name, prefix.prefix, prefix.key, prefix.value
+                ));
+            }
+            markdown.push('\n');
+        }
+        markdown
+    }
+
+    /// Iterates over all registered prefixes together with the name of the module using them
+    pub fn prefixes(&self) -> impl Iterator<Item = (&'static str, &'static DbPrefix)> + '_ {
+        self.modules
+            .iter()
+            .flat_map(|(module, prefixes)| prefixes.iter().map(move |prefix| (*module, prefix)))
+    }
+}
+
+/// Decodes an entry as key `K` and value `V`. Re-encoding both has to yield the original bytes, so
+/// e.g. trailing garbage that decoding would silently ignore is rejected too.
+pub fn decode_entry<K, V>(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError>
+where
+    K: DatabaseKey + Serialize,
+    V: DatabaseValue + Serialize,
+{
+    decode_entry_with::<K, V, _, _>(key, value, |value| value)
+}
+
+/// Like [`decode_entry`], but renders the value using `render`, e.g. to leave out secrets
+pub fn decode_entry_with<K, V, R, F>(
+    key: &[u8],
+    value: &[u8],
+    render: F,
+) -> Result<DecodedEntry, DecodingError>
+where
+    K: DatabaseKey + Serialize,
+    V: DatabaseValue,
+    R: Serialize,
+    F: FnOnce(V) -> R,
+{
+    try_decode_entry_with::<K, V, _, _>(key, value, |value| Ok(render(value)))
+}
+
+/// Like [`decode_entry_with`], but rendering the value may fail, e.g. if it contains an invalid key
+pub fn try_decode_entry_with<K, V, R, F>(
+    key: &[u8],
+    value: &[u8],
+    render: F,
+) -> Result<DecodedEntry, DecodingError>
+where
+    K: DatabaseKey + Serialize,
+    V: DatabaseValue,
+    R: Serialize,
+    F: FnOnce(V) -> Result<R, DecodingError>,
+{
+    let decoded_key = K::from_bytes(key)?;
+    let decoded_value = V::from_bytes(value)?;
+    if decoded_key.to_bytes() != key || decoded_value.to_bytes() != value {
+        return Err(DecodingError::NonCanonical);
+    }
+
+    Ok(DecodedEntry {
+        key: serde_json::to_value(&decoded_key).map_err(DecodingError::other)?,
+        value: serde_json::to_value(&render(decoded_value)?).map_err(DecodingError::other)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_entry, DbPrefix, DecodedEntry, PrefixCollision, PrefixRegistry};
+    use crate::db::tests::{TestKey, TestVal};
+    use crate::db::{DatabaseKeyPrefix, DecodingError, SerializableDatabaseValue};
+    use serde_json::json;
+
+    const FOO: &[DbPrefix] = &[
+        DbPrefix {
+            prefix: 0x10,
+            name: "Foo",
+            key: "id",
+            value: "foo",
+            decode: decode_entry::<TestKey, TestVal>,
+        },
+        DbPrefix {
+            prefix: 0x11,
+            name: "Bar",
+            key: "id",
+            value: "bar",
+            decode: decode_entry::<TestKey, TestVal>,
+        },
+    ];
+
+    const BAZ: &[DbPrefix] = &[DbPrefix {
+        prefix: 0x11,
+        name: "Baz",
+        key: "none",
+        value: "baz",
+        decode: decode_entry::<TestKey, TestVal>,
+    }];
+
+    #[test]
+    fn test_collision() {
+        assert_eq!(
+            PrefixRegistry::new().with_module("foo", FOO).check(),
+            Ok(())
+        );
+
+        let registry = PrefixRegistry::new()
+            .with_module("foo", FOO)
+            .with_registry(PrefixRegistry::new().with_module("baz", BAZ));
+        assert_eq!(
+            registry.check(),
+            Err(PrefixCollision {
+                prefix: 0x11,
+                first_module: "foo",
+                first: "Bar",
+                second_module: "baz",
+                second: "Baz",
+            })
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            PrefixRegistry::new().with_module("Foo", FOO).to_markdown(),
+            "### Foo\n\n\
+             | Name | Prefix | Key | Value |\n\
+             |------|--------|-----|-------|\n\
+             | Foo | `0x10` | id | foo |\n\
+             | Bar | `0x11` | id | bar |\n\n"
+        );
+    }
+
+    #[test]
+    fn test_decode_entry() {
+        let key = DatabaseKeyPrefix::to_bytes(&TestKey(42));
+        let mut value = TestVal(1337).to_bytes();
+        assert_eq!(
+            decode_entry::<TestKey, TestVal>(&key, &value).unwrap(),
+            DecodedEntry {
+                key: json!(42),
+                value: json!(1337),
+            }
+        );
+
+        value.push(0);
+        assert!(matches!(
+            decode_entry::<TestKey, TestVal>(&key, &value),
+            Err(DecodingError::NonCanonical)
+        ));
+    }
+}
@@ -2,16 +2,37 @@ use super::batch::{BatchItem, DbBatch};
 use super::{DatabaseError, DecodingError, RawDatabase};
 use crate::db::PrefixIter;
 use sled::transaction::TransactionError;
+use std::sync::RwLock;
 use tracing::{error, trace};
 
+/// [`RawDatabase`] implementation backed by a sled tree
+#[derive(Debug)]
+pub struct SledDb {
+    tree: sled::Tree,
+    /// Sled iterators aren't isolated from concurrent writes, so writes hold this lock shared and
+    /// snapshots exclusively
+    snapshot_lock: RwLock<()>,
+}
+
+impl SledDb {
+    pub fn new(tree: sled::Tree) -> SledDb {
+        SledDb {
+            tree,
+            snapshot_lock: RwLock::new(()),
+        }
+    }
+}
+
 // TODO: maybe make the concrete impl its own crate
-impl RawDatabase for sled::Tree {
+impl RawDatabase for SledDb {
     fn raw_insert_entry(
         &self,
         key: &[u8],
         value: Vec<u8>,
     ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let _write = self.snapshot_lock.read().unwrap();
         Ok(self
+            .tree
             .insert(key, value)
             .map_err(|e| DatabaseError::DbError(Box::new(e)))?
             .map(|bytes| bytes.to_vec()))
@@ -19,20 +40,23 @@ impl RawDatabase for sled::Tree {
 
     fn raw_get_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
         Ok(self
+            .tree
             .get(key)
             .map_err(|e| DatabaseError::DbError(Box::new(e)))?
             .map(|bytes| bytes.to_vec()))
     }
 
     fn raw_remove_entry(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let _write = self.snapshot_lock.read().unwrap();
         Ok(self
+            .tree
             .remove(key)
             .map_err(|e| DatabaseError::DbError(Box::new(e)))?
             .map(|bytes| bytes.to_vec()))
     }
 
     fn raw_find_by_prefix(&self, key_prefix: &[u8]) -> PrefixIter {
-        Box::new(self.scan_prefix(key_prefix).map(|res| {
+        Box::new(self.tree.scan_prefix(key_prefix).map(|res| {
             res.map(|(key_bytes, value_bytes)| (key_bytes.to_vec(), value_bytes.to_vec()))
                 .map_err(|e| DatabaseError::DbError(Box::new(e)))
         }))
@@ -41,48 +65,63 @@ impl RawDatabase for sled::Tree {
     fn raw_apply_batch(&self, batch: DbBatch) -> Result<(), DatabaseError> {
         let batch: Vec<_> = batch.into();
 
-        self.transaction(|t| {
-            for change in batch.iter() {
-                match change {
-                    BatchItem::InsertNewElement(element) => {
-                        if t.insert(element.key.to_bytes(), element.value.to_bytes())?
-                            .is_some()
-                        {
-                            error!("Database replaced element! This should not happen!");
-                            trace!("Problematic key: {:?}", element.key);
+        let _write = self.snapshot_lock.read().unwrap();
+        self.tree
+            .transaction(|t| {
+                for change in batch.iter() {
+                    match change {
+                        BatchItem::InsertNewElement(element) => {
+                            if t.insert(element.key.to_bytes(), element.value.to_bytes())?
+                                .is_some()
+                            {
+                                error!("Database replaced element! This should not happen!");
+                                trace!("Problematic key: {:?}", element.key);
+                            }
                         }
-                    }
-                    BatchItem::InsertElement(element) => {
-                        t.insert(element.key.to_bytes(), element.value.to_bytes())?;
-                    }
-                    BatchItem::DeleteElement(key) => {
-                        if t.remove(key.to_bytes())?.is_none() {
-                            error!("Database deleted absent element! This should not happen!");
-                            trace!("Problematic key: {:?}", key);
+                        BatchItem::InsertElement(element) => {
+                            t.insert(element.key.to_bytes(), element.value.to_bytes())?;
                         }
-                    }
-                    BatchItem::MaybeDeleteElement(key) => {
-                        t.remove(key.to_bytes())?;
-                    }
-                    BatchItem::MaybeUpdate(update) => {
-                        let key = update.key.to_bytes();
-                        let maybe_val = self.get(&key)?;
-                        let maybe_val_ref = maybe_val.as_ref().map(|v| v.as_ref());
-                        let maybe_new_val = (update.updater)(&key, maybe_val_ref)?;
+                        BatchItem::DeleteElement(key) => {
+                            if t.remove(key.to_bytes())?.is_none() {
+                                error!("Database deleted absent element! This should not happen!");
+                                trace!("Problematic key: {:?}", key);
+                            }
+                        }
+                        BatchItem::MaybeDeleteElement(key) => {
+                            t.remove(key.to_bytes())?;
+                        }
+                        BatchItem::MaybeUpdate(update) => {
+                            let key = update.key.to_bytes();
+                            let maybe_val = self.tree.get(&key)?;
+                            let maybe_val_ref = maybe_val.as_ref().map(|v| v.as_ref());
+                            let maybe_new_val = (update.updater)(&key, maybe_val_ref)?;
 
-                        if let Some(new_val) = maybe_new_val {
-                            self.insert(&key, new_val)?;
+                            if let Some(new_val) = maybe_new_val {
+                                self.tree.insert(&key, new_val)?;
+                            }
                         }
                     }
                 }
-            }
 
-            Ok(())
-        })
-        .map_err(|e: TransactionError<DecodingError>| match e {
-            TransactionError::Abort(e) => DatabaseError::DecodingError(e),
-            TransactionError::Storage(e) => DatabaseError::DbError(Box::new(e)),
-        })
+                Ok(())
+            })
+            .map_err(|e: TransactionError<DecodingError>| match e {
+                TransactionError::Abort(e) => DatabaseError::DecodingError(e),
+                TransactionError::Storage(e) => DatabaseError::DbError(Box::new(e)),
+            })
+    }
+
+    /// Blocks writes through this handle while iterating, so the snapshot is consistent as long as
+    /// all writers share it. Sled locks its directory against other processes.
+    fn raw_snapshot(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatabaseError> {
+        let _snapshot = self.snapshot_lock.write().unwrap();
+        self.tree
+            .iter()
+            .map(|res| {
+                res.map(|(key_bytes, value_bytes)| (key_bytes.to_vec(), value_bytes.to_vec()))
+                    .map_err(|e| DatabaseError::DbError(Box::new(e)))
+            })
+            .collect()
     }
 }
 
@@ -100,12 +139,13 @@ impl From<sled::Error> for DatabaseError {
 
 #[cfg(test)]
 mod tests {
+    use super::SledDb;
     use std::sync::Arc;
 
     #[test]
     fn test_basic_rw() {
         let path = tempdir::TempDir::new("fcb-sled-test").unwrap();
         let db = sled::open(path).unwrap();
-        crate::db::tests::test_db_impl(Arc::new(db.open_tree("default").unwrap()));
+        crate::db::tests::test_db_impl(Arc::new(SledDb::new(db.open_tree("default").unwrap())));
     }
 }
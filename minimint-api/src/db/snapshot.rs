@@ -0,0 +1,335 @@
+use super::batch::{BatchItem, DbBatch, Element};
+use super::registry::PrefixRegistry;
+use super::{
+    DatabaseError, DatabaseKeyPrefix, DecodingError, RawDatabase, SerializableDatabaseValue,
+};
+use crate::encoding::{Decodable, DecodeError, Encodable};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use std::io::{Read, Write};
+use thiserror::Error;
+
+/// Magic bytes every snapshot starts with
+const SNAPSHOT_MAGIC: [u8; 4] = *b"mmdb";
+
+/// Version of the snapshot encoding, independent of the schema versions of the modules' entries
+const SNAPSHOT_FORMAT_VERSION: u16 = 0;
+
+/// Consistent copy of all entries of a database, e.g. for backups
+///
+/// A snapshot is encoded as the magic bytes `mmdb`, the format version (`u16`), the number of
+/// entries (`u64`) followed by that many key and value byte vectors ordered by key. The SHA256
+/// hash of everything before it concludes the encoding, so corrupted backups are detected when
+/// decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbSnapshot {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Entry {key} has a prefix unknown to all modules")]
+    UnknownPrefix { key: String },
+    #[error("Entry {key} of {module}/{table} is invalid: {error}")]
+    InvalidEntry {
+        key: String,
+        module: &'static str,
+        table: &'static str,
+        error: DecodingError,
+    },
+    #[error("Snapshots can only be restored into an empty database")]
+    DatabaseNotEmpty,
+    #[error("Database error: {0}")]
+    DatabaseError(DatabaseError),
+}
+
+impl DbSnapshot {
+    /// Takes a snapshot of `db`. It may only be written to concurrently if its
+    /// [`RawDatabase::raw_snapshot`] is isolated from writes, like RocksDB's, otherwise the caller
+    /// needs exclusive access.
+    pub fn take(db: &dyn RawDatabase) -> Result<DbSnapshot, DatabaseError> {
+        let mut entries = db.raw_snapshot()?;
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(DbSnapshot { entries })
+    }
+
+    pub fn entries(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.entries
+    }
+
+    /// Checks that every entry belongs to a table of `registry` and decodes as its key and value
+    /// types
+    pub fn validate(&self, registry: &PrefixRegistry) -> Result<(), SnapshotError> {
+        for (key, value) in &self.entries {
+            let (module, table) = key
+                .first()
+                .and_then(|prefix| registry.find(*prefix))
+                .ok_or_else(|| SnapshotError::UnknownPrefix {
+                    key: hex::encode(key),
+                })?;
+
+            (table.decode)(key, value).map_err(|error| SnapshotError::InvalidEntry {
+                key: hex::encode(key),
+                module,
+                table: table.name,
+                error,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Validates the snapshot and writes all its entries to `db` in one batch. To not mix up
+    /// state of different points in time `db` has to be empty.
+    pub fn restore(
+        &self,
+        db: &dyn RawDatabase,
+        registry: &PrefixRegistry,
+    ) -> Result<(), SnapshotError> {
+        self.validate(registry)?;
+
+        if db.raw_find_by_prefix(&[]).next().is_some() {
+            return Err(SnapshotError::DatabaseNotEmpty);
+        }
+
+        let mut batch = DbBatch::new();
+        batch.reserve(self.entries.len());
+        let mut tx = batch.transaction();
+        tx.append_from_iter(self.entries.iter().cloned().map(|(key, value)| {
+            BatchItem::InsertNewElement(Element::new(RawKey(key), RawValue(value)))
+        }));
+        tx.commit();
+
+        db.raw_apply_batch(batch)?;
+        Ok(())
+    }
+}
+
+impl Encodable for DbSnapshot {
+    fn consensus_encode<W: Write>(&self, writer: W) -> Result<usize, std::io::Error> {
+        let mut writer = HashWriter::new(writer);
+
+        let mut len = 0;
+        len += SNAPSHOT_MAGIC.consensus_encode(&mut writer)?;
+        len += SNAPSHOT_FORMAT_VERSION.consensus_encode(&mut writer)?;
+        len += (self.entries.len() as u64).consensus_encode(&mut writer)?;
+        for (key, value) in &self.entries {
+            len += key.consensus_encode(&mut writer)?;
+            len += value.consensus_encode(&mut writer)?;
+        }
+
+        let (mut writer, checksum) = writer.finalize();
+        len += checksum.consensus_encode(&mut writer)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for DbSnapshot {
+    fn consensus_decode<D: Read>(d: D) -> Result<Self, DecodeError> {
+        let mut reader = HashReader::new(d);
+
+        if <[u8; 4]>::consensus_decode(&mut reader)? != SNAPSHOT_MAGIC {
+            return Err(DecodeError::from_str("Not a database snapshot"));
+        }
+        if u16::consensus_decode(&mut reader)? != SNAPSHOT_FORMAT_VERSION {
+            return Err(DecodeError::from_str("Unsupported snapshot format version"));
+        }
+
+        let len = u64::consensus_decode(&mut reader)?;
+        let mut entries = Vec::<(Vec<u8>, Vec<u8>)>::new();
+        for _ in 0..len {
+            let key = Vec::<u8>::consensus_decode(&mut reader)?;
+            let value = Vec::<u8>::consensus_decode(&mut reader)?;
+
+            // Strictly ascending keys also rule out duplicates
+            if matches!(entries.last(), Some((last_key, _)) if *last_key >= key) {
+                return Err(DecodeError::from_str("Snapshot entries aren't ordered"));
+            }
+            entries.push((key, value));
+        }
+
+        let (mut reader, checksum) = reader.finalize();
+        if sha256::Hash::consensus_decode(&mut reader)? != checksum {
+            return Err(DecodeError::from_str("Snapshot checksum mismatch"));
+        }
+
+        Ok(DbSnapshot { entries })
+    }
+}
+
+impl From<DatabaseError> for SnapshotError {
+    fn from(e: DatabaseError) -> Self {
+        SnapshotError::DatabaseError(e)
+    }
+}
+
+/// Key that is already encoded, only used for restoring validated entries
+#[derive(Debug)]
+struct RawKey(Vec<u8>);
+
+impl DatabaseKeyPrefix for RawKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+/// Value that is already encoded, only used for restoring validated entries
+#[derive(Debug)]
+struct RawValue(Vec<u8>);
+
+impl SerializableDatabaseValue for RawValue {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+/// Hashes everything written through it
+struct HashWriter<W> {
+    inner: W,
+    engine: sha256::HashEngine,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        HashWriter {
+            inner,
+            engine: sha256::Hash::engine(),
+        }
+    }
+
+    fn finalize(self) -> (W, sha256::Hash) {
+        (self.inner, sha256::Hash::from_engine(self.engine))
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.engine.input(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hashes everything read through it
+struct HashReader<R> {
+    inner: R,
+    engine: sha256::HashEngine,
+}
+
+impl<R: Read> HashReader<R> {
+    fn new(inner: R) -> Self {
+        HashReader {
+            inner,
+            engine: sha256::Hash::engine(),
+        }
+    }
+
+    fn finalize(self) -> (R, sha256::Hash) {
+        (self.inner, sha256::Hash::from_engine(self.engine))
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.engine.input(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DbSnapshot, SnapshotError};
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::registry::{decode_entry, DbPrefix, PrefixRegistry};
+    use crate::db::tests::{TestKey, TestVal};
+    use crate::db::{Database, DatabaseKeyPrefix, RawDatabase};
+    use crate::encoding::{Decodable, Encodable};
+
+    const TEST_PREFIXES: &[DbPrefix] = &[DbPrefix {
+        prefix: 0x42,
+        name: "Test",
+        key: "number",
+        value: "number",
+        decode: decode_entry::<TestKey, TestVal>,
+    }];
+
+    fn test_db() -> MemDatabase {
+        let db = MemDatabase::new();
+        let db_ref: &dyn RawDatabase = &db;
+        for i in 0..10 {
+            db_ref.insert_entry(&TestKey(i), &TestVal(i + 1)).unwrap();
+        }
+        db
+    }
+
+    fn encode(snapshot: &DbSnapshot) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot.consensus_encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let db = test_db();
+        let snapshot = DbSnapshot::take(&db).unwrap();
+        assert_eq!(snapshot.entries().len(), 10);
+
+        let bytes = encode(&snapshot);
+        let decoded = DbSnapshot::consensus_decode(std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let registry = PrefixRegistry::new().with_module("test", TEST_PREFIXES);
+        let restored = MemDatabase::new();
+        decoded.restore(&restored, &registry).unwrap();
+        let restored: &dyn RawDatabase = &restored;
+        assert_eq!(restored.get_value(&TestKey(3)).unwrap(), Some(TestVal(4)));
+        assert_eq!(DbSnapshot::take(restored).unwrap(), snapshot);
+
+        // Restoring on top of existing data is refused
+        assert!(matches!(
+            decoded.restore(restored, &registry),
+            Err(SnapshotError::DatabaseNotEmpty)
+        ));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut bytes = encode(&DbSnapshot::take(&test_db()).unwrap());
+        let last_value_byte = bytes.len() - 33;
+        bytes[last_value_byte] ^= 1;
+        assert!(DbSnapshot::consensus_decode(std::io::Cursor::new(&bytes)).is_err());
+
+        bytes.truncate(bytes.len() - 1);
+        assert!(DbSnapshot::consensus_decode(std::io::Cursor::new(&bytes)).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let db = test_db();
+        let registry = PrefixRegistry::new().with_module("test", TEST_PREFIXES);
+        DbSnapshot::take(&db).unwrap().validate(&registry).unwrap();
+
+        // Trailing bytes after the encoded value
+        let key = DatabaseKeyPrefix::to_bytes(&TestKey(3));
+        let value = db.raw_insert_entry(&key, vec![0; 9]).unwrap().unwrap();
+        assert!(matches!(
+            DbSnapshot::take(&db).unwrap().validate(&registry),
+            Err(SnapshotError::InvalidEntry { .. })
+        ));
+        db.raw_insert_entry(&key, value).unwrap();
+
+        db.raw_insert_entry(&[0x43, 0x00], vec![]).unwrap();
+        let snapshot = DbSnapshot::take(&db).unwrap();
+        assert!(matches!(
+            snapshot.validate(&registry),
+            Err(SnapshotError::UnknownPrefix { .. })
+        ));
+        assert!(matches!(
+            snapshot.restore(&MemDatabase::new(), &registry),
+            Err(SnapshotError::UnknownPrefix { .. })
+        ));
+    }
+}
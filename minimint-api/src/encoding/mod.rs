@@ -7,6 +7,7 @@ mod secp256k1;
 mod tbs;
 
 pub use minimint_derive::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Formatter};
 use std::io::Error;
 use thiserror::Error;
@@ -24,11 +25,110 @@ pub trait Encodable {
 pub trait Decodable: Sized {
     /// Decode an object with a well-defined format
     fn consensus_decode<D: std::io::Read>(d: D) -> Result<Self, DecodeError>;
+
+    /// Decode an object from untrusted input, reading at most `max_bytes` bytes from `d`. Since
+    /// all nested decoders read from the same [`LimitedReader`] the budget applies to the object
+    /// as a whole.
+    fn consensus_decode_limited<D: std::io::Read>(
+        d: D,
+        max_bytes: u64,
+    ) -> Result<Self, DecodeError> {
+        Self::consensus_decode(LimitedReader::new(d, max_bytes))
+    }
+}
+
+/// Maximum number of items of a collection (e.g. a `Vec`) that will be decoded. Decoders of
+/// length-prefixed collections have to check this limit (see [`decode_collection_len`]) since
+/// otherwise a forged length could make them loop almost forever, even without any more input
+/// for items that take up no bytes.
+pub const MAX_COLLECTION_LEN: u64 = 4_000_000;
+
+/// Reads the `u64` length prefix of a collection, failing if it exceeds [`MAX_COLLECTION_LEN`]
+pub fn decode_collection_len<D: std::io::Read>(d: D) -> Result<u64, DecodeError> {
+    let len = u64::consensus_decode(d)?;
+    if len > MAX_COLLECTION_LEN {
+        return Err(DecodeError::from_str("Collection length exceeds limit"));
+    }
+    Ok(len)
+}
+
+/// Reader that fails once more than a certain number of bytes were read through it, which bounds
+/// the resources spent on decoding untrusted input from streams
+pub struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+#[derive(Debug, Error)]
+#[error("Decoding exceeded the byte budget")]
+pub struct ByteBudgetExceeded;
+
+impl<R: std::io::Read> LimitedReader<R> {
+    pub fn new(inner: R, max_bytes: u64) -> LimitedReader<R> {
+        LimitedReader {
+            inner,
+            remaining: max_bytes,
+        }
+    }
+
+    /// Number of bytes that may still be read
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                ByteBudgetExceeded,
+            ));
+        }
+
+        let max_len = std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        let read = self.inner.read(&mut buf[..max_len])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
 }
 
 #[derive(Debug, Error)]
 pub struct DecodeError(pub(crate) Box<dyn std::error::Error + Send>);
 
+/// Module specific item (e.g. a transaction input) tagged with the id of the module it belongs to
+/// and encoded as a length-prefixed byte string. This way decoders can skip over or keep around
+/// items of modules they don't know, so adding modules doesn't change the encoding of existing
+/// items.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct ModulePayload {
+    pub module: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl ModulePayload {
+    /// Encodes `item` as payload of `module`
+    pub fn new<T: Encodable>(module: u16, item: &T) -> ModulePayload {
+        let mut bytes = Vec::new();
+        item.consensus_encode(&mut bytes)
+            .expect("writing to vec can't fail");
+        ModulePayload { module, bytes }
+    }
+
+    /// Decodes the payload, failing if it isn't consumed entirely
+    pub fn decode<T: Decodable>(&self) -> Result<T, DecodeError> {
+        let mut cursor = std::io::Cursor::new(&self.bytes);
+        let item = T::consensus_decode(&mut cursor)?;
+        if cursor.position() != self.bytes.len() as u64 {
+            return Err(DecodeError::from_str("Module payload has trailing bytes"));
+        }
+        Ok(item)
+    }
+}
+
 macro_rules! impl_encode_decode_num {
     ($num_type:ty) => {
         impl Encodable for $num_type {
@@ -109,7 +209,8 @@ where
     T: Decodable,
 {
     fn consensus_decode<D: std::io::Read>(mut d: D) -> Result<Self, DecodeError> {
-        let len = u64::consensus_decode(&mut d)?;
+        let len = decode_collection_len(&mut d)?;
+        // Items are pushed one by one, so the length prefix alone can't cause a large allocation
         (0..len).map(|_| T::consensus_decode(&mut d)).collect()
     }
 }
@@ -173,6 +274,22 @@ where
     }
 }
 
+impl Encodable for bool {
+    fn consensus_encode<W: std::io::Write>(&self, writer: W) -> Result<usize, Error> {
+        (*self as u8).consensus_encode(writer)
+    }
+}
+
+impl Decodable for bool {
+    fn consensus_decode<D: std::io::Read>(d: D) -> Result<Self, DecodeError> {
+        match u8::consensus_decode(d)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::from_str("Invalid bool, expected 0 or 1")),
+        }
+    }
+}
+
 impl<T> Encodable for Box<T>
 where
     T: Encodable,
@@ -246,7 +363,7 @@ impl std::fmt::Display for DecodeError {
 
 #[cfg(test)]
 mod tests {
-    use crate::encoding::{Decodable, Encodable};
+    use crate::encoding::{Decodable, Encodable, ModulePayload, MAX_COLLECTION_LEN};
     use std::fmt::Debug;
     use std::io::Cursor;
 
@@ -333,4 +450,155 @@ mod tests {
             test_roundtrip_expected(reference, &bytes);
         }
     }
+
+    #[test]
+    fn test_derive_enum_tags() {
+        #[derive(Debug, Encodable, Decodable, Eq, PartialEq)]
+        enum TestEnum {
+            #[encoding(tag = 2)]
+            Foo(u8),
+            Bar,
+            #[encoding(tag = 0)]
+            Baz {
+                num: u8,
+            },
+        }
+
+        test_roundtrip_expected(TestEnum::Foo(42), &[2, 0, 0, 0, 0, 0, 0, 0, 42]);
+        test_roundtrip_expected(TestEnum::Bar, &[1, 0, 0, 0, 0, 0, 0, 0]);
+        test_roundtrip_expected(TestEnum::Baz { num: 42 }, &[0, 0, 0, 0, 0, 0, 0, 0, 42]);
+        assert!(TestEnum::consensus_decode(Cursor::new(&[3, 0, 0, 0, 0, 0, 0, 0])).is_err());
+    }
+
+    mod as_u8 {
+        use crate::encoding::{Decodable, DecodeError, Encodable};
+
+        pub fn consensus_encode<W: std::io::Write>(
+            value: &u32,
+            writer: W,
+        ) -> Result<usize, std::io::Error> {
+            (*value as u8).consensus_encode(writer)
+        }
+
+        pub fn consensus_decode<D: std::io::Read>(d: D) -> Result<u32, DecodeError> {
+            Ok(u8::consensus_decode(d)? as u32)
+        }
+    }
+
+    #[test]
+    fn test_derive_field_attributes() {
+        #[derive(Debug, Encodable, Decodable, Eq, PartialEq)]
+        struct TestStruct {
+            #[encoding(with = "as_u8")]
+            small: u32,
+            #[encoding(skip)]
+            cache: Option<u64>,
+            num: u16,
+        }
+
+        test_roundtrip_expected(
+            TestStruct {
+                small: 42,
+                cache: None,
+                num: 1,
+            },
+            &[42, 1, 0],
+        );
+
+        let mut bytes = Vec::new();
+        TestStruct {
+            small: 42,
+            cache: Some(3),
+            num: 1,
+        }
+        .consensus_encode(&mut bytes)
+        .unwrap();
+        assert_eq!(
+            TestStruct::consensus_decode(Cursor::new(&bytes)).unwrap(),
+            TestStruct {
+                small: 42,
+                cache: None,
+                num: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_derive_generics() {
+        #[derive(Debug, Encodable, Decodable, Eq, PartialEq)]
+        struct Wrapper<T, M> {
+            items: Vec<T>,
+            #[encoding(skip)]
+            _marker: std::marker::PhantomData<M>,
+        }
+
+        #[derive(Debug, Encodable, Decodable, Eq, PartialEq)]
+        enum Either<L, R> {
+            Left(L),
+            Right(R),
+        }
+
+        // `M` needs neither `Encodable` nor `Decodable` since it's only used by a skipped field
+        #[derive(Debug, Default, Eq, PartialEq)]
+        struct NotEncodable;
+
+        test_roundtrip_expected(
+            Wrapper::<u8, NotEncodable> {
+                items: vec![1, 2],
+                _marker: Default::default(),
+            },
+            &[2, 0, 0, 0, 0, 0, 0, 0, 1, 2],
+        );
+        test_roundtrip_expected(Either::<u8, u16>::Right(3), &[1, 0, 0, 0, 0, 0, 0, 0, 3, 0]);
+    }
+
+    #[test]
+    fn test_bool() {
+        test_roundtrip_expected(false, &[0]);
+        test_roundtrip_expected(true, &[1]);
+        assert!(bool::consensus_decode(Cursor::new(&[2])).is_err());
+    }
+
+    #[test]
+    fn test_module_payload() {
+        let payload = ModulePayload::new(1, &42u32);
+        test_roundtrip_expected(
+            payload.clone(),
+            &[1, 0, 4, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0],
+        );
+
+        assert_eq!(payload.decode::<u32>().unwrap(), 42);
+        assert!(payload.decode::<u16>().is_err());
+        assert!(payload.decode::<u64>().is_err());
+    }
+
+    #[test]
+    fn test_collection_len_limit() {
+        let mut bytes = Vec::new();
+        MAX_COLLECTION_LEN.consensus_encode(&mut bytes).unwrap();
+        assert_eq!(
+            Vec::<()>::consensus_decode(Cursor::new(&bytes))
+                .unwrap()
+                .len() as u64,
+            MAX_COLLECTION_LEN
+        );
+
+        bytes.clear();
+        u64::MAX.consensus_encode(&mut bytes).unwrap();
+        assert!(Vec::<()>::consensus_decode(Cursor::new(&bytes)).is_err());
+        assert!(String::consensus_decode(Cursor::new(&bytes)).is_err());
+    }
+
+    #[test]
+    fn test_byte_budget() {
+        let mut bytes = Vec::new();
+        vec![1u32, 2, 3].consensus_encode(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 20);
+
+        assert_eq!(
+            Vec::<u32>::consensus_decode_limited(Cursor::new(&bytes), 20).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(Vec::<u32>::consensus_decode_limited(Cursor::new(&bytes), 19).is_err());
+    }
 }
@@ -33,7 +33,7 @@ impl_external_encode_bls!(tbs::Signature, tbs::MessagePoint, 48);
 
 impl Encodable for tbs::BlindingKey {
     fn consensus_encode<W: std::io::Write>(&self, mut writer: W) -> Result<usize, std::io::Error> {
-        let bytes = self.0.to_bytes();
+        let bytes = self.to_secret_bytes();
         writer.write_all(&bytes)?;
         Ok(bytes.len())
     }
@@ -43,15 +43,8 @@ impl Decodable for tbs::BlindingKey {
     fn consensus_decode<D: std::io::Read>(mut d: D) -> Result<Self, DecodeError> {
         let mut bytes = [0u8; 32];
         d.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
-        let key = tbs::Scalar::from_bytes(&bytes);
-
-        if key.is_some().unwrap_u8() == 1 {
-            Ok(tbs::BlindingKey(key.unwrap()))
-        } else {
-            Err(crate::encoding::DecodeError::from_str(
-                "Error decoding blinding key",
-            ))
-        }
+        tbs::BlindingKey::from_secret_bytes(&bytes)
+            .ok_or_else(|| crate::encoding::DecodeError::from_str("Error decoding blinding key"))
     }
 }
 
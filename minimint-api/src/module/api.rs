@@ -0,0 +1,70 @@
+use crate::FederationModule;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Handler of a module's API endpoint, takes the JSON encoded request and returns the JSON
+/// encoded response
+pub type ApiHandler<M> = fn(&M, serde_json::Value) -> Result<serde_json::Value, ApiError>;
+
+/// API endpoint of a module as mounted by the server, see [`FederationModule::api_endpoints`]
+pub struct ApiEndpoint<M> {
+    /// Path relative to the module's base path, starting with `/`
+    pub path: &'static str,
+    pub handler: ApiHandler<M>,
+}
+
+/// Definition of a module API endpoint that is shared by server and client, so both agree on the
+/// path as well as the request and response types
+pub trait TypedApiEndpoint {
+    type State: FederationModule;
+    type Request: Serialize + DeserializeOwned;
+    type Response: Serialize + DeserializeOwned;
+
+    /// Path relative to the module's base path, starting with `/`
+    const PATH: &'static str;
+
+    /// Answers `request` using the module's state, only called by the server
+    fn handle(state: &Self::State, request: Self::Request) -> Result<Self::Response, ApiError>;
+}
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+    #[error("Not found")]
+    NotFound,
+}
+
+impl ApiError {
+    /// HTTP status code the error is reported as
+    pub fn code(&self) -> u16 {
+        match self {
+            ApiError::BadRequest(_) => 400,
+            ApiError::NotFound => 404,
+        }
+    }
+}
+
+impl<M> ApiEndpoint<M> {
+    /// Type-erases the typed endpoint `E`
+    pub fn from_typed<E>() -> ApiEndpoint<M>
+    where
+        E: TypedApiEndpoint<State = M>,
+    {
+        ApiEndpoint {
+            path: E::PATH,
+            handler: |state, request| {
+                let request = serde_json::from_value(request)
+                    .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+                let response = E::handle(state, request)?;
+                Ok(serde_json::to_value(&response).expect("encoding error"))
+            },
+        }
+    }
+}
+
+/// Path under which the endpoint at `path` of module `M` is served
+pub fn module_api_path<M: FederationModule>(path: &str) -> String {
+    format!("/{}{}", M::API_BASE_NAME, path)
+}
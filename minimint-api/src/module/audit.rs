@@ -0,0 +1,105 @@
+use crate::{Amount, FederationModule};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Funds held by a module and funds it owes to users, see [`FederationModule::audit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ModuleAudit {
+    pub assets: Amount,
+    pub liabilities: Amount,
+    /// Funds the module paid out although its own records say they don't exist, e.g. more e-cash
+    /// redeemed than issued. Any shortfall means there is a bug and makes the federation insolvent.
+    pub shortfall: Amount,
+}
+
+/// Assets and liabilities of all modules of the federation, indexed by their API base name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Audit {
+    pub modules: BTreeMap<String, ModuleAudit>,
+}
+
+impl Audit {
+    /// Adds the audit of `module`
+    pub fn add_module<M: FederationModule>(&mut self, module: &M) {
+        self.modules
+            .insert(M::API_BASE_NAME.to_owned(), module.audit());
+    }
+
+    pub fn assets(&self) -> Amount {
+        self.modules.values().map(|audit| audit.assets).sum()
+    }
+
+    pub fn liabilities(&self) -> Amount {
+        self.modules.values().map(|audit| audit.liabilities).sum()
+    }
+
+    pub fn shortfall(&self) -> Amount {
+        self.modules.values().map(|audit| audit.shortfall).sum()
+    }
+
+    /// The federation is solvent if its assets cover all its liabilities and no module has a
+    /// shortfall. Since assets can only be moved between modules by balanced transactions, being
+    /// insolvent means there is a bug.
+    pub fn is_solvent(&self) -> bool {
+        self.shortfall() == Amount::ZERO && self.assets() >= self.liabilities()
+    }
+}
+
+impl std::fmt::Display for Audit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "assets {}, liabilities {}",
+            self.assets(),
+            self.liabilities()
+        )?;
+        for (module, audit) in &self.modules {
+            write!(
+                f,
+                "; {}: assets {}, liabilities {}",
+                module, audit.assets, audit.liabilities
+            )?;
+            if audit.shortfall != Amount::ZERO {
+                write!(f, ", shortfall {}", audit.shortfall)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Audit, ModuleAudit};
+    use crate::Amount;
+
+    #[test]
+    fn test_solvency() {
+        let mut audit = Audit::default();
+        audit.modules.insert(
+            "wallet".to_owned(),
+            ModuleAudit {
+                assets: Amount::from_sat(100),
+                liabilities: Amount::from_sat(10),
+                shortfall: Amount::ZERO,
+            },
+        );
+        audit.modules.insert(
+            "mint".to_owned(),
+            ModuleAudit {
+                assets: Amount::ZERO,
+                liabilities: Amount::from_sat(89),
+                shortfall: Amount::ZERO,
+            },
+        );
+        assert!(audit.is_solvent());
+
+        audit.modules.get_mut("mint").unwrap().liabilities = Amount::from_sat(91);
+        assert!(!audit.is_solvent());
+
+        // A shortfall can't be made up for by other modules
+        let mint = audit.modules.get_mut("mint").unwrap();
+        mint.liabilities = Amount::ZERO;
+        mint.shortfall = Amount::from_sat(1);
+        assert!(!audit.is_solvent());
+    }
+}
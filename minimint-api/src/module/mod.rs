@@ -1,6 +1,10 @@
+pub mod api;
+pub mod audit;
 pub mod testing;
 
 use crate::db::batch::BatchTx;
+use crate::module::api::ApiEndpoint;
+use crate::module::audit::ModuleAudit;
 use crate::{Amount, PeerId};
 use async_trait::async_trait;
 use rand::CryptoRng;
@@ -12,6 +16,18 @@ pub struct InputMeta<'a> {
     pub puk_keys: Box<dyn Iterator<Item = schnorrsig::PublicKey> + 'a>,
 }
 
+/// Facts the federation agreed on that are shared by all modules, so they don't have to read each
+/// other's state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConsensusContext {
+    /// Consensus epoch being processed, when validating outside of consensus the next epoch
+    pub epoch: u64,
+    /// Bitcoin block height agreed on by the federation, `None` before the first round consensus
+    pub block_height: Option<u32>,
+    /// Randomness beacon of the last round consensus, `None` before the first one
+    pub randomness_beacon: Option<[u8; 32]>,
+}
+
 #[async_trait(?Send)]
 pub trait FederationModule {
     type Error;
@@ -20,6 +36,9 @@ pub trait FederationModule {
     type TxOutputOutcome;
     type ConsensusItem;
 
+    /// Name of the module, its API endpoints are served below `/<API_BASE_NAME>`
+    const API_BASE_NAME: &'static str;
+
     /// This module's contribution to the next consensus proposal
     async fn consensus_proposal<'a>(
         &'a self,
@@ -29,9 +48,11 @@ pub trait FederationModule {
     /// This function is called once before transaction processing starts. All module consensus
     /// items of this round are supplied as `consensus_items`. The batch will be committed to the
     /// database after all other modules ran `begin_consensus_epoch`, so the results are available
-    /// when processing transactions.  
+    /// when processing transactions. The `context` still contains the block height and randomness
+    /// beacon of the previous epoch.
     async fn begin_consensus_epoch<'a>(
         &'a self,
+        context: &'a ConsensusContext,
         batch: BatchTx<'a>,
         consensus_items: Vec<(PeerId, Self::ConsensusItem)>,
         rng: impl RngCore + CryptoRng + 'a,
@@ -41,7 +62,11 @@ pub trait FederationModule {
     /// function has no side effects and may be called at any time. False positives due to outdated
     /// database state are ok since they get filtered out after consensus has been reached on them
     /// and merely generate a warning.
-    fn validate_input<'a>(&self, input: &'a Self::TxInput) -> Result<InputMeta<'a>, Self::Error>;
+    fn validate_input<'a>(
+        &self,
+        context: &ConsensusContext,
+        input: &'a Self::TxInput,
+    ) -> Result<InputMeta<'a>, Self::Error>;
 
     /// Try to spend a transaction input. On success all necessary updates will be part of the
     /// database `batch`. On failure (e.g. double spend) the batch is reset and the operation will
@@ -52,6 +77,7 @@ pub trait FederationModule {
     /// processed.
     fn apply_input<'a, 'b>(
         &'a self,
+        context: &ConsensusContext,
         batch: BatchTx<'a>,
         input: &'b Self::TxInput,
     ) -> Result<InputMeta<'b>, Self::Error>;
@@ -74,6 +100,7 @@ pub trait FederationModule {
     /// processed.
     fn apply_output<'a>(
         &'a self,
+        context: &ConsensusContext,
         batch: BatchTx<'a>,
         output: &'a Self::TxOutput,
         out_point: crate::OutPoint,
@@ -91,4 +118,17 @@ pub trait FederationModule {
     /// needed by the client to access funds or give an estimate of when funds will be available.
     /// Returns `None` if the output is unknown, **NOT** if it is just not ready yet.
     fn output_status(&self, out_point: crate::OutPoint) -> Option<Self::TxOutputOutcome>;
+
+    /// Sums up the funds the module holds and the ones it owes to users according to the committed
+    /// database state. This is checked after every epoch to make sure the federation is solvent.
+    fn audit(&self) -> ModuleAudit;
+
+    /// API endpoints exposing module state to clients, which are mounted by the server below
+    /// [`FederationModule::API_BASE_NAME`]
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>>
+    where
+        Self: Sized,
+    {
+        vec![]
+    }
 }
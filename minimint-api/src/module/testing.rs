@@ -2,12 +2,24 @@ use crate::config::GenerateConfig;
 use crate::db::batch::DbBatch;
 use crate::db::mem_impl::MemDatabase;
 use crate::db::{Database, RawDatabase};
-use crate::{Amount, FederationModule, InputMeta, OutPoint, PeerId};
+use crate::module::audit::ModuleAudit;
+use crate::{Amount, ConsensusContext, FederationModule, InputMeta, OutPoint, PeerId};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
-pub struct FakeFed<M, CC> {
+/// Turns the consensus items an honest member would propose into the ones a byzantine one does
+type CorruptProposal<CI> = Box<dyn Fn(Vec<CI>) -> Vec<CI>>;
+
+pub struct FakeFed<M, CC>
+where
+    M: FederationModule,
+{
     members: Vec<(PeerId, M, MemDatabase)>,
     client_cfg: CC,
+    context: ConsensusContext,
+    /// Members whose proposals are included in consensus rounds, all if `None`
+    proposing: Option<HashSet<PeerId>>,
+    byzantine: HashMap<PeerId, CorruptProposal<M::ConsensusItem>>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -16,6 +28,13 @@ pub struct TestInputMeta {
     pub keys: Vec<secp256k1_zkp::schnorrsig::PublicKey>,
 }
 
+/// Results of applying the inputs and outputs of a consensus round, in the order they were given
+#[derive(Debug, PartialEq, Eq)]
+pub struct RoundResults<E> {
+    pub inputs: Vec<Result<TestInputMeta, E>>,
+    pub outputs: Vec<Result<Amount, E>>,
+}
+
 impl<M, CC> FakeFed<M, CC>
 where
     M: FederationModule,
@@ -51,12 +70,19 @@ where
         FakeFed {
             members,
             client_cfg,
+            context: ConsensusContext {
+                epoch: 0,
+                block_height: None,
+                randomness_beacon: None,
+            },
+            proposing: None,
+            byzantine: HashMap::new(),
         }
     }
 
     pub fn verify_input(&self, input: &M::TxInput) -> Result<TestInputMeta, M::Error> {
         let results = self.members.iter().map(|(_, member, _)| {
-            let InputMeta { amount, puk_keys } = member.validate_input(input)?;
+            let InputMeta { amount, puk_keys } = member.validate_input(&self.context, input)?;
             Ok(TestInputMeta {
                 amount,
                 keys: puk_keys.collect(),
@@ -73,44 +99,74 @@ where
         assert_all_equal(results)
     }
 
-    // TODO: add expected result to inputs/outputs
+    /// Runs a consensus round in which all `inputs` and `outputs` are expected to be valid
     pub async fn consensus_round(
         &mut self,
         inputs: &[M::TxInput],
         outputs: &[(OutPoint, M::TxOutput)],
     ) {
+        let results = self.consensus_round_with_results(inputs, outputs).await;
+        for result in results.inputs {
+            result.expect("Faulty input");
+        }
+        for result in results.outputs {
+            result.expect("Faulty output");
+        }
+    }
+
+    /// Runs a consensus round and returns the result of every input and output, which all members
+    /// have to agree on. Failed inputs and outputs leave no trace in the database.
+    pub async fn consensus_round_with_results(
+        &mut self,
+        inputs: &[M::TxInput],
+        outputs: &[(OutPoint, M::TxOutput)],
+    ) -> RoundResults<M::Error> {
         let mut rng = rand::rngs::OsRng::new().unwrap();
 
-        // TODO: only include some of the proposals for realism
         let mut consensus = vec![];
         for (id, member, _db) in &mut self.members {
-            consensus.extend(
-                member
-                    .consensus_proposal(&mut rng)
-                    .await
-                    .into_iter()
-                    .map(|ci| (*id, ci)),
-            );
+            let included = self
+                .proposing
+                .as_ref()
+                .map_or(true, |proposing| proposing.contains(id));
+            if !included {
+                continue;
+            }
+
+            let mut proposal = member.consensus_proposal(&mut rng).await;
+            if let Some(corrupt) = self.byzantine.get(id) {
+                proposal = corrupt(proposal);
+            }
+            consensus.extend(proposal.into_iter().map(|ci| (*id, ci)));
         }
 
+        let context = self.context;
+        let mut results = Vec::new();
         for (_peer, member, db) in &mut self.members {
             let mut batch = DbBatch::new();
 
             member
-                .begin_consensus_epoch(batch.transaction(), consensus.clone(), &mut rng)
+                .begin_consensus_epoch(&context, batch.transaction(), consensus.clone(), &mut rng)
                 .await;
 
-            for input in inputs {
-                member
-                    .apply_input(batch.transaction(), input)
-                    .expect("Faulty input");
-            }
+            let input_results: Vec<Result<TestInputMeta, M::Error>> = inputs
+                .iter()
+                .map(|input| {
+                    let InputMeta { amount, puk_keys } =
+                        member.apply_input(&context, batch.transaction(), input)?;
+                    Ok(TestInputMeta {
+                        amount,
+                        keys: puk_keys.collect(),
+                    })
+                })
+                .collect();
 
-            for (out_point, output) in outputs {
-                member
-                    .apply_output(batch.transaction(), output, *out_point)
-                    .expect("Faulty output");
-            }
+            let output_results = outputs
+                .iter()
+                .map(|(out_point, output)| {
+                    member.apply_output(&context, batch.transaction(), output, *out_point)
+                })
+                .collect();
 
             (db as &mut dyn RawDatabase)
                 .apply_batch(batch)
@@ -124,7 +180,40 @@ where
             (db as &mut dyn RawDatabase)
                 .apply_batch(batch)
                 .expect("DB error");
+
+            results.push(RoundResults {
+                inputs: input_results,
+                outputs: output_results,
+            });
         }
+
+        self.context.epoch += 1;
+        assert_all_equal(results.into_iter())
+    }
+
+    /// Only includes the consensus proposals of `peers` in the following rounds, simulating the
+    /// other members being offline or too slow
+    pub fn only_include_proposals_of(&mut self, peers: &[PeerId]) {
+        self.proposing = Some(peers.iter().copied().collect());
+    }
+
+    /// Includes the consensus proposals of all members again
+    pub fn include_all_proposals(&mut self) {
+        self.proposing = None;
+    }
+
+    /// Lets `peer` propose `corrupt` applied to the consensus items it would honestly propose.
+    /// Apart from that the member keeps processing consensus like everyone else.
+    pub fn make_byzantine<F>(&mut self, peer: PeerId, corrupt: F)
+    where
+        F: Fn(Vec<M::ConsensusItem>) -> Vec<M::ConsensusItem> + 'static,
+    {
+        self.byzantine.insert(peer, Box::new(corrupt));
+    }
+
+    /// Sets the block height the federation agreed on as seen by the modules
+    pub fn set_block_height(&mut self, block_height: u32) {
+        self.context.block_height = Some(block_height);
     }
 
     pub fn output_outcome(&self, out_point: OutPoint) -> Option<M::TxOutputOutcome> {
@@ -139,6 +228,10 @@ where
         )
     }
 
+    pub fn audit(&self) -> ModuleAudit {
+        assert_all_equal(self.members.iter().map(|(_, member, _)| member.audit()))
+    }
+
     pub fn patch_dbs<U>(&mut self, update: U)
     where
         U: Fn(&mut dyn RawDatabase),
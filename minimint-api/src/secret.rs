@@ -0,0 +1,130 @@
+//! Wrapper for secret key material. Secrets are overwritten when dropped, are redacted from
+//! `Debug` output (and thus from logs) and don't implement `Serialize`. Structs that have to
+//! persist a secret opt in explicitly using `#[serde(with = "minimint_api::secret::exposed")]`.
+
+use crate::encoding::{Decodable, DecodeError, Encodable};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Secret value of type `T` that gets erased on drop
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret<T: Erasable>(T);
+
+/// Types that can be overwritten with a value that doesn't contain any secret. They have to be
+/// `Copy`, which guarantees that the secret doesn't own any memory that overwriting it would miss.
+/// This also means that copies can be made silently, e.g. by dereferencing [`Secret::expose`],
+/// which aren't erased.
+pub trait Erasable: Copy {
+    /// Value that replaces the secret, e.g. all zeros
+    fn erased() -> Self;
+}
+
+impl<T: Erasable> Secret<T> {
+    pub fn new(secret: T) -> Secret<T> {
+        Secret(secret)
+    }
+
+    /// Accesses the secret. Copies of it, e.g. `*secret.expose()`, are not erased when this wrapper
+    /// is dropped, so the reference should be used directly wherever possible.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Erasable> Drop for Secret<T> {
+    fn drop(&mut self) {
+        // A volatile write can't be optimized away although the value is never read again
+        unsafe { std::ptr::write_volatile(&mut self.0, T::erased()) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl<T: Erasable> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl<T: Erasable + Encodable> Encodable for Secret<T> {
+    fn consensus_encode<W: std::io::Write>(&self, writer: W) -> Result<usize, std::io::Error> {
+        self.0.consensus_encode(writer)
+    }
+}
+
+impl<T: Erasable + Decodable> Decodable for Secret<T> {
+    fn consensus_decode<D: std::io::Read>(d: D) -> Result<Self, DecodeError> {
+        Ok(Secret(T::consensus_decode(d)?))
+    }
+}
+
+impl Erasable for [u8; 32] {
+    fn erased() -> Self {
+        [0; 32]
+    }
+}
+
+impl Erasable for bitcoin::secp256k1::SecretKey {
+    fn erased() -> Self {
+        bitcoin::secp256k1::ONE_KEY
+    }
+}
+
+impl Erasable for tbs::SecretKeyShare {
+    fn erased() -> Self {
+        tbs::SecretKeyShare::from_secret_bytes(&[0; 32]).expect("Zero is a valid scalar")
+    }
+}
+
+impl Erasable for tbs::BlindingKey {
+    fn erased() -> Self {
+        tbs::BlindingKey::from_secret_bytes(&[0; 32]).expect("Zero is a valid scalar")
+    }
+}
+
+/// Serializes the secret itself, to be used with `#[serde(with = "...")]`
+pub mod exposed {
+    use super::{Erasable, Secret};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(secret: &Secret<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        T: Erasable + Serialize,
+        S: Serializer,
+    {
+        secret.0.serialize(s)
+    }
+
+    pub fn deserialize<'d, T, D>(d: D) -> Result<Secret<T>, D::Error>
+    where
+        T: Erasable + Deserialize<'d>,
+        D: Deserializer<'d>,
+    {
+        Ok(Secret(T::deserialize(d)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+    use std::mem::ManuallyDrop;
+
+    #[test]
+    fn test_redacted() {
+        let secret = Secret::new([42u8; 32]);
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(secret.expose(), &[42u8; 32]);
+    }
+
+    #[test]
+    fn test_erased_on_drop() {
+        let mut secret = ManuallyDrop::new(Secret::new([42u8; 32]));
+        let copy = *secret.expose();
+
+        // Keeps the memory of the secret around after dropping it so it can be inspected
+        unsafe { ManuallyDrop::drop(&mut secret) };
+        assert_eq!(secret.expose(), &[0u8; 32]);
+
+        // Copies aren't erased
+        assert_eq!(copy, [42u8; 32]);
+    }
+}
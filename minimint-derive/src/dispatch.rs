@@ -0,0 +1,525 @@
+//! Implementation of the `FederationModules` derive
+//!
+//! The derive is applied to the struct holding the federation's modules. Every field annotated
+//! with `#[module(id = N)]` is a `FederationModule`, optionally `variant = "Name"` sets the name of
+//! the module's variants (the default is the field name in upper camel case). Module ids are used
+//! to tag the module's in- and outputs in their consensus encoding and may never be changed or
+//! reused. Other fields are ignored.
+//!
+//! The following items are generated next to the struct, which expect `Transaction` and `PeerId`
+//! to be in scope:
+//!
+//! * `Input`, `Output`, `OutputOutcome` and `ConsensusItem` enums with one variant per module,
+//!   `ConsensusItem` additionally contains `Transaction`s
+//! * `InputError` and `OutputError` wrapping the modules' errors
+//! * inherent functions on the struct dispatching in- and outputs, consensus items and the epoch
+//!   hooks to the right module
+
+use heck::{CamelCase, SnakeCase};
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote};
+use std::collections::BTreeMap;
+use syn::{
+    Attribute, Data, DataStruct, DeriveInput, Error, Fields, Ident, Lit, Meta, NestedMeta, Result,
+    Type,
+};
+
+struct Module {
+    field: Ident,
+    ty: Type,
+    id: u16,
+    variant: Ident,
+}
+
+/// Parses the `#[module(...)]` attribute of a field, returns `None` for fields without one
+fn module_attr(attrs: &[Attribute], field: &Ident) -> Result<Option<(u16, Ident)>> {
+    let attr = match attrs.iter().find(|attr| attr.path.is_ident("module")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+
+    let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        meta => return Err(Error::new_spanned(meta, "expected #[module(id = N)]")),
+    };
+
+    let mut id = None;
+    let mut variant = None;
+    for nested in list.nested {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("id") => match nv.lit {
+                Lit::Int(int) => id = Some(int.base10_parse::<u16>()?),
+                lit => return Err(Error::new_spanned(lit, "expected an integer module id")),
+            },
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("variant") => match nv.lit {
+                Lit::Str(name) => variant = Some(name.parse::<Ident>()?),
+                lit => return Err(Error::new_spanned(lit, "expected a variant name")),
+            },
+            nested => {
+                return Err(Error::new_spanned(
+                    nested,
+                    "unknown module option, expected `id = N` or `variant = \"...\"`",
+                ))
+            }
+        }
+    }
+
+    let id = id.ok_or_else(|| Error::new_spanned(attr, "missing module id"))?;
+    let variant = variant.unwrap_or_else(|| format_ident!("{}", field.to_string().to_camel_case()));
+    Ok(Some((id, variant)))
+}
+
+fn modules(input: &DeriveInput) -> Result<Vec<Module>> {
+    let fields = match &input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => fields,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "FederationModules can only be derived for structs with named fields",
+            ))
+        }
+    };
+
+    let mut ids = BTreeMap::<u16, Ident>::new();
+    let mut variants = BTreeMap::<String, Ident>::new();
+    let mut modules = Vec::new();
+    for field in &fields.named {
+        let field_ident = field.ident.clone().expect("fields are named");
+        let (id, variant) = match module_attr(&field.attrs, &field_ident)? {
+            Some(attr) => attr,
+            None => continue,
+        };
+
+        if let Some(other) = ids.insert(id, field_ident.clone()) {
+            return Err(Error::new_spanned(
+                &field_ident,
+                format!("module id {} is already used by {}", id, other),
+            ));
+        }
+        if variant == "Transaction" || variant == "Unknown" || variant == "UnknownModule" {
+            return Err(Error::new_spanned(
+                &field_ident,
+                format!("variant name {} is reserved", variant),
+            ));
+        }
+        if let Some(other) = variants.insert(variant.to_string(), field_ident.clone()) {
+            return Err(Error::new_spanned(
+                &field_ident,
+                format!("variant {} is already used by {}", variant, other),
+            ));
+        }
+
+        modules.push(Module {
+            field: field_ident,
+            ty: field.ty.clone(),
+            id,
+            variant,
+        });
+    }
+
+    if modules.is_empty() {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "no field is marked as module using #[module(id = N)]",
+        ));
+    }
+    Ok(modules)
+}
+
+pub fn derive_federation_modules(input: DeriveInput) -> Result<TokenStream> {
+    let modules = modules(&input)?;
+
+    let field = modules.iter().map(|m| &m.field).collect::<Vec<_>>();
+    let ty = modules.iter().map(|m| &m.ty).collect::<Vec<_>>();
+    let id = modules
+        .iter()
+        .map(|m| Literal::u16_suffixed(m.id))
+        .collect::<Vec<_>>();
+    let variant = modules.iter().map(|m| &m.variant).collect::<Vec<_>>();
+    let variant_name = modules
+        .iter()
+        .map(|m| m.variant.to_string())
+        .collect::<Vec<_>>();
+    let items = modules
+        .iter()
+        .map(|m| format_ident!("{}", m.variant.to_string().to_snake_case()))
+        .collect::<Vec<_>>();
+    let input_error = modules
+        .iter()
+        .map(|m| format!("{} input error: {{0}}", m.variant))
+        .collect::<Vec<_>>();
+    let output_error = modules
+        .iter()
+        .map(|m| format!("{} output error: {{0}}", m.variant))
+        .collect::<Vec<_>>();
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        /// Transaction input, encoded as [`ModulePayload`](::minimint_api::encoding::ModulePayload)
+        /// so that adding modules doesn't change the encoding (and thus hashes) of existing
+        /// transactions
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Debug, Clone, Eq, PartialEq, Hash, ::serde::Deserialize, ::serde::Serialize)]
+        pub enum Input {
+            #(#variant(<#ty as ::minimint_api::FederationModule>::TxInput),)*
+        }
+
+        /// Transaction output, encoded like [`Input`]
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Debug, Clone, Eq, PartialEq, Hash, ::serde::Deserialize, ::serde::Serialize)]
+        pub enum Output {
+            #(#variant(<#ty as ::minimint_api::FederationModule>::TxOutput),)*
+        }
+
+        /// Outcome of a transaction output, encoded like [`Input`]. Its serde representation is
+        /// tagged with the module's variant name, outcomes tagged with names unknown to us are
+        /// deserialized as [`OutputOutcome::UnknownModule`] so that clients can still decode
+        /// transaction outcomes that contain outputs of newer modules.
+        #[allow(clippy::large_enum_variant)]
+        #[derive(Debug, Clone, Eq, PartialEq, Hash, ::serde::Serialize)]
+        pub enum OutputOutcome {
+            #(#variant(<#ty as ::minimint_api::FederationModule>::TxOutputOutcome),)*
+            /// Outcome of an output belonging to a module we don't know, as decoded from its
+            /// consensus encoding
+            Unknown(::minimint_api::encoding::ModulePayload),
+            /// Outcome of an output belonging to a module we don't know, as received through the
+            /// API. Only the module's variant name is kept, so it can't be consensus encoded.
+            UnknownModule(String),
+        }
+
+        #[allow(clippy::large_enum_variant)]
+        #[derive(
+            Debug,
+            Clone,
+            Eq,
+            PartialEq,
+            Hash,
+            ::serde::Deserialize,
+            ::serde::Serialize,
+            ::minimint_derive::UnzipConsensus,
+            ::minimint_api::encoding::Encodable,
+            ::minimint_api::encoding::Decodable,
+        )]
+        pub enum ConsensusItem {
+            Transaction(Transaction),
+            #(#variant(<#ty as ::minimint_api::FederationModule>::ConsensusItem),)*
+        }
+
+        #[derive(Debug, ::thiserror::Error)]
+        pub enum InputError {
+            #(
+                #[error(#input_error)]
+                #variant(<#ty as ::minimint_api::FederationModule>::Error),
+            )*
+        }
+
+        #[derive(Debug, ::thiserror::Error)]
+        pub enum OutputError {
+            #(
+                #[error(#output_error)]
+                #variant(<#ty as ::minimint_api::FederationModule>::Error),
+            )*
+        }
+
+        impl ::minimint_api::encoding::Encodable for Input {
+            fn consensus_encode<W: std::io::Write>(
+                &self,
+                writer: W,
+            ) -> Result<usize, std::io::Error> {
+                let payload = match self {
+                    #(Input::#variant(input) => {
+                        ::minimint_api::encoding::ModulePayload::new(#id, input)
+                    })*
+                };
+                ::minimint_api::encoding::Encodable::consensus_encode(&payload, writer)
+            }
+        }
+
+        impl ::minimint_api::encoding::Decodable for Input {
+            fn consensus_decode<D: std::io::Read>(
+                d: D,
+            ) -> Result<Self, ::minimint_api::encoding::DecodeError> {
+                let payload = <::minimint_api::encoding::ModulePayload as ::minimint_api::encoding::Decodable>::consensus_decode(d)?;
+                match payload.module {
+                    #(#id => Ok(Input::#variant(payload.decode()?)),)*
+                    _ => Err(::minimint_api::encoding::DecodeError::from_str(
+                        "Input of unknown module",
+                    )),
+                }
+            }
+        }
+
+        impl ::minimint_api::encoding::Encodable for Output {
+            fn consensus_encode<W: std::io::Write>(
+                &self,
+                writer: W,
+            ) -> Result<usize, std::io::Error> {
+                let payload = match self {
+                    #(Output::#variant(output) => {
+                        ::minimint_api::encoding::ModulePayload::new(#id, output)
+                    })*
+                };
+                ::minimint_api::encoding::Encodable::consensus_encode(&payload, writer)
+            }
+        }
+
+        impl ::minimint_api::encoding::Decodable for Output {
+            fn consensus_decode<D: std::io::Read>(
+                d: D,
+            ) -> Result<Self, ::minimint_api::encoding::DecodeError> {
+                let payload = <::minimint_api::encoding::ModulePayload as ::minimint_api::encoding::Decodable>::consensus_decode(d)?;
+                match payload.module {
+                    #(#id => Ok(Output::#variant(payload.decode()?)),)*
+                    _ => Err(::minimint_api::encoding::DecodeError::from_str(
+                        "Output of unknown module",
+                    )),
+                }
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for OutputOutcome {
+            fn deserialize<D: ::serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Self, D::Error> {
+                #[allow(clippy::large_enum_variant)]
+                #[derive(::serde::Deserialize)]
+                enum Tagged {
+                    #(#variant(<#ty as ::minimint_api::FederationModule>::TxOutputOutcome),)*
+                    Unknown(::minimint_api::encoding::ModulePayload),
+                    UnknownModule(String),
+                }
+
+                #[allow(clippy::large_enum_variant)]
+                #[derive(::serde::Deserialize)]
+                #[serde(untagged)]
+                enum Repr {
+                    Tagged(Tagged),
+                    Other(std::collections::BTreeMap<String, ::serde::de::IgnoredAny>),
+                }
+
+                match <Repr as ::serde::Deserialize>::deserialize(deserializer)? {
+                    #(Repr::Tagged(Tagged::#variant(outcome)) => Ok(OutputOutcome::#variant(outcome)),)*
+                    Repr::Tagged(Tagged::Unknown(payload)) => Ok(OutputOutcome::Unknown(payload)),
+                    Repr::Tagged(Tagged::UnknownModule(name)) => {
+                        Ok(OutputOutcome::UnknownModule(name))
+                    }
+                    Repr::Other(tagged) => match tagged.into_keys().collect::<Vec<_>>().as_slice() {
+                        // Known outcomes that fail to deserialize must not be mistaken for outcomes
+                        // of newer modules
+                        [name] if ![#(#variant_name,)* "Unknown", "UnknownModule"].contains(&name.as_str()) => {
+                            Ok(OutputOutcome::UnknownModule(name.clone()))
+                        }
+                        _ => Err(<D::Error as ::serde::de::Error>::custom(
+                            "invalid output outcome",
+                        )),
+                    },
+                }
+            }
+        }
+
+        impl ::minimint_api::encoding::Encodable for OutputOutcome {
+            fn consensus_encode<W: std::io::Write>(
+                &self,
+                writer: W,
+            ) -> Result<usize, std::io::Error> {
+                let payload = match self {
+                    #(OutputOutcome::#variant(outcome) => {
+                        ::minimint_api::encoding::ModulePayload::new(#id, outcome)
+                    })*
+                    OutputOutcome::Unknown(payload) => payload.clone(),
+                    OutputOutcome::UnknownModule(name) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("Can't encode outcome of unknown module {}", name),
+                        ))
+                    }
+                };
+                ::minimint_api::encoding::Encodable::consensus_encode(&payload, writer)
+            }
+        }
+
+        impl ::minimint_api::encoding::Decodable for OutputOutcome {
+            fn consensus_decode<D: std::io::Read>(
+                d: D,
+            ) -> Result<Self, ::minimint_api::encoding::DecodeError> {
+                let payload = <::minimint_api::encoding::ModulePayload as ::minimint_api::encoding::Decodable>::consensus_decode(d)?;
+                Ok(match payload.module {
+                    #(#id => OutputOutcome::#variant(payload.decode()?),)*
+                    _ => OutputOutcome::Unknown(payload),
+                })
+            }
+        }
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Validates `input` using the module it belongs to, see
+            /// [`FederationModule::validate_input`](::minimint_api::FederationModule::validate_input)
+            pub fn validate_input<'a>(
+                &self,
+                context: &::minimint_api::ConsensusContext,
+                input: &'a Input,
+            ) -> Result<::minimint_api::InputMeta<'a>, InputError> {
+                match input {
+                    #(Input::#variant(input) => {
+                        ::minimint_api::FederationModule::validate_input(&self.#field, context, input)
+                            .map_err(InputError::#variant)
+                    })*
+                }
+            }
+
+            /// Spends `input` using the module it belongs to, see
+            /// [`FederationModule::apply_input`](::minimint_api::FederationModule::apply_input)
+            pub fn apply_input<'a, 'b>(
+                &'a self,
+                context: &::minimint_api::ConsensusContext,
+                batch: ::minimint_api::db::batch::BatchTx<'a>,
+                input: &'b Input,
+            ) -> Result<::minimint_api::InputMeta<'b>, InputError> {
+                match input {
+                    #(Input::#variant(input) => {
+                        ::minimint_api::FederationModule::apply_input(
+                            &self.#field,
+                            context,
+                            batch,
+                            input,
+                        )
+                        .map_err(InputError::#variant)
+                    })*
+                }
+            }
+
+            /// Validates `output` using the module it belongs to, see
+            /// [`FederationModule::validate_output`](::minimint_api::FederationModule::validate_output)
+            pub fn validate_output(
+                &self,
+                output: &Output,
+            ) -> Result<::minimint_api::Amount, OutputError> {
+                match output {
+                    #(Output::#variant(output) => {
+                        ::minimint_api::FederationModule::validate_output(&self.#field, output)
+                            .map_err(OutputError::#variant)
+                    })*
+                }
+            }
+
+            /// Creates `output` using the module it belongs to, see
+            /// [`FederationModule::apply_output`](::minimint_api::FederationModule::apply_output)
+            pub fn apply_output<'a>(
+                &'a self,
+                context: &::minimint_api::ConsensusContext,
+                batch: ::minimint_api::db::batch::BatchTx<'a>,
+                output: &'a Output,
+                out_point: ::minimint_api::OutPoint,
+            ) -> Result<::minimint_api::Amount, OutputError> {
+                match output {
+                    #(Output::#variant(output) => {
+                        ::minimint_api::FederationModule::apply_output(
+                            &self.#field,
+                            context,
+                            batch,
+                            output,
+                            out_point,
+                        )
+                        .map_err(OutputError::#variant)
+                    })*
+                }
+            }
+
+            /// Looks up the outcome of `output`, which was created as `out_point`, in the module
+            /// it belongs to
+            pub fn output_status(
+                &self,
+                output: &Output,
+                out_point: ::minimint_api::OutPoint,
+            ) -> Option<OutputOutcome> {
+                match output {
+                    #(Output::#variant(_) => {
+                        ::minimint_api::FederationModule::output_status(&self.#field, out_point)
+                            .map(OutputOutcome::#variant)
+                    })*
+                }
+            }
+
+            /// Audits all modules, see
+            /// [`FederationModule::audit`](::minimint_api::FederationModule::audit)
+            pub fn audit(&self) -> ::minimint_api::module::audit::Audit {
+                let mut audit = ::minimint_api::module::audit::Audit::default();
+                #(audit.add_module(&self.#field);)*
+                audit
+            }
+
+            /// Collects the consensus proposals of all modules, `rng` is called once per module
+            pub async fn modules_consensus_proposal<G, F>(&self, mut rng: F) -> Vec<ConsensusItem>
+            where
+                F: FnMut() -> G,
+                G: ::rand::RngCore + ::rand::CryptoRng,
+            {
+                let mut items = Vec::new();
+                #(
+                    items.extend(
+                        ::minimint_api::FederationModule::consensus_proposal(&self.#field, rng())
+                            .await
+                            .into_iter()
+                            .map(ConsensusItem::#variant),
+                    );
+                )*
+                items
+            }
+
+            /// Hands the modules their consensus items of the epoch and returns the transactions,
+            /// which have to be processed afterwards
+            pub async fn modules_begin_consensus_epoch<G, F>(
+                &self,
+                context: &::minimint_api::ConsensusContext,
+                db_batch: &mut ::minimint_api::db::batch::DbBatch,
+                consensus_items: UnzipConsensusItem,
+                mut rng: F,
+            ) -> Vec<(PeerId, Transaction)>
+            where
+                F: FnMut() -> G,
+                G: ::rand::RngCore + ::rand::CryptoRng,
+            {
+                let UnzipConsensusItem {
+                    transaction,
+                    #(#items,)*
+                } = consensus_items;
+
+                #(
+                    ::minimint_api::FederationModule::begin_consensus_epoch(
+                        &self.#field,
+                        context,
+                        db_batch.transaction(),
+                        #items,
+                        rng(),
+                    )
+                    .await;
+                )*
+
+                transaction
+            }
+
+            /// Runs the end of epoch hooks of all modules
+            pub async fn modules_end_consensus_epoch<G, F>(
+                &self,
+                db_batch: &mut ::minimint_api::db::batch::DbBatch,
+                mut rng: F,
+            ) where
+                F: FnMut() -> G,
+                G: ::rand::RngCore + ::rand::CryptoRng,
+            {
+                #(
+                    ::minimint_api::FederationModule::end_consensus_epoch(
+                        &self.#field,
+                        db_batch.transaction(),
+                        rng(),
+                    )
+                    .await;
+                )*
+            }
+        }
+    })
+}
@@ -0,0 +1,364 @@
+//! Implementation of the `Encodable` and `Decodable` derives
+//!
+//! Structs are encoded as the concatenation of their fields, enums as a `u64` tag followed by the
+//! fields of the variant. The following attributes are supported:
+//!
+//! * `#[encoding(tag = N)]` on a variant: encode it using tag `N` instead of its position, so
+//!   variants can be reordered without changing the encoding
+//! * `#[encoding(skip)]` on a field: don't encode it, decoding uses `Default::default()`
+//! * `#[encoding(with = "path")]` on a field: encode it using `path::consensus_encode` and
+//!   `path::consensus_decode`, which have the same signatures as the trait functions but take
+//!   the value as their first argument when encoding
+
+use proc_macro2::{Literal, TokenStream, TokenTree};
+use quote::{format_ident, quote};
+use std::collections::BTreeMap;
+use syn::spanned::Spanned;
+use syn::{
+    Attribute, Data, DeriveInput, Error, Field, Fields, GenericParam, Generics, Ident, Lit, Meta,
+    NestedMeta, Path, Result, Type, Variant,
+};
+
+#[derive(Default)]
+struct FieldAttrs {
+    skip: bool,
+    with: Option<Path>,
+}
+
+impl FieldAttrs {
+    /// Whether the field's type has to implement the trait being derived
+    fn uses_trait(&self) -> bool {
+        !self.skip && self.with.is_none()
+    }
+}
+
+/// Returns the `key = value` and `key` items of all `#[encoding(...)]` attributes
+fn encoding_attrs(attrs: &[Attribute]) -> Result<Vec<Meta>> {
+    let mut items = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("encoding")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => items.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(Error::new_spanned(lit, "expected an encoding option"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(Error::new_spanned(meta, "expected #[encoding(...)]")),
+        }
+    }
+    Ok(items)
+}
+
+fn field_attrs(field: &Field) -> Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for meta in encoding_attrs(&field.attrs)? {
+        match meta {
+            Meta::Path(path) if path.is_ident("skip") => attrs.skip = true,
+            Meta::NameValue(nv) if nv.path.is_ident("with") => match nv.lit {
+                Lit::Str(path) => attrs.with = Some(path.parse()?),
+                lit => return Err(Error::new_spanned(lit, "expected a path as string")),
+            },
+            meta => {
+                return Err(Error::new_spanned(
+                    meta,
+                    "unknown field option, expected `skip` or `with = \"...\"`",
+                ))
+            }
+        }
+    }
+
+    if attrs.skip && attrs.with.is_some() {
+        return Err(Error::new(
+            field.span(),
+            "`skip` and `with` can't be used together",
+        ));
+    }
+    Ok(attrs)
+}
+
+fn variant_tag(variant: &Variant, position: usize) -> Result<u64> {
+    let mut tag = None;
+    for meta in encoding_attrs(&variant.attrs)? {
+        match meta {
+            Meta::NameValue(nv) if nv.path.is_ident("tag") => match nv.lit {
+                Lit::Int(int) => tag = Some(int.base10_parse::<u64>()?),
+                lit => return Err(Error::new_spanned(lit, "expected an integer tag")),
+            },
+            meta => {
+                return Err(Error::new_spanned(
+                    meta,
+                    "unknown variant option, expected `tag = N`",
+                ))
+            }
+        }
+    }
+    Ok(tag.unwrap_or(position as u64))
+}
+
+/// Determines the tags of all variants, which have to be unique
+fn variant_tags<'a>(
+    variants: impl Iterator<Item = &'a Variant>,
+) -> Result<Vec<(&'a Variant, Literal)>> {
+    let mut used = BTreeMap::<u64, &Ident>::new();
+    variants
+        .enumerate()
+        .map(|(position, variant)| {
+            let tag = variant_tag(variant, position)?;
+            if let Some(other) = used.insert(tag, &variant.ident) {
+                return Err(Error::new_spanned(
+                    &variant.ident,
+                    format!("tag {} is already used by variant {}", tag, other),
+                ));
+            }
+            Ok((variant, Literal::u64_suffixed(tag)))
+        })
+        .collect()
+}
+
+/// Checks if `ty` mentions `ident` anywhere, e.g. `Vec<T>` mentions `T`
+fn mentions(ty: &Type, ident: &Ident) -> bool {
+    fn mentions_tokens(tokens: TokenStream, ident: &Ident) -> bool {
+        tokens.into_iter().any(|token| match token {
+            TokenTree::Ident(i) => &i == ident,
+            TokenTree::Group(group) => mentions_tokens(group.stream(), ident),
+            _ => false,
+        })
+    }
+    mentions_tokens(quote!(#ty), ident)
+}
+
+/// Adds `bound` to every type parameter used by a field that is encoded using its own trait impl
+fn add_bounds(
+    mut generics: Generics,
+    fields: &[(&Field, FieldAttrs)],
+    bound: TokenStream,
+) -> Generics {
+    let bounded = generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(param.ident.clone()),
+            _ => None,
+        })
+        .filter(|ident| {
+            fields
+                .iter()
+                .any(|(field, attrs)| attrs.uses_trait() && mentions(&field.ty, ident))
+        })
+        .collect::<Vec<_>>();
+
+    let where_clause = generics.make_where_clause();
+    for ident in bounded {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#ident: #bound));
+    }
+    generics
+}
+
+fn all_fields(data: &Data) -> Result<Vec<(&Field, FieldAttrs)>> {
+    let fields: Box<dyn Iterator<Item = &Field>> = match data {
+        Data::Struct(data) => Box::new(data.fields.iter()),
+        Data::Enum(data) => Box::new(data.variants.iter().flat_map(|v| v.fields.iter())),
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "Encodable and Decodable can't be derived for unions",
+            ))
+        }
+    };
+    fields
+        .map(|field| Ok((field, field_attrs(field)?)))
+        .collect()
+}
+
+/// Names the fields are bound to in patterns and constructors, which can't clash with the
+/// generated code's variables
+fn binding_names(fields: &Fields) -> Vec<Ident> {
+    (0..fields.len())
+        .map(|idx| format_ident!("bound_{}", idx))
+        .collect()
+}
+
+/// Pattern destructuring `fields`, skipped ones are ignored
+fn pattern(path: TokenStream, fields: &Fields, names: &[Ident]) -> Result<TokenStream> {
+    let bindings = fields
+        .iter()
+        .zip(names)
+        .map(|(field, name)| {
+            let skip = field_attrs(field)?.skip;
+            let binding = if skip { quote!(_) } else { quote!(#name) };
+            Ok(match &field.ident {
+                Some(ident) => quote!(#ident: #binding),
+                None => binding,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(match fields {
+        Fields::Named(_) => quote!(#path { #(#bindings,)* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings,)*)),
+        Fields::Unit => quote!(#path),
+    })
+}
+
+/// Statements encoding the `fields` bound to references named `names`
+fn encode_fields(fields: &Fields, names: &[Ident]) -> Result<TokenStream> {
+    let statements = fields
+        .iter()
+        .zip(names)
+        .map(|(field, name)| {
+            let attrs = field_attrs(field)?;
+            Ok(if attrs.skip {
+                quote!()
+            } else if let Some(with) = attrs.with {
+                quote!(len += #with::consensus_encode(#name, &mut writer)?;)
+            } else {
+                quote!(len += ::minimint_api::encoding::Encodable::consensus_encode(#name, &mut writer)?;)
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote!(#(#statements)*))
+}
+
+/// Expression decoding `fields` and constructing `path` from them
+fn decode_fields(path: TokenStream, fields: &Fields, names: &[Ident]) -> Result<TokenStream> {
+    let statements = fields
+        .iter()
+        .zip(names)
+        .map(|(field, name)| {
+            let attrs = field_attrs(field)?;
+            Ok(if attrs.skip {
+                quote!(let #name = ::std::default::Default::default();)
+            } else if let Some(with) = attrs.with {
+                quote!(let #name = #with::consensus_decode(&mut d)?;)
+            } else {
+                quote!(let #name = ::minimint_api::encoding::Decodable::consensus_decode(&mut d)?;)
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let constructor = match fields {
+        Fields::Named(_) => {
+            let idents = fields.iter().map(|field| &field.ident);
+            quote!(#path { #(#idents: #names,)* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#names,)*)),
+        Fields::Unit => quote!(#path),
+    };
+    Ok(quote! {
+        {
+            #(#statements)*
+            #constructor
+        }
+    })
+}
+
+pub fn derive_encodable(input: DeriveInput) -> Result<TokenStream> {
+    let ident = &input.ident;
+    let fields = all_fields(&input.data)?;
+    let generics = add_bounds(
+        input.generics.clone(),
+        &fields,
+        quote!(::minimint_api::encoding::Encodable),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let names = binding_names(&data.fields);
+            let pattern = pattern(quote!(#ident), &data.fields, &names)?;
+            let encode = encode_fields(&data.fields, &names)?;
+            quote! {
+                let #pattern = self;
+                #encode
+            }
+        }
+        Data::Enum(data) => {
+            let arms = variant_tags(data.variants.iter())?
+                .into_iter()
+                .map(|(variant, tag)| {
+                    let variant_ident = &variant.ident;
+                    let names = binding_names(&variant.fields);
+                    let pattern = pattern(quote!(#ident::#variant_ident), &variant.fields, &names)?;
+                    let encode = encode_fields(&variant.fields, &names)?;
+                    Ok(quote! {
+                        #pattern => {
+                            len += ::minimint_api::encoding::Encodable::consensus_encode(&#tag, &mut writer)?;
+                            #encode
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => unreachable!("rejected by all_fields"),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::minimint_api::encoding::Encodable for #ident #ty_generics #where_clause {
+            #[allow(unused_mut, unused_variables)]
+            fn consensus_encode<W: std::io::Write>(&self, mut writer: W) -> std::result::Result<usize, std::io::Error> {
+                let mut len = 0;
+                #body
+                Ok(len)
+            }
+        }
+    })
+}
+
+pub fn derive_decodable(input: DeriveInput) -> Result<TokenStream> {
+    let ident = &input.ident;
+    let fields = all_fields(&input.data)?;
+    let generics = add_bounds(
+        input.generics.clone(),
+        &fields,
+        quote!(::minimint_api::encoding::Decodable),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let names = binding_names(&data.fields);
+            decode_fields(quote!(#ident), &data.fields, &names)?
+        }
+        Data::Enum(data) => {
+            let arms = variant_tags(data.variants.iter())?
+                .into_iter()
+                .map(|(variant, tag)| {
+                    let variant_ident = &variant.ident;
+                    let names = binding_names(&variant.fields);
+                    let decode =
+                        decode_fields(quote!(#ident::#variant_ident), &variant.fields, &names)?;
+                    Ok(quote!(#tag => #decode,))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quote! {
+                match <u64 as ::minimint_api::encoding::Decodable>::consensus_decode(&mut d)? {
+                    #(#arms)*
+                    _ => {
+                        return Err(::minimint_api::encoding::DecodeError::from_str("invalid enum variant"));
+                    }
+                }
+            }
+        }
+        Data::Union(_) => unreachable!("rejected by all_fields"),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::minimint_api::encoding::Decodable for #ident #ty_generics #where_clause {
+            #[allow(unused_mut, unused_variables)]
+            fn consensus_decode<D: std::io::Read>(mut d: D) -> std::result::Result<Self, ::minimint_api::encoding::DecodeError> {
+                Ok(#body)
+            }
+        }
+    })
+}
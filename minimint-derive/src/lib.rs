@@ -1,9 +1,10 @@
-#![cfg_attr(feature = "diagnostics", feature(proc_macro_diagnostic))]
-
 use heck::SnakeCase;
 use proc_macro::{self, TokenStream};
 use quote::{format_ident, quote};
-use syn::{parse_macro_input, Data, DataEnum, DataStruct, DeriveInput, Index};
+use syn::{parse_macro_input, DataEnum, DeriveInput, Error};
+
+mod dispatch;
+mod encoding;
 
 #[proc_macro_derive(UnzipConsensus)]
 pub fn derive_unzip_consensus(input: TokenStream) -> TokenStream {
@@ -16,25 +17,24 @@ pub fn derive_unzip_consensus(input: TokenStream) -> TokenStream {
                 let fields = variant.fields.iter().collect::<Vec<_>>();
 
                 if fields.len() != 1 || fields[0].ident.is_some() {
-                    return Err("UnzipConsensus only supports 1-tuple variants");
+                    return Err(Error::new_spanned(
+                        variant,
+                        "UnzipConsensus only supports 1-tuple variants",
+                    ));
                 }
 
                 Ok((variant.ident.clone(), fields[0].ty.clone()))
             })
             .collect::<std::result::Result<Vec<_>, _>>(),
-        _ => Err("UnzipConsensus can only be derived for enums"),
+        _ => Err(Error::new_spanned(
+            &ident,
+            "UnzipConsensus can only be derived for enums",
+        )),
     };
 
-    #[allow(unreachable_code)]
     let variants = match variants {
         Ok(variants) => variants,
-        Err(e) => {
-            #[cfg(feature = "diagnostics")]
-            ident.span().unstable().error(e).emit();
-            #[cfg(not(feature = "diagnostics"))]
-            panic!("Error: {}", e);
-            return TokenStream::new();
-        }
+        Err(e) => return e.to_compile_error().into(),
     };
 
     let unzip_struct_ident = format_ident!("Unzip{}", ident);
@@ -81,216 +81,23 @@ pub fn derive_unzip_consensus(input: TokenStream) -> TokenStream {
     output.into()
 }
 
-#[proc_macro_derive(Encodable)]
+#[proc_macro_derive(Encodable, attributes(encoding))]
 pub fn derive_encodable(input: TokenStream) -> TokenStream {
-    let DeriveInput { ident, data, .. } = parse_macro_input!(input);
-
-    let output = match data {
-        Data::Struct(DataStruct { fields, .. }) => {
-            if fields.iter().any(|field| field.ident.is_none()) {
-                // Tuple struct
-                let field_names = fields
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, _)| Index::from(idx))
-                    .collect::<Vec<_>>();
-                quote! {
-                    impl Encodable for #ident {
-                        fn consensus_encode<W: std::io::Write>(&self, mut writer: W) -> std::result::Result<usize, std::io::Error> {
-                            let mut len = 0;
-                            #(len += Encodable::consensus_encode(&self.#field_names, &mut writer)?;)*
-                            Ok(len)
-                        }
-                    }
-                }
-            } else {
-                // Tuple struct
-                let field_names = fields
-                    .iter()
-                    .map(|field| field.ident.clone().unwrap())
-                    .collect::<Vec<_>>();
-                quote! {
-                    impl Encodable for #ident {
-                        fn consensus_encode<W: std::io::Write>(&self, mut writer: W) -> std::result::Result<usize, std::io::Error> {
-                            let mut len = 0;
-                            #(len += Encodable::consensus_encode(&self.#field_names, &mut writer)?;)*
-                            Ok(len)
-                        }
-                    }
-                }
-            }
-        }
-        syn::Data::Enum(DataEnum { variants, .. }) => {
-            let match_arms = variants.iter().enumerate().map(|(variant_idx, variant)| {
-                let variant_ident = variant.ident.clone();
-
-                if variant.fields.iter().any(|field| field.ident.is_none()) {
-                    let variant_fields = variant
-                        .fields
-                        .iter()
-                        .enumerate()
-                        .map(|(idx, _)| {
-                            format_ident!("bound_{}", idx)
-                        })
-                        .collect::<Vec<_>>();
-                    quote! {
-                        #ident::#variant_ident(#(#variant_fields,)*) => {
-                            len += Encodable::consensus_encode(&(#variant_idx as u64), &mut writer)?;
-                            #(len += Encodable::consensus_encode(#variant_fields, &mut writer)?;)*
-                        }
-                    }
-                } else {
-                    let variant_fields = variant
-                        .fields
-                        .iter()
-                        .map(|field| {
-                            field
-                                .ident
-                                .clone().unwrap()
-                        })
-                        .collect::<Vec<_>>();
-                    quote! {
-                        #ident::#variant_ident { #(#variant_fields,)*} => {
-                            len += Encodable::consensus_encode(&(#variant_idx as u64), &mut writer)?;
-                            #(len += Encodable::consensus_encode(#variant_fields, &mut writer)?;)*
-                        }
-                    }
-                }
-            });
-
-            quote! {
-                impl Encodable for #ident {
-                    fn consensus_encode<W: std::io::Write>(&self, mut writer: W) -> std::result::Result<usize, std::io::Error> {
-                        let mut len = 0;
-                        match self {
-                            #(#match_arms)*
-                        }
-                        Ok(len)
-                    }
-                }
-            }
-        }
-        #[allow(unreachable_code)]
-        syn::Data::Union(_) => {
-            #[cfg(feature = "diagnostics")]
-            ident
-                .span()
-                .unstable()
-                .error("Encodable can't be derived for unions")
-                .emit();
-            #[cfg(not(feature = "diagnostics"))]
-            panic!("Error: Encodable can't be derived for unions");
-            return TokenStream::new();
-        }
-    };
-
-    output.into()
+    encoding::derive_encodable(parse_macro_input!(input))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
 }
 
-#[proc_macro_derive(Decodable)]
+#[proc_macro_derive(Decodable, attributes(encoding))]
 pub fn derive_decodable(input: TokenStream) -> TokenStream {
-    let DeriveInput { ident, data, .. } = parse_macro_input!(input);
-
-    let output = match data {
-        Data::Struct(DataStruct { fields, .. }) => {
-            if fields.iter().any(|field| field.ident.is_none()) {
-                // Tuple struct
-                let field_names = fields
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, _)| format_ident!("field_{}", idx))
-                    .collect::<Vec<_>>();
-                quote! {
-                    impl Decodable for #ident {
-                        fn consensus_decode<D: std::io::Read>(mut d: D) -> std::result::Result<Self, ::minimint_api::encoding::DecodeError> {
-                            let mut len = 0;
-                            #(let #field_names = Decodable::consensus_decode(&mut d)?;)*
-                            Ok(#ident(#(#field_names,)*))
-                        }
-                    }
-                }
-            } else {
-                // Tuple struct
-                let field_names = fields
-                    .iter()
-                    .map(|field| field.ident.clone().unwrap())
-                    .collect::<Vec<_>>();
-                quote! {
-                    impl Decodable for #ident {
-                        fn consensus_decode<D: std::io::Read>(mut d: D) -> std::result::Result<Self, ::minimint_api::encoding::DecodeError> {
-                            let mut len = 0;
-                            #(let #field_names = Decodable::consensus_decode(&mut d)?;)*
-                            Ok(#ident{
-                                #(#field_names,)*
-                            })
-                        }
-                    }
-                }
-            }
-        }
-        syn::Data::Enum(DataEnum { variants, .. }) => {
-            let match_arms = variants.iter().enumerate().map(|(variant_idx, variant)| {
-                let variant_ident = variant.ident.clone();
-
-                if variant.fields.iter().any(|field| field.ident.is_none()) {
-                    let variant_fields = variant
-                        .fields
-                        .iter()
-                        .enumerate()
-                        .map(|(idx, _)| format_ident!("bound_{}", idx))
-                        .collect::<Vec<_>>();
-                    quote! {
-                        #variant_idx => {
-                            #(let #variant_fields = Decodable::consensus_decode(&mut d)?;)*
-                            #ident::#variant_ident(#(#variant_fields,)*)
-                        }
-                    }
-                } else {
-                    let variant_fields = variant
-                        .fields
-                        .iter()
-                        .map(|field| field.ident.clone().unwrap())
-                        .collect::<Vec<_>>();
-                    quote! {
-                        #variant_idx => {
-                            #(let #variant_fields = Decodable::consensus_decode(&mut d)?;)*
-                            #ident::#variant_ident{
-                                #(#variant_fields,)*
-                            }
-                        }
-                    }
-                }
-            });
-
-            quote! {
-                impl Decodable for #ident {
-                    fn consensus_decode<D: std::io::Read>(mut d: D) -> std::result::Result<Self, ::minimint_api::encoding::DecodeError> {
-                        let variant = <u64 as Decodable>::consensus_decode(&mut d)? as usize;
-                        let decoded = match variant {
-                            #(#match_arms)*
-                            _ => {
-                                return Err(::minimint_api::encoding::DecodeError::from_str("invalid enum variant"));
-                            }
-                        };
-                        Ok(decoded)
-                    }
-                }
-            }
-        }
-        #[allow(unreachable_code)]
-        syn::Data::Union(_) => {
-            #[cfg(feature = "diagnostics")]
-            ident
-                .span()
-                .unstable()
-                .error("Encodable can't be derived for unions")
-                .emit();
-            #[cfg(not(feature = "diagnostics"))]
-            panic!("Error: Encodable can't be derived for unions");
-
-            return TokenStream::new();
-        }
-    };
+    encoding::derive_decodable(parse_macro_input!(input))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
 
-    output.into()
+#[proc_macro_derive(FederationModules, attributes(module))]
+pub fn derive_federation_modules(input: TokenStream) -> TokenStream {
+    dispatch::derive_federation_modules(parse_macro_input!(input))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
 }
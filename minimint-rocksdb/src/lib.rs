@@ -0,0 +1,257 @@
+use minimint_api::db::batch::{BatchItem, DbBatch};
+use minimint_api::db::{DatabaseError, PrefixIter, RawDatabase};
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{error, trace};
+
+/// Number of entries read from disk at once when iterating over a key prefix
+const PREFIX_PAGE_SIZE: usize = 128;
+
+/// [`RawDatabase`] implementation backed by RocksDB
+///
+/// All writes are serialized so that the read-modify-write cycles of single operations and
+/// batches can't interleave. Batches are applied atomically by collecting their effects in a
+/// single RocksDB `WriteBatch`.
+#[derive(Clone)]
+pub struct RocksDb {
+    db: Arc<DB>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl RocksDb {
+    /// Opens the database at `path`, creating it if it doesn't exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<RocksDb, rocksdb::Error> {
+        let db = DB::open_default(path)?;
+        Ok(RocksDb {
+            db: Arc::new(db),
+            write_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Reads `key` taking the not yet written changes of a batch into account
+    fn get_pending(
+        &self,
+        pending: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        match pending.get(key) {
+            Some(maybe_value) => Ok(maybe_value.clone()),
+            None => self.raw_get_value(key),
+        }
+    }
+}
+
+impl RawDatabase for RocksDb {
+    fn raw_insert_entry(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let old_value = self.db.get(key).map_err(db_error)?;
+        self.db.put(key, value).map_err(db_error)?;
+        Ok(old_value)
+    }
+
+    fn raw_get_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.db.get(key).map_err(db_error)
+    }
+
+    fn raw_remove_entry(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let _write_guard = self.write_lock.lock().unwrap();
+        let old_value = self.db.get(key).map_err(db_error)?;
+        if old_value.is_some() {
+            self.db.delete(key).map_err(db_error)?;
+        }
+        Ok(old_value)
+    }
+
+    fn raw_find_by_prefix(&self, key_prefix: &[u8]) -> PrefixIter {
+        Box::new(RocksDbPrefixIter {
+            db: self.db.clone(),
+            prefix: key_prefix.to_vec(),
+            next_key: Some(key_prefix.to_vec()),
+            page: VecDeque::new(),
+        })
+    }
+
+    fn raw_apply_batch(&self, batch: DbBatch) -> Result<(), DatabaseError> {
+        let batch: Vec<_> = batch.into();
+        let _write_guard = self.write_lock.lock().unwrap();
+
+        // Final state of every key touched by the batch, `None` meaning it gets deleted
+        let mut pending = BTreeMap::<Vec<u8>, Option<Vec<u8>>>::new();
+
+        for change in batch.iter() {
+            match change {
+                BatchItem::InsertNewElement(element) => {
+                    let key = element.key.to_bytes();
+                    if self.get_pending(&pending, &key)?.is_some() {
+                        error!("Database replaced element! This should not happen!");
+                        trace!("Problematic key: {:?}", element.key);
+                    }
+                    pending.insert(key, Some(element.value.to_bytes()));
+                }
+                BatchItem::InsertElement(element) => {
+                    pending.insert(element.key.to_bytes(), Some(element.value.to_bytes()));
+                }
+                BatchItem::DeleteElement(key) => {
+                    let key_bytes = key.to_bytes();
+                    if self.get_pending(&pending, &key_bytes)?.is_none() {
+                        error!("Database deleted absent element! This should not happen!");
+                        trace!("Problematic key: {:?}", key);
+                    }
+                    pending.insert(key_bytes, None);
+                }
+                BatchItem::MaybeDeleteElement(key) => {
+                    pending.insert(key.to_bytes(), None);
+                }
+                BatchItem::MaybeUpdate(update) => {
+                    let key = update.key.to_bytes();
+                    let maybe_val = self.get_pending(&pending, &key)?;
+                    let maybe_val_ref = maybe_val.as_ref().map(|v| v.as_ref());
+                    let maybe_new_val = (update.updater)(&key, maybe_val_ref)?;
+
+                    if let Some(new_val) = maybe_new_val {
+                        pending.insert(key, Some(new_val));
+                    }
+                }
+            }
+        }
+
+        let mut write_batch = WriteBatch::default();
+        for (key, maybe_value) in pending {
+            match maybe_value {
+                Some(value) => write_batch.put(key, value),
+                None => write_batch.delete(key),
+            }
+        }
+
+        self.db.write(write_batch).map_err(db_error)
+    }
+
+    fn raw_snapshot(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatabaseError> {
+        // Batches are written atomically, so RocksDB's snapshots are consistent without locking
+        let snapshot = self.db.snapshot();
+        Ok(snapshot
+            .iterator(IteratorMode::Start)
+            .map(|(key, value)| (key.into_vec(), value.into_vec()))
+            .collect())
+    }
+}
+
+/// Iterates over all entries with a certain key prefix, reading them from disk page by page.
+///
+/// Since RocksDB iterators borrow the database the iterator re-seeks to the first key not yet
+/// returned for every page instead of keeping a RocksDB iterator around.
+struct RocksDbPrefixIter {
+    db: Arc<DB>,
+    prefix: Vec<u8>,
+    /// Key to seek to when loading the next page, `None` if there are no more pages
+    next_key: Option<Vec<u8>>,
+    page: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Iterator for RocksDbPrefixIter {
+    type Item = Result<(Vec<u8>, Vec<u8>), DatabaseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(entry) = self.page.pop_front() {
+            return Some(Ok(entry));
+        }
+
+        let seek_key = self.next_key.take()?;
+        let prefix = &self.prefix;
+        let mut page = self
+            .db
+            .iterator(IteratorMode::From(&seek_key, Direction::Forward))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .take(PREFIX_PAGE_SIZE + 1)
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect::<Vec<_>>();
+
+        // The additional entry only tells us where to continue
+        if page.len() > PREFIX_PAGE_SIZE {
+            let (next_key, _) = page.pop().expect("page can't be empty");
+            self.next_key = Some(next_key);
+        }
+
+        self.page = page.into();
+        self.page.pop_front().map(Ok)
+    }
+}
+
+fn db_error(e: rocksdb::Error) -> DatabaseError {
+    DatabaseError::DbError(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RocksDb;
+    use minimint_api::db::batch::DbBatch;
+    use minimint_api::db::tests::{TestKey, TestVal};
+    use minimint_api::db::{Database, RawDatabase};
+    use std::sync::Arc;
+
+    fn open_temp_db(name: &str) -> (tempdir::TempDir, Arc<dyn RawDatabase>) {
+        let path = tempdir::TempDir::new(name).unwrap();
+        let db = RocksDb::open(path.path()).unwrap();
+        (path, Arc::new(db))
+    }
+
+    #[test]
+    fn test_basic_rw() {
+        let (_path, db) = open_temp_db("fcb-rocksdb-test");
+        minimint_api::db::tests::test_db_impl(db);
+    }
+
+    #[test]
+    fn test_find_by_prefix_pages() {
+        let (_path, db) = open_temp_db("fcb-rocksdb-prefix-test");
+        let entries = (super::PREFIX_PAGE_SIZE * 2 + 3) as u64;
+
+        for idx in 0..entries {
+            db.insert_entry(&TestKey(idx), &TestVal(idx)).unwrap();
+        }
+        // Keys sorting right behind the prefix must not be returned
+        db.raw_insert_entry(&[0x43], vec![]).unwrap();
+
+        let found = db
+            .find_by_prefix::<_, TestKey, TestVal>(&TestKeyPrefix)
+            .map(|res| res.unwrap())
+            .map(|(key, value)| {
+                assert_eq!(key.0, value.0);
+                key.0
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, (0..entries).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let (_path, db) = open_temp_db("fcb-rocksdb-batch-test");
+        db.insert_entry(&TestKey(1), &TestVal(1)).unwrap();
+
+        let mut batch = DbBatch::new();
+        batch.autocommit(|tx| {
+            tx.append_delete(TestKey(1));
+            tx.append_insert_new(TestKey(2), TestVal(2));
+            tx.append_maybe_update(TestKey(2), |_, value: Option<TestVal>| {
+                value.map(|value| TestVal(value.0 + 40))
+            });
+        });
+        db.apply_batch(batch).unwrap();
+
+        assert_eq!(db.get_value::<_, TestVal>(&TestKey(1)).unwrap(), None);
+        assert_eq!(db.get_value(&TestKey(2)).unwrap(), Some(TestVal(42)));
+    }
+
+    #[derive(Debug, minimint_api::encoding::Encodable)]
+    struct TestKeyPrefix;
+
+    impl minimint_api::db::DatabaseKeyPrefixConst for TestKeyPrefix {
+        const DB_PREFIX: u8 = 0x42;
+    }
+}
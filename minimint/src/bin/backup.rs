@@ -0,0 +1,136 @@
+use minimint::config::{load_from_file, ServerConfig};
+use minimint_api::db::snapshot::DbSnapshot;
+use minimint_api::encoding::{Decodable, Encodable};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+#[derive(StructOpt)]
+#[structopt(about = "Backup and restore the database of a guardian")]
+enum Opts {
+    /// Writes a consistent snapshot of the database to a file. If the server's admin API is
+    /// enabled the snapshot is fetched from the running server, otherwise the server has to be
+    /// stopped.
+    Export {
+        cfg_path: PathBuf,
+        out_path: PathBuf,
+        /// Read the database directly even if the admin API is enabled, only possible while the
+        /// server is stopped
+        #[structopt(long)]
+        offline: bool,
+    },
+    /// Restores a snapshot into the (empty) database, the server has to be stopped. Snapshots
+    /// have to be taken by the same version of the software since entries are validated against
+    /// the current key and value types before importing them.
+    Import {
+        cfg_path: PathBuf,
+        snapshot_path: PathBuf,
+    },
+    /// Checks the snapshot's checksum and validates all its entries
+    Verify { snapshot_path: PathBuf },
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    match Opts::from_args() {
+        Opts::Export {
+            cfg_path,
+            out_path,
+            offline,
+        } => {
+            let cfg: ServerConfig = load_from_file(&cfg_path);
+            let bytes = match cfg.admin_port.filter(|_| !offline) {
+                Some(admin_port) => fetch_snapshot(admin_port).await,
+                None => {
+                    // Opening the database fails while the server holds it, so nothing writes to
+                    // it while the snapshot is taken
+                    let db = open_stopped_db(&cfg);
+                    let snapshot = DbSnapshot::take(db.as_ref()).expect("Couldn't read database");
+                    let mut bytes = Vec::new();
+                    snapshot
+                        .consensus_encode(&mut bytes)
+                        .expect("writing to vec can't fail");
+                    bytes
+                }
+            };
+
+            // Make sure we don't write a corrupted backup
+            let snapshot = decode_snapshot(&bytes);
+            std::fs::write(&out_path, &bytes).expect("Couldn't write snapshot");
+            info!(
+                "Exported {} entries to {}",
+                snapshot.entries().len(),
+                out_path.display()
+            );
+        }
+        Opts::Import {
+            cfg_path,
+            snapshot_path,
+        } => {
+            let cfg: ServerConfig = load_from_file(&cfg_path);
+            let snapshot = read_snapshot(&snapshot_path);
+            let db = open_stopped_db(&cfg);
+            snapshot
+                .restore(db.as_ref(), &minimint::db::prefix_registry())
+                .expect("Couldn't restore snapshot");
+            info!(
+                "Imported {} entries into {}",
+                snapshot.entries().len(),
+                cfg.db_path.display()
+            );
+        }
+        Opts::Verify { snapshot_path } => {
+            let snapshot = read_snapshot(&snapshot_path);
+            snapshot
+                .validate(&minimint::db::prefix_registry())
+                .expect("Invalid snapshot");
+            info!(
+                "Snapshot with {} entries is valid",
+                snapshot.entries().len()
+            );
+        }
+    }
+}
+
+/// Opens the server's database, which fails while the server is running
+fn open_stopped_db(cfg: &ServerConfig) -> std::sync::Arc<dyn minimint_api::db::RawDatabase> {
+    match cfg.db_backend.open(&cfg.db_path, "mint") {
+        Ok(db) => db,
+        Err(e) => {
+            error!(
+                "Couldn't open database {}, the server has to be stopped: {}",
+                cfg.db_path.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn fetch_snapshot(admin_port: u16) -> Vec<u8> {
+    let url = format!("http://127.0.0.1:{}/snapshot", admin_port);
+    let response = reqwest::get(&url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .expect("Couldn't fetch snapshot from server");
+    response
+        .bytes()
+        .await
+        .expect("Couldn't fetch snapshot from server")
+        .to_vec()
+}
+
+fn read_snapshot(path: &Path) -> DbSnapshot {
+    decode_snapshot(&std::fs::read(path).expect("Couldn't read snapshot"))
+}
+
+fn decode_snapshot(bytes: &[u8]) -> DbSnapshot {
+    DbSnapshot::consensus_decode(std::io::Cursor::new(bytes)).expect("Corrupted snapshot")
+}
@@ -12,6 +12,9 @@ struct Options {
     hbbft_base_port: u16,
     api_base_port: u16,
     amount_tiers: Vec<Amount>,
+    /// Enables the admin API of every peer on this port plus its id
+    #[structopt(long)]
+    admin_base_port: Option<u16>,
 }
 
 fn main() {
@@ -21,6 +24,7 @@ fn main() {
         hbbft_base_port,
         api_base_port,
         amount_tiers,
+        admin_base_port,
     } = StructOpt::from_args();
     let mut rng = OsRng::new().unwrap();
 
@@ -33,6 +37,7 @@ fn main() {
     let params = ServerConfigParams {
         hbbft_base_port,
         api_base_port,
+        admin_base_port,
         amount_tiers,
     };
 
@@ -0,0 +1,104 @@
+use minimint::config::{load_from_file, ClientConfig, JoiningPeer, ServerConfig};
+use minimint_api::PeerId;
+use rand::rngs::OsRng;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(about = "Reshare the threshold keys of a federation among a new set of peers")]
+struct Options {
+    /// Directory containing `client.json` and the `server-<id>.json` configs of at least as many
+    /// peers as needed to sign, all of them deal sub-shares
+    cfg_path: PathBuf,
+    /// Directory the new server configs and `client.json` are written to
+    out_path: PathBuf,
+    /// Ids of the peers forming the federation afterwards, their configs have to be supplied unless
+    /// they join
+    peers: Vec<u16>,
+    /// Network identity of a joining peer as `<id>,<hbbft port>,<api port>,<api url>` optionally
+    /// followed by `,<admin port>`, can be given multiple times
+    #[structopt(long = "join", parse(try_from_str = parse_joining_peer))]
+    joining: Vec<(PeerId, JoiningPeer)>,
+}
+
+fn parse_joining_peer(s: &str) -> Result<(PeerId, JoiningPeer), String> {
+    let fields = s.split(',').collect::<Vec<_>>();
+    let (id, hbbft_port, api_port, api_url, admin_port) = match fields[..] {
+        [id, hbbft_port, api_port, api_url] => (id, hbbft_port, api_port, api_url, None),
+        [id, hbbft_port, api_port, api_url, admin_port] => {
+            (id, hbbft_port, api_port, api_url, Some(admin_port))
+        }
+        _ => return Err(format!("Expected 4 or 5 comma separated fields, got {}", s)),
+    };
+    let parse_u16 = |s: &str| s.parse::<u16>().map_err(|e| format!("{}: {}", s, e));
+
+    let joining = JoiningPeer {
+        hbbft_port: parse_u16(hbbft_port)?,
+        api_port: parse_u16(api_port)?,
+        admin_port: admin_port.map(parse_u16).transpose()?,
+        api_url: api_url.to_owned(),
+    };
+    Ok((PeerId::from(parse_u16(id)?), joining))
+}
+
+fn main() {
+    let Options {
+        cfg_path,
+        out_path,
+        peers,
+        joining,
+    } = StructOpt::from_args();
+    let mut rng = OsRng::new().unwrap();
+
+    let client_cfg: ClientConfig = load_from_file(&cfg_path.join("client.json"));
+    let server_cfgs = std::fs::read_dir(&cfg_path)
+        .expect("Could not read cfg directory")
+        .filter_map(|entry| {
+            let path = entry.expect("Could not read cfg directory").path();
+            let name = path.file_name()?.to_str()?;
+            let id = name.strip_prefix("server-")?.strip_suffix(".json")?;
+            let peer = PeerId::from(id.parse::<u16>().ok()?);
+            let cfg: ServerConfig = load_from_file(&path);
+            assert_eq!(
+                cfg.identity,
+                peer,
+                "{} belongs to another peer",
+                path.display()
+            );
+            Some((peer, cfg))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let peers = peers.into_iter().map(PeerId::from).collect::<Vec<_>>();
+    let joining = joining.into_iter().collect::<BTreeMap<_, _>>();
+    let (server_cfgs, client_cfg) = match ServerConfig::trusted_dealer_reshare(
+        &server_cfgs,
+        &client_cfg,
+        &peers,
+        &joining,
+        &mut rng,
+    ) {
+        Ok(cfgs) => cfgs,
+        Err(e) => {
+            eprintln!("Could not reshare keys: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Reshared keys such that up to {} of {} peers may fail/be evil",
+        hbbft::util::max_faulty(server_cfgs.len()),
+        server_cfgs.len()
+    );
+
+    std::fs::create_dir_all(&out_path).expect("Could not create output directory");
+    for (id, cfg) in server_cfgs {
+        let path = out_path.join(format!("server-{}.json", id));
+        let file = std::fs::File::create(path).expect("Could not create cfg file");
+        serde_json::to_writer_pretty(file, &cfg).unwrap();
+    }
+
+    let client_cfg_file =
+        std::fs::File::create(out_path.join("client.json")).expect("Could not create cfg file");
+    serde_json::to_writer_pretty(client_cfg_file, &client_cfg).unwrap();
+}
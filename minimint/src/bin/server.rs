@@ -14,5 +14,5 @@ async fn main() {
     let opts: ServerOpts = StructOpt::from_args();
     let cfg: ServerConfig = load_from_file(&opts.cfg_path);
 
-    run_minimint(cfg).await;
+    run_minimint(cfg, opts.resume).await;
 }
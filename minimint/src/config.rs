@@ -1,20 +1,34 @@
-use bitcoin::secp256k1::rand::{CryptoRng, RngCore};
+use bitcoin::secp256k1::rand::{CryptoRng, Rng, RngCore};
+use hbbft::crypto::poly::Poly;
 use hbbft::crypto::serde_impl::SerdeSecret;
 use minimint_api::config::GenerateConfig;
+use minimint_api::db::sled_impl::SledDb;
+use minimint_api::db::{DatabaseError, RawDatabase};
+use minimint_api::secret::Secret;
 use minimint_api::PeerId;
-use minimint_ln::config::{LightningModuleClientConfig, LightningModuleConfig};
+use minimint_ln::config::{
+    trusted_dealer_reshare_key_set, LightningModuleClientConfig, LightningModuleConfig,
+    ThresholdKeyShare,
+};
 use minimint_mint::config::{MintClientConfig, MintConfig};
 use minimint_wallet::config::{WalletClientConfig, WalletConfig};
-use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde::de::{DeserializeOwned, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 use structopt::StructOpt;
+use thiserror::Error;
 
 #[derive(StructOpt)]
 pub struct ServerOpts {
     pub cfg_path: PathBuf,
+    /// Take part in consensus again after the server halted because the federation was insolvent,
+    /// only use this once the cause was found and fixed
+    #[structopt(long)]
+    pub resume: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,16 +36,23 @@ pub struct ServerConfig {
     pub identity: PeerId,
     pub hbbft_port: u16,
     pub api_port: u16,
+    /// Port of the admin API, which serves database snapshots to the `backup` tool while the
+    /// server runs. It is only bound to localhost and disabled if unset.
+    #[serde(default)]
+    pub admin_port: Option<u16>,
 
     pub peers: BTreeMap<PeerId, Peer>,
     #[serde(with = "serde_binary_human_readable")]
     pub hbbft_sk: hbbft::crypto::serde_impl::SerdeSecret<hbbft::crypto::SecretKey>,
-    #[serde(with = "serde_binary_human_readable")]
-    pub hbbft_sks: hbbft::crypto::serde_impl::SerdeSecret<hbbft::crypto::SecretKeyShare>,
+    #[serde(with = "serde_secret_binary_human_readable")]
+    pub hbbft_sks: Secret<ThresholdKeyShare>,
     #[serde(with = "serde_binary_human_readable")]
     pub hbbft_pk_set: hbbft::crypto::PublicKeySet,
 
     pub db_path: PathBuf,
+    /// Storage backend of the database at `db_path`
+    #[serde(default)]
+    pub db_backend: DatabaseBackend,
 
     pub wallet: WalletConfig,
     pub mint: MintConfig,
@@ -49,16 +70,30 @@ pub struct Peer {
     pub hbbft_pk: hbbft::crypto::PublicKey,
 }
 
+/// Network identity of a peer joining the federation in [`ServerConfig::trusted_dealer_reshare`]
+#[derive(Debug, Clone)]
+pub struct JoiningPeer {
+    pub hbbft_port: u16,
+    pub api_port: u16,
+    pub admin_port: Option<u16>,
+    /// URL clients reach the API of the peer at
+    pub api_url: String,
+}
+
 #[derive(Debug)]
 pub struct ServerConfigParams {
     pub hbbft_base_port: u16,
     pub api_base_port: u16,
+    pub admin_base_port: Option<u16>,
     pub amount_tiers: Vec<minimint_api::Amount>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
-    pub api_endpoints: Vec<String>,
+    /// API URLs of the peers. Configs created before peers could leave the federation list them
+    /// ordered by peer id starting at zero.
+    #[serde(deserialize_with = "deserialize_api_endpoints")]
+    pub api_endpoints: BTreeMap<PeerId, String>,
     pub mint: MintClientConfig,
     pub wallet: WalletClientConfig,
     pub ln: LightningModuleClientConfig,
@@ -88,6 +123,10 @@ impl GenerateConfig for ServerConfig {
     ) -> (BTreeMap<PeerId, Self>, Self::ClientConfig) {
         let netinfo = hbbft::NetworkInfo::generate_map(peers.to_vec(), &mut rng)
             .expect("Could not generate HBBFT netinfo");
+        // The threshold key set is generated separately since `NetworkInfo` doesn't expose the
+        // scalars of its key shares. HBBFT indexes them by the position of a peer among all peers.
+        let hbbft_poly = Poly::random(hbbft::util::max_faulty(netinfo.len()), &mut rng);
+        let hbbft_pk_set = hbbft::crypto::PublicKeySet::from(hbbft_poly.commitment());
 
         let cfg_peers = netinfo
             .iter()
@@ -121,17 +160,20 @@ impl GenerateConfig for ServerConfig {
 
         let server_config = netinfo
             .iter()
-            .map(|(&id, netinf)| {
+            .enumerate()
+            .map(|(idx, (&id, netinf))| {
                 let id_u16: u16 = id.into();
                 let config = ServerConfig {
                     identity: id,
                     hbbft_port: params.hbbft_base_port + id_u16,
                     api_port: params.api_base_port + id_u16,
+                    admin_port: params.admin_base_port.map(|port| port + id_u16),
                     peers: cfg_peers.clone(),
                     hbbft_sk: SerdeSecret(netinf.secret_key().clone()),
-                    hbbft_sks: SerdeSecret(netinf.secret_key_share().unwrap().clone()),
-                    hbbft_pk_set: netinf.public_key_set().clone(),
+                    hbbft_sks: Secret::new(ThresholdKeyShare::from_poly(&hbbft_poly, idx)),
+                    hbbft_pk_set: hbbft_pk_set.clone(),
                     db_path: format!("cfg/mint-{}.db", id).into(),
+                    db_backend: DatabaseBackend::default(),
                     wallet: wallet_server_cfg[&id].clone(),
                     mint: mint_server_cfg[&id].clone(),
                     ln: ln_server_cfg[&id].clone(),
@@ -145,10 +187,11 @@ impl GenerateConfig for ServerConfig {
             api_endpoints: peers
                 .iter()
                 .map(|&peer| {
-                    format!(
+                    let url = format!(
                         "http://127.0.0.1:{}",
                         params.api_base_port + u16::from(peer)
-                    )
+                    );
+                    (peer, url)
                 })
                 .collect(),
             mint: mint_client_cfg,
@@ -169,13 +212,297 @@ impl ServerConfig {
         self.api_port
     }
 
+    /// Number of peers that connect to us, which are the ones with lower ids
     pub fn get_incoming_count(&self) -> u16 {
-        self.identity.into()
+        self.peers
+            .keys()
+            .filter(|&&peer| peer < self.identity)
+            .count() as u16
     }
 
     pub fn max_faulty(&self) -> usize {
         hbbft::util::max_faulty(self.peers.len())
     }
+
+    /// Moves the federation to `new_peers` by resharing the threshold keys of HBBFT, the mint and
+    /// the lightning module. Their public keys don't change, so coins issued and preimages
+    /// encrypted before stay valid. Like for new federations all but
+    /// [`hbbft::util::max_faulty`] of the new peers are needed to reach consensus, sign and
+    /// decrypt. Only the peers in `cfgs` deal sub-shares, which allows excluding compromised or
+    /// lost peers as long as enough remain. Like [`GenerateConfig::trusted_dealer_gen`] the
+    /// protocol is run for all peers locally.
+    ///
+    /// New peers without a config join the federation and need an entry in `joining`, their HBBFT
+    /// and peg-in keys are generated. The wallet's peg-in descriptor can't change without moving
+    /// the federation's funds, so only the peers whose keys are part of it can sign peg-outs, see
+    /// [`WalletConfig::trusted_dealer_add_peers`]. At least as many of them as the descriptor
+    /// requires have to remain.
+    pub fn trusted_dealer_reshare(
+        cfgs: &BTreeMap<PeerId, ServerConfig>,
+        client_cfg: &ClientConfig,
+        new_peers: &[PeerId],
+        joining: &BTreeMap<PeerId, JoiningPeer>,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Result<(BTreeMap<PeerId, ServerConfig>, ClientConfig), ReshareError> {
+        let template = cfgs.values().next().ok_or(ReshareError::NoConfigs)?;
+        let new_peers = new_peers.iter().copied().collect::<BTreeSet<_>>();
+        if new_peers.is_empty() {
+            return Err(ReshareError::NoPeers);
+        }
+        if let Some(&peer) = cfgs.keys().find(|peer| !template.peers.contains_key(*peer)) {
+            return Err(ReshareError::UnknownPeer(peer));
+        }
+        if let Some(&peer) = joining
+            .keys()
+            .find(|peer| template.peers.contains_key(*peer))
+        {
+            return Err(ReshareError::AlreadyJoined(peer));
+        }
+        if let Some(&peer) = new_peers
+            .iter()
+            .find(|peer| !cfgs.contains_key(*peer) && !joining.contains_key(*peer))
+        {
+            return Err(ReshareError::MissingConfig(peer));
+        }
+
+        let max_evil = template.max_faulty();
+        let threshold = template.peers.len() - max_evil;
+        if cfgs.len() < threshold {
+            return Err(ReshareError::NotEnoughConfigs {
+                configs: cfgs.len(),
+                threshold,
+            });
+        }
+        let peg_in_threshold = template
+            .wallet
+            .peg_in_threshold()
+            .ok_or(ReshareError::UnsupportedDescriptor)?;
+        let peg_in_signers = template.wallet.peg_in_signers();
+        let remaining_signers = new_peers
+            .iter()
+            .filter(|peer| peg_in_signers.contains(*peer))
+            .count();
+        if remaining_signers < peg_in_threshold {
+            return Err(ReshareError::NotEnoughPegInKeys {
+                peers: remaining_signers,
+                threshold: peg_in_threshold,
+            });
+        }
+        let new_max_evil = hbbft::util::max_faulty(new_peers.len());
+        let new_threshold = new_peers.len() - new_max_evil;
+        if new_threshold < threshold && !template.mint.retired_generations.is_empty() {
+            return Err(ReshareError::ThresholdDecrease);
+        }
+
+        let new_peers = new_peers.into_iter().collect::<Vec<_>>();
+        let (hbbft_pk_set, hbbft_sks) =
+            Self::trusted_dealer_reshare_hbbft(cfgs, &new_peers, &mut rng);
+        let mint_cfgs = cfgs
+            .iter()
+            .map(|(&peer, cfg)| (peer, cfg.mint.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let mut new_client_cfg = client_cfg.clone();
+        let mut mint_cfgs = MintConfig::trusted_dealer_reshare(
+            &mint_cfgs,
+            &mut new_client_cfg.mint,
+            max_evil,
+            &new_peers,
+            new_max_evil,
+        );
+        let ln_cfgs = cfgs
+            .iter()
+            .map(|(&peer, cfg)| (peer, cfg.ln.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let joining_peers = new_peers
+            .iter()
+            .filter_map(|peer| Some((*peer, joining.get(peer)?)))
+            .collect::<BTreeMap<_, _>>();
+        let joining_ids = joining_peers.keys().copied().collect::<Vec<_>>();
+        let wallet_cfgs = cfgs
+            .iter()
+            .filter(|(peer, _)| new_peers.contains(*peer))
+            .map(|(&peer, cfg)| (peer, cfg.wallet.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let mut wallet_cfgs =
+            WalletConfig::trusted_dealer_add_peers(&wallet_cfgs, &joining_ids, &mut rng);
+        let hbbft_keys = joining_ids
+            .iter()
+            .map(|&peer| (peer, rng.gen::<hbbft::crypto::SecretKey>()))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut ln_cfgs =
+            LightningModuleConfig::trusted_dealer_reshare(&ln_cfgs, &new_peers, new_max_evil, rng);
+
+        let peers = template
+            .peers
+            .iter()
+            .filter(|(peer, _)| new_peers.contains(*peer))
+            .map(|(&peer, cfg)| (peer, cfg.clone()))
+            .chain(joining_peers.iter().map(|(&peer, joining)| {
+                let cfg = Peer {
+                    hbbft_port: joining.hbbft_port,
+                    api_port: joining.api_port,
+                    hbbft_pk: hbbft_keys[&peer].public_key(),
+                };
+                (peer, cfg)
+            }))
+            .collect::<BTreeMap<_, _>>();
+        let server_cfgs = hbbft_sks
+            .into_iter()
+            .map(|(peer, sks)| {
+                let base_cfg = match joining_peers.get(&peer) {
+                    Some(joining) => ServerConfig {
+                        identity: peer,
+                        hbbft_port: joining.hbbft_port,
+                        api_port: joining.api_port,
+                        admin_port: joining.admin_port,
+                        hbbft_sk: SerdeSecret(hbbft_keys[&peer].clone()),
+                        db_path: format!("cfg/mint-{}.db", peer).into(),
+                        db_backend: DatabaseBackend::default(),
+                        ..template.clone()
+                    },
+                    None => cfgs[&peer].clone(),
+                };
+                let cfg = ServerConfig {
+                    peers: peers.clone(),
+                    hbbft_sks: Secret::new(sks),
+                    hbbft_pk_set: hbbft_pk_set.clone(),
+                    wallet: wallet_cfgs
+                        .remove(&peer)
+                        .expect("Wallet config for every new peer"),
+                    mint: mint_cfgs
+                        .remove(&peer)
+                        .expect("Mint config for every new peer"),
+                    ln: ln_cfgs.remove(&peer).expect("LN config for every new peer"),
+                    ..base_cfg
+                };
+                (peer, cfg)
+            })
+            .collect();
+        new_client_cfg
+            .api_endpoints
+            .retain(|peer, _| new_peers.contains(peer));
+        new_client_cfg.api_endpoints.extend(
+            joining_peers
+                .iter()
+                .map(|(&peer, joining)| (peer, joining.api_url.clone())),
+        );
+
+        Ok((server_cfgs, new_client_cfg))
+    }
+
+    /// Moves the HBBFT threshold keys of the peers in `cfgs` to `new_peers` using
+    /// [`trusted_dealer_reshare_key_set`] without changing the public key. HBBFT indexes key shares
+    /// by the position of a peer among all peers and derives the threshold from the number of
+    /// peers. Returns the new public key set and the key share of every new peer.
+    ///
+    /// # Panics
+    /// * If `cfgs` contains fewer configs than needed to reconstruct the key
+    fn trusted_dealer_reshare_hbbft(
+        cfgs: &BTreeMap<PeerId, ServerConfig>,
+        new_peers: &[PeerId],
+        rng: impl RngCore + CryptoRng,
+    ) -> (
+        hbbft::crypto::PublicKeySet,
+        BTreeMap<PeerId, ThresholdKeyShare>,
+    ) {
+        let template = cfgs.values().next().expect("No configs supplied");
+        let shares = cfgs
+            .iter()
+            .map(|(peer, cfg)| {
+                let idx = template
+                    .peers
+                    .keys()
+                    .position(|p| p == peer)
+                    .expect("Unknown peer");
+                (idx, *cfg.hbbft_sks.expose())
+            })
+            .collect();
+
+        let new_peers = new_peers.iter().copied().collect::<BTreeSet<_>>();
+        let new_keys = (0..new_peers.len()).collect::<Vec<_>>();
+        let (pk_set, sks) = trusted_dealer_reshare_key_set(
+            &template.hbbft_pk_set,
+            &shares,
+            &new_keys,
+            hbbft::util::max_faulty(new_peers.len()),
+            rng,
+        );
+
+        let sks = new_peers
+            .into_iter()
+            .zip(sks.into_iter())
+            .map(|(peer, (_, sk))| (peer, sk))
+            .collect();
+        (pk_set, sks)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReshareError {
+    #[error("No configs supplied")]
+    NoConfigs,
+    #[error("The federation needs at least one peer")]
+    NoPeers,
+    #[error("Peer {0} is not part of the federation")]
+    UnknownPeer(PeerId),
+    #[error("Neither the config nor the network identity of peer {0} was supplied")]
+    MissingConfig(PeerId),
+    #[error("Peer {0} is already part of the federation and can't join")]
+    AlreadyJoined(PeerId),
+    #[error("Only {configs} configs supplied, {threshold} are needed to reshare the keys")]
+    NotEnoughConfigs { configs: usize, threshold: usize },
+    #[error("Only {peers} peers holding peg-in keys would remain, {threshold} are needed to sign peg-outs")]
+    NotEnoughPegInKeys { peers: usize, threshold: usize },
+    #[error("The peg-in descriptor is not a sorted multisig")]
+    UnsupportedDescriptor,
+    #[error("Can't decrease the threshold while the mint has retired key generations")]
+    ThresholdDecrease,
+}
+
+/// Storage backends the server and client databases can be kept in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    Sled,
+    RocksDb,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown database backend {0}, expected sled or rocksdb")]
+pub struct UnknownDatabaseBackend(String);
+
+impl DatabaseBackend {
+    /// Opens the database at `path`. Backends supporting multiple trees per database keep the
+    /// data in the one called `tree`.
+    pub fn open(self, path: &Path, tree: &str) -> Result<Arc<dyn RawDatabase>, DatabaseError> {
+        let database: Arc<dyn RawDatabase> = match self {
+            DatabaseBackend::Sled => Arc::new(SledDb::new(sled::open(path)?.open_tree(tree)?)),
+            DatabaseBackend::RocksDb => Arc::new(
+                minimint_rocksdb::RocksDb::open(path)
+                    .map_err(|e| DatabaseError::DbError(Box::new(e)))?,
+            ),
+        };
+        Ok(database)
+    }
+}
+
+impl Default for DatabaseBackend {
+    fn default() -> Self {
+        DatabaseBackend::Sled
+    }
+}
+
+impl FromStr for DatabaseBackend {
+    type Err = UnknownDatabaseBackend;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sled" => Ok(DatabaseBackend::Sled),
+            "rocksdb" => Ok(DatabaseBackend::RocksDb),
+            _ => Err(UnknownDatabaseBackend(s.to_owned())),
+        }
+    }
 }
 
 pub fn load_from_file<T: DeserializeOwned>(path: &Path) -> T {
@@ -183,6 +510,39 @@ pub fn load_from_file<T: DeserializeOwned>(path: &Path) -> T {
     serde_json::from_reader(file).expect("Could not parse cfg file.")
 }
 
+/// Accepts both a map of peer ids to URLs and the list of URLs ordered by peer id used previously
+fn deserialize_api_endpoints<'d, D: Deserializer<'d>>(
+    d: D,
+) -> Result<BTreeMap<PeerId, String>, D::Error> {
+    struct EndpointsVisitor;
+
+    impl<'d> Visitor<'d> for EndpointsVisitor {
+        type Value = BTreeMap<PeerId, String>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a map of peer ids to URLs or a list of URLs")
+        }
+
+        fn visit_seq<A: SeqAccess<'d>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut endpoints = BTreeMap::new();
+            while let Some(url) = seq.next_element::<String>()? {
+                endpoints.insert(PeerId::from(endpoints.len() as u16), url);
+            }
+            Ok(endpoints)
+        }
+
+        fn visit_map<A: MapAccess<'d>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut endpoints = BTreeMap::new();
+            while let Some((peer, url)) = map.next_entry::<PeerId, String>()? {
+                endpoints.insert(peer, url);
+            }
+            Ok(endpoints)
+        }
+    }
+
+    d.deserialize_any(EndpointsVisitor)
+}
+
 mod serde_binary_human_readable {
     use serde::de::DeserializeOwned;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -207,3 +567,294 @@ mod serde_binary_human_readable {
         }
     }
 }
+
+mod serde_secret_binary_human_readable {
+    use minimint_api::secret::{Erasable, Secret};
+    use serde::de::DeserializeOwned;
+    use serde::{Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(x: &Secret<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        T: Erasable + Serialize,
+        S: Serializer,
+    {
+        super::serde_binary_human_readable::serialize(x.expose(), s)
+    }
+
+    pub fn deserialize<'d, T, D>(d: D) -> Result<Secret<T>, D::Error>
+    where
+        T: Erasable + DeserializeOwned,
+        D: Deserializer<'d>,
+    {
+        Ok(Secret::new(
+            super::serde_binary_human_readable::deserialize(d)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        deserialize_api_endpoints, JoiningPeer, ReshareError, ServerConfig, ServerConfigParams,
+    };
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::{Amount, PeerId};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_reshare_hbbft_keys() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let params = ServerConfigParams {
+            hbbft_base_port: 5000,
+            api_base_port: 6000,
+            admin_base_port: None,
+            amount_tiers: vec![Amount::from_sat(1)],
+        };
+        let (mut cfgs, _) =
+            ServerConfig::trusted_dealer_gen(&peers, 1, &params, rand::rngs::OsRng::new().unwrap());
+        let pk_set = cfgs[&PeerId::from(0)].hbbft_pk_set.clone();
+
+        // Peer 3 leaves and peers 5, 7 and 9 join, HBBFT indexes them by position
+        cfgs.remove(&PeerId::from(3));
+        let new_peers = [0u16, 1, 2, 5, 7, 9]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        let (new_pk_set, new_sks) = ServerConfig::trusted_dealer_reshare_hbbft(
+            &cfgs,
+            &new_peers,
+            rand::rngs::OsRng::new().unwrap(),
+        );
+        assert_eq!(new_pk_set.public_key(), pk_set.public_key());
+        assert_eq!(new_pk_set.threshold(), 1);
+        assert_eq!(new_sks.keys().copied().collect::<Vec<_>>(), new_peers);
+
+        let sig_shares = new_sks
+            .values()
+            .enumerate()
+            .skip(4)
+            .map(|(idx, sk)| {
+                let sk = sk.to_secret_key_share();
+                assert_eq!(sk.public_key_share(), new_pk_set.public_key_share(idx));
+                (idx, sk.sign(b"epoch"))
+            })
+            .collect::<Vec<_>>();
+        let sig = new_pk_set
+            .combine_signatures(sig_shares.iter().map(|(idx, share)| (*idx, share)))
+            .unwrap();
+        assert!(pk_set.public_key().verify(&sig, b"epoch"));
+    }
+
+    #[test]
+    fn test_reshare_federation() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let params = ServerConfigParams {
+            hbbft_base_port: 5000,
+            api_base_port: 6000,
+            admin_base_port: None,
+            amount_tiers: vec![Amount::from_sat(1)],
+        };
+        let (mut cfgs, client_cfg) =
+            ServerConfig::trusted_dealer_gen(&peers, 1, &params, rand::rngs::OsRng::new().unwrap());
+
+        // Peer 1 leaves, so the remaining peer ids aren't contiguous
+        cfgs.remove(&PeerId::from(1));
+        let new_peers = [0u16, 2, 3]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        let (new_cfgs, new_client_cfg) = ServerConfig::trusted_dealer_reshare(
+            &cfgs,
+            &client_cfg,
+            &new_peers,
+            &BTreeMap::new(),
+            rand::rngs::OsRng::new().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(new_cfgs.keys().copied().collect::<Vec<_>>(), new_peers);
+        assert_eq!(
+            new_client_cfg
+                .api_endpoints
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            new_peers
+        );
+        assert_eq!(new_client_cfg.mint.tbs_pks, client_cfg.mint.tbs_pks);
+        assert_eq!(new_client_cfg.mint.threshold, 3);
+        assert_eq!(
+            new_client_cfg.ln.threshold_pub_key,
+            client_cfg.ln.threshold_pub_key
+        );
+        for (peer, cfg) in &new_cfgs {
+            let old_cfg = &cfgs[peer];
+            assert_eq!(cfg.identity, *peer);
+            assert_eq!(
+                cfg.get_incoming_count(),
+                new_peers.iter().filter(|&p| p < peer).count() as u16
+            );
+            assert_eq!(cfg.peers.keys().copied().collect::<Vec<_>>(), new_peers);
+            assert_eq!(cfg.max_faulty(), 0);
+            assert_eq!(
+                cfg.hbbft_pk_set.public_key(),
+                old_cfg.hbbft_pk_set.public_key()
+            );
+            assert_eq!(cfg.db_path, old_cfg.db_path);
+            assert_eq!(cfg.mint.peer_tbs_pks, new_client_cfg.mint.peer_tbs_pks);
+            assert_eq!(cfg.ln.threshold, 3);
+        }
+
+        // Joining peers need a network identity
+        let joining = [PeerId::from(0), PeerId::from(5)];
+        assert!(matches!(
+            ServerConfig::trusted_dealer_reshare(
+                &cfgs,
+                &client_cfg,
+                &joining,
+                &BTreeMap::new(),
+                rand::rngs::OsRng::new().unwrap()
+            ),
+            Err(ReshareError::MissingConfig(peer)) if peer == PeerId::from(5)
+        ));
+
+        // Peg-outs need the signatures of three peers
+        assert!(matches!(
+            ServerConfig::trusted_dealer_reshare(
+                &cfgs,
+                &client_cfg,
+                &new_peers[..2],
+                &BTreeMap::new(),
+                rand::rngs::OsRng::new().unwrap()
+            ),
+            Err(ReshareError::NotEnoughPegInKeys {
+                peers: 2,
+                threshold: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_grow_federation() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let params = ServerConfigParams {
+            hbbft_base_port: 5000,
+            api_base_port: 6000,
+            admin_base_port: None,
+            amount_tiers: vec![Amount::from_sat(1)],
+        };
+        let (mut cfgs, client_cfg) =
+            ServerConfig::trusted_dealer_gen(&peers, 1, &params, rand::rngs::OsRng::new().unwrap());
+
+        // Peer 1 leaves while peers 4, 5 and 6 join
+        cfgs.remove(&PeerId::from(1));
+        let joining = (4..7)
+            .map(|id| {
+                let joining = JoiningPeer {
+                    hbbft_port: 5000 + id,
+                    api_port: 6000 + id,
+                    admin_port: Some(7000 + id),
+                    api_url: format!("http://mint-{}.example", id),
+                };
+                (PeerId::from(id), joining)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let new_peers = [0u16, 2, 3, 4, 5, 6]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        let (new_cfgs, new_client_cfg) = ServerConfig::trusted_dealer_reshare(
+            &cfgs,
+            &client_cfg,
+            &new_peers,
+            &joining,
+            rand::rngs::OsRng::new().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(new_cfgs.keys().copied().collect::<Vec<_>>(), new_peers);
+        assert_eq!(
+            new_client_cfg.api_endpoints[&PeerId::from(5)],
+            "http://mint-5.example"
+        );
+        assert_eq!(
+            new_client_cfg.api_endpoints[&PeerId::from(2)],
+            client_cfg.api_endpoints[&PeerId::from(2)]
+        );
+        assert_eq!(new_client_cfg.mint.tbs_pks, client_cfg.mint.tbs_pks);
+        assert_eq!(new_client_cfg.mint.threshold, 5);
+        assert_eq!(
+            new_client_cfg.wallet.peg_in_descriptor,
+            client_cfg.wallet.peg_in_descriptor
+        );
+
+        let signers = [0u16, 1, 2, 3].iter().map(|&id| PeerId::from(id)).collect();
+        for (peer, cfg) in &new_cfgs {
+            assert_eq!(cfg.identity, *peer);
+            assert_eq!(cfg.peers.keys().copied().collect::<Vec<_>>(), new_peers);
+            assert_eq!(cfg.max_faulty(), 1);
+            assert_eq!(
+                cfg.hbbft_pk_set.public_key(),
+                cfgs[&PeerId::from(0)].hbbft_pk_set.public_key()
+            );
+            assert_eq!(cfg.peers[peer].hbbft_pk, cfg.hbbft_sk.0.public_key());
+            assert_eq!(cfg.wallet.peg_in_signers(), signers);
+            assert_eq!(cfg.mint.peer_tbs_pks, new_client_cfg.mint.peer_tbs_pks);
+            assert_eq!(cfg.ln.threshold, 5);
+
+            match joining.get(peer) {
+                Some(joining) => {
+                    assert_eq!(cfg.hbbft_port, joining.hbbft_port);
+                    assert_eq!(cfg.api_port, joining.api_port);
+                    assert_eq!(cfg.admin_port, joining.admin_port);
+                    assert_eq!(cfg.peers[peer].api_port, joining.api_port);
+                }
+                None => assert_eq!(
+                    cfg.hbbft_sk.0.public_key(),
+                    cfgs[peer].hbbft_sk.0.public_key()
+                ),
+            }
+        }
+
+        // Peers can't join twice
+        let mut rejoining = joining.clone();
+        rejoining.insert(PeerId::from(2), joining[&PeerId::from(4)].clone());
+        assert!(matches!(
+            ServerConfig::trusted_dealer_reshare(
+                &cfgs,
+                &client_cfg,
+                &new_peers,
+                &rejoining,
+                rand::rngs::OsRng::new().unwrap()
+            ),
+            Err(ReshareError::AlreadyJoined(peer)) if peer == PeerId::from(2)
+        ));
+
+        // Joining peers can't sign peg-outs, so three of the original peers have to remain
+        let new_peers = [0u16, 2, 4, 5, 6]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            ServerConfig::trusted_dealer_reshare(
+                &cfgs,
+                &client_cfg,
+                &new_peers,
+                &joining,
+                rand::rngs::OsRng::new().unwrap()
+            ),
+            Err(ReshareError::NotEnoughPegInKeys {
+                peers: 2,
+                threshold: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_legacy_api_endpoints() {
+        let endpoints =
+            deserialize_api_endpoints(serde_json::json!(["http://a", "http://b"])).unwrap();
+        assert_eq!(endpoints[&PeerId::from(0)], "http://a");
+        assert_eq!(endpoints[&PeerId::from(1)], "http://b");
+    }
+}
@@ -0,0 +1,194 @@
+//! Consensus encoding of HBBFT's protocol messages
+//!
+//! HBBFT's message types can't be constructed outside of the `hbbft` crate, only deserialized
+//! using serde. Messages are thus converted to and from a mirror of HBBFT's message types with the
+//! same serde representation, and the consensus encoding of the mirror is what is sent to peers.
+//! Group elements of `threshold_crypto` are encoded in their compressed form.
+
+use super::HoneyBadgerMessage;
+use minimint_api::encoding::{Decodable, DecodeError, Encodable};
+use minimint_api::PeerId;
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Size of a compressed G1 element, used for decryption shares
+const G1_SIZE: usize = 48;
+/// Size of a compressed G2 element, used for signature shares
+const G2_SIZE: usize = 96;
+
+/// Mirror of `hbbft::broadcast::merkle::Digest`
+type Digest = [u8; 32];
+
+/// Mirror of `hbbft::honey_badger::Message`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct Message {
+    epoch: u64,
+    content: MessageContent,
+}
+
+/// Mirror of `hbbft::honey_badger::MessageContent`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+enum MessageContent {
+    Subset(SubsetMessage),
+    DecryptionShare {
+        proposer_id: PeerId,
+        share: DecryptionShare,
+    },
+}
+
+/// Mirror of `hbbft::subset::Message`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct SubsetMessage {
+    proposer_id: PeerId,
+    content: SubsetMessageContent,
+}
+
+/// Mirror of `hbbft::subset::MessageContent`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+enum SubsetMessageContent {
+    Broadcast(BroadcastMessage),
+    BinaryAgreement(AgreementMessage),
+}
+
+/// Mirror of `hbbft::broadcast::Message`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+enum BroadcastMessage {
+    Value(Proof),
+    Echo(Proof),
+    Ready(Digest),
+}
+
+/// Mirror of `hbbft::broadcast::merkle::Proof<Vec<u8>>`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct Proof {
+    value: Vec<u8>,
+    #[encoding(with = "as_u64")]
+    index: usize,
+    digests: Vec<Digest>,
+    root_hash: Digest,
+}
+
+/// Mirror of `hbbft::binary_agreement::Message`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct AgreementMessage {
+    epoch: u64,
+    content: AgreementMessageContent,
+}
+
+/// Mirror of `hbbft::binary_agreement::MessageContent`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+enum AgreementMessageContent {
+    SbvBroadcast(SbvBroadcastMessage),
+    Conf(BoolSet),
+    Term(bool),
+    Coin(Box<SignatureShare>),
+}
+
+/// Mirror of `hbbft::binary_agreement::sbv_broadcast::Message`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+enum SbvBroadcastMessage {
+    BVal(bool),
+    Aux(bool),
+}
+
+/// Mirror of `hbbft::binary_agreement::BoolSet`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct BoolSet(u8);
+
+/// Mirror of `hbbft::threshold_sign::Message`, a `threshold_crypto::SignatureShare`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct SignatureShare(GroupElement<G2_SIZE>);
+
+/// Mirror of `hbbft::threshold_decrypt::Message`, a `threshold_crypto::DecryptionShare`
+#[derive(Serialize, Deserialize, Encodable, Decodable)]
+struct DecryptionShare(GroupElement<G1_SIZE>);
+
+/// Compressed curve point, serialized as a tuple of bytes like `threshold_crypto` does
+struct GroupElement<const SIZE: usize>([u8; SIZE]);
+
+mod as_u64 {
+    use minimint_api::encoding::{Decodable, DecodeError, Encodable};
+    use std::convert::TryFrom;
+
+    pub fn consensus_encode<W: std::io::Write>(
+        value: &usize,
+        writer: W,
+    ) -> Result<usize, std::io::Error> {
+        (*value as u64).consensus_encode(writer)
+    }
+
+    pub fn consensus_decode<D: std::io::Read>(d: D) -> Result<usize, DecodeError> {
+        usize::try_from(u64::consensus_decode(d)?).map_err(DecodeError::from_err)
+    }
+}
+
+/// Converts between two types with the same serde representation
+fn convert<T: Serialize, U: DeserializeOwned>(value: &T) -> Result<U, serde_json::Error> {
+    serde_json::from_value(serde_json::to_value(value)?)
+}
+
+impl Encodable for HoneyBadgerMessage {
+    fn consensus_encode<W: std::io::Write>(&self, writer: W) -> Result<usize, std::io::Error> {
+        let message: Message =
+            convert(&self.0).expect("The mirror can represent all HBBFT messages");
+        message.consensus_encode(writer)
+    }
+}
+
+impl Decodable for HoneyBadgerMessage {
+    fn consensus_decode<D: std::io::Read>(d: D) -> Result<Self, DecodeError> {
+        let message = Message::consensus_decode(d)?;
+        // Fails e.g. for group elements that aren't valid curve points
+        let message = convert(&message).map_err(DecodeError::from_err)?;
+        Ok(HoneyBadgerMessage(message))
+    }
+}
+
+impl<const SIZE: usize> Encodable for GroupElement<SIZE> {
+    fn consensus_encode<W: std::io::Write>(&self, writer: W) -> Result<usize, std::io::Error> {
+        self.0.consensus_encode(writer)
+    }
+}
+
+impl<const SIZE: usize> Decodable for GroupElement<SIZE> {
+    fn consensus_decode<D: std::io::Read>(d: D) -> Result<Self, DecodeError> {
+        Ok(GroupElement(Decodable::consensus_decode(d)?))
+    }
+}
+
+impl<const SIZE: usize> Serialize for GroupElement<SIZE> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(SIZE)?;
+        for byte in &self.0 {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, const SIZE: usize> Deserialize<'de> for GroupElement<SIZE> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor<const SIZE: usize>;
+
+        impl<'de, const SIZE: usize> Visitor<'de> for BytesVisitor<SIZE> {
+            type Value = GroupElement<SIZE>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a tuple of {} bytes", SIZE)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bytes = [0u8; SIZE];
+                for (idx, byte) in bytes.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(idx, &self))?;
+                }
+                Ok(GroupElement(bytes))
+            }
+        }
+
+        deserializer.deserialize_tuple(SIZE, BytesVisitor::<SIZE>)
+    }
+}
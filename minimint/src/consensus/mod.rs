@@ -1,38 +1,73 @@
-mod conflictfilter;
-
 use crate::config::ServerConfig;
-use crate::consensus::conflictfilter::ConflictFilterable;
-use crate::db::{AcceptedTransactionKey, ProposedTransactionKey, ProposedTransactionKeyPrefix};
-use crate::outcome::OutputOutcome;
+use crate::db::{
+    AcceptedTransactionKey, HaltedKey, LastEpochKey, ProposedTransactionKey,
+    ProposedTransactionKeyPrefix,
+};
 use crate::rng::RngGenerator;
-use crate::transaction::{Input, Output, Transaction, TransactionError};
+use crate::transaction::{Transaction, TransactionError};
 use hbbft::honey_badger::Batch;
-use minimint_api::db::batch::{BatchTx, DbBatch};
+use minimint_api::db::batch::{BatchItem, BatchTx, DbBatch};
 use minimint_api::db::{Database, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{FederationModule, OutPoint, PeerId, TransactionId};
-use minimint_derive::UnzipConsensus;
-use minimint_ln::{LightningModule, LightningModuleError};
-use minimint_mint::{Mint, MintError};
-use minimint_wallet::{Wallet, WalletError};
+use minimint_api::module::audit::Audit;
+use minimint_api::{ConsensusContext, OutPoint, PeerId, TransactionId};
+use minimint_derive::FederationModules;
+use minimint_ln::LightningModule;
+use minimint_mint::Mint;
+use minimint_wallet::Wallet;
 use rand::{CryptoRng, RngCore};
-use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 use tracing::{debug, error, info, trace, warn};
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, UnzipConsensus)]
-pub enum ConsensusItem {
-    Transaction(Transaction),
-    Mint(<Mint as FederationModule>::ConsensusItem),
-    Wallet(<Wallet as FederationModule>::ConsensusItem),
-    LN(<LightningModule as FederationModule>::ConsensusItem),
+mod honey_badger;
+
+/// Consensus items proposed by one peer for an epoch
+///
+/// HBBFT serializes contributions using serde before encrypting them. To only have one wire format
+/// for consensus items a contribution's serde representation is the consensus encoding of its
+/// items as byte vector.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ConsensusContribution(pub Vec<ConsensusItem>);
+
+/// Message of the HBBFT protocol exchanged between peers
+///
+/// HBBFT only supports serde for its messages, their consensus encoding is defined by a mirror of
+/// HBBFT's message types in the `honey_badger` module.
+#[derive(Debug, Clone)]
+pub struct HoneyBadgerMessage(pub hbbft::honey_badger::Message<PeerId>);
+
+pub type ConsensusOutcome = Batch<ConsensusContribution, PeerId>;
+
+impl Serialize for ConsensusContribution {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = Vec::new();
+        self.0
+            .consensus_encode(&mut bytes)
+            .expect("writing to vec can't fail");
+        bytes.serialize(serializer)
+    }
 }
 
-pub type HoneyBadgerMessage = hbbft::honey_badger::Message<PeerId>;
-pub type ConsensusOutcome = Batch<Vec<ConsensusItem>, PeerId>;
+impl<'de> Deserialize<'de> for ConsensusContribution {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let mut cursor = std::io::Cursor::new(&bytes);
+        let items = Vec::<ConsensusItem>::consensus_decode_limited(&mut cursor, bytes.len() as u64)
+            .map_err(serde::de::Error::custom)?;
+        if cursor.position() != bytes.len() as u64 {
+            return Err(serde::de::Error::custom("Contribution has trailing bytes"));
+        }
+        Ok(ConsensusContribution(items))
+    }
+}
 
+/// The module specific enums (e.g. [`Input`] and [`ConsensusItem`]) and the functions
+/// dispatching them to the modules are generated from the fields marked with `#[module]`, see
+/// [`FederationModules`]. Module ids may never be changed or reused.
+#[derive(FederationModules)]
 pub struct FediMintConsensus<R>
 where
     R: RngCore + CryptoRng,
@@ -43,18 +78,24 @@ where
     pub cfg: ServerConfig, // TODO: make custom config
 
     /// Our local mint
-    pub mint: Mint, // TODO: generate consensus code using Macro, making modules replaceable for testing and easy adaptability
+    #[module(id = 0)]
+    pub mint: Mint,
+    #[module(id = 1)]
     pub wallet: Wallet,
+    #[module(id = 2, variant = "LN")]
     pub ln: LightningModule,
 
     /// KV Database into which all state is persisted to recover from in case of a crash
     pub db: Arc<dyn RawDatabase>,
+    /// Audit taken after the last processed epoch, served to clients instead of auditing the
+    /// whole database on every request
+    pub last_audit: Mutex<Option<Audit>>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-struct AcceptedTransaction {
-    epoch: u64,
-    transaction: Transaction,
+pub(crate) struct AcceptedTransaction {
+    pub(crate) epoch: u64,
+    pub(crate) transaction: Transaction,
 }
 
 impl<R> FediMintConsensus<R>
@@ -68,46 +109,25 @@ where
         let tx_hash = transaction.tx_hash();
         debug!("Received mint transaction {}", tx_hash);
 
+        if let Some(epoch) = self.halted_after() {
+            return Err(TransactionSubmissionError::Halted(epoch));
+        }
+
+        let context = self.consensus_context(self.next_epoch());
+
+        transaction.validate_version()?;
+        transaction.validate_expiry(context.epoch, context.block_height)?;
         transaction.validate_funding(&self.cfg.fee_consensus)?;
 
         let mut pub_keys = Vec::new();
         for input in &transaction.inputs {
-            let meta = match input {
-                Input::Mint(coins) => self
-                    .mint
-                    .validate_input(coins)
-                    .map_err(TransactionSubmissionError::InputCoinError)?,
-                Input::Wallet(peg_in) => self
-                    .wallet
-                    .validate_input(peg_in)
-                    .map_err(TransactionSubmissionError::InputPegIn)?,
-                Input::LN(input) => self
-                    .ln
-                    .validate_input(input)
-                    .map_err(TransactionSubmissionError::ContractInputError)?,
-            };
-            pub_keys.push(meta.puk_keys);
+            let meta = self.validate_input(&context, input)?;
+            pub_keys.push(meta.puk_keys.collect::<Vec<_>>());
         }
-        transaction.validate_signature(pub_keys.into_iter().flatten())?;
+        transaction.validate_signature(&pub_keys)?;
 
         for output in &transaction.outputs {
-            match output {
-                Output::Mint(coins) => {
-                    self.mint
-                        .validate_output(coins)
-                        .map_err(TransactionSubmissionError::OutputCoinError)?;
-                }
-                Output::Wallet(peg_out) => {
-                    self.wallet
-                        .validate_output(peg_out)
-                        .map_err(TransactionSubmissionError::OutputPegOut)?;
-                }
-                Output::LN(output) => {
-                    self.ln
-                        .validate_output(output)
-                        .map_err(TransactionSubmissionError::ContractOutputError)?;
-                }
-            }
+            self.validate_output(output)?;
         }
 
         let new = self
@@ -126,84 +146,106 @@ where
         let epoch = consensus_outcome.epoch;
         info!("Processing output of epoch {}", epoch);
 
-        let UnzipConsensusItem {
-            transaction: transaction_cis,
-            wallet: wallet_cis,
-            mint: mint_cis,
-            ln: ln_cis,
-        } = consensus_outcome
+        let consensus_items = consensus_outcome
             .contributions
             .into_iter()
-            .flat_map(|(peer, cis)| cis.into_iter().map(move |ci| (peer, ci)))
+            .flat_map(|(peer, cis)| cis.0.into_iter().map(move |ci| (peer, ci)))
             .unzip_consensus_item();
 
+        let context = self.consensus_context(epoch);
         let mut db_batch = DbBatch::new();
-        self.wallet
-            .begin_consensus_epoch(db_batch.transaction(), wallet_cis, self.rng_gen.get_rng())
-            .await;
-        self.mint
-            .begin_consensus_epoch(db_batch.transaction(), mint_cis, self.rng_gen.get_rng())
-            .await;
-        self.ln
-            .begin_consensus_epoch(db_batch.transaction(), ln_cis, self.rng_gen.get_rng())
+        let transaction_cis = self
+            .modules_begin_consensus_epoch(&context, &mut db_batch, consensus_items, || {
+                self.rng_gen.get_rng()
+            })
             .await;
         self.db.apply_batch(db_batch).expect("DB error");
 
-        // Since the changes to the database will happen all at once we won't be able to handle
-        // conflicts between consensus items in one batch there. Thus we need to make sure that
-        // all items in a batch are consistent/deterministically filter out inconsistent ones.
-        // There are two item types that need checking:
-        //  * peg-ins that each peg-in tx is only used to issue coins once
-        //  * coin spends to avoid double spends in one batch
-        let filtered_transactions = transaction_cis
-            .into_iter()
-            .filter_conflicts(|(_, tx)| tx)
-            .collect::<Vec<_>>();
-
-        // TODO: implement own parallel execution to avoid allocations and get rid of rayon
-        let par_db_batches = filtered_transactions
-            .into_par_iter()
-            .map(|(peer, transaction)| {
-                trace!(
-                    "Processing transaction {:?} from peer {}",
-                    transaction,
-                    peer
-                );
-                let mut db_batch = DbBatch::new();
-                db_batch.autocommit(|batch_tx| {
-                    batch_tx.append_maybe_delete(ProposedTransactionKey(transaction.tx_hash()))
-                });
-                // TODO: use borrowed transaction
-                match self.process_transaction(db_batch.transaction(), transaction.clone()) {
-                    Ok(()) => {
-                        db_batch.autocommit(|batch_tx| {
-                            batch_tx.append_insert(
-                                AcceptedTransactionKey(transaction.tx_hash()),
-                                AcceptedTransaction { epoch, transaction },
-                            );
-                        });
-                    }
-                    Err(e) => {
-                        // TODO: log error for user
-                        warn!("Transaction proposed by peer {} failed: {}", peer, e);
-                    }
+        // The wallet may have agreed on a new block height and beacon this epoch, which
+        // transactions are checked against
+        let context = self.consensus_context(epoch);
+
+        // Transactions are processed one after another on top of a single batch. Modules validate
+        // against an overlay of the database and the batch, so e.g. a coin spent by one transaction
+        // can't be spent again by a later one of the same epoch.
+        let mut db_batch = DbBatch::new();
+        let mut processed = HashSet::new();
+        for (peer, transaction) in transaction_cis {
+            let tx_hash = transaction.tx_hash();
+            // Usually every peer proposes the same transactions
+            if !processed.insert(tx_hash) {
+                continue;
+            }
+
+            trace!(
+                "Processing transaction {:?} from peer {}",
+                transaction,
+                peer
+            );
+            let mut batch_tx = db_batch.transaction();
+            batch_tx.append_maybe_delete(ProposedTransactionKey(tx_hash));
+            // TODO: use borrowed transaction
+            match self.process_transaction(&context, batch_tx.subtransaction(), transaction.clone())
+            {
+                Ok(()) => {
+                    batch_tx.append_insert(
+                        AcceptedTransactionKey(tx_hash),
+                        AcceptedTransaction { epoch, transaction },
+                    );
                 }
+                Err(e) => {
+                    // TODO: log error for user
+                    warn!("Transaction proposed by peer {} failed: {}", peer, e);
+                }
+            }
+            batch_tx.commit();
+        }
+        self.db.apply_batch(db_batch).expect("DB error");
 
-                db_batch
-            })
-            .collect::<Vec<_>>();
+        // Transactions that can't be accepted in the next epoch anymore are dropped, so clients can
+        // be sure they failed and retry
         let mut db_batch = DbBatch::new();
-        db_batch.autocommit(|tx| tx.append_from_accumulators(par_db_batches.into_iter()));
+        db_batch.autocommit(|batch_tx| {
+            batch_tx.append_insert(LastEpochKey, epoch);
+            batch_tx.append_from_iter(
+                self.db
+                    .find_by_prefix::<_, ProposedTransactionKey, Transaction>(
+                        &ProposedTransactionKeyPrefix,
+                    )
+                    .filter_map(|res| {
+                        let (key, transaction) = res.expect("DB error");
+                        match transaction.validate_expiry(epoch + 1, context.block_height) {
+                            Ok(()) => None,
+                            Err(e) => {
+                                debug!("Dropping proposed transaction {}: {}", key.0, e);
+                                Some(BatchItem::delete(key))
+                            }
+                        }
+                    }),
+            );
+        });
         self.db.apply_batch(db_batch).expect("DB error");
 
         let mut db_batch = DbBatch::new();
-        self.wallet
-            .end_consensus_epoch(db_batch.transaction(), self.rng_gen.get_rng())
-            .await;
-        self.mint
-            .end_consensus_epoch(db_batch.transaction(), self.rng_gen.get_rng())
+        self.modules_end_consensus_epoch(&mut db_batch, || self.rng_gen.get_rng())
             .await;
         self.db.apply_batch(db_batch).expect("DB error");
+
+        // Continuing after a shortfall could only make things worse, so we halt to let the
+        // guardians investigate. The epoch was committed already, so the halt is persisted to
+        // keep a restarted server from carrying on.
+        let audit = self.audit();
+        debug!("Audit after epoch {}: {}", epoch, audit);
+        if !audit.is_solvent() {
+            error!("Federation is insolvent after epoch {}: {}", epoch, audit);
+            self.db.insert_entry(&HaltedKey, &epoch).expect("DB error");
+        }
+        *self.last_audit.lock().unwrap() = Some(audit);
+    }
+
+    /// Returns the epoch after which the federation was found to be insolvent if we halted
+    pub fn halted_after(&self) -> Option<u64> {
+        self.db.get_value(&HaltedKey).expect("DB error")
     }
 
     pub async fn get_consensus_proposal(&self) -> Vec<ConsensusItem> {
@@ -214,79 +256,62 @@ where
                 ConsensusItem::Transaction(value)
             })
             .chain(
-                self.wallet
-                    .consensus_proposal(self.rng_gen.get_rng())
-                    .await
-                    .into_iter()
-                    .map(ConsensusItem::Wallet),
-            )
-            .chain(
-                self.mint
-                    .consensus_proposal(self.rng_gen.get_rng())
-                    .await
-                    .into_iter()
-                    .map(ConsensusItem::Mint),
+                self.modules_consensus_proposal(|| self.rng_gen.get_rng())
+                    .await,
             )
             .collect()
     }
 
     fn process_transaction(
         &self,
+        context: &ConsensusContext,
         mut batch: BatchTx,
         transaction: Transaction,
     ) -> Result<(), TransactionSubmissionError> {
+        transaction.validate_version()?;
+        transaction.validate_expiry(context.epoch, context.block_height)?;
         transaction.validate_funding(&self.cfg.fee_consensus)?;
 
         let tx_hash = transaction.tx_hash();
 
         let mut pub_keys = Vec::new();
         for input in transaction.inputs.iter() {
-            let meta = match input {
-                Input::Mint(coins) => self
-                    .mint
-                    .apply_input(batch.subtransaction(), coins)
-                    .map_err(TransactionSubmissionError::InputCoinError)?,
-                Input::Wallet(peg_in) => self
-                    .wallet
-                    .apply_input(batch.subtransaction(), peg_in)
-                    .map_err(TransactionSubmissionError::InputPegIn)?,
-                Input::LN(input) => self
-                    .ln
-                    .apply_input(batch.subtransaction(), input)
-                    .map_err(TransactionSubmissionError::ContractInputError)?,
-            };
-            pub_keys.push(meta.puk_keys);
+            let meta = self.apply_input(context, batch.subtransaction(), input)?;
+            pub_keys.push(meta.puk_keys.collect::<Vec<_>>());
         }
-        transaction.validate_signature(pub_keys.into_iter().flatten())?;
+        transaction.validate_signature(&pub_keys)?;
 
-        for (idx, output) in transaction.outputs.into_iter().enumerate() {
+        for (idx, output) in transaction.outputs.iter().enumerate() {
             let out_point = OutPoint {
                 txid: tx_hash,
                 out_idx: idx as u64,
             };
-            match output {
-                Output::Mint(new_tokens) => {
-                    self.mint
-                        .apply_output(batch.subtransaction(), &new_tokens, out_point)
-                        .map_err(TransactionSubmissionError::OutputCoinError)?;
-                }
-                Output::Wallet(peg_out) => {
-                    self.wallet
-                        .apply_output(batch.subtransaction(), &peg_out, out_point)
-                        .map_err(TransactionSubmissionError::OutputPegOut)?;
-                }
-                Output::LN(output) => {
-                    self.ln
-                        .apply_output(batch.subtransaction(), &output, out_point)
-                        .map_err(TransactionSubmissionError::ContractOutputError)?;
-                }
-            }
+            self.apply_output(context, batch.subtransaction(), output, out_point)?;
         }
 
         batch.commit();
         Ok(())
     }
 
+    /// Builds the context modules see while processing `epoch` from the wallet's round consensus
+    fn consensus_context(&self, epoch: u64) -> ConsensusContext {
+        let round_consensus = self.wallet.current_round_consensus();
+        ConsensusContext {
+            epoch,
+            block_height: round_consensus.as_ref().map(|rc| rc.block_height()),
+            randomness_beacon: round_consensus.map(|rc| rc.randomness_beacon()),
+        }
+    }
+
+    /// Returns the number of the next epoch to be processed
+    fn next_epoch(&self) -> u64 {
+        self.db
+            .get_value::<_, u64>(&LastEpochKey)
+            .expect("DB error")
+            .map(|epoch| epoch + 1)
+            .unwrap_or(0)
+    }
+
     pub fn transaction_status(
         &self,
         txid: TransactionId,
@@ -307,29 +332,8 @@ where
                         txid,
                         out_idx: out_idx as u64,
                     };
-                    match output {
-                        Output::Mint(_) => {
-                            let outcome = self
-                                .mint
-                                .output_status(outpoint)
-                                .expect("the transaction was processed, so should be known");
-                            OutputOutcome::Mint(outcome)
-                        }
-                        Output::Wallet(_) => {
-                            let outcome = self
-                                .wallet
-                                .output_status(outpoint)
-                                .expect("the transaction was processed, so should be known");
-                            OutputOutcome::Wallet(outcome)
-                        }
-                        Output::LN(_) => {
-                            let outcome = self
-                                .ln
-                                .output_status(outpoint)
-                                .expect("the transaction was processed, so should be known");
-                            OutputOutcome::LN(outcome)
-                        }
-                    }
+                    self.output_status(output, outpoint)
+                        .expect("the transaction was processed, so should be known")
                 })
                 .collect();
 
@@ -347,18 +351,12 @@ where
 pub enum TransactionSubmissionError {
     #[error("High level transaction error: {0}")]
     TransactionError(TransactionError),
-    #[error("Input coin error: {0}")]
-    InputCoinError(MintError),
-    #[error("Input peg-in error: {0}")]
-    InputPegIn(WalletError),
-    #[error("LN contract input error: {0}")]
-    ContractInputError(LightningModuleError),
-    #[error("Output coin error: {0}")]
-    OutputCoinError(MintError),
-    #[error("Output coin error: {0}")]
-    OutputPegOut(WalletError),
-    #[error("LN contract output error: {0}")]
-    ContractOutputError(LightningModuleError),
+    #[error("{0}")]
+    InputError(InputError),
+    #[error("{0}")]
+    OutputError(OutputError),
+    #[error("Consensus halted since the federation was insolvent after epoch {0}")]
+    Halted(u64),
 }
 
 impl From<TransactionError> for TransactionSubmissionError {
@@ -366,3 +364,15 @@ impl From<TransactionError> for TransactionSubmissionError {
         TransactionSubmissionError::TransactionError(e)
     }
 }
+
+impl From<InputError> for TransactionSubmissionError {
+    fn from(e: InputError) -> Self {
+        TransactionSubmissionError::InputError(e)
+    }
+}
+
+impl From<OutputError> for TransactionSubmissionError {
+    fn from(e: OutputError) -> Self {
+        TransactionSubmissionError::OutputError(e)
+    }
+}
@@ -1,12 +1,61 @@
-use minimint_api::db::DatabaseKeyPrefixConst;
+use crate::consensus::AcceptedTransaction;
+use crate::transaction::{Input, Output, Transaction, TransactionSignature};
+use minimint_api::db::batch::BatchTx;
+use minimint_api::db::migration::{MigrationRegistry, ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry, DbPrefix, PrefixRegistry};
+use minimint_api::db::{Database, DatabaseError, DatabaseKeyPrefixConst, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::TransactionId;
+use minimint_api::{FederationModule, TransactionId};
+use minimint_ln::LightningModule;
+use minimint_mint::db::CoinV0;
+use minimint_mint::tiered::coins::Coins;
+use minimint_wallet::Wallet;
+use secp256k1_zkp::schnorrsig;
+use serde::Serialize;
 use std::fmt::Debug;
+use tracing::warn;
 
 pub const DB_PREFIX_PROPOSED_TRANSACTION: u8 = 0x01;
 pub const DB_PREFIX_ACCEPTED_TRANSACTION: u8 = 0x02;
+pub const DB_PREFIX_LAST_EPOCH: u8 = 0x03;
+pub const DB_PREFIX_HALTED: u8 = 0x04;
 
-#[derive(Debug, Encodable, Decodable)]
+/// Key prefixes used by the consensus logic itself
+pub const DB_PREFIXES: &[DbPrefix] = &[
+    DbPrefix {
+        prefix: DB_PREFIX_PROPOSED_TRANSACTION,
+        name: "Pending Transactions",
+        key: "transaction id",
+        value: "transaction",
+        decode: decode_entry::<ProposedTransactionKey, crate::transaction::Transaction>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_ACCEPTED_TRANSACTION,
+        name: "Accepted Transactions",
+        key: "transaction id",
+        value: "confirmation epoch, transaction",
+        decode: decode_entry::<AcceptedTransactionKey, crate::consensus::AcceptedTransaction>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_LAST_EPOCH,
+        name: "Last Epoch",
+        key: "none",
+        value: "last processed epoch",
+        decode: decode_entry::<LastEpochKey, u64>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_HALTED,
+        name: "Halted",
+        key: "none",
+        value: "epoch after which the federation was insolvent",
+        decode: decode_entry::<HaltedKey, u64>,
+    },
+];
+
+/// Version of the consensus database layout
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(1);
+
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct ProposedTransactionKey(pub TransactionId);
 
 impl DatabaseKeyPrefixConst for ProposedTransactionKey {
@@ -20,9 +69,238 @@ impl DatabaseKeyPrefixConst for ProposedTransactionKeyPrefix {
     const DB_PREFIX: u8 = DB_PREFIX_PROPOSED_TRANSACTION;
 }
 
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct AcceptedTransactionKey(pub TransactionId);
 
 impl DatabaseKeyPrefixConst for AcceptedTransactionKey {
     const DB_PREFIX: u8 = DB_PREFIX_ACCEPTED_TRANSACTION;
 }
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AcceptedTransactionKeyPrefix;
+
+impl DatabaseKeyPrefixConst for AcceptedTransactionKeyPrefix {
+    const DB_PREFIX: u8 = DB_PREFIX_ACCEPTED_TRANSACTION;
+}
+
+/// Number of the last consensus epoch that was processed
+#[derive(Debug, Serialize, Encodable, Decodable)]
+pub struct LastEpochKey;
+
+impl DatabaseKeyPrefixConst for LastEpochKey {
+    const DB_PREFIX: u8 = DB_PREFIX_LAST_EPOCH;
+}
+
+/// Set to the epoch after which the audit found the federation to be insolvent. The server stops
+/// participating in consensus until its operator resumes it.
+#[derive(Debug, Serialize, Encodable, Decodable)]
+pub struct HaltedKey;
+
+impl DatabaseKeyPrefixConst for HaltedKey {
+    const DB_PREFIX: u8 = DB_PREFIX_HALTED;
+}
+
+/// Migrations of all database schemas used by the server, run before the database is used. The
+/// consensus schema is migrated last since its migrations may complete those of the modules.
+pub fn migrations() -> MigrationRegistry {
+    MigrationRegistry::new()
+        .with_module(minimint_mint::db::migrations())
+        .with_module(minimint_wallet::db::migrations())
+        .with_module(minimint_ln::db::migrations())
+        .with_module(
+            ModuleMigrations::new("consensus", DB_SCHEMA_VERSION)
+                .with_migration(SchemaVersion(0), migrate_v0),
+        )
+}
+
+/// Transaction as stored by schema version 0, without version and expiry and with in- and outputs
+/// tagged by their position in the enum instead of their module id
+#[derive(Debug, Encodable, Decodable)]
+struct TransactionV0 {
+    inputs: Vec<InputV0>,
+    outputs: Vec<OutputV0>,
+    signature: Option<schnorrsig::Signature>,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+enum InputV0 {
+    Mint(Coins<CoinV0>),
+    Wallet(<Wallet as FederationModule>::TxInput),
+    LN(<LightningModule as FederationModule>::TxInput),
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Encodable, Decodable)]
+enum OutputV0 {
+    Mint(<minimint_mint::Mint as FederationModule>::TxOutput),
+    Wallet(<Wallet as FederationModule>::TxOutput),
+    LN(<LightningModule as FederationModule>::TxOutput),
+}
+
+#[derive(Debug, Encodable, Decodable)]
+struct AcceptedTransactionV0 {
+    epoch: u64,
+    transaction: TransactionV0,
+}
+
+impl From<TransactionV0> for Transaction {
+    fn from(tx: TransactionV0) -> Transaction {
+        let inputs = tx
+            .inputs
+            .into_iter()
+            .map(|input| match input {
+                InputV0::Mint(coins) => Input::Mint(
+                    coins
+                        .into_iter()
+                        .map(|(amount, coin)| (amount, coin.into()))
+                        .collect(),
+                ),
+                InputV0::Wallet(peg_in) => Input::Wallet(peg_in),
+                InputV0::LN(input) => Input::LN(input),
+            })
+            .collect();
+        let outputs = tx
+            .outputs
+            .into_iter()
+            .map(|output| match output {
+                OutputV0::Mint(tokens) => Output::Mint(tokens),
+                OutputV0::Wallet(peg_out) => Output::Wallet(peg_out),
+                OutputV0::LN(output) => Output::LN(output),
+            })
+            .collect();
+        Transaction {
+            version: 0,
+            inputs,
+            outputs,
+            expiry: None,
+            signature: tx.signature.map(TransactionSignature::Aggregate),
+        }
+    }
+}
+
+/// Schema version 1 stores transactions with a version and an optional expiry, and encodes their
+/// in- and outputs with the id of their module.
+///
+/// Accepted transactions stay stored under their original id, which clients use to query them,
+/// even though the converted transaction hashes differently. Transactions that were proposed but
+/// not yet accepted are dropped since their signatures commit to the old hash and would be
+/// rejected by the other peers anyway, their clients have to submit them again.
+///
+/// The mint didn't record the amounts of spent coins before, they are recovered from the accepted
+/// transactions that spent them.
+fn migrate_v0(db: &dyn RawDatabase, batch: &mut BatchTx) -> Result<(), DatabaseError> {
+    let mut spent_coins = Vec::new();
+    for res in db.find_by_prefix::<_, AcceptedTransactionKey, AcceptedTransactionV0>(
+        &AcceptedTransactionKeyPrefix,
+    ) {
+        let (key, tx) = res?;
+        for input in &tx.transaction.inputs {
+            if let InputV0::Mint(coins) = input {
+                spent_coins.extend(coins.iter().map(|(amount, coin)| (coin.0.clone(), amount)));
+            }
+        }
+        batch.append_insert(
+            key,
+            AcceptedTransaction {
+                epoch: tx.epoch,
+                transaction: tx.transaction.into(),
+            },
+        );
+    }
+
+    let proposed = db
+        .find_by_prefix::<_, ProposedTransactionKey, TransactionV0>(&ProposedTransactionKeyPrefix)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !proposed.is_empty() {
+        warn!(
+            "Dropping {} proposed transactions of schema version 0, they have to be submitted again",
+            proposed.len()
+        );
+    }
+    for (key, _) in proposed {
+        batch.append_delete(key);
+    }
+
+    minimint_mint::db::recover_spent_amounts(db, batch, spent_coins)
+}
+
+/// Key prefixes of all modules sharing the server's database
+pub fn prefix_registry() -> PrefixRegistry {
+    PrefixRegistry::new()
+        .with_module("Schema Versions", minimint_api::db::migration::DB_PREFIXES)
+        .with_module("Consensus", DB_PREFIXES)
+        .with_module("Mint", minimint_mint::db::DB_PREFIXES)
+        .with_module("Wallet", minimint_wallet::db::DB_PREFIXES)
+        .with_module("Lightning", minimint_ln::db::DB_PREFIXES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AcceptedTransactionKey, ProposedTransactionKey, ProposedTransactionKeyPrefix};
+    use crate::consensus::AcceptedTransaction;
+    use crate::transaction::{Input, Output, Transaction, TransactionSignature};
+    use bitcoin::hashes::Hash;
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::db::Database;
+    use minimint_api::{Amount, TransactionId};
+    use minimint_mint::db::{LegacyIssuanceKey, NonceKey};
+    use minimint_mint::KeyGeneration;
+
+    #[test]
+    fn test_migrate_v0() {
+        let db = MemDatabase::from_dump(include_str!("fixtures/db_v0.dump")).unwrap();
+        super::migrations().apply(&db).unwrap();
+
+        let txid = TransactionId::from_inner([0xaa; 32]);
+        let AcceptedTransaction { epoch, transaction } = db
+            .get_value(&AcceptedTransactionKey(txid))
+            .unwrap()
+            .unwrap();
+        assert_eq!(epoch, 7);
+        let Transaction {
+            version,
+            inputs,
+            outputs,
+            expiry,
+            signature,
+        } = transaction;
+        assert_eq!(version, 0);
+        assert_eq!(expiry, None);
+        assert!(matches!(
+            signature,
+            Some(TransactionSignature::Aggregate(_))
+        ));
+        let spent = match &inputs[..] {
+            [Input::Mint(coins)] => {
+                assert_eq!(coins.amount(), Amount::from_sat(2));
+                assert!(coins
+                    .iter()
+                    .all(|(_, coin)| coin.2 == KeyGeneration::default()));
+                let (_, coin) = coins.iter().next().unwrap();
+                coin.0.clone()
+            }
+            inputs => panic!("Unexpected inputs {:?}", inputs),
+        };
+        match &outputs[..] {
+            [Output::Mint(tokens)] => assert_eq!(tokens.amount(), Amount::from_sat(2)),
+            outputs => panic!("Unexpected outputs {:?}", outputs),
+        }
+
+        // Signatures of proposed transactions commit to the old transaction hash
+        assert_eq!(
+            db.find_by_prefix::<_, ProposedTransactionKey, Transaction>(
+                &ProposedTransactionKeyPrefix
+            )
+            .count(),
+            0
+        );
+
+        // The amount of the spent coin is recovered from the transaction, so the mint doesn't
+        // need to assume any legacy e-cash to be redeemed
+        assert_eq!(
+            db.get_value::<_, Amount>(&NonceKey(spent)).unwrap(),
+            Some(Amount::from_sat(2))
+        );
+        assert_eq!(db.get_value::<_, Amount>(&LegacyIssuanceKey).unwrap(), None);
+    }
+}
@@ -7,16 +7,20 @@ use hbbft::honey_badger::{HoneyBadger, Step};
 use hbbft::{Epoched, NetworkInfo};
 use rand::{CryptoRng, RngCore};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::task::{spawn, JoinHandle};
-use tracing::{debug, info, trace, warn};
+use tokio::sync::Notify;
+use tokio::task::{spawn, spawn_blocking, JoinHandle};
+use tracing::{debug, error, info, trace, warn};
 
 use config::ServerConfig;
 use consensus::ConsensusOutcome;
-use minimint_api::db::RawDatabase;
-use minimint_api::PeerId;
+use minimint_api::db::snapshot::DbSnapshot;
+use minimint_api::db::Database;
 use minimint_ln::LightningModule;
 
-use crate::consensus::{ConsensusItem, FediMintConsensus};
+use crate::consensus::{
+    ConsensusContribution, ConsensusItem, FediMintConsensus, HoneyBadgerMessage,
+};
+use crate::net::api::SnapshotRequest;
 use crate::net::connect::Connections;
 use crate::net::PeerConnections;
 use crate::rng::RngGenerator;
@@ -44,18 +48,41 @@ pub mod modules {
     pub use minimint_wallet as wallet;
 }
 
-/// Start all the components of the mintan d plug them together
-pub async fn run_minimint(cfg: ServerConfig) {
-    assert_eq!(
-        cfg.peers.keys().max().copied().map(|id| id.to_usize()),
-        Some(cfg.peers.len() - 1)
-    );
-    assert_eq!(cfg.peers.keys().min().copied(), Some(PeerId::from(0)));
+/// Start all the components of the mintan d plug them together. A server that halted since the
+/// federation was insolvent only starts again if `resume` is set.
+pub async fn run_minimint(cfg: ServerConfig, resume: bool) {
+    // Peer ids don't have to be contiguous since peers may have left the federation
+    assert!(cfg.peers.contains_key(&cfg.identity));
 
     let threshold = cfg.peers.len() - cfg.max_faulty();
 
-    let database: Arc<dyn RawDatabase> =
-        Arc::new(sled::open(&cfg.db_path).unwrap().open_tree("mint").unwrap());
+    let database = cfg
+        .db_backend
+        .open(&cfg.db_path, "mint")
+        .expect("Couldn't open database");
+    db::prefix_registry()
+        .check()
+        .expect("Database key prefixes collide");
+    db::migrations()
+        .apply(database.as_ref())
+        .expect("Couldn't migrate database");
+
+    if let Some(epoch) = database
+        .get_value::<_, u64>(&db::HaltedKey)
+        .expect("DB error")
+    {
+        if !resume {
+            error!(
+                "Halted since the federation was insolvent after epoch {}, restart with --resume once the cause was fixed",
+                epoch
+            );
+            return;
+        }
+        warn!("Resuming consensus after halting in epoch {}", epoch);
+        database
+            .remove_entry::<_, u64>(&db::HaltedKey)
+            .expect("DB error");
+    }
 
     let mint = minimint_mint::Mint::new(cfg.mint.clone(), threshold, database.clone());
 
@@ -72,9 +99,22 @@ pub async fn run_minimint(cfg: ServerConfig) {
         wallet,
         ln,
         db: database,
+        last_audit: Mutex::new(None),
     });
 
     spawn(net::api::run_server(cfg.clone(), mint_consensus.clone()));
+    let (snapshot_sender, mut snapshot_receiver) = channel::<SnapshotRequest>(1);
+    if let Some(admin_port) = cfg.admin_port {
+        spawn(net::api::run_admin_server(admin_port, snapshot_sender));
+    }
+
+    // Outputs accepted before a restart may not have been signed yet
+    let epoch_processed = Arc::new(Notify::new());
+    epoch_processed.notify_one();
+    spawn(run_issuance_signer(
+        mint_consensus.clone(),
+        epoch_processed.clone(),
+    ));
 
     let (output_sender, mut output_receiver) = channel::<ConsensusOutcome>(1);
     let (proposal_sender, proposal_receiver) = channel::<Vec<ConsensusItem>>(1);
@@ -99,11 +139,20 @@ pub async fn run_minimint(cfg: ServerConfig) {
         // duplicates. Yet we can not remove them from the database entirely because we might crash
         // while processing the outcome.
         let outcome = {
-            let outcome = output_receiver.recv().await.expect("other thread died");
+            // Snapshots are taken while waiting for the next epoch, when consensus doesn't write.
+            // Other writers only apply self-contained batches.
+            let outcome = loop {
+                tokio::select! {
+                    outcome = output_receiver.recv() => break outcome.expect("other thread died"),
+                    Some(request) = snapshot_receiver.recv() => {
+                        let _ = request.send(DbSnapshot::take(mint_consensus.db.as_ref()));
+                    }
+                }
+            };
             let outcome_filter_set = outcome
                 .contributions
                 .values()
-                .flatten()
+                .flat_map(|contribution| contribution.0.iter())
                 .filter(|ci| !matches!(ci, ConsensusItem::Wallet(_)))
                 .collect::<HashSet<_>>();
 
@@ -125,9 +174,21 @@ pub async fn run_minimint(cfg: ServerConfig) {
         debug!(
             "Processing consensus outcome from epoch {} with {} items",
             outcome.epoch,
-            outcome.contributions.values().flatten().count()
+            outcome
+                .contributions
+                .values()
+                .map(|contribution| contribution.0.len())
+                .sum::<usize>()
         );
         mint_consensus.process_consensus_outcome(outcome).await;
+        if let Some(epoch) = mint_consensus.halted_after() {
+            error!(
+                "Stopping since the federation is insolvent after epoch {}, see the audit log above",
+                epoch
+            );
+            return;
+        }
+        epoch_processed.notify_one();
 
         if we_contributed {
             // TODO: define latency target for consensus rounds and monitor it
@@ -151,7 +212,7 @@ async fn spawn_hbbft(
 
         let net_info = NetworkInfo::new(
             cfg.identity,
-            cfg.hbbft_sks.inner().clone(),
+            cfg.hbbft_sks.expose().to_secret_key_share(),
             cfg.hbbft_pk_set.clone(),
             cfg.hbbft_sk.inner().clone(),
             cfg.peers
@@ -160,19 +221,21 @@ async fn spawn_hbbft(
                 .collect(),
         );
 
-        let mut hb: HoneyBadger<Vec<ConsensusItem>, _> =
+        let mut hb: HoneyBadger<ConsensusContribution, _> =
             HoneyBadger::builder(Arc::new(net_info)).build();
         info!("Created Honey Badger instance");
 
         let mut next_consensus_items = Some(initial_cis);
         loop {
-            let contribution = next_consensus_items
-                .take()
-                .expect("This is always refilled");
+            let contribution = ConsensusContribution(
+                next_consensus_items
+                    .take()
+                    .expect("This is always refilled"),
+            );
 
             debug!(
                 "Proposing a contribution with {} consensus items for epoch {}",
-                contribution.len(),
+                contribution.0.len(),
                 hb.epoch()
             );
             trace!("Contribution: {:?}", contribution);
@@ -193,14 +256,16 @@ async fn spawn_hbbft(
                     None => {
                         let (peer, peer_msg) = connections.receive().await;
                         trace!("Received message from {}", peer);
-                        hb.handle_message(&peer, peer_msg)
+                        hb.handle_message(&peer, peer_msg.0)
                             .expect("Failed to process HBBFT input")
                     }
                 };
 
                 for msg in messages {
                     trace!("sending message to {:?}", msg.target);
-                    connections.send(msg.target, msg.message).await;
+                    connections
+                        .send(msg.target, HoneyBadgerMessage(msg.message))
+                        .await;
                 }
 
                 if !fault_log.is_empty() {
@@ -225,6 +290,25 @@ async fn spawn_hbbft(
     })
 }
 
+/// Blind signs the e-cash outputs accepted by consensus whenever an epoch was processed, so that
+/// signing doesn't hold up consensus
+async fn run_issuance_signer(
+    fedimint: Arc<FediMintConsensus<rand::rngs::OsRng>>,
+    epoch_processed: Arc<Notify>,
+) {
+    loop {
+        epoch_processed.notified().await;
+
+        let fedimint = fedimint.clone();
+        let signed = spawn_blocking(move || fedimint.mint.sign_pending_issuances())
+            .await
+            .expect("Issuance signer panicked");
+        if signed > 0 {
+            debug!("Signed {} issuances", signed);
+        }
+    }
+}
+
 struct CloneRngGen<T: RngCore + CryptoRng + Clone + Send>(Mutex<T>);
 
 impl<T: RngCore + CryptoRng + Clone + Send> RngGenerator for CloneRngGen<T> {
@@ -1,13 +1,23 @@
 use crate::config::ServerConfig;
 use crate::consensus::FediMintConsensus;
 use crate::transaction::Transaction;
-use minimint_api::TransactionId;
-use minimint_ln::contracts::ContractId;
+use futures::AsyncReadExt;
+use minimint_api::db::snapshot::DbSnapshot;
+use minimint_api::db::DatabaseError;
+use minimint_api::encoding::Encodable;
+use minimint_api::module::api::{module_api_path, ApiEndpoint, ApiHandler};
+use minimint_api::{FederationModule, TransactionId};
+use serde::de::DeserializeOwned;
 use std::fmt::Formatter;
 use std::sync::Arc;
 use tide::{Body, Request, Response};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
 use tracing::{debug, trace};
 
+/// Maximum size of a request body, larger requests are rejected without reading them entirely
+pub const MAX_REQUEST_LEN: u64 = 1024 * 1024;
+
 #[derive(Clone)]
 struct State {
     fedimint: Arc<FediMintConsensus<rand::rngs::OsRng>>,
@@ -24,17 +34,65 @@ pub async fn run_server(cfg: ServerConfig, fedimint: Arc<FediMintConsensus<rand:
     let mut server = tide::with_state(state);
     server.at("/transaction").put(submit_transaction);
     server.at("/transaction/:txid").get(fetch_outcome);
-    server.at("/offers").get(list_offers);
-    server.at("/account/:contract_id").get(get_contract_account);
+    server.at("/audit").get(fetch_audit);
+    attach_endpoints(&mut server, |fedimint| &fedimint.mint);
+    attach_endpoints(&mut server, |fedimint| &fedimint.wallet);
+    attach_endpoints(&mut server, |fedimint| &fedimint.ln);
     server
         .listen(format!("127.0.0.1:{}", cfg.get_api_port()))
         .await
         .expect("Could not start API server");
 }
 
+/// Request for a database snapshot, which the consensus task answers between epochs so that
+/// snapshots never contain a partially processed epoch
+pub type SnapshotRequest = oneshot::Sender<Result<DbSnapshot, DatabaseError>>;
+
+/// Runs the admin API, which is only reachable by the guardian since it is bound to localhost on a
+/// port separate from the client API. Snapshots are requested from the consensus task through
+/// `snapshots`.
+pub async fn run_admin_server(port: u16, snapshots: Sender<SnapshotRequest>) {
+    let mut server = tide::with_state(snapshots);
+    server.at("/snapshot").get(export_snapshot);
+    server
+        .listen(format!("127.0.0.1:{}", port))
+        .await
+        .expect("Could not start admin API server");
+}
+
+/// Mounts the API endpoints of the module returned by `module` below its base path. Module
+/// endpoints take their JSON encoded request as body of a POST request.
+fn attach_endpoints<M>(
+    server: &mut tide::Server<State>,
+    module: fn(&FediMintConsensus<rand::rngs::OsRng>) -> &M,
+) where
+    M: FederationModule + 'static,
+{
+    let endpoints: Vec<ApiEndpoint<M>> = module(&server.state().fedimint).api_endpoints();
+    for endpoint in endpoints {
+        let handler = endpoint.handler;
+        server
+            .at(&module_api_path::<M>(endpoint.path))
+            .post(move |req: Request<State>| module_request(req, module, handler));
+    }
+}
+
+async fn module_request<M>(
+    mut req: Request<State>,
+    module: fn(&FediMintConsensus<rand::rngs::OsRng>) -> &M,
+    handler: ApiHandler<M>,
+) -> tide::Result {
+    let request: serde_json::Value = read_json_body(&mut req).await?;
+    let response = handler(module(&req.state().fedimint), request)
+        .map_err(|e| tide::Error::from_str(e.code(), e.to_string()))?;
+
+    let body = Body::from_json(&response).expect("encoding error");
+    Ok(body.into())
+}
+
 async fn submit_transaction(mut req: Request<State>) -> tide::Result {
     trace!("Received API request {:?}", req);
-    let transaction: Transaction = req.body_json().await?;
+    let transaction: Transaction = read_json_body(&mut req).await?;
     let tx_id = transaction.tx_hash();
     debug!("Sending peg-in request to consensus");
     req.state()
@@ -66,31 +124,48 @@ async fn fetch_outcome(req: Request<State>) -> tide::Result {
     Ok(body.into())
 }
 
-async fn list_offers(req: Request<State>) -> tide::Result {
-    let offers = req.state().fedimint.ln.get_offers();
-
-    let body = Body::from_json(&offers).expect("encoding error");
+async fn fetch_audit(req: Request<State>) -> tide::Result {
+    let audit = req
+        .state()
+        .fedimint
+        .last_audit
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| tide::Error::from_str(404, "No epoch was audited yet"))?;
+    let body = Body::from_json(&audit).expect("encoding error");
     Ok(body.into())
 }
 
-async fn get_contract_account(req: Request<State>) -> tide::Result {
-    let contract_id: ContractId = match req
-        .param("contract_id")
-        .expect("Contract id not supplied")
-        .parse()
-    {
-        Ok(id) => id,
-        Err(_) => return Ok(Response::new(400)),
-    };
+async fn export_snapshot(req: Request<Sender<SnapshotRequest>>) -> tide::Result {
+    // The receiver is only dropped once consensus stopped
+    let (request, response) = oneshot::channel();
+    req.state()
+        .send(request)
+        .await
+        .map_err(|_| tide::Error::from_str(503, "Consensus stopped"))?;
+    let snapshot = response
+        .await
+        .map_err(|_| tide::Error::from_str(503, "Consensus stopped"))?
+        .map_err(|e| tide::Error::from_str(500, e.to_string()))?;
 
-    let contract_account = req
-        .state()
-        .fedimint
-        .ln
-        .get_contract_account(contract_id)
-        .ok_or_else(|| tide::Error::from_str(404, "Not found"))?;
+    let mut bytes = Vec::new();
+    snapshot
+        .consensus_encode(&mut bytes)
+        .expect("writing to vec can't fail");
+    debug!("Exporting database snapshot of {} bytes", bytes.len());
+    Ok(Body::from_bytes(bytes).into())
+}
 
-    debug!("Sending contract account info for {}", contract_id);
-    let body = Body::from_json(&contract_account).expect("encoding error");
-    Ok(body.into())
+/// Reads and deserializes a JSON request body of at most [`MAX_REQUEST_LEN`] bytes
+async fn read_json_body<T: DeserializeOwned>(req: &mut Request<State>) -> tide::Result<T> {
+    let mut bytes = Vec::new();
+    req.take_body()
+        .take(MAX_REQUEST_LEN + 1)
+        .read_to_end(&mut bytes)
+        .await?;
+    if bytes.len() as u64 > MAX_REQUEST_LEN {
+        return Err(tide::Error::from_str(413, "Request body too large"));
+    }
+    serde_json::from_slice(&bytes).map_err(|e| tide::Error::from_str(400, e.to_string()))
 }
@@ -7,9 +7,8 @@ use futures::future::try_join_all;
 use futures::StreamExt;
 use futures::{FutureExt, SinkExt};
 use hbbft::Target;
+use minimint_api::encoding::{Decodable, Encodable};
 use minimint_api::PeerId;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
 use std::collections::HashMap;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -26,7 +25,7 @@ pub struct Connections<T> {
 
 impl<T: 'static> Connections<T>
 where
-    T: Serialize + DeserializeOwned + Unpin + Send,
+    T: Encodable + Decodable + Unpin + Send,
 {
     pub async fn connect_to_all(cfg: &ServerConfig) -> Self {
         info!("Starting mint {}", cfg.identity);
@@ -120,7 +119,7 @@ where
 #[async_trait]
 impl<T> PeerConnections<T> for Connections<T>
 where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+    T: Encodable + Decodable + Unpin + Send + Sync + 'static,
 {
     type Id = PeerId;
 
@@ -1,12 +1,14 @@
 use futures::{AsyncRead, AsyncWrite};
 use futures::{Sink, Stream};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use minimint_api::encoding::{Decodable, DecodeError, Encodable};
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tracing::{debug, trace};
 
+/// Maximum size of a single frame, larger ones are rejected before allocating a buffer for them
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
 // FIXME: look into using tokio for that, I  just didn't know it was a core component by now
 pub struct Framed<S, T> {
     stream: S,
@@ -21,7 +23,7 @@ pub struct Framed<S, T> {
 impl<S, T> Framed<S, T>
 where
     S: AsyncRead + AsyncWrite + Unpin,
-    T: Serialize + DeserializeOwned + Unpin,
+    T: Encodable + Decodable + Unpin,
 {
     pub fn new(stream: S) -> Self {
         Framed {
@@ -39,7 +41,7 @@ where
 impl<S, T> Sink<&T> for Framed<S, T>
 where
     S: AsyncWrite + Unpin,
-    T: Serialize + Unpin,
+    T: Encodable + Unpin,
 {
     type Error = FrameError;
 
@@ -64,18 +66,20 @@ where
     }
 
     fn start_send(self: Pin<&mut Self>, item: &T) -> Result<(), Self::Error> {
-        match bincode::serialize(item) {
-            Ok(encoded) => {
-                let mut frame = Vec::with_capacity(encoded.len() + 8);
-                frame.extend_from_slice(&encoded.len().to_be_bytes());
-                frame.extend_from_slice(&encoded);
-                debug!("Sending  {} bytes", encoded.len());
-                trace!("Sending  {:x?}", encoded);
-                self.get_mut().write_buffer = frame;
-                Ok(())
-            }
-            Err(e) => Err(FrameError::CodingError(e)),
+        let mut encoded = Vec::new();
+        item.consensus_encode(&mut encoded)
+            .expect("writing to vec can't fail");
+        if encoded.len() > MAX_FRAME_LEN {
+            return Err(FrameError::TooLarge(encoded.len() as u64));
         }
+
+        let mut frame = Vec::with_capacity(encoded.len() + 8);
+        frame.extend_from_slice(&(encoded.len() as u64).to_be_bytes());
+        frame.extend_from_slice(&encoded);
+        debug!("Sending  {} bytes", encoded.len());
+        trace!("Sending  {:x?}", encoded);
+        self.get_mut().write_buffer = frame;
+        Ok(())
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -90,7 +94,7 @@ where
 impl<S, T> Stream for Framed<S, T>
 where
     S: AsyncRead + Unpin,
-    T: DeserializeOwned + Unpin,
+    T: Decodable + Unpin,
 {
     type Item = Result<T, FrameError>;
 
@@ -110,7 +114,11 @@ where
         }
 
         if mut_self.read_len_len == 8 {
-            let exp_len = u64::from_be_bytes(mut_self.read_len_buffer) as usize;
+            let exp_len = u64::from_be_bytes(mut_self.read_len_buffer);
+            if exp_len > MAX_FRAME_LEN as u64 {
+                return Poll::Ready(Some(Err(FrameError::TooLarge(exp_len))));
+            }
+            let exp_len = exp_len as usize;
             if exp_len != mut_self.read_buffer.len() {
                 mut_self.read_buffer = vec![0; exp_len as usize];
             }
@@ -130,10 +138,7 @@ where
             if exp_len == mut_self.read_len_actual {
                 debug!("Received {} bytes", exp_len);
                 trace!("Received {:x?}", mut_self.read_buffer);
-                let res = match bincode::deserialize(&mut_self.read_buffer) {
-                    Ok(decoded) => Ok(decoded),
-                    Err(e) => Err(FrameError::CodingError(e)),
-                };
+                let res = decode_frame(&mut_self.read_buffer);
 
                 mut_self.read_len_len = 0;
                 mut_self.read_len_actual = 0;
@@ -146,14 +151,27 @@ where
     }
 }
 
+/// Decodes a frame's content, which has to be consumed entirely. Decoding may not read more than
+/// the frame's length, so nested length prefixes can't make it loop beyond the received bytes.
+fn decode_frame<T: Decodable>(frame: &[u8]) -> Result<T, FrameError> {
+    let mut cursor = std::io::Cursor::new(frame);
+    let decoded = T::consensus_decode_limited(&mut cursor, frame.len() as u64)?;
+    if cursor.position() != frame.len() as u64 {
+        return Err(FrameError::TrailingBytes);
+    }
+    Ok(decoded)
+}
+
 #[derive(Debug)]
 pub enum FrameError {
-    CodingError(bincode::Error),
+    CodingError(DecodeError),
     IOError(std::io::Error),
+    TooLarge(u64),
+    TrailingBytes,
 }
 
-impl From<bincode::Error> for FrameError {
-    fn from(e: bincode::Error) -> Self {
+impl From<DecodeError> for FrameError {
+    fn from(e: DecodeError) -> Self {
         FrameError::CodingError(e)
     }
 }
@@ -163,3 +181,111 @@ impl From<std::io::Error> for FrameError {
         FrameError::IOError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Framed;
+    use crate::consensus::{ConsensusContribution, ConsensusItem, HoneyBadgerMessage};
+    use crate::transaction::{Transaction, TransactionExpiry, TRANSACTION_VERSION};
+    use futures::StreamExt;
+    use hbbft::honey_badger::HoneyBadger;
+    use hbbft::{NetworkInfo, Target};
+    use minimint_api::encoding::Encodable;
+    use minimint_api::PeerId;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::Arc;
+
+    fn encode_frame(item: &impl Encodable) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        item.consensus_encode(&mut encoded).unwrap();
+        let mut frame = (encoded.len() as u64).to_be_bytes().to_vec();
+        frame.extend_from_slice(&encoded);
+        frame
+    }
+
+    fn contribution() -> ConsensusContribution {
+        ConsensusContribution(vec![ConsensusItem::Transaction(Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![],
+            outputs: vec![],
+            expiry: Some(TransactionExpiry::Epoch(42)),
+            signature: None,
+        })])
+    }
+
+    #[test]
+    fn test_contribution_roundtrip() {
+        let contribution = contribution();
+        let bytes = bincode::serialize(&contribution).unwrap();
+        let decoded: ConsensusContribution = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, contribution);
+
+        let mut items = Vec::new();
+        contribution.0.consensus_encode(&mut items).unwrap();
+        items.push(0);
+        let trailing = bincode::serialize(&items).unwrap();
+        assert!(bincode::deserialize::<ConsensusContribution>(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_honey_badger_message_roundtrip() {
+        let mut rng = rand::rngs::OsRng::new().unwrap();
+        let peers: Vec<PeerId> = (0..4u16).map(PeerId::from).collect();
+        let mut nodes = NetworkInfo::generate_map(peers.clone(), &mut rng)
+            .unwrap()
+            .into_iter()
+            .map(|(peer, netinfo)| {
+                let hb: HoneyBadger<ConsensusContribution, PeerId> =
+                    HoneyBadger::builder(Arc::new(netinfo)).build();
+                (peer, hb)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut queue = VecDeque::new();
+        let mut outputs = BTreeMap::new();
+        for (peer, hb) in nodes.iter_mut() {
+            let step = hb.propose(&contribution(), &mut rng).unwrap();
+            queue.extend(step.messages.into_iter().map(|msg| (*peer, msg)));
+            outputs.insert(*peer, step.output);
+        }
+
+        // Every message is delivered in its decoded form, so the peers only agree on a batch if
+        // all kinds of messages HBBFT sends survive encoding
+        while let Some((sender, msg)) = queue.pop_front() {
+            let frame = encode_frame(&HoneyBadgerMessage(msg.message));
+            let mut framed: Framed<_, HoneyBadgerMessage> =
+                Framed::new(futures::io::Cursor::new(frame.clone()));
+            let decoded = futures::executor::block_on(framed.next()).unwrap().unwrap();
+            assert_eq!(encode_frame(&decoded), frame);
+
+            let recipients = match msg.target {
+                Target::All => peers
+                    .iter()
+                    .filter(|&&peer| peer != sender)
+                    .copied()
+                    .collect(),
+                Target::Node(peer) => vec![peer],
+            };
+            for recipient in recipients {
+                let step = nodes
+                    .get_mut(&recipient)
+                    .unwrap()
+                    .handle_message(&sender, decoded.0.clone())
+                    .unwrap();
+                assert!(step.fault_log.is_empty());
+                queue.extend(step.messages.into_iter().map(|msg| (recipient, msg)));
+                outputs.get_mut(&recipient).unwrap().extend(step.output);
+            }
+        }
+
+        let batches = outputs
+            .into_iter()
+            .map(|(_, mut batches)| {
+                assert_eq!(batches.len(), 1);
+                batches.remove(0).contributions
+            })
+            .collect::<Vec<_>>();
+        assert!(batches[0].len() >= 3);
+        assert!(batches.iter().all(|batch| batch == &batches[0]));
+    }
+}
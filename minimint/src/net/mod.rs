@@ -1,7 +1,6 @@
 use async_trait::async_trait;
 use hbbft::{NodeIdT, Target};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use minimint_api::encoding::{Decodable, Encodable};
 
 pub mod api;
 pub mod connect;
@@ -10,7 +9,7 @@ pub mod framed;
 #[async_trait]
 pub trait PeerConnections<T>
 where
-    T: Serialize + DeserializeOwned + Unpin + Send,
+    T: Encodable + Decodable + Unpin + Send,
 {
     type Id: NodeIdT;
 
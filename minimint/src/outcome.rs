@@ -1,6 +1,5 @@
-use minimint_api::FederationModule;
+pub use crate::consensus::OutputOutcome;
 use minimint_ln::contracts::ContractOutcome;
-use minimint_ln::LightningModule;
 use minimint_mint::SigResponse;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -18,14 +17,6 @@ pub enum TransactionStatus {
     },
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
-pub enum OutputOutcome {
-    Mint(Option<SigResponse>),
-    // TODO: maybe include the transaction id eventually. But unclear how to propagate it cleanly right now.
-    Wallet(()),
-    LN(<LightningModule as FederationModule>::TxOutputOutcome),
-}
-
 pub trait Final {
     fn is_final(&self) -> bool;
 }
@@ -57,6 +48,8 @@ impl Final for OutputOutcome {
                     ContractOutcome::Outgoing => true,
                 }
             }
+            // We can't track the progress of outputs we don't understand
+            OutputOutcome::Unknown(_) | OutputOutcome::UnknownModule(_) => true,
         }
     }
 }
@@ -80,6 +73,9 @@ impl TryIntoOutcome for Option<SigResponse> {
             OutputOutcome::Mint(outcome) => Ok(outcome),
             OutputOutcome::Wallet(_) => Err(MismatchingVariant("mint", "wallet")),
             OutputOutcome::LN(_) => Err(MismatchingVariant("mint", "ln")),
+            OutputOutcome::Unknown(_) | OutputOutcome::UnknownModule(_) => {
+                Err(MismatchingVariant("mint", "unknown"))
+            }
         }
     }
 }
@@ -90,6 +86,9 @@ impl TryIntoOutcome for () {
             OutputOutcome::Mint(_) => Err(MismatchingVariant("wallet", "mint")),
             OutputOutcome::Wallet(outcome) => Ok(outcome),
             OutputOutcome::LN(_) => Err(MismatchingVariant("wallet", "ln")),
+            OutputOutcome::Unknown(_) | OutputOutcome::UnknownModule(_) => {
+                Err(MismatchingVariant("wallet", "unknown"))
+            }
         }
     }
 }
@@ -100,6 +99,47 @@ impl TryIntoOutcome for minimint_ln::OutputOutcome {
             OutputOutcome::Mint(_) => Err(MismatchingVariant("ln", "mint")),
             OutputOutcome::Wallet(_) => Err(MismatchingVariant("ln", "wallet")),
             OutputOutcome::LN(outcome) => Ok(outcome),
+            OutputOutcome::Unknown(_) | OutputOutcome::UnknownModule(_) => {
+                Err(MismatchingVariant("ln", "unknown"))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::OutputOutcome;
+    use minimint_api::encoding::ModulePayload;
+
+    #[test]
+    fn test_outcome_json() {
+        let outcome = OutputOutcome::Mint(None);
+        let json = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(json, serde_json::json!({ "Mint": null }));
+        assert_eq!(
+            serde_json::from_value::<OutputOutcome>(json).unwrap(),
+            outcome
+        );
+
+        let unknown = OutputOutcome::Unknown(ModulePayload {
+            module: 42,
+            bytes: vec![1, 2, 3],
+        });
+        let json = serde_json::to_value(&unknown).unwrap();
+        assert_eq!(
+            serde_json::from_value::<OutputOutcome>(json).unwrap(),
+            unknown
+        );
+
+        // Outcomes of modules added by newer servers are kept by name
+        assert_eq!(
+            serde_json::from_value::<OutputOutcome>(serde_json::json!({ "Vault": { "id": 3 } }))
+                .unwrap(),
+            OutputOutcome::UnknownModule("Vault".to_owned())
+        );
+        assert!(
+            serde_json::from_value::<OutputOutcome>(serde_json::json!({ "Mint": "invalid" }))
+                .is_err()
+        );
+    }
+}
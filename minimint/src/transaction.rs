@@ -1,34 +1,61 @@
 use crate::config::FeeConsensus;
+pub use crate::consensus::{Input, Output};
 use bitcoin::hashes::Hash as BitcoinHash;
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{Amount, FederationModule, TransactionId};
+use minimint_api::{Amount, TransactionId};
 use rand::Rng;
 use secp256k1_zkp::{schnorrsig, Secp256k1, Signing};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+/// Version of the transaction format understood by this implementation
+pub const TRANSACTION_VERSION: u16 = 0;
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct Transaction {
+    /// Transactions of unknown versions are rejected, see [`TRANSACTION_VERSION`]
+    pub version: u16,
     pub inputs: Vec<Input>,
     pub outputs: Vec<Output>,
-    pub signature: Option<schnorrsig::Signature>,
+    /// If set the transaction is only accepted till the given epoch or block height, afterwards it
+    /// is guaranteed to fail and can be safely retried
+    pub expiry: Option<TransactionExpiry>,
+    pub signature: Option<TransactionSignature>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub enum Input {
-    // TODO: maybe treat every coin as a seperate input?
-    Mint(<minimint_mint::Mint as FederationModule>::TxInput),
-    Wallet(<minimint_wallet::Wallet as FederationModule>::TxInput),
-    LN(<minimint_ln::LightningModule as FederationModule>::TxInput),
+/// Latest point in consensus at which a transaction may still be accepted
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum TransactionExpiry {
+    /// Last consensus epoch in which the transaction may be accepted
+    Epoch(u64),
+    /// Last Bitcoin block height agreed on by the federation at which the transaction may be
+    /// accepted
+    BlockHeight(u32),
+}
+
+impl TransactionExpiry {
+    /// Checks if the expiry lies before `epoch` or `block_height`. If the federation didn't agree
+    /// on a block height yet block height based expiries can't have passed.
+    pub fn is_expired(&self, epoch: u64, block_height: Option<u32>) -> bool {
+        match *self {
+            TransactionExpiry::Epoch(max_epoch) => epoch > max_epoch,
+            TransactionExpiry::BlockHeight(max_height) => {
+                block_height.map_or(false, |height| height > max_height)
+            }
+        }
+    }
 }
 
-// TODO: check if clippy is right
-#[allow(clippy::large_enum_variant)]
+/// Signature binding the inputs of a transaction to it. Both modes sign the
+/// [`Transaction::tx_hash`].
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub enum Output {
-    Mint(<minimint_mint::Mint as FederationModule>::TxOutput),
-    Wallet(<minimint_wallet::Wallet as FederationModule>::TxOutput),
-    LN(<minimint_ln::LightningModule as FederationModule>::TxOutput),
+pub enum TransactionSignature {
+    /// One MuSig signature by the aggregate of all input keys, requires an interactive signing
+    /// session if the inputs belong to different parties
+    Aggregate(schnorrsig::Signature),
+    /// One Schnorr signature for every key of every input, in the order of the inputs and their
+    /// keys. Input owners can sign independently of each other.
+    PerInput(Vec<Vec<schnorrsig::Signature>>),
 }
 
 /// Common properties of transaction in- and outputs
@@ -82,6 +109,30 @@ impl TransactionItem for Output {
 }
 
 impl Transaction {
+    /// Checks that the transaction uses a format version we understand
+    pub fn validate_version(&self) -> Result<(), TransactionError> {
+        if self.version == TRANSACTION_VERSION {
+            Ok(())
+        } else {
+            Err(TransactionError::UnsupportedVersion(self.version))
+        }
+    }
+
+    /// Checks that the transaction may still be accepted in `epoch` given the federation's
+    /// consensus `block_height`
+    pub fn validate_expiry(
+        &self,
+        epoch: u64,
+        block_height: Option<u32>,
+    ) -> Result<(), TransactionError> {
+        match self.expiry {
+            Some(expiry) if expiry.is_expired(epoch, block_height) => {
+                Err(TransactionError::Expired(expiry))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn validate_funding(&self, fee_consensus: &FeeConsensus) -> Result<(), TransactionError> {
         let in_amount = self
             .inputs
@@ -118,32 +169,52 @@ impl Transaction {
     /// Hash the transaction excluding the signature. This hash is what the signature inside the
     /// transaction commits to. To generate it without already having a signature use [tx_hash_from_parts].
     pub fn tx_hash(&self) -> TransactionId {
-        Self::tx_hash_from_parts(&self.inputs, &self.outputs)
+        Self::tx_hash_versioned(self.version, &self.inputs, &self.outputs, self.expiry)
+    }
+
+    /// Generates the hash of a transaction of the current [`TRANSACTION_VERSION`] without
+    /// constructing the transaction (which would require a signature).
+    pub fn tx_hash_from_parts(
+        inputs: &[Input],
+        outputs: &[Output],
+        expiry: Option<TransactionExpiry>,
+    ) -> TransactionId {
+        Self::tx_hash_versioned(TRANSACTION_VERSION, inputs, outputs, expiry)
     }
 
-    /// Generates the transaction hash without constructing the transaction (which would require a
-    /// signature).
-    pub fn tx_hash_from_parts(inputs: &[Input], outputs: &[Output]) -> TransactionId {
+    /// The hash commits to the version first, so future versions can change how the remainder of
+    /// the transaction is hashed without risking collisions with older transactions.
+    fn tx_hash_versioned(
+        version: u16,
+        inputs: &[Input],
+        outputs: &[Output],
+        expiry: Option<TransactionExpiry>,
+    ) -> TransactionId {
         let mut engine = TransactionId::engine();
+        version
+            .consensus_encode(&mut engine)
+            .expect("write to hash engine can't fail");
         inputs
             .consensus_encode(&mut engine)
             .expect("write to hash engine can't fail");
         outputs
             .consensus_encode(&mut engine)
             .expect("write to hash engine can't fail");
+        expiry
+            .consensus_encode(&mut engine)
+            .expect("write to hash engine can't fail");
         TransactionId::from_engine(engine)
     }
 
+    /// Validates the transaction signature given the keys each input has to be signed with
     pub fn validate_signature(
         &self,
-        keys: impl Iterator<Item = schnorrsig::PublicKey>,
+        keys: &[Vec<schnorrsig::PublicKey>],
     ) -> Result<(), TransactionError> {
-        let keys = keys.collect::<Vec<_>>();
-
         // If there are no keys from inputs there are no inputs to protect from re-binding. This
         // behavior is useful for non-monetary transactions that just announce something, like LN
         // incoming contract offers.
-        if keys.is_empty() {
+        if keys.iter().all(Vec::is_empty) {
             return Ok(());
         }
 
@@ -153,17 +224,47 @@ impl Transaction {
             .as_ref()
             .ok_or(TransactionError::MissingSignature)?;
 
-        let agg_pub_key = agg_keys(&keys);
         let msg =
             secp256k1_zkp::Message::from_slice(&self.tx_hash()[..]).expect("hash has right length");
 
-        if secp256k1_zkp::global::SECP256K1
-            .schnorrsig_verify(signature, &msg, &agg_pub_key)
-            .is_ok()
-        {
-            Ok(())
-        } else {
-            Err(TransactionError::InvalidSignature)
+        match signature {
+            TransactionSignature::Aggregate(signature) => {
+                let agg_pub_key = agg_keys(&keys.iter().flatten().copied().collect::<Vec<_>>());
+
+                if secp256k1_zkp::global::SECP256K1
+                    .schnorrsig_verify(signature, &msg, &agg_pub_key)
+                    .is_ok()
+                {
+                    Ok(())
+                } else {
+                    Err(TransactionError::InvalidSignature)
+                }
+            }
+            TransactionSignature::PerInput(signatures) => {
+                if signatures.len() != keys.len() {
+                    return Err(TransactionError::WrongInputSignatureCount {
+                        inputs: keys.len(),
+                        signatures: signatures.len(),
+                    });
+                }
+
+                for (idx, (input_keys, input_sigs)) in keys.iter().zip(signatures).enumerate() {
+                    if input_keys.len() != input_sigs.len() {
+                        return Err(TransactionError::MissingInputSignature(idx));
+                    }
+
+                    let valid = input_keys.iter().zip(input_sigs).all(|(key, sig)| {
+                        secp256k1_zkp::global::SECP256K1
+                            .schnorrsig_verify(sig, &msg, key)
+                            .is_ok()
+                    });
+                    if !valid {
+                        return Err(TransactionError::InvalidInputSignature(idx));
+                    }
+                }
+
+                Ok(())
+            }
         }
     }
 }
@@ -243,6 +344,458 @@ where
         .expect("Should not fail for cooperative protocol runs")
 }
 
+/// A transaction that is built and signed by multiple parties, similar to a PSBT. Every party
+/// adds its inputs (together with the keys that have to sign them) and outputs, after which the
+/// transaction is frozen using [`PartialTransaction::freeze`] and signed using a two round MuSig
+/// protocol:
+///
+/// 1. Every signer adds its public nonces using [`PartialTransaction::add_nonces`]
+/// 2. Once all nonces are known every signer adds its partial signatures using
+///    [`PartialTransaction::add_partial_signatures`]
+///
+/// Alternatively every signer can non-interactively add signatures for its inputs using
+/// [`PartialTransaction::add_input_signatures`], see [`TransactionSignature::PerInput`]. The two
+/// modes can't be mixed.
+///
+/// Copies of the partial transaction held by different parties can be merged using
+/// [`PartialTransaction::combine`]. When all signatures are present
+/// [`PartialTransaction::finalize`] produces the [`Transaction`] that can be submitted.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PartialTransaction {
+    pub version: u16,
+    pub inputs: Vec<Input>,
+    /// Keys that have to sign each input, in the same order the federation derives them
+    pub input_keys: Vec<Vec<schnorrsig::PublicKey>>,
+    pub outputs: Vec<Output>,
+    pub expiry: Option<TransactionExpiry>,
+    /// Set once all parties contributed their in- and outputs, which fixes the transaction id
+    pub frozen: bool,
+    /// Public nonces, one per signing key in the order of [`PartialTransaction::signing_keys`]
+    pub nonces: Vec<Option<PublicNonce>>,
+    /// Partial signatures, one per signing key in the order of [`PartialTransaction::signing_keys`]
+    pub partial_sigs: Vec<Option<PartialSignature>>,
+    /// Non-interactive signatures, one per signing key in the order of
+    /// [`PartialTransaction::signing_keys`]
+    pub input_sigs: Vec<Option<schnorrsig::Signature>>,
+}
+
+/// Serialized MuSig public nonce
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PublicNonce(pub Vec<u8>);
+
+/// Serialized MuSig partial signature
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PartialSignature(pub Vec<u8>);
+
+impl PartialTransaction {
+    /// Creates an empty partial transaction of the current [`TRANSACTION_VERSION`]
+    pub fn new() -> PartialTransaction {
+        PartialTransaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![],
+            input_keys: vec![],
+            outputs: vec![],
+            expiry: None,
+            frozen: false,
+            nonces: vec![],
+            partial_sigs: vec![],
+            input_sigs: vec![],
+        }
+    }
+
+    /// Adds an input that has to be signed by `keys`. Keys have to be supplied in the same order
+    /// the federation returns them when validating the input.
+    pub fn add_input(
+        &mut self,
+        input: Input,
+        keys: Vec<schnorrsig::PublicKey>,
+    ) -> Result<(), PartialTransactionError> {
+        self.ensure_not_frozen()?;
+        self.inputs.push(input);
+        self.input_keys.push(keys);
+        Ok(())
+    }
+
+    pub fn add_output(&mut self, output: Output) -> Result<(), PartialTransactionError> {
+        self.ensure_not_frozen()?;
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    /// Limits the time the transaction may be accepted in, see [`Transaction::expiry`]
+    pub fn set_expiry(
+        &mut self,
+        expiry: Option<TransactionExpiry>,
+    ) -> Result<(), PartialTransactionError> {
+        self.ensure_not_frozen()?;
+        self.expiry = expiry;
+        Ok(())
+    }
+
+    /// Prevents further changes to the in- and outputs so the transaction can be signed. Parties
+    /// that only contributed outputs have to wait for the frozen transaction too, only then its id
+    /// under which their coins are issued is known.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// Changing in- or outputs after freezing would invalidate the nonces and signatures
+    pub fn is_frozen(&self) -> bool {
+        self.frozen || !self.nonces.is_empty() || !self.input_sigs.is_empty()
+    }
+
+    fn ensure_not_frozen(&self) -> Result<(), PartialTransactionError> {
+        if self.is_frozen() {
+            Err(PartialTransactionError::Frozen)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn ensure_frozen(&self) -> Result<(), PartialTransactionError> {
+        if self.is_frozen() {
+            Ok(())
+        } else {
+            Err(PartialTransactionError::NotFrozen)
+        }
+    }
+
+    /// Hash of the transaction that will be signed, which is only final once it is frozen
+    pub fn tx_hash(&self) -> TransactionId {
+        Transaction::tx_hash_versioned(self.version, &self.inputs, &self.outputs, self.expiry)
+    }
+
+    /// All keys that have to sign the transaction in the order of their aggregation
+    pub fn signing_keys(&self) -> impl Iterator<Item = &schnorrsig::PublicKey> {
+        self.input_keys.iter().flatten()
+    }
+
+    /// Adds public nonces for all signing positions of `keys` to the frozen transaction. The
+    /// nonces are derived from `session_seed`, which has to be kept secret and used for exactly one
+    /// call to [`PartialTransaction::add_partial_signatures`] since reusing nonces leaks the
+    /// secret keys.
+    pub fn add_nonces<C: Signing>(
+        &mut self,
+        keys: &[schnorrsig::KeyPair],
+        session_seed: [u8; 32],
+        ctx: &Secp256k1<C>,
+    ) -> Result<(), PartialTransactionError> {
+        self.ensure_frozen()?;
+        if !self.input_sigs.is_empty() {
+            return Err(PartialTransactionError::MixedSignatureModes);
+        }
+
+        let (pre_session, msg) = self.signing_context(ctx)?;
+        let key_count = self.signing_keys().count();
+        self.nonces.resize(key_count, None);
+
+        let own_nonces = self
+            .own_positions(keys, ctx)
+            .map(|(idx, key)| {
+                let session_id = position_session_id(session_seed, idx);
+                let (_, pub_nonce) = pre_session
+                    .nonce_gen(ctx, &session_id, key, &msg, None)
+                    .expect("should not fail for valid inputs (ensured by type system)");
+                (idx, PublicNonce(pub_nonce.serialize().to_vec()))
+            })
+            .collect::<Vec<_>>();
+
+        if own_nonces.is_empty() {
+            return Err(PartialTransactionError::NoOwnKeys);
+        }
+
+        for (idx, nonce) in own_nonces {
+            set_slot(&mut self.nonces[idx], nonce)
+                .map_err(|_| PartialTransactionError::ConflictingNonce(idx))?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds partial signatures for all signing positions of `keys`. Requires the nonces of all
+    /// signers to be present and `session_seed` to be the one previously used in
+    /// [`PartialTransaction::add_nonces`].
+    pub fn add_partial_signatures<C: Signing>(
+        &mut self,
+        keys: &[schnorrsig::KeyPair],
+        session_seed: [u8; 32],
+        ctx: &Secp256k1<C>,
+    ) -> Result<(), PartialTransactionError> {
+        let (pre_session, msg) = self.signing_context(ctx)?;
+        let pub_nonces = self.pub_nonces()?;
+        let agg_nonce = secp256k1_zkp::MusigAggNonce::new(ctx, &pub_nonces)
+            .map_err(|_| PartialTransactionError::InvalidNonce)?;
+        let session = pre_session
+            .nonce_process(ctx, &agg_nonce, &msg, None)
+            .map_err(|_| PartialTransactionError::InvalidNonce)?;
+
+        let own_sigs = self
+            .own_positions(keys, ctx)
+            .map(|(idx, key)| {
+                let session_id = position_session_id(session_seed, idx);
+                let (mut sec_nonce, pub_nonce) = pre_session
+                    .nonce_gen(ctx, &session_id, key, &msg, None)
+                    .expect("should not fail for valid inputs (ensured by type system)");
+
+                // Make sure we don't sign using a nonce that wasn't committed to in the first round
+                let committed_nonce = self.nonces[idx].as_ref().map(|nonce| &nonce.0[..]);
+                if committed_nonce != Some(&pub_nonce.serialize()[..]) {
+                    return Err(PartialTransactionError::ConflictingNonce(idx));
+                }
+
+                let partial_sig = session
+                    .partial_sign(ctx, &mut sec_nonce, key, &pre_session)
+                    .map_err(|_| PartialTransactionError::InvalidNonce)?;
+                Ok((idx, PartialSignature(partial_sig.serialize().to_vec())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if own_sigs.is_empty() {
+            return Err(PartialTransactionError::NoOwnKeys);
+        }
+
+        self.partial_sigs.resize(pub_nonces.len(), None);
+        for (idx, sig) in own_sigs {
+            set_slot(&mut self.partial_sigs[idx], sig)
+                .map_err(|_| PartialTransactionError::ConflictingPartialSignature(idx))?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds non-interactive signatures for all signing positions of `keys` to the frozen
+    /// transaction.
+    pub fn add_input_signatures<R, C>(
+        &mut self,
+        keys: &[schnorrsig::KeyPair],
+        ctx: &Secp256k1<C>,
+        mut rng: R,
+    ) -> Result<(), PartialTransactionError>
+    where
+        R: rand::RngCore + rand::CryptoRng,
+        C: Signing,
+    {
+        self.ensure_frozen()?;
+        if !self.nonces.is_empty() {
+            return Err(PartialTransactionError::MixedSignatureModes);
+        }
+
+        let (_, msg) = self.signing_context(ctx)?;
+        let own_sigs = self
+            .own_positions(keys, ctx)
+            .map(|(idx, key)| {
+                (
+                    idx,
+                    ctx.schnorrsig_sign_with_aux_rand(&msg, key, &rng.gen()),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if own_sigs.is_empty() {
+            return Err(PartialTransactionError::NoOwnKeys);
+        }
+
+        let key_count = self.signing_keys().count();
+        self.input_sigs.resize(key_count, None);
+        for (idx, sig) in own_sigs {
+            set_slot(&mut self.input_sigs[idx], sig)
+                .map_err(|_| PartialTransactionError::ConflictingPartialSignature(idx))?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges the nonces and partial signatures of another copy of the same transaction into this
+    /// one. The result is frozen if either copy was.
+    pub fn combine(&mut self, other: PartialTransaction) -> Result<(), PartialTransactionError> {
+        if self.version != other.version
+            || self.inputs != other.inputs
+            || self.input_keys != other.input_keys
+            || self.outputs != other.outputs
+            || self.expiry != other.expiry
+        {
+            return Err(PartialTransactionError::MismatchingTransaction);
+        }
+
+        self.frozen |= other.frozen;
+        merge_slots(&mut self.nonces, other.nonces)
+            .map_err(PartialTransactionError::ConflictingNonce)?;
+        merge_slots(&mut self.partial_sigs, other.partial_sigs)
+            .map_err(PartialTransactionError::ConflictingPartialSignature)?;
+        merge_slots(&mut self.input_sigs, other.input_sigs)
+            .map_err(PartialTransactionError::ConflictingPartialSignature)?;
+
+        if !self.nonces.is_empty() && !self.input_sigs.is_empty() {
+            return Err(PartialTransactionError::MixedSignatureModes);
+        }
+
+        Ok(())
+    }
+
+    /// Aggregates the partial signatures and returns the finished transaction
+    pub fn finalize<C: Signing>(
+        self,
+        ctx: &Secp256k1<C>,
+    ) -> Result<Transaction, PartialTransactionError> {
+        let signing_keys = self.signing_keys().copied().collect::<Vec<_>>();
+
+        let signature = if signing_keys.is_empty() {
+            None
+        } else if !self.input_sigs.is_empty() {
+            if self.input_sigs.len() != signing_keys.len() {
+                return Err(PartialTransactionError::MissingPartialSignatures);
+            }
+
+            let mut input_sigs = self.input_sigs.iter();
+            let signatures = self
+                .input_keys
+                .iter()
+                .map(|keys| {
+                    input_sigs
+                        .by_ref()
+                        .take(keys.len())
+                        .map(|sig| sig.ok_or(PartialTransactionError::MissingPartialSignatures))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Some(TransactionSignature::PerInput(signatures))
+        } else {
+            let (pre_session, msg) = self.signing_context(ctx)?;
+            let pub_nonces = self.pub_nonces()?;
+            let agg_nonce = secp256k1_zkp::MusigAggNonce::new(ctx, &pub_nonces)
+                .map_err(|_| PartialTransactionError::InvalidNonce)?;
+            let session = pre_session
+                .nonce_process(ctx, &agg_nonce, &msg, None)
+                .map_err(|_| PartialTransactionError::InvalidNonce)?;
+
+            if self.partial_sigs.len() != signing_keys.len() {
+                return Err(PartialTransactionError::MissingPartialSignatures);
+            }
+            let partial_sigs = self
+                .partial_sigs
+                .iter()
+                .map(|sig| {
+                    let sig = sig
+                        .as_ref()
+                        .ok_or(PartialTransactionError::MissingPartialSignatures)?;
+                    secp256k1_zkp::MusigPartialSignature::from_slice(&sig.0)
+                        .map_err(|_| PartialTransactionError::InvalidSignature)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let signature = session
+                .partial_sig_agg(ctx, &partial_sigs)
+                .map_err(|_| PartialTransactionError::InvalidSignature)?;
+            Some(TransactionSignature::Aggregate(signature))
+        };
+
+        let transaction = Transaction {
+            version: self.version,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            expiry: self.expiry,
+            signature,
+        };
+
+        // Catch invalid partial signatures before bothering the federation
+        transaction
+            .validate_signature(&self.input_keys)
+            .map_err(|_| PartialTransactionError::InvalidSignature)?;
+
+        Ok(transaction)
+    }
+
+    fn signing_context<C: Signing>(
+        &self,
+        ctx: &Secp256k1<C>,
+    ) -> Result<(secp256k1_zkp::MusigPreSession, secp256k1_zkp::Message), PartialTransactionError>
+    {
+        let keys = self.signing_keys().copied().collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(PartialTransactionError::NoSigningKeys);
+        }
+
+        let msg =
+            secp256k1_zkp::Message::from_slice(&self.tx_hash()[..]).expect("hash has right length");
+        Ok((new_pre_session(&keys, ctx), msg))
+    }
+
+    fn own_positions<'a, C: Signing>(
+        &'a self,
+        keys: &'a [schnorrsig::KeyPair],
+        ctx: &'a Secp256k1<C>,
+    ) -> impl Iterator<Item = (usize, &'a schnorrsig::KeyPair)> + 'a {
+        self.signing_keys()
+            .enumerate()
+            .filter_map(move |(idx, pub_key)| {
+                keys.iter()
+                    .find(|key| schnorrsig::PublicKey::from_keypair(ctx, key) == *pub_key)
+                    .map(|key| (idx, key))
+            })
+    }
+
+    fn pub_nonces(&self) -> Result<Vec<secp256k1_zkp::MusigPubNonce>, PartialTransactionError> {
+        if self.nonces.len() != self.signing_keys().count() {
+            return Err(PartialTransactionError::MissingNonces);
+        }
+
+        self.nonces
+            .iter()
+            .map(|nonce| {
+                let nonce = nonce
+                    .as_ref()
+                    .ok_or(PartialTransactionError::MissingNonces)?;
+                secp256k1_zkp::MusigPubNonce::from_slice(&nonce.0)
+                    .map_err(|_| PartialTransactionError::InvalidNonce)
+            })
+            .collect()
+    }
+}
+
+impl Default for PartialTransaction {
+    fn default() -> Self {
+        PartialTransaction::new()
+    }
+}
+
+/// Derives a distinct session id for every signing position, otherwise a key that has to sign at
+/// multiple positions would reuse its nonce.
+fn position_session_id(session_seed: [u8; 32], position: usize) -> [u8; 32] {
+    let mut engine = bitcoin::hashes::sha256::Hash::engine();
+    session_seed
+        .consensus_encode(&mut engine)
+        .expect("write to hash engine can't fail");
+    (position as u64)
+        .consensus_encode(&mut engine)
+        .expect("write to hash engine can't fail");
+    bitcoin::hashes::sha256::Hash::from_engine(engine).into_inner()
+}
+
+fn set_slot<T: Eq>(slot: &mut Option<T>, value: T) -> Result<(), ()> {
+    match slot {
+        Some(existing) if *existing != value => Err(()),
+        _ => {
+            *slot = Some(value);
+            Ok(())
+        }
+    }
+}
+
+/// Merges `other` into `slots`, returning the index of the first conflicting slot on error
+fn merge_slots<T: Eq>(slots: &mut Vec<Option<T>>, other: Vec<Option<T>>) -> Result<(), usize> {
+    if slots.len() < other.len() {
+        slots.resize_with(other.len(), || None);
+    }
+
+    for (idx, value) in other.into_iter().enumerate() {
+        if let Some(value) = value {
+            set_slot(&mut slots[idx], value).map_err(|_| idx)?;
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Error)]
 pub enum TransactionError {
     #[error("The transaction is insufficiently funded (in={inputs}, out={outputs}, fee={fee})")]
@@ -255,4 +808,171 @@ pub enum TransactionError {
     InvalidSignature,
     #[error("The transaction did not have a signature although there were inputs to be signed")]
     MissingSignature,
+    #[error("The transaction has unsupported version {0}")]
+    UnsupportedVersion(u16),
+    #[error("The transaction expired ({0:?})")]
+    Expired(TransactionExpiry),
+    #[error("The transaction has {inputs} inputs but signatures for {signatures}")]
+    WrongInputSignatureCount { inputs: usize, signatures: usize },
+    #[error("Input {0} is missing signatures")]
+    MissingInputSignature(usize),
+    #[error("The signature of input {0} is invalid")]
+    InvalidInputSignature(usize),
+}
+
+#[derive(Debug, Error)]
+pub enum PartialTransactionError {
+    #[error("The transaction can't be modified anymore since it was frozen for signing")]
+    Frozen,
+    #[error("The transaction has to be frozen before signing")]
+    NotFrozen,
+    #[error("The partial transactions describe different transactions")]
+    MismatchingTransaction,
+    #[error("The transaction doesn't have to be signed by any of our keys")]
+    NoOwnKeys,
+    #[error("The transaction doesn't have any inputs that need to be signed")]
+    NoSigningKeys,
+    #[error("Not all signers supplied their nonces yet")]
+    MissingNonces,
+    #[error("Not all signers supplied their partial signatures yet")]
+    MissingPartialSignatures,
+    #[error("Conflicting nonces for signing position {0}")]
+    ConflictingNonce(usize),
+    #[error("Nonces and non-interactive signatures can't be mixed")]
+    MixedSignatureModes,
+    #[error("Conflicting partial signatures for signing position {0}")]
+    ConflictingPartialSignature(usize),
+    #[error("One of the nonces is invalid")]
+    InvalidNonce,
+    #[error("The partial signatures don't aggregate to a valid signature")]
+    InvalidSignature,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::{
+        Input, PartialTransaction, PartialTransactionError, TransactionError, TransactionSignature,
+    };
+    use bitcoin::hashes::Hash;
+    use minimint_api::Amount;
+    use minimint_ln::contracts::ContractId;
+    use minimint_ln::ContractInput;
+    use rand::Rng;
+    use secp256k1_zkp::schnorrsig;
+
+    fn ln_input(id: u8) -> Input {
+        Input::LN(ContractInput {
+            crontract_id: ContractId::from_inner([id; 32]),
+            amount: Amount::from_sat(1000),
+            witness: None,
+        })
+    }
+
+    #[test]
+    fn test_collaborative_signing() {
+        let mut rng = rand::rngs::OsRng::new().unwrap();
+        let ctx = secp256k1_zkp::SECP256K1;
+
+        let alice = schnorrsig::KeyPair::new(ctx, &mut rng);
+        let bob = schnorrsig::KeyPair::new(ctx, &mut rng);
+        let alice_pk = schnorrsig::PublicKey::from_keypair(ctx, &alice);
+        let bob_pk = schnorrsig::PublicKey::from_keypair(ctx, &bob);
+
+        let mut ptx = PartialTransaction::new();
+        ptx.add_input(ln_input(0), vec![alice_pk]).unwrap();
+        // Bob signs at two positions, which must not lead to nonce reuse
+        ptx.add_input(ln_input(1), vec![bob_pk, bob_pk]).unwrap();
+        assert!(matches!(
+            ptx.clone().add_nonces(&[alice], rng.gen(), ctx),
+            Err(PartialTransactionError::NotFrozen)
+        ));
+        ptx.freeze();
+
+        let mut alice_ptx = ptx.clone();
+        let mut bob_ptx = ptx;
+        let alice_seed = rng.gen();
+        let bob_seed = rng.gen();
+
+        alice_ptx.add_nonces(&[alice], alice_seed, ctx).unwrap();
+        bob_ptx.add_nonces(&[bob], bob_seed, ctx).unwrap();
+        assert!(matches!(
+            alice_ptx.add_input(ln_input(2), vec![]),
+            Err(PartialTransactionError::Frozen)
+        ));
+        assert!(matches!(
+            alice_ptx.add_partial_signatures(&[alice], alice_seed, ctx),
+            Err(PartialTransactionError::MissingNonces)
+        ));
+
+        alice_ptx.combine(bob_ptx.clone()).unwrap();
+        bob_ptx.combine(alice_ptx.clone()).unwrap();
+
+        alice_ptx
+            .add_partial_signatures(&[alice], alice_seed, ctx)
+            .unwrap();
+        bob_ptx
+            .add_partial_signatures(&[bob], bob_seed, ctx)
+            .unwrap();
+        assert!(matches!(
+            bob_ptx.clone().finalize(ctx),
+            Err(PartialTransactionError::MissingPartialSignatures)
+        ));
+
+        alice_ptx.combine(bob_ptx).unwrap();
+        let tx = alice_ptx.finalize(ctx).unwrap();
+        tx.validate_signature(&[vec![alice_pk], vec![bob_pk, bob_pk]])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_per_input_signatures() {
+        let mut rng = rand::rngs::OsRng::new().unwrap();
+        let ctx = secp256k1_zkp::SECP256K1;
+
+        let alice = schnorrsig::KeyPair::new(ctx, &mut rng);
+        let bob = schnorrsig::KeyPair::new(ctx, &mut rng);
+        let alice_pk = schnorrsig::PublicKey::from_keypair(ctx, &alice);
+        let bob_pk = schnorrsig::PublicKey::from_keypair(ctx, &bob);
+        let input_keys = [vec![alice_pk], vec![bob_pk]];
+
+        let mut alice_ptx = PartialTransaction::new();
+        alice_ptx.add_input(ln_input(0), vec![alice_pk]).unwrap();
+        alice_ptx.add_input(ln_input(1), vec![bob_pk]).unwrap();
+        alice_ptx.freeze();
+        let mut bob_ptx = alice_ptx.clone();
+
+        alice_ptx
+            .add_input_signatures(&[alice], ctx, &mut rng)
+            .unwrap();
+        bob_ptx.add_input_signatures(&[bob], ctx, &mut rng).unwrap();
+        assert!(matches!(
+            bob_ptx.add_nonces(&[bob], rng.gen(), ctx),
+            Err(PartialTransactionError::MixedSignatureModes)
+        ));
+
+        alice_ptx.combine(bob_ptx).unwrap();
+        let mut tx = alice_ptx.finalize(ctx).unwrap();
+        tx.validate_signature(&input_keys).unwrap();
+
+        // Swapping the signatures makes both inputs invalid, the first one is reported
+        if let Some(TransactionSignature::PerInput(sigs)) = tx.signature.as_mut() {
+            sigs.swap(0, 1);
+        }
+        assert!(matches!(
+            tx.validate_signature(&input_keys),
+            Err(TransactionError::InvalidInputSignature(0))
+        ));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let epoch_expiry = TransactionExpiry::Epoch(10);
+        assert!(!epoch_expiry.is_expired(10, Some(1000)));
+        assert!(epoch_expiry.is_expired(11, None));
+
+        let height_expiry = TransactionExpiry::BlockHeight(100);
+        assert!(!height_expiry.is_expired(1000, Some(100)));
+        assert!(height_expiry.is_expired(0, Some(101)));
+        assert!(!height_expiry.is_expired(1000, None));
+    }
 }
@@ -1,6 +1,8 @@
 use futures::{Future, StreamExt, TryFutureExt};
 use minimint::outcome::{MismatchingVariant, TransactionStatus, TryIntoOutcome};
 use minimint::transaction::Transaction;
+use minimint_api::module::api::{module_api_path, TypedApiEndpoint};
+use minimint_api::module::audit::Audit;
 use minimint_api::{OutPoint, PeerId, TransactionId};
 use reqwest::Url;
 use serde::de::DeserializeOwned;
@@ -69,12 +71,52 @@ impl FederationApi {
         }
     }
 
+    /// Fetch the assets and liabilities of the federation's modules
+    pub async fn fetch_audit(&self) -> Result<Audit> {
+        self.get("/audit").await
+    }
+
     /// Submit a transaction to all federtion members
     pub async fn submit_transaction(&self, tx: Transaction) -> Result<TransactionId> {
         // TODO: check the id is correct
         self.put("/transaction", tx).await
     }
 
+    /// Query the module API endpoint `E` of all federation members
+    pub async fn request<E>(&self, request: E::Request) -> Result<E::Response>
+    where
+        E: TypedApiEndpoint,
+        E::Request: Clone + Send + Sync,
+        E::Response: Eq + Hash,
+    {
+        self.post(&module_api_path::<E::State>(E::PATH), request)
+            .await
+    }
+
+    /// Query the module API endpoint `E` of every federation member individually, which is needed
+    /// for data that differs between members like signature shares. Returns every member's result.
+    pub async fn request_each<E>(&self, request: E::Request) -> Vec<(PeerId, Result<E::Response>)>
+    where
+        E: TypedApiEndpoint,
+    {
+        let path = module_api_path::<E::State>(E::PATH);
+        let requests = self
+            .federation_member_api_hosts
+            .iter()
+            .map(|(id, base_url)| {
+                let request_url = base_url.join(&path).expect("Invalid API endpoint");
+                let request = self.http_client.post(request_url).json(&request);
+                async move {
+                    let response = request
+                        .send()
+                        .and_then(|resp| async { resp.error_for_status()?.json().await })
+                        .await;
+                    (*id, response.map_err(ApiError::from))
+                }
+            });
+        futures::future::join_all(requests).await
+    }
+
     /// Send a GET request to all federation members and make sure that there is consensus about the
     /// return value between members.
     ///
@@ -124,6 +166,32 @@ impl FederationApi {
         .await
     }
 
+    /// Send a POST request to all federation members and make sure that there is consensus about
+    /// the return value between members.
+    ///
+    /// # Panics
+    /// If `api_endpoint` is not a valid relative URL.
+    pub async fn post<S, R>(&self, api_endpoint: &str, data: S) -> Result<R>
+    where
+        S: Serialize + Clone + Send + Sync,
+        R: DeserializeOwned + Eq + Hash,
+    {
+        self.parallel_http_op(|http_client, id, base_url| {
+            let cloned_data = data.clone();
+            Box::pin(async move {
+                let request_url = base_url.join(api_endpoint).expect("Invalid API endpoint");
+                let response = http_client
+                    .post(request_url)
+                    .json(&cloned_data)
+                    .send()
+                    .and_then(|resp| async { resp.error_for_status()?.json().await })
+                    .await;
+                (id, response)
+            })
+        })
+        .await
+    }
+
     // TODO: check for consistency of replies, needs epoch-versioned API replies
     /// This function is used to run the same HTTP request against multiple endpoint belonging to
     /// different federation members and returns the first success or if none occurs the last error.
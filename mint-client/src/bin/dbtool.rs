@@ -0,0 +1,146 @@
+use minimint::config::DatabaseBackend;
+use minimint_api::db::registry::PrefixRegistry;
+use minimint_api::db::RawDatabase;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(about = "Inspect the database of a guardian or client, which must not be running")]
+struct Options {
+    db_path: PathBuf,
+    #[structopt(
+        long,
+        default_value = "sled",
+        help = "Database backend (sled or rocksdb)"
+    )]
+    db_backend: DatabaseBackend,
+    #[structopt(long, help = "Open a client instead of a guardian database")]
+    client: bool,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(about = "List all known prefixes and how many entries are stored under them")]
+    Prefixes,
+    #[structopt(about = "Print entries decoded as JSON")]
+    Dump {
+        #[structopt(
+            long = "prefix",
+            parse(try_from_str = parse_prefix),
+            help = "Only print entries with this prefix (hex), can be repeated"
+        )]
+        prefixes: Vec<u8>,
+        #[structopt(
+            long = "id",
+            help = "Only print entries whose key contains this id, e.g. a transaction id, can be repeated"
+        )]
+        ids: Vec<String>,
+    },
+}
+
+/// Database entry as printed by the dump command, entries that couldn't be decoded are printed as
+/// hex together with the reason
+#[derive(Serialize)]
+struct Entry {
+    module: Option<&'static str>,
+    table: Option<&'static str>,
+    key: Value,
+    value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn main() {
+    let opts: Options = StructOpt::from_args();
+
+    let tree = if opts.client { "mint-client" } else { "mint" };
+    let db = opts
+        .db_backend
+        .open(&opts.db_path, tree)
+        .expect("Couldn't open database");
+
+    // Server and client prefixes don't overlap, so a single registry can decode both databases
+    let registry =
+        minimint::db::prefix_registry().with_registry(mint_client::db::prefix_registry());
+
+    match opts.command {
+        Command::Prefixes => {
+            for (module, prefix) in registry.prefixes() {
+                let entries = db.raw_find_by_prefix(&[prefix.prefix]).count();
+                println!(
+                    "{:#04x}  {:<20}  {:<40}  {}",
+                    prefix.prefix, module, prefix.name, entries
+                );
+            }
+        }
+        Command::Dump { prefixes, ids } => {
+            let key_prefixes = if prefixes.is_empty() {
+                vec![vec![]]
+            } else {
+                prefixes.into_iter().map(|prefix| vec![prefix]).collect()
+            };
+
+            for key_prefix in key_prefixes {
+                for res in db.raw_find_by_prefix(&key_prefix) {
+                    let (key, value) = res.expect("DB error");
+                    let entry = decode(&registry, &key, &value);
+
+                    if !ids.is_empty() && !ids.iter().any(|id| contains_id(&entry.key, id)) {
+                        continue;
+                    }
+
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&entry).expect("encoding error")
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn decode(registry: &PrefixRegistry, key: &[u8], value: &[u8]) -> Entry {
+    let table = key.first().and_then(|prefix| registry.find(*prefix));
+    let raw_entry = |error: String| Entry {
+        module: table.map(|(module, _)| module),
+        table: table.map(|(_, prefix)| prefix.name),
+        key: Value::String(hex::encode(key)),
+        value: Value::String(hex::encode(value)),
+        error: Some(error),
+    };
+
+    let (module, prefix) = match table {
+        Some(table) => table,
+        None => return raw_entry("Unknown prefix".to_owned()),
+    };
+
+    match (prefix.decode)(key, value) {
+        Ok(decoded) => Entry {
+            module: Some(module),
+            table: Some(prefix.name),
+            key: decoded.key,
+            value: decoded.value,
+            error: None,
+        },
+        Err(e) => raw_entry(e.to_string()),
+    }
+}
+
+/// Checks if any string or number contained in `value` equals `id`
+fn contains_id(value: &Value, id: &str) -> bool {
+    match value {
+        Value::String(s) => s == id,
+        Value::Number(n) => n.to_string() == id,
+        Value::Array(values) => values.iter().any(|v| contains_id(v, id)),
+        Value::Object(fields) => fields.values().any(|v| contains_id(v, id)),
+        Value::Null | Value::Bool(_) => false,
+    }
+}
+
+fn parse_prefix(s: &str) -> Result<u8, std::num::ParseIntError> {
+    u8::from_str_radix(s.trim_start_matches("0x"), 16)
+}
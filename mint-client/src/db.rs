@@ -0,0 +1,202 @@
+use minimint::modules::mint::tiered::coins::Coins;
+use minimint::modules::mint::BlindToken;
+use minimint_api::db::migration::{MigrationRegistry, ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry_with, DbPrefix, DecodedEntry, PrefixRegistry};
+use minimint_api::db::{DatabaseKeyPrefixConst, DecodingError};
+use minimint_api::encoding::{Decodable, Encodable};
+use minimint_api::TransactionId;
+use secp256k1_zkp::{schnorrsig, Secp256k1};
+use serde::Serialize;
+
+pub const DB_PREFIX_PARTIAL_TX_SIGNING_KEY: u8 = 0x23;
+pub const DB_PREFIX_PARTIAL_TX_SIGNING_SESSION: u8 = 0x24;
+pub const DB_PREFIX_PARTIAL_TX_OUTPUT: u8 = 0x25;
+
+/// Key prefixes of the partial transaction signing state
+pub const DB_PREFIXES: &[DbPrefix] = &[
+    DbPrefix {
+        prefix: DB_PREFIX_PARTIAL_TX_SIGNING_KEY,
+        name: "Partial Transaction Signing Keys",
+        key: "public key",
+        value: "key pair",
+        decode: decode_signing_key,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PARTIAL_TX_SIGNING_SESSION,
+        name: "Partial Transaction Signing Sessions",
+        key: "transaction id",
+        value: "nonce seed",
+        decode: decode_signing_session,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PARTIAL_TX_OUTPUT,
+        name: "Pending Partial Transaction Outputs",
+        key: "blind tokens",
+        value: "coin finalization data",
+        decode: decode_pending_output,
+    },
+];
+
+/// Only shows the public half of signing keys when inspecting the database
+fn decode_signing_key(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    decode_entry_with::<SigningKeyKey, schnorrsig::KeyPair, _, _>(key, value, |key_pair| {
+        schnorrsig::PublicKey::from_keypair(&Secp256k1::signing_only(), &key_pair)
+    })
+}
+
+/// Never shows the nonce seed when inspecting the database since it allows recovering the key
+fn decode_signing_session(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    decode_entry_with::<SigningSessionKey, [u8; 32], _, _>(key, value, |_| "<redacted>")
+}
+
+/// Like for issuances only the nonces of the requested coins are shown, not their spend keys
+fn decode_pending_output(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    decode_entry_with::<PendingOutputKey, crate::mint::CoinFinalizationData, _, _>(
+        key,
+        value,
+        |data| data.nonces(),
+    )
+}
+
+/// Version of the partial transaction database layout
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(0);
+
+/// Secret key we need to sign a partial transaction with
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
+pub struct SigningKeyKey(pub schnorrsig::PublicKey);
+
+impl DatabaseKeyPrefixConst for SigningKeyKey {
+    const DB_PREFIX: u8 = DB_PREFIX_PARTIAL_TX_SIGNING_KEY;
+}
+
+/// Seed of the nonces we committed to for a partial transaction, deleted once we signed
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
+pub struct SigningSessionKey(pub TransactionId);
+
+impl DatabaseKeyPrefixConst for SigningSessionKey {
+    const DB_PREFIX: u8 = DB_PREFIX_PARTIAL_TX_SIGNING_SESSION;
+}
+
+/// Coin finalization data of an output we added to a partial transaction whose id isn't known yet
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
+pub struct PendingOutputKey(pub Coins<BlindToken>);
+
+impl DatabaseKeyPrefixConst for PendingOutputKey {
+    const DB_PREFIX: u8 = DB_PREFIX_PARTIAL_TX_OUTPUT;
+}
+
+/// Migrations of all database schemas used by the client, run before the database is used
+pub fn migrations() -> MigrationRegistry {
+    MigrationRegistry::new()
+        .with_module(ModuleMigrations::new("client", DB_SCHEMA_VERSION))
+        .with_module(crate::mint::db::migrations())
+        .with_module(crate::wallet::db::migrations())
+        .with_module(crate::ln::db::migrations())
+}
+
+/// Key prefixes used by the client. Schema versions are stored under the same prefix as in the
+/// server and thus are part of [`minimint::db::prefix_registry`].
+pub fn prefix_registry() -> PrefixRegistry {
+    PrefixRegistry::new()
+        .with_module("Client Mint", crate::mint::db::DB_PREFIXES)
+        .with_module("Client Wallet", crate::wallet::db::DB_PREFIXES)
+        .with_module("Client Lightning", crate::ln::db::DB_PREFIXES)
+        .with_module("Partial Transactions", DB_PREFIXES)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    const DOCS_BEGIN: &str = "<!-- BEGIN GENERATED PREFIX TABLES -->\n";
+    const DOCS_END: &str = "<!-- END GENERATED PREFIX TABLES -->";
+
+    #[test]
+    fn test_prefix_registry() {
+        minimint::db::prefix_registry()
+            .with_registry(super::prefix_registry())
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_secrets_redacted() {
+        use bitcoin_hashes::Hash;
+        use minimint::modules::mint::Keys;
+        use minimint_api::db::{DatabaseKeyPrefix, SerializableDatabaseValue};
+        use minimint_api::Amount;
+        use serde_json::json;
+        use tbs::MessageDerivation;
+
+        let secret = [1u8; 32];
+        let secret_json = serde_json::to_value(&secret).unwrap();
+
+        let session_key =
+            super::SigningSessionKey(minimint_api::TransactionId::from_inner([2u8; 32]));
+        let session = super::decode_signing_session(
+            &DatabaseKeyPrefix::to_bytes(&session_key),
+            &secret.to_bytes(),
+        )
+        .unwrap();
+        assert_eq!(session.value, json!("<redacted>"));
+
+        let peg_in_key = crate::wallet::db::PegInKey {
+            peg_in_script: bitcoin::Script::new(),
+        };
+        let peg_in = (crate::wallet::db::DB_PREFIXES[0].decode)(
+            &DatabaseKeyPrefix::to_bytes(&peg_in_key),
+            &secret.to_bytes(),
+        )
+        .unwrap();
+        assert_ne!(peg_in.value, secret_json);
+        assert!(peg_in.value.is_string());
+
+        let tiers = [Amount::from_sat(1), Amount::from_sat(2)]
+            .iter()
+            .map(|&amount| (amount, ()))
+            .collect::<Keys<()>>();
+        let (finalization_data, sign_request) = crate::mint::CoinFinalizationData::new(
+            Amount::from_sat(3),
+            &tiers,
+            &MessageDerivation::default(),
+            &secp256k1_zkp::Secp256k1::new(),
+            rand::rngs::OsRng::new().unwrap(),
+        );
+        let pending_output = super::decode_pending_output(
+            &DatabaseKeyPrefix::to_bytes(&super::PendingOutputKey(sign_request.into())),
+            &finalization_data.to_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            pending_output.value,
+            serde_json::to_value(finalization_data.nonces()).unwrap()
+        );
+    }
+
+    /// Checks that `docs/database.md` lists all registered prefixes, run it with
+    /// `MINIMINT_UPDATE_DOCS=1` to regenerate the tables.
+    #[test]
+    fn test_database_docs() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../docs/database.md");
+        let docs = std::fs::read_to_string(&path).unwrap();
+        let begin = docs.find(DOCS_BEGIN).expect("Missing begin marker") + DOCS_BEGIN.len();
+        let end = docs.find(DOCS_END).expect("Missing end marker");
+
+        let generated = format!(
+            "## Server DB Layout\n\n{}## Client DB Layout\n\n{}",
+            minimint::db::prefix_registry().to_markdown(),
+            super::prefix_registry().to_markdown()
+        );
+
+        if std::env::var_os("MINIMINT_UPDATE_DOCS").is_some() {
+            let updated = format!("{}{}{}", &docs[..begin], generated, &docs[end..]);
+            std::fs::write(&path, updated).unwrap();
+        } else {
+            assert_eq!(
+                docs[begin..end],
+                generated,
+                "docs/database.md is outdated, run with MINIMINT_UPDATE_DOCS=1 to regenerate it"
+            );
+        }
+    }
+}
@@ -2,8 +2,8 @@ use std::sync::Arc;
 
 use bitcoin::{Address, Transaction};
 use lightning_invoice::Invoice;
-use rand::{CryptoRng, RngCore};
-use secp256k1_zkp::{All, Secp256k1};
+use rand::{CryptoRng, Rng, RngCore};
+use secp256k1_zkp::{schnorrsig, All, Secp256k1};
 use thiserror::Error;
 
 use minimint::config::ClientConfig;
@@ -11,19 +11,24 @@ use minimint::modules::ln::contracts::ContractId;
 use minimint::modules::mint::tiered::coins::Coins;
 use minimint::modules::wallet::txoproof::{PegInProofError, TxOutProof};
 use minimint::transaction as mint_tx;
-use minimint::transaction::{Output, TransactionItem};
-use minimint_api::db::batch::DbBatch;
+use minimint::transaction::{
+    Output, PartialTransaction, PartialTransactionError, TransactionExpiry, TransactionItem,
+};
+use minimint_api::db::batch::{BatchItem, BatchTx, DbBatch};
 use minimint_api::db::{Database, RawDatabase};
+use minimint_api::encoding::{Decodable, DecodeError, Encodable};
+use minimint_api::OutPoint;
 use minimint_api::{Amount, TransactionId};
-use minimint_api::{OutPoint, PeerId};
 
 use crate::api::ApiError;
+use crate::db::{PendingOutputKey, SigningKeyKey, SigningSessionKey};
 use crate::ln::gateway::LightningGateway;
 use crate::ln::LnClientError;
-use crate::mint::{MintClientError, SpendableCoin};
+use crate::mint::{CoinFinalizationData, MintClientError, SpendableCoin};
 use crate::wallet::WalletClientError;
 
 mod api;
+pub mod db;
 pub mod ln;
 pub mod mint;
 pub mod wallet;
@@ -42,12 +47,18 @@ pub struct MintClient {
 
 impl MintClient {
     pub fn new(cfg: ClientConfig, db: Arc<dyn RawDatabase>, secp: Secp256k1<All>) -> Self {
+        minimint::db::prefix_registry()
+            .with_registry(db::prefix_registry())
+            .check()
+            .expect("Database key prefixes collide");
+        db::migrations()
+            .apply(db.as_ref())
+            .expect("Couldn't migrate database");
+
         let api = api::FederationApi::new(
             cfg.api_endpoints
                 .iter()
-                .enumerate()
-                .map(|(id, url)| {
-                    let peer_id = PeerId::from(id as u16); // FIXME: potentially wrong, currently works imo
+                .map(|(&peer_id, url)| {
                     let url = url.parse().expect("Invalid URL in config");
                     (peer_id, url)
                 })
@@ -90,6 +101,7 @@ impl MintClient {
         &self,
         txout_proof: TxOutProof,
         btc_transaction: Transaction,
+        expiry: Option<TransactionExpiry>,
         mut rng: R,
     ) -> Result<TransactionId, ClientError> {
         let mut batch = DbBatch::new();
@@ -108,7 +120,7 @@ impl MintClient {
 
         let inputs = vec![mint_tx::Input::Wallet(Box::new(peg_in_proof))];
         let outputs = vec![mint_tx::Output::Mint(coin_output)];
-        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs);
+        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs, expiry);
 
         self.mint.save_coin_finalization_data(
             batch.transaction(),
@@ -120,9 +132,11 @@ impl MintClient {
             minimint::transaction::agg_sign(&[peg_in_key], txid.as_hash(), &self.secp, &mut rng);
 
         let mint_transaction = mint_tx::Transaction {
+            version: mint_tx::TRANSACTION_VERSION,
             inputs,
             outputs,
-            signature: Some(peg_in_req_sig),
+            expiry,
+            signature: Some(mint_tx::TransactionSignature::Aggregate(peg_in_req_sig)),
         };
 
         let mint_tx_id = self.api.submit_transaction(mint_transaction).await?;
@@ -139,6 +153,7 @@ impl MintClient {
     pub async fn reissue<R: RngCore + CryptoRng>(
         &self,
         coins: Coins<SpendableCoin>,
+        expiry: Option<TransactionExpiry>,
         mut rng: R,
     ) -> Result<TransactionId, ClientError> {
         let mut batch = DbBatch::new();
@@ -150,7 +165,7 @@ impl MintClient {
 
         let inputs = vec![mint_tx::Input::Mint(coin_input)];
         let outputs = vec![mint_tx::Output::Mint(coin_output)];
-        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs);
+        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs, expiry);
 
         self.mint.save_coin_finalization_data(
             batch.transaction(),
@@ -162,9 +177,11 @@ impl MintClient {
             minimint::transaction::agg_sign(&coin_keys, txid.as_hash(), &self.secp, &mut rng);
 
         let transaction = mint_tx::Transaction {
+            version: mint_tx::TRANSACTION_VERSION,
             inputs,
             outputs,
-            signature: Some(signature),
+            expiry,
+            signature: Some(mint_tx::TransactionSignature::Aggregate(signature)),
         };
 
         let mint_tx_id = self.api.submit_transaction(transaction).await?;
@@ -182,6 +199,7 @@ impl MintClient {
         &self,
         amt: bitcoin::Amount,
         address: bitcoin::Address,
+        expiry: Option<TransactionExpiry>,
         mut rng: R,
     ) -> Result<TransactionId, ClientError> {
         let mut batch = DbBatch::new();
@@ -194,15 +212,17 @@ impl MintClient {
 
         let inputs = vec![mint_tx::Input::Mint(coin_input)];
         let outputs = vec![mint_tx::Output::Wallet(pegout_output)];
-        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs);
+        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs, expiry);
 
         let signature =
             minimint::transaction::agg_sign(&coin_keys, txid.as_hash(), &self.secp, &mut rng);
 
         let transaction = mint_tx::Transaction {
+            version: mint_tx::TRANSACTION_VERSION,
             inputs,
             outputs,
-            signature: Some(signature),
+            expiry,
+            signature: Some(mint_tx::TransactionSignature::Aggregate(signature)),
         };
         let tx_id = transaction.tx_hash();
 
@@ -258,6 +278,7 @@ impl MintClient {
         gateway: &LightningGateway,
         invoice: Invoice,
         absolute_timelock: u32,
+        expiry: Option<TransactionExpiry>,
         mut rng: R,
     ) -> Result<TransactionId, ClientError> {
         let mut batch = DbBatch::new();
@@ -279,15 +300,17 @@ impl MintClient {
 
         let inputs = vec![mint_tx::Input::Mint(coin_input)];
         let outputs = vec![ln_output];
-        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs);
+        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs, expiry);
 
         let signature =
             minimint::transaction::agg_sign(&coin_keys, txid.as_hash(), &self.secp, &mut rng);
 
         let transaction = mint_tx::Transaction {
+            version: mint_tx::TRANSACTION_VERSION,
             inputs,
             outputs,
-            signature: Some(signature),
+            expiry,
+            signature: Some(mint_tx::TransactionSignature::Aggregate(signature)),
         };
 
         let mint_tx_id = self.api.submit_transaction(transaction).await?;
@@ -306,6 +329,7 @@ impl MintClient {
         contract: ContractId,
         preimage: [u8; 32],
         gw_key: secp256k1_zkp::schnorrsig::KeyPair,
+        expiry: Option<TransactionExpiry>,
         mut rng: R,
     ) -> Result<TransactionId, ClientError> {
         let mut batch = DbBatch::new();
@@ -317,7 +341,7 @@ impl MintClient {
 
         let inputs = vec![mint_tx::Input::LN(ln_input)];
         let outputs = vec![mint_tx::Output::Mint(coin_output)];
-        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs);
+        let txid = mint_tx::Transaction::tx_hash_from_parts(&inputs, &outputs, expiry);
 
         self.mint.save_coin_finalization_data(
             batch.transaction(),
@@ -329,9 +353,11 @@ impl MintClient {
             minimint::transaction::agg_sign(&[gw_key], txid.as_hash(), &self.secp, &mut rng);
 
         let transaction = mint_tx::Transaction {
+            version: mint_tx::TRANSACTION_VERSION,
             inputs,
             outputs,
-            signature: Some(signature),
+            expiry,
+            signature: Some(mint_tx::TransactionSignature::Aggregate(signature)),
         };
 
         let mint_tx_id = self.api.submit_transaction(transaction).await?;
@@ -344,6 +370,244 @@ impl MintClient {
         self.db.apply_batch(batch).expect("DB error");
         Ok(txid)
     }
+
+    /// Adds coins worth at least `amount` as input to a collaboratively built transaction. The
+    /// coins are marked as spent and their keys are remembered to sign the transaction later on.
+    pub fn contribute_coins(
+        &self,
+        ptx: &mut PartialTransaction,
+        amount: Amount,
+    ) -> Result<(), ClientError> {
+        let mut batch = DbBatch::new();
+        let mut batch_tx = batch.transaction();
+
+        let (coin_keys, coin_input) = self
+            .mint
+            .create_coin_input(batch_tx.subtransaction(), amount)?;
+        let pub_keys = coin_keys
+            .iter()
+            .map(|key| schnorrsig::PublicKey::from_keypair(&self.secp, key))
+            .collect::<Vec<_>>();
+
+        ptx.add_input(mint_tx::Input::Mint(coin_input), pub_keys.clone())?;
+
+        batch_tx.append_from_iter(
+            pub_keys
+                .into_iter()
+                .zip(coin_keys)
+                .map(|(pub_key, key)| BatchItem::insert_new(SigningKeyKey(pub_key), key)),
+        );
+        batch_tx.commit();
+        self.db.apply_batch(batch).expect("DB error");
+        Ok(())
+    }
+
+    /// Adds an output issuing coins worth `amount` to us to a collaboratively built transaction.
+    /// The coins can be fetched once the transaction was accepted, given that we passed the frozen
+    /// transaction to [`MintClient::commit_partial_transaction`] or
+    /// [`MintClient::sign_partial_transaction_inputs`].
+    pub fn contribute_coin_output<R: RngCore + CryptoRng>(
+        &self,
+        ptx: &mut PartialTransaction,
+        amount: Amount,
+        mut rng: R,
+    ) -> Result<(), ClientError> {
+        let (coin_finalization_data, coin_output) = self.mint.create_coin_output(amount, &mut rng);
+        ptx.add_output(Output::Mint(coin_output.clone()))?;
+
+        self.db
+            .insert_entry(&PendingOutputKey(coin_output), &coin_finalization_data)
+            .expect("DB error");
+        Ok(())
+    }
+
+    /// First signing round: adds our nonces to the frozen transaction. This also has to be called
+    /// by parties that only contributed outputs so they can fetch their coins later on. Every
+    /// transaction can only be committed to once since a second session would reuse our nonces.
+    pub fn commit_partial_transaction<R: RngCore + CryptoRng>(
+        &self,
+        ptx: &mut PartialTransaction,
+        mut rng: R,
+    ) -> Result<(), ClientError> {
+        if !ptx.is_frozen() {
+            return Err(PartialTransactionError::NotFrozen.into());
+        }
+
+        let mut batch = DbBatch::new();
+        let mut batch_tx = batch.transaction();
+        let txid = ptx.tx_hash();
+        if self
+            .db
+            .get_value::<_, [u8; 32]>(&SigningSessionKey(txid))
+            .expect("DB error")
+            .is_some()
+        {
+            return Err(ClientError::AlreadyCommitted(txid));
+        }
+
+        let keys = self.partial_transaction_keys(ptx);
+        if !keys.is_empty() {
+            let session_seed: [u8; 32] = rng.gen();
+            ptx.add_nonces(&keys, session_seed, &self.secp)?;
+            batch_tx.append_insert_new(SigningSessionKey(txid), session_seed);
+        }
+
+        self.register_pending_outputs(batch_tx.subtransaction(), ptx);
+
+        batch_tx.commit();
+        self.db.apply_batch(batch).expect("DB error");
+        Ok(())
+    }
+
+    /// Second signing round: adds our partial signatures once the nonces of all signers are known.
+    /// This can only be done once per transaction since signing twice could leak our keys.
+    pub fn sign_partial_transaction(
+        &self,
+        ptx: &mut PartialTransaction,
+    ) -> Result<(), ClientError> {
+        let mut batch = DbBatch::new();
+        let mut batch_tx = batch.transaction();
+        let txid = ptx.tx_hash();
+
+        let session_seed = self
+            .db
+            .get_value::<_, [u8; 32]>(&SigningSessionKey(txid))
+            .expect("DB error")
+            .ok_or(ClientError::UnknownSigningSession(txid))?;
+        let keys = self.partial_transaction_keys(ptx);
+
+        ptx.add_partial_signatures(&keys, session_seed, &self.secp)?;
+
+        batch_tx.append_delete(SigningSessionKey(txid));
+        batch_tx.append_from_iter(keys.iter().map(|key| {
+            BatchItem::delete(SigningKeyKey(schnorrsig::PublicKey::from_keypair(
+                &self.secp, key,
+            )))
+        }));
+        batch_tx.commit();
+        self.db.apply_batch(batch).expect("DB error");
+        Ok(())
+    }
+
+    /// Non-interactive alternative to [`MintClient::commit_partial_transaction`] and
+    /// [`MintClient::sign_partial_transaction`]: signs our inputs of the frozen transaction
+    /// independently of other signers. Like the former it also has to be called by parties that
+    /// only contributed outputs.
+    pub fn sign_partial_transaction_inputs<R: RngCore + CryptoRng>(
+        &self,
+        ptx: &mut PartialTransaction,
+        rng: R,
+    ) -> Result<(), ClientError> {
+        if !ptx.is_frozen() {
+            return Err(PartialTransactionError::NotFrozen.into());
+        }
+
+        let mut batch = DbBatch::new();
+        let mut batch_tx = batch.transaction();
+
+        let keys = self.partial_transaction_keys(ptx);
+        if !keys.is_empty() {
+            ptx.add_input_signatures(&keys, &self.secp, rng)?;
+            batch_tx.append_from_iter(keys.iter().map(|key| {
+                BatchItem::delete(SigningKeyKey(schnorrsig::PublicKey::from_keypair(
+                    &self.secp, key,
+                )))
+            }));
+        }
+
+        self.register_pending_outputs(batch_tx.subtransaction(), ptx);
+
+        batch_tx.commit();
+        self.db.apply_batch(batch).expect("DB error");
+        Ok(())
+    }
+
+    /// Merges copies of the same partial transaction containing contributions of different signers
+    pub fn combine_partial_transactions(
+        &self,
+        ptxs: Vec<PartialTransaction>,
+    ) -> Result<PartialTransaction, ClientError> {
+        let mut ptxs = ptxs.into_iter();
+        let mut combined = ptxs.next().ok_or(ClientError::NoPartialTransactions)?;
+        for ptx in ptxs {
+            combined.combine(ptx)?;
+        }
+        Ok(combined)
+    }
+
+    /// Aggregates all partial signatures and submits the resulting transaction to the federation
+    pub async fn finalize_partial_transaction(
+        &self,
+        ptx: PartialTransaction,
+    ) -> Result<TransactionId, ClientError> {
+        let transaction = ptx.finalize(&self.secp)?;
+        let txid = transaction.tx_hash();
+
+        let mint_tx_id = self.api.submit_transaction(transaction).await?;
+        // TODO: make check part of submit_transaction
+        assert_eq!(
+            txid, mint_tx_id,
+            "Federation is faulty, returned wrong tx id."
+        );
+
+        Ok(txid)
+    }
+
+    /// Serializes a partial transaction so it can be handed to other parties
+    pub fn export_partial_transaction(&self, ptx: &PartialTransaction) -> String {
+        let mut bytes = Vec::new();
+        ptx.consensus_encode(&mut bytes)
+            .expect("writing to vec can't fail");
+        hex::encode(bytes)
+    }
+
+    /// Parses a partial transaction exported by [`MintClient::export_partial_transaction`]
+    pub fn import_partial_transaction(&self, ptx: &str) -> Result<PartialTransaction, ClientError> {
+        let bytes = hex::decode(ptx)
+            .map_err(|e| ClientError::MalformedPartialTransaction(DecodeError::from_err(e)))?;
+        PartialTransaction::consensus_decode(std::io::Cursor::new(bytes))
+            .map_err(ClientError::MalformedPartialTransaction)
+    }
+
+    /// Prepares the outputs we contributed to the frozen `ptx` for fetching, its id is final now
+    fn register_pending_outputs(&self, mut batch: BatchTx, ptx: &PartialTransaction) {
+        let txid = ptx.tx_hash();
+        for (out_idx, output) in ptx.outputs.iter().enumerate() {
+            let coin_output = match output {
+                Output::Mint(coin_output) => coin_output,
+                _ => continue,
+            };
+
+            let pending_key = PendingOutputKey(coin_output.clone());
+            if let Some(coin_finalization_data) = self
+                .db
+                .get_value::<_, CoinFinalizationData>(&pending_key)
+                .expect("DB error")
+            {
+                self.mint.save_coin_finalization_data(
+                    batch.subtransaction(),
+                    OutPoint {
+                        txid,
+                        out_idx: out_idx as u64,
+                    },
+                    coin_finalization_data,
+                );
+                batch.append_delete(pending_key);
+            }
+        }
+        batch.commit();
+    }
+
+    /// Returns the key pairs we remembered for signing `ptx`
+    fn partial_transaction_keys(&self, ptx: &PartialTransaction) -> Vec<schnorrsig::KeyPair> {
+        ptx.signing_keys()
+            .filter_map(|pub_key| {
+                self.db
+                    .get_value::<_, schnorrsig::KeyPair>(&SigningKeyKey(*pub_key))
+                    .expect("DB error")
+            })
+            .collect()
+    }
 }
 
 #[derive(Error, Debug)]
@@ -358,6 +622,16 @@ pub enum ClientError {
     LnClientError(LnClientError),
     #[error("Peg-in amount must be greater than peg-in fee")]
     PegInAmountTooSmall,
+    #[error("Partial transaction error: {0}")]
+    PartialTransactionError(PartialTransactionError),
+    #[error("Malformed partial transaction: {0}")]
+    MalformedPartialTransaction(DecodeError),
+    #[error("We didn't commit to signing transaction {0}")]
+    UnknownSigningSession(TransactionId),
+    #[error("We already committed to signing transaction {0}")]
+    AlreadyCommitted(TransactionId),
+    #[error("No partial transactions supplied")]
+    NoPartialTransactions,
 }
 
 impl From<ApiError> for ClientError {
@@ -378,8 +652,159 @@ impl From<MintClientError> for ClientError {
     }
 }
 
+impl From<PartialTransactionError> for ClientError {
+    fn from(e: PartialTransactionError) -> Self {
+        ClientError::PartialTransactionError(e)
+    }
+}
+
 impl From<LnClientError> for ClientError {
     fn from(e: LnClientError) -> Self {
         ClientError::LnClientError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::db::SigningKeyKey;
+    use crate::mint::db::OutputFinalizationKey;
+    use crate::mint::CoinFinalizationData;
+    use crate::{ClientError, MintClient};
+    use bitcoin_hashes::Hash;
+    use minimint::config::{ClientConfig, ServerConfig, ServerConfigParams};
+    use minimint::modules::ln::contracts::ContractId;
+    use minimint::modules::ln::ContractInput;
+    use minimint::transaction::{Input, PartialTransaction, PartialTransactionError};
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::db::Database;
+    use minimint_api::{Amount, OutPoint, PeerId};
+    use secp256k1_zkp::{schnorrsig, Secp256k1};
+    use std::sync::Arc;
+
+    fn client_config() -> ClientConfig {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let params = ServerConfigParams {
+            hbbft_base_port: 5000,
+            api_base_port: 6000,
+            admin_base_port: None,
+            amount_tiers: vec![Amount::from_sat(1)],
+        };
+        let (_, client_cfg) =
+            ServerConfig::trusted_dealer_gen(&peers, 1, &params, rand::rngs::OsRng::new().unwrap());
+        client_cfg
+    }
+
+    fn new_client(cfg: &ClientConfig) -> (MintClient, MemDatabase) {
+        let db = MemDatabase::new();
+        let client = MintClient::new(cfg.clone(), Arc::new(db.clone()), Secp256k1::new());
+        (client, db)
+    }
+
+    fn has_issuance(db: &MemDatabase, out_point: OutPoint) -> bool {
+        db.get_value::<_, CoinFinalizationData>(&OutputFinalizationKey(out_point))
+            .unwrap()
+            .is_some()
+    }
+
+    /// Starts a partial transaction with an input that has to be signed by a key of `client`
+    fn spending_transaction(client: &MintClient, db: &MemDatabase) -> PartialTransaction {
+        let key = schnorrsig::KeyPair::new(&client.secp, &mut rand::rngs::OsRng::new().unwrap());
+        let pub_key = schnorrsig::PublicKey::from_keypair(&client.secp, &key);
+        db.insert_entry(&SigningKeyKey(pub_key), &key).unwrap();
+
+        let mut ptx = PartialTransaction::new();
+        ptx.add_input(
+            Input::LN(ContractInput {
+                crontract_id: ContractId::from_inner([0; 32]),
+                amount: Amount::from_sat(5),
+                witness: None,
+            }),
+            vec![pub_key],
+        )
+        .unwrap();
+        ptx
+    }
+
+    #[test]
+    fn test_output_only_participant() {
+        let mut rng = rand::rngs::OsRng::new().unwrap();
+        let cfg = client_config();
+        let (alice, alice_db) = new_client(&cfg);
+        let (bob, bob_db) = new_client(&cfg);
+
+        // Alice spends an input, Bob only receives coins
+        let mut ptx = spending_transaction(&alice, &alice_db);
+        bob.contribute_coin_output(&mut ptx, Amount::from_sat(3), &mut rng)
+            .unwrap();
+
+        // Alice may still add outputs, which changes the transaction id
+        assert!(matches!(
+            bob.commit_partial_transaction(&mut ptx.clone(), &mut rng),
+            Err(ClientError::PartialTransactionError(
+                PartialTransactionError::NotFrozen
+            ))
+        ));
+        alice
+            .contribute_coin_output(&mut ptx, Amount::from_sat(2), &mut rng)
+            .unwrap();
+        ptx.freeze();
+
+        let mut alice_ptx = ptx.clone();
+        let mut bob_ptx = ptx;
+        bob.commit_partial_transaction(&mut bob_ptx, &mut rng)
+            .unwrap();
+        alice
+            .commit_partial_transaction(&mut alice_ptx, &mut rng)
+            .unwrap();
+        assert!(matches!(
+            alice.commit_partial_transaction(&mut alice_ptx.clone(), &mut rng),
+            Err(ClientError::AlreadyCommitted(_))
+        ));
+
+        alice_ptx.combine(bob_ptx).unwrap();
+        alice.sign_partial_transaction(&mut alice_ptx).unwrap();
+        let txid = alice_ptx.finalize(&alice.secp).unwrap().tx_hash();
+
+        assert!(has_issuance(&bob_db, OutPoint { txid, out_idx: 0 }));
+        assert!(!has_issuance(&bob_db, OutPoint { txid, out_idx: 1 }));
+        assert!(has_issuance(&alice_db, OutPoint { txid, out_idx: 1 }));
+    }
+
+    #[test]
+    fn test_output_only_participant_per_input() {
+        let mut rng = rand::rngs::OsRng::new().unwrap();
+        let cfg = client_config();
+        let (alice, alice_db) = new_client(&cfg);
+        let (bob, bob_db) = new_client(&cfg);
+
+        let mut ptx = spending_transaction(&alice, &alice_db);
+        bob.contribute_coin_output(&mut ptx, Amount::from_sat(3), &mut rng)
+            .unwrap();
+
+        assert!(matches!(
+            bob.sign_partial_transaction_inputs(&mut ptx.clone(), &mut rng),
+            Err(ClientError::PartialTransactionError(
+                PartialTransactionError::NotFrozen
+            ))
+        ));
+        alice
+            .contribute_coin_output(&mut ptx, Amount::from_sat(2), &mut rng)
+            .unwrap();
+        ptx.freeze();
+
+        let mut alice_ptx = ptx.clone();
+        let mut bob_ptx = ptx;
+        bob.sign_partial_transaction_inputs(&mut bob_ptx, &mut rng)
+            .unwrap();
+        alice
+            .sign_partial_transaction_inputs(&mut alice_ptx, &mut rng)
+            .unwrap();
+
+        alice_ptx.combine(bob_ptx).unwrap();
+        let txid = alice_ptx.finalize(&alice.secp).unwrap().tx_hash();
+
+        assert!(has_issuance(&bob_db, OutPoint { txid, out_idx: 0 }));
+        assert!(has_issuance(&alice_db, OutPoint { txid, out_idx: 1 }));
+    }
+}
@@ -1,12 +1,139 @@
+use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
 use minimint::modules::ln::contracts::ContractId;
-use minimint_api::db::DatabaseKeyPrefixConst;
+use minimint_api::db::batch::BatchTx;
+use minimint_api::db::migration::{ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{try_decode_entry_with, DbPrefix, DecodedEntry};
+use minimint_api::db::{
+    Database, DatabaseError, DatabaseKeyPrefixConst, DecodingError, RawDatabase,
+};
 use minimint_api::encoding::{Decodable, Encodable};
+use secp256k1_zkp::{schnorrsig, Secp256k1};
+use serde::Serialize;
 
-const DB_PREFIX_OUTGOING_PAYMENT: u8 = 0x40;
+const DB_PREFIX_OUTGOING_PAYMENT: u8 = 0x26;
+/// Outgoing payments used to collide with the lightning module's contract prefix
+const DB_PREFIX_OUTGOING_PAYMENT_V0: u8 = 0x40;
 
-#[derive(Debug, Encodable, Decodable)]
+/// Key prefixes of the client's lightning payments
+pub const DB_PREFIXES: &[DbPrefix] = &[DbPrefix {
+    prefix: DB_PREFIX_OUTGOING_PAYMENT,
+    name: "Outgoing Payments",
+    key: "contract id",
+    value: "outgoing contract data",
+    decode: decode_outgoing_payment,
+}];
+
+/// Outgoing payment as shown by inspection tools, only revealing the public recovery key
+#[derive(Serialize)]
+struct OutgoingPaymentView {
+    recovery_key: schnorrsig::PublicKey,
+    contract_account: OutgoingContractAccount,
+}
+
+fn decode_outgoing_payment(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    try_decode_entry_with::<OutgoingPaymentKey, OutgoingContractData, _, _>(key, value, |payment| {
+        let ctx = Secp256k1::signing_only();
+        let recovery_key =
+            schnorrsig::KeyPair::from_seckey_slice(&ctx, payment.recovery_key.expose())
+                .map_err(DecodingError::other)?;
+        Ok(OutgoingPaymentView {
+            recovery_key: schnorrsig::PublicKey::from_keypair(&ctx, &recovery_key),
+            contract_account: payment.contract_account,
+        })
+    })
+}
+
+/// Version of the client's lightning database layout
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(1);
+
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct OutgoingPaymentKey(pub ContractId);
 
 impl DatabaseKeyPrefixConst for OutgoingPaymentKey {
     const DB_PREFIX: u8 = DB_PREFIX_OUTGOING_PAYMENT;
 }
+
+#[derive(Debug, Encodable, Decodable)]
+struct OutgoingPaymentKeyV0(ContractId);
+
+impl DatabaseKeyPrefixConst for OutgoingPaymentKeyV0 {
+    const DB_PREFIX: u8 = DB_PREFIX_OUTGOING_PAYMENT_V0;
+}
+
+#[derive(Debug, Encodable)]
+struct OutgoingPaymentKeyPrefixV0;
+
+impl DatabaseKeyPrefixConst for OutgoingPaymentKeyPrefixV0 {
+    const DB_PREFIX: u8 = DB_PREFIX_OUTGOING_PAYMENT_V0;
+}
+
+pub fn migrations() -> ModuleMigrations {
+    ModuleMigrations::new("client-ln", DB_SCHEMA_VERSION)
+        .with_migration(SchemaVersion(0), move_outgoing_payments)
+}
+
+/// Moves outgoing payments out of the lightning module's prefix range
+fn move_outgoing_payments(db: &dyn RawDatabase, batch: &mut BatchTx) -> Result<(), DatabaseError> {
+    let payments = db.find_by_prefix::<_, OutgoingPaymentKeyV0, OutgoingContractData>(
+        &OutgoingPaymentKeyPrefixV0,
+    );
+    for res in payments {
+        let (OutgoingPaymentKeyV0(contract_id), payment) = res?;
+        batch.append_delete(OutgoingPaymentKeyV0(contract_id));
+        batch.append_insert_new(OutgoingPaymentKey(contract_id), payment);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_invalid_recovery_key() {
+        use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
+        use minimint::modules::ln::contracts::outgoing::OutgoingContract;
+        use minimint::modules::ln::contracts::IdentifyableContract;
+        use minimint_api::db::{DatabaseKeyPrefix, SerializableDatabaseValue};
+        use minimint_api::secret::Secret;
+        use minimint_api::Amount;
+        use secp256k1_zkp::schnorrsig;
+
+        let ctx = secp256k1_zkp::Secp256k1::new();
+        let key = schnorrsig::PublicKey::from_keypair(
+            &ctx,
+            &schnorrsig::KeyPair::from_seckey_slice(&ctx, &[1u8; 32]).unwrap(),
+        );
+        let contract = OutgoingContract {
+            hash: bitcoin_hashes::Hash::hash(b"preimage"),
+            gateway_key: key,
+            timelock: 100,
+            user_key: key,
+            invoice: String::new(),
+        };
+        let payment_key = super::OutgoingPaymentKey(contract.contract_id());
+        let payment = |recovery_key| OutgoingContractData {
+            recovery_key: Secret::new(recovery_key),
+            contract_account: OutgoingContractAccount {
+                amount: Amount::from_sat(42),
+                contract: contract.clone(),
+            },
+        };
+        let decode = super::DB_PREFIXES[0].decode;
+
+        let decoded = decode(
+            &DatabaseKeyPrefix::to_bytes(&payment_key),
+            &payment([1u8; 32]).to_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            decoded.value["recovery_key"],
+            serde_json::to_value(&key).unwrap()
+        );
+
+        // Foreign or corrupted entries are reported instead of crashing inspection tools
+        assert!(decode(
+            &DatabaseKeyPrefix::to_bytes(&payment_key),
+            &payment([0u8; 32]).to_bytes(),
+        )
+        .is_err());
+    }
+}
@@ -1,4 +1,4 @@
-mod db;
+pub mod db;
 pub mod gateway;
 mod outgoing;
 
@@ -9,6 +9,7 @@ use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
 use crate::ApiError;
 use lightning_invoice::Invoice;
 use minimint::modules::ln;
+use minimint::modules::ln::api::ContractAccountEndpoint;
 use minimint::modules::ln::contracts::outgoing::OutgoingContract;
 use minimint::modules::ln::contracts::{
     Contract, ContractId, FundedContract, IdentifyableContract,
@@ -18,6 +19,7 @@ use minimint::modules::ln::{
 };
 use minimint_api::db::batch::BatchTx;
 use minimint_api::db::RawDatabase;
+use minimint_api::secret::Secret;
 use minimint_api::Amount;
 use rand::{CryptoRng, RngCore};
 use std::sync::Arc;
@@ -63,7 +65,7 @@ impl LnClient {
         };
 
         let outgoing_payment = OutgoingContractData {
-            recovery_key: user_sk,
+            recovery_key: Secret::new(user_sk.serialize_secret()),
             contract_account: OutgoingContractAccount {
                 amount: contract_amount,
                 contract: contract.clone(),
@@ -80,7 +82,7 @@ impl LnClient {
 
     pub async fn get_contract_account(&self, id: ContractId) -> Result<ContractAccount> {
         self.api
-            .get(&format!("/account/{}", id))
+            .request::<ContractAccountEndpoint>(id)
             .await
             .map_err(LnClientError::ApiError)
     }
@@ -96,14 +98,16 @@ impl LnClient {
         }
     }
 
-    pub fn refund_outgoing_contract<'a>(
+    pub fn refund_outgoing_contract(
         &self,
-        contract_data: &'a OutgoingContractData,
-    ) -> (&'a secp256k1_zkp::schnorrsig::KeyPair, ContractInput) {
-        (
-            &contract_data.recovery_key,
-            contract_data.contract_account.refund(),
+        contract_data: &OutgoingContractData,
+    ) -> (secp256k1_zkp::schnorrsig::KeyPair, ContractInput) {
+        let recovery_key = secp256k1_zkp::schnorrsig::KeyPair::from_seckey_slice(
+            &self.secp,
+            contract_data.recovery_key.expose(),
         )
+        .expect("Recovery key was generated by us");
+        (recovery_key, contract_data.contract_account.refund())
     }
 }
 
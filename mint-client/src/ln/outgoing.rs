@@ -2,15 +2,18 @@ use minimint::modules::ln::contracts::outgoing::{OutgoingContract, Preimage};
 use minimint::modules::ln::contracts::IdentifyableContract;
 use minimint::modules::ln::ContractInput;
 use minimint_api::encoding::{Decodable, Encodable};
+use minimint_api::secret::Secret;
 use minimint_api::Amount;
+use serde::Serialize;
 
 #[derive(Debug, Encodable, Decodable)]
 pub struct OutgoingContractData {
-    pub recovery_key: secp256k1_zkp::schnorrsig::KeyPair,
+    /// Secret key of the key pair that can refund the contract after its timelock expired
+    pub recovery_key: Secret<[u8; 32]>,
     pub contract_account: OutgoingContractAccount,
 }
 
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct OutgoingContractAccount {
     pub amount: Amount,
     pub contract: OutgoingContract,
@@ -1,8 +1,9 @@
 use bitcoin::{Address, Transaction};
 use bitcoin_hashes::hex::ToHex;
-use minimint::config::{load_from_file, ClientConfig};
+use minimint::config::{load_from_file, ClientConfig, DatabaseBackend};
 use minimint::modules::mint::tiered::coins::Coins;
 use minimint::modules::wallet::txoproof::TxOutProof;
+use minimint::transaction::TransactionExpiry;
 use minimint_api::encoding::Decodable;
 use minimint_api::Amount;
 use mint_client::mint::SpendableCoin;
@@ -11,7 +12,6 @@ use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::path::PathBuf;
-use std::sync::Arc;
 use structopt::StructOpt;
 use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
@@ -19,6 +19,23 @@ use tracing_subscriber::EnvFilter;
 #[derive(StructOpt)]
 struct Options {
     workdir: PathBuf,
+    #[structopt(
+        long,
+        default_value = "sled",
+        help = "Database backend (sled or rocksdb)"
+    )]
+    db_backend: DatabaseBackend,
+    #[structopt(
+        long,
+        help = "Last consensus epoch in which submitted transactions may be accepted"
+    )]
+    expiry_epoch: Option<u64>,
+    #[structopt(
+        long,
+        conflicts_with = "expiry-epoch",
+        help = "Last block height at which submitted transactions may be accepted"
+    )]
+    expiry_height: Option<u32>,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -77,14 +94,18 @@ async fn main() {
     let cfg_path = opts.workdir.join("client.json");
     let db_path = opts.workdir.join("client.db");
     let cfg: ClientConfig = load_from_file(&cfg_path);
-    let db = sled::open(&db_path)
-        .unwrap()
-        .open_tree("mint-client")
-        .unwrap();
+    let db = opts
+        .db_backend
+        .open(&db_path, "mint-client")
+        .expect("Couldn't open database");
 
     let mut rng = rand::rngs::OsRng::new().unwrap();
 
-    let client = MintClient::new(cfg, Arc::new(db), Default::default());
+    let client = MintClient::new(cfg, db, Default::default());
+    let expiry = opts
+        .expiry_epoch
+        .map(TransactionExpiry::Epoch)
+        .or_else(|| opts.expiry_height.map(TransactionExpiry::BlockHeight));
 
     match opts.command {
         Command::PegInAddress => {
@@ -95,7 +116,7 @@ async fn main() {
             transaction,
         } => {
             let id = client
-                .peg_in(txout_proof, transaction, &mut rng)
+                .peg_in(txout_proof, transaction, expiry, &mut rng)
                 .await
                 .unwrap();
             info!(
@@ -105,7 +126,7 @@ async fn main() {
         }
         Command::Reissue { coins } => {
             info!("Starting reissuance transaction for {}", coins.amount());
-            let id = client.reissue(coins, &mut rng).await.unwrap();
+            let id = client.reissue(coins, expiry, &mut rng).await.unwrap();
             info!(
                 "Started reissuance {}, please fetch the result later",
                 id.to_hex()
@@ -138,7 +159,10 @@ async fn main() {
             }
         }
         Command::PegOut { address, amount } => {
-            client.peg_out(amount, address, &mut rng).await.unwrap();
+            client
+                .peg_out(amount, address, expiry, &mut rng)
+                .await
+                .unwrap();
         }
         Command::LnPay { gateway, bolt11 } => {
             let amt = Amount::from_msat(bolt11.amount_milli_satoshis().unwrap());
@@ -1,12 +1,55 @@
+use crate::mint::{CoinFinalizationData, SpendableCoin};
+use minimint::modules::mint::db::CoinV0;
 use minimint::modules::mint::CoinNonce;
-use minimint_api::db::DatabaseKeyPrefixConst;
+use minimint_api::db::batch::BatchTx;
+use minimint_api::db::migration::{ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry_with, DbPrefix, DecodedEntry};
+use minimint_api::db::{
+    Database, DatabaseError, DatabaseKeyPrefixConst, DecodingError, RawDatabase,
+};
 use minimint_api::encoding::{Decodable, Encodable};
+use minimint_api::secret::Secret;
 use minimint_api::{Amount, OutPoint};
+use serde::Serialize;
 
 pub const DB_PREFIX_COIN: u8 = 0x20;
 pub const DB_PREFIX_OUTPUT_FINALIZATION_DATA: u8 = 0x21;
 
-#[derive(Debug, Clone, Encodable, Decodable)]
+/// Key prefixes of the client's coin store
+pub const DB_PREFIXES: &[DbPrefix] = &[
+    DbPrefix {
+        prefix: DB_PREFIX_COIN,
+        name: "Coins",
+        key: "amount, coin nonce",
+        value: "spendable coin",
+        decode: decode_spendable_coin,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_OUTPUT_FINALIZATION_DATA,
+        name: "Issuances",
+        key: "mint outpoint",
+        value: "coin finalization data",
+        decode: decode_finalization_data,
+    },
+];
+
+/// Inspection tools only get to see the coin, not its spend key
+fn decode_spendable_coin(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    decode_entry_with::<CoinKey, SpendableCoin, _, _>(key, value, |coin| coin.coin)
+}
+
+/// Inspection tools only get to see the nonces of the requested coins, not the keys belonging to
+/// them
+fn decode_finalization_data(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    decode_entry_with::<OutputFinalizationKey, CoinFinalizationData, _, _>(key, value, |data| {
+        data.nonces()
+    })
+}
+
+/// Version of the client's coin database layout
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(1);
+
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
 pub struct CoinKey {
     pub amount: Amount,
     pub nonce: CoinNonce,
@@ -23,7 +66,7 @@ impl DatabaseKeyPrefixConst for CoinKeyPrefix {
     const DB_PREFIX: u8 = DB_PREFIX_COIN;
 }
 
-#[derive(Debug, Clone, Encodable, Decodable)]
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
 pub struct OutputFinalizationKey(pub OutPoint);
 
 impl DatabaseKeyPrefixConst for OutputFinalizationKey {
@@ -36,3 +79,60 @@ pub struct OutputFinalizationKeyPrefix;
 impl DatabaseKeyPrefixConst for OutputFinalizationKeyPrefix {
     const DB_PREFIX: u8 = DB_PREFIX_OUTPUT_FINALIZATION_DATA;
 }
+
+pub fn migrations() -> ModuleMigrations {
+    ModuleMigrations::new("client-mint", DB_SCHEMA_VERSION)
+        .with_migration(SchemaVersion(0), migrate_v0)
+}
+
+/// Spendable coin as stored by schema version 0, before coins referenced their key generation
+#[derive(Debug, Encodable, Decodable)]
+struct SpendableCoinV0 {
+    coin: CoinV0,
+    spend_key: [u8; 32],
+}
+
+/// Schema version 1 stores coins together with the key generation they were signed with, which is
+/// the first one for all coins of schema version 0
+fn migrate_v0(db: &dyn RawDatabase, batch: &mut BatchTx) -> Result<(), DatabaseError> {
+    for res in db.find_by_prefix::<_, CoinKey, SpendableCoinV0>(&CoinKeyPrefix) {
+        let (key, coin) = res?;
+        batch.append_insert(
+            key,
+            SpendableCoin {
+                coin: coin.coin.into(),
+                spend_key: Secret::new(coin.spend_key),
+            },
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoinKey, CoinKeyPrefix};
+    use crate::mint::SpendableCoin;
+    use minimint::modules::mint::KeyGeneration;
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::db::Database;
+    use minimint_api::Amount;
+
+    #[test]
+    fn test_migrate_v0() {
+        let db = MemDatabase::from_dump(include_str!("fixtures/db_v0.dump")).unwrap();
+        super::migrations().apply(&db).unwrap();
+
+        let mut coins = db
+            .find_by_prefix::<_, CoinKey, SpendableCoin>(&CoinKeyPrefix)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(coins.len(), 2);
+        coins.sort_by_key(|(key, _)| key.amount);
+        for ((key, coin), sat) in coins.into_iter().zip(1..) {
+            assert_eq!(key.amount, Amount::from_sat(sat));
+            assert_eq!(coin.coin.0, key.nonce);
+            assert_eq!(coin.coin.2, KeyGeneration::default());
+            assert_eq!(coin.spend_key.expose(), &[sat as u8; 32]);
+        }
+    }
+}
@@ -1,26 +1,33 @@
-mod db;
+pub mod db;
 
 use crate::api::{ApiError, FederationApi};
 use bitcoin::schnorr::KeyPair;
 use db::{CoinKey, CoinKeyPrefix, OutputFinalizationKey, OutputFinalizationKeyPrefix};
 use minimint::modules::mint;
-use minimint::modules::mint::tiered::coins::Coins;
+use minimint::modules::mint::api::SignatureShareEndpoint;
+use minimint::modules::mint::tiered::coins::{Coins, TieredMultiZip};
 use minimint::modules::mint::{
-    BlindToken, Coin, CoinNonce, InvalidAmountTierError, Keys, SigResponse, SignRequest,
+    BlindToken, Coin, CoinNonce, InvalidAmountTierError, KeyGeneration, Keys, PartialSigResponse,
+    SigResponse, SignRequest,
 };
 use minimint_api::db::batch::{BatchItem, BatchTx};
 use minimint_api::db::{Database, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{Amount, OutPoint, TransactionId};
+use minimint_api::secret::Secret;
+use minimint_api::{Amount, OutPoint, PeerId, TransactionId};
 use rand::{CryptoRng, Rng, RngCore};
 use reqwest::StatusCode;
 use secp256k1_zkp::{Secp256k1, Signing};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
-use tbs::{blind_message, unblind_signature, AggregatePublicKey, BlindedMessage, BlindingKey};
+use tbs::{
+    blind_message, combine_valid_shares, unblind_signature, verify_blind_share, AggregatePublicKey,
+    BlindedMessage, BlindingKey, MessageDerivation,
+};
 use thiserror::Error;
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 /// Federation module client for the Mint module. It can both create transaction inputs and outputs
 /// of the mint type.
@@ -33,19 +40,19 @@ pub struct MintClient {
 
 /// Client side representation of one coin in an issuance request that keeps all necessary
 /// information to generate one spendable coin once the blind signature arrives.
-#[derive(Debug, Clone, Deserialize, Serialize, Encodable, Decodable)]
+#[derive(Debug, Clone, Encodable, Decodable)]
 pub struct CoinRequest {
     /// Spend key from which the coin nonce (corresponding public key) is derived
-    spend_key: [u8; 32], // FIXME: either make KeyPair Serializable or add secret key newtype
+    spend_key: Secret<[u8; 32]>,
     /// Nonce belonging to the secret key
     nonce: CoinNonce,
     /// Key to unblind the blind signature supplied by the mint for this coin
-    blinding_key: BlindingKey,
+    blinding_key: Secret<BlindingKey>,
 }
 
 /// Client side representation of a coin reissuance that keeps all necessary information to
 /// generate spendable coins once the blind signatures arrive.
-#[derive(Debug, Clone, Deserialize, Serialize, Encodable, Decodable)]
+#[derive(Debug, Clone, Encodable, Decodable)]
 pub struct CoinFinalizationData {
     /// Finalization data for all coin outputs in this request
     coins: Coins<CoinRequest>,
@@ -56,7 +63,8 @@ pub struct CoinFinalizationData {
 #[derive(Debug, Clone, Deserialize, Serialize, Encodable, Decodable)]
 pub struct SpendableCoin {
     pub coin: Coin,
-    pub spend_key: [u8; 32],
+    #[serde(with = "minimint_api::secret::exposed")]
+    pub spend_key: Secret<[u8; 32]>,
 }
 
 impl MintClient {
@@ -70,6 +78,15 @@ impl MintClient {
             .collect()
     }
 
+    /// Returns all coins that were issued under a retired key generation. These should be
+    /// reissued before their generation gets deprecated.
+    pub fn retired_coins(&self) -> Coins<SpendableCoin> {
+        self.coins()
+            .into_iter()
+            .filter(|(_, coin)| coin.coin.2 != self.cfg.generation)
+            .collect()
+    }
+
     // FIXME: implement three step process: unspent -> in flight -> spent/unspent
     pub fn mark_coins_spent(&self, mut batch: BatchTx, coins: &Coins<SpendableCoin>) {
         batch.append_from_iter(coins.iter().map(|(amount, coin)| {
@@ -123,7 +140,7 @@ impl MintClient {
             .map(|(amt, coin)| {
                 let spend_key = secp256k1_zkp::schnorrsig::KeyPair::from_seckey_slice(
                     &self.secp,
-                    &coin.spend_key,
+                    coin.spend_key.expose(),
                 )
                 .map_err(|_| MintClientError::ReceivedUspendableCoin)?;
 
@@ -146,8 +163,13 @@ impl MintClient {
         amount: Amount,
         mut rng: R,
     ) -> (CoinFinalizationData, Coins<BlindToken>) {
-        let (coin_finalization_data, sig_req) =
-            CoinFinalizationData::new(amount, &self.cfg.tbs_pks, &self.secp, &mut rng);
+        let (coin_finalization_data, sig_req) = CoinFinalizationData::new(
+            amount,
+            &self.cfg.tbs_pks,
+            &self.cfg.message_derivation,
+            &self.secp,
+            &mut rng,
+        );
 
         let coin_output = sig_req
             .0
@@ -181,13 +203,41 @@ impl MintClient {
                 CoinFinalizationError::UnknownIssuance,
             ))?;
 
-        let bsig = self
+        // The federation only combines signatures of outputs issued by older versions, otherwise
+        // the outcome just tells us that the output was accepted and is being signed
+        let bsig = match self
             .api
             .fetch_output_outcome::<Option<SigResponse>>(outpoint)
             .await?
-            .ok_or(MintClientError::OutputNotReadyYet(outpoint))?;
+        {
+            Some(bsig) => bsig,
+            None => {
+                let shares = self
+                    .api
+                    .request_each::<SignatureShareEndpoint>(outpoint)
+                    .await
+                    .into_iter()
+                    .filter_map(|(peer, res)| match res {
+                        Ok(share) => Some((peer, share)),
+                        Err(e) => {
+                            trace!("Peer {} didn't return a sig share: {}", peer, e);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                issuance
+                    .combine_shares(shares, &self.cfg)?
+                    .ok_or(MintClientError::OutputNotReadyYet(outpoint))?
+            }
+        };
 
-        let coins = issuance.finalize(bsig, &self.cfg.tbs_pks)?;
+        let mint_pub_key =
+            self.cfg
+                .generation_keys(bsig.1)
+                .ok_or(MintClientError::FinalizationError(
+                    CoinFinalizationError::UnknownKeyGeneration(bsig.1),
+                ))?;
+        let coins = issuance.finalize(bsig, mint_pub_key, &self.cfg.message_derivation)?;
 
         batch.append_from_iter(
             coins
@@ -244,6 +294,7 @@ impl CoinFinalizationData {
     pub fn new<K, C>(
         amount: Amount,
         amount_tiers: &Keys<K>,
+        derivation: &MessageDerivation,
         ctx: &Secp256k1<C>,
         mut rng: impl RngCore + CryptoRng,
     ) -> (CoinFinalizationData, SignRequest)
@@ -254,7 +305,7 @@ impl CoinFinalizationData {
             Coins::represent_amount(amount, amount_tiers)
                 .into_iter()
                 .map(|(amt, ())| {
-                    let (request, blind_msg) = CoinRequest::new(ctx, &mut rng);
+                    let (request, blind_msg) = CoinRequest::new(derivation, ctx, &mut rng);
                     ((amt, request), (amt, blind_msg))
                 })
                 .unzip();
@@ -272,37 +323,145 @@ impl CoinFinalizationData {
         (issuance_req, sig_req)
     }
 
+    /// Returns the nonces of the requested coins, which unlike their spend keys are safe to show
+    pub fn nonces(&self) -> Coins<CoinNonce> {
+        self.coins
+            .iter()
+            .map(|(amount, request)| (amount, request.nonce.clone()))
+            .collect()
+    }
+
+    /// Combines the signature shares fetched from the peers into a [`SigResponse`]. Shares that
+    /// don't belong to this issuance or are signed with the wrong key are discarded. Every share is
+    /// checked against the keys of the generation it was created with, so issuances signed before
+    /// a key rotation can still be combined. Returns `None` if fewer than the threshold of valid
+    /// shares of one generation remain, e.g. because some peers didn't sign yet.
+    pub fn combine_shares(
+        &self,
+        shares: Vec<(PeerId, PartialSigResponse)>,
+        cfg: &mint::config::MintClientConfig,
+    ) -> std::result::Result<Option<SigResponse>, CoinFinalizationError> {
+        if !cfg.can_combine_shares() {
+            return Err(CoinFinalizationError::OutdatedConfig);
+        }
+
+        let mut generation_shares = BTreeMap::<KeyGeneration, Vec<_>>::new();
+        for (peer, share) in shares {
+            match self.validate_share(peer, &share, cfg) {
+                Ok(()) => generation_shares
+                    .entry(share.1)
+                    .or_default()
+                    .push((peer, share)),
+                Err(e) => warn!("Peer {} returned an invalid sig share: {}", peer, e),
+            }
+        }
+
+        // Peers only sign with another generation than the rest while keys are being rotated,
+        // prefer the newest one
+        let (generation, valid_shares) = match generation_shares
+            .into_iter()
+            .rev()
+            .find(|(_, shares)| shares.len() >= cfg.threshold)
+        {
+            Some(valid_shares) => valid_shares,
+            None => return Ok(None),
+        };
+
+        let bsigs = TieredMultiZip::new(
+            valid_shares
+                .iter()
+                .map(|(_, PartialSigResponse(sigs, _))| sigs.iter())
+                .collect(),
+        )
+        .map(|(amt, coin_shares)| {
+            let sig_shares = coin_shares
+                .into_iter()
+                .zip(valid_shares.iter())
+                .map(|((_msg, sig), (peer, _))| (peer.to_usize(), *sig));
+            (amt, combine_valid_shares(sig_shares, cfg.threshold))
+        })
+        .collect();
+
+        Ok(Some(SigResponse(bsigs, generation)))
+    }
+
+    /// Checks that `share` contains a valid signature share by `peer` for every coin of this
+    /// issuance
+    fn validate_share(
+        &self,
+        peer: PeerId,
+        share: &PartialSigResponse,
+        cfg: &mint::config::MintClientConfig,
+    ) -> std::result::Result<(), CoinFinalizationError> {
+        if !self.coins.structural_eq(&share.0) {
+            return Err(CoinFinalizationError::WrongMintAnswer);
+        }
+
+        let peer_pks = cfg
+            .generation_peer_keys(share.1)
+            .ok_or(CoinFinalizationError::UnknownKeyGeneration(share.1))?
+            .get(&peer)
+            .ok_or(CoinFinalizationError::UnknownPeer(peer))?;
+        self.coins
+            .iter()
+            .zip(share.0.iter())
+            .enumerate()
+            .try_for_each(|(idx, ((amt, coin_req), (_, (msg, sig))))| {
+                let pk = *peer_pks.tier(&amt)?;
+                let expected_msg = coin_req
+                    .blinding_key
+                    .expose()
+                    .blind(coin_req.nonce.to_message(&cfg.message_derivation));
+                if *msg != expected_msg || !verify_blind_share(expected_msg, *sig, pk) {
+                    return Err(CoinFinalizationError::InvalidSignature(idx));
+                }
+                Ok(())
+            })
+    }
+
     /// Finalize the issuance request using a [`SigResponse`] from the mint containing the blind
     /// signatures for all coins in this `IssuanceRequest`. It also takes the mint's
-    /// [`AggregatePublicKey`] to validate the supplied blind signatures.
+    /// [`AggregatePublicKey`] and [`MessageDerivation`] to validate the supplied blind signatures.
     pub fn finalize(
         &self,
         bsigs: SigResponse,
         mint_pub_key: &Keys<AggregatePublicKey>,
+        derivation: &MessageDerivation,
     ) -> std::result::Result<Coins<SpendableCoin>, CoinFinalizationError> {
         if !self.coins.structural_eq(&bsigs.0) {
             return Err(CoinFinalizationError::WrongMintAnswer);
         }
 
-        self.coins
+        let generation = bsigs.1;
+        let coins = self
+            .coins
             .iter()
             .zip(bsigs.0)
-            .enumerate()
-            .map(|(idx, ((amt, coin_req), (_amt, bsig)))| {
-                let sig = unblind_signature(coin_req.blinding_key, bsig);
-                let coin = Coin(coin_req.nonce.clone(), sig);
-                if coin.verify(*mint_pub_key.tier(&amt)?) {
-                    let coin = SpendableCoin {
-                        coin,
-                        spend_key: coin_req.spend_key,
-                    };
-
-                    Ok((amt, coin))
-                } else {
-                    Err(CoinFinalizationError::InvalidSignature(idx))
-                }
+            .map(|((amt, coin_req), (_amt, bsig))| {
+                let sig = unblind_signature(coin_req.blinding_key.expose(), bsig);
+                let coin = Coin(coin_req.nonce.clone(), sig, generation);
+                Ok((
+                    amt,
+                    coin,
+                    *mint_pub_key.tier(&amt)?,
+                    coin_req.spend_key.clone(),
+                ))
             })
-            .collect()
+            .collect::<std::result::Result<Vec<_>, CoinFinalizationError>>()?;
+
+        if !Coin::verify_batch(coins.iter().map(|(_, coin, pk, _)| (coin, *pk)), derivation) {
+            // Only look for the culprit once we know there is one
+            let idx = coins
+                .iter()
+                .position(|(_, coin, pk, _)| !coin.verify(*pk, derivation))
+                .expect("batch verification failed, so one coin has to be invalid");
+            return Err(CoinFinalizationError::InvalidSignature(idx));
+        }
+
+        Ok(coins
+            .into_iter()
+            .map(|(amt, coin, _, spend_key)| (amt, SpendableCoin { coin, spend_key }))
+            .collect())
     }
 
     pub fn coin_count(&self) -> usize {
@@ -314,6 +473,7 @@ impl CoinRequest {
     /// Generate a request session for a single coin and returns it plus the corresponding blinded
     /// message
     fn new<C>(
+        derivation: &MessageDerivation,
         ctx: &Secp256k1<C>,
         mut rng: impl RngCore + CryptoRng,
     ) -> (CoinRequest, BlindedMessage)
@@ -325,12 +485,12 @@ impl CoinRequest {
             ctx, &spend_key,
         ));
 
-        let (blinding_key, blinded_nonce) = blind_message(nonce.to_message());
+        let (blinding_key, blinded_nonce) = blind_message(nonce.to_message(derivation));
 
         let cr = CoinRequest {
-            spend_key: spend_key.serialize_secret(),
+            spend_key: Secret::new(spend_key.serialize_secret()),
             nonce,
-            blinding_key,
+            blinding_key: Secret::new(blinding_key),
         };
 
         (cr, blinded_nonce)
@@ -351,6 +511,14 @@ pub enum CoinFinalizationError {
     InvalidAmountTier(Amount),
     #[error("The client does not know this issuance")]
     UnknownIssuance,
+    #[error("The coins were issued using unknown key generation {0}")]
+    UnknownKeyGeneration(KeyGeneration),
+    #[error("The client does not know peer {0}")]
+    UnknownPeer(PeerId),
+    #[error(
+        "The client config lacks the peers' public key shares, refetch it from the federation"
+    )]
+    OutdatedConfig,
 }
 
 #[derive(Error, Debug)]
@@ -400,3 +568,170 @@ impl From<InvalidAmountTierError> for CoinFinalizationError {
         CoinFinalizationError::InvalidAmountTier(e.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mint::{CoinFinalizationData, CoinFinalizationError};
+    use minimint::modules::mint::api::SignatureShareEndpoint;
+    use minimint::modules::mint::config::{MintClientConfig, MintConfig};
+    use minimint::modules::mint::tiered::coins::Coins;
+    use minimint::modules::mint::{BlindToken, KeyGeneration, Mint, PartialSigResponse};
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::db::batch::DbBatch;
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::db::RawDatabase;
+    use minimint_api::module::api::TypedApiEndpoint;
+    use minimint_api::{Amount, ConsensusContext, FederationModule, OutPoint, PeerId};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn configs() -> (BTreeMap<PeerId, MintConfig>, MintClientConfig) {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        MintConfig::trusted_dealer_gen(
+            &peers,
+            1,
+            &[Amount::from_sat(1), Amount::from_sat(2)],
+            rand::rngs::OsRng::new().unwrap(),
+        )
+    }
+
+    /// Lets every peer accept and sign an output requesting 3 sat and fetches the signature
+    /// shares through the API
+    fn issue(
+        cfgs: &BTreeMap<PeerId, MintConfig>,
+        client_cfg: &MintClientConfig,
+    ) -> (CoinFinalizationData, Vec<(PeerId, PartialSigResponse)>) {
+        let (issuance, sign_request) = CoinFinalizationData::new(
+            Amount::from_sat(3),
+            &client_cfg.tbs_pks,
+            &client_cfg.message_derivation,
+            &secp256k1_zkp::Secp256k1::new(),
+            rand::rngs::OsRng::new().unwrap(),
+        );
+        let output = Coins::<BlindToken>::from(sign_request);
+        let out_point = OutPoint {
+            txid: Default::default(),
+            out_idx: 0,
+        };
+        let context = ConsensusContext {
+            epoch: 0,
+            block_height: None,
+            randomness_beacon: None,
+        };
+
+        let shares = cfgs
+            .iter()
+            .map(|(peer, cfg)| {
+                let db = MemDatabase::new();
+                let mint = Mint::new(cfg.clone(), client_cfg.threshold, Arc::new(db.clone()));
+
+                let mut batch = DbBatch::new();
+                mint.apply_output(&context, batch.transaction(), &output, out_point)
+                    .unwrap();
+                db.apply_batch(batch).unwrap();
+                // Shares are only served once signed
+                assert!(SignatureShareEndpoint::handle(&mint, out_point).is_err());
+
+                assert_eq!(mint.sign_pending_issuances(), 1);
+                let share = SignatureShareEndpoint::handle(&mint, out_point).unwrap();
+                (*peer, share)
+            })
+            .collect();
+
+        (issuance, shares)
+    }
+
+    /// Checks that `issuance` can be finalized into valid coins using the combined `shares`
+    fn assert_combines(
+        issuance: &CoinFinalizationData,
+        shares: Vec<(PeerId, PartialSigResponse)>,
+        client_cfg: &MintClientConfig,
+    ) -> KeyGeneration {
+        let bsig = issuance
+            .combine_shares(shares, client_cfg)
+            .unwrap()
+            .expect("Enough valid shares");
+        let generation = bsig.1;
+        let coins = issuance
+            .finalize(
+                bsig,
+                client_cfg.generation_keys(generation).unwrap(),
+                &client_cfg.message_derivation,
+            )
+            .unwrap();
+        assert_eq!(coins.amount(), Amount::from_sat(3));
+        generation
+    }
+
+    #[test]
+    fn test_combine_shares() {
+        let (cfgs, client_cfg) = configs();
+        let (issuance, shares) = issue(&cfgs, &client_cfg);
+
+        assert_combines(&issuance, shares.clone(), &client_cfg);
+        // Any threshold of shares is enough
+        assert_combines(&issuance, shares[1..].to_vec(), &client_cfg);
+        assert!(issuance
+            .combine_shares(shares[2..].to_vec(), &client_cfg)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_combine_invalid_shares() {
+        let (cfgs, client_cfg) = configs();
+        let (issuance, shares) = issue(&cfgs, &client_cfg);
+        let (other_issuance, other_shares) = issue(&cfgs, &client_cfg);
+
+        // Peer 3 passes off the share of peer 2 as its own, the others still combine
+        let mut forged = shares.clone();
+        forged[3].1 = shares[2].1.clone();
+        assert_combines(&issuance, forged.clone(), &client_cfg);
+
+        // Peer 1 additionally returns a share of another issuance, leaving too few valid ones
+        forged[1].1 = other_shares[1].1.clone();
+        assert!(issuance
+            .combine_shares(forged, &client_cfg)
+            .unwrap()
+            .is_none());
+        assert_combines(&other_issuance, other_shares, &client_cfg);
+    }
+
+    #[test]
+    fn test_combine_shares_after_rotation() {
+        let (mut cfgs, client_cfg) = configs();
+        let (issuance, shares) = issue(&cfgs, &client_cfg);
+
+        // Shares signed before rotating the keys are checked against the retired generation's keys
+        let mut rotated_client_cfg = client_cfg.clone();
+        MintConfig::trusted_dealer_rotate(&mut cfgs, &mut rotated_client_cfg, 1, 100);
+        assert_eq!(
+            assert_combines(&issuance, shares, &rotated_client_cfg),
+            KeyGeneration(0)
+        );
+
+        let (issuance, shares) = issue(&cfgs, &rotated_client_cfg);
+        assert_eq!(
+            assert_combines(&issuance, shares, &rotated_client_cfg),
+            KeyGeneration(1)
+        );
+    }
+
+    #[test]
+    fn test_outdated_config() {
+        let (cfgs, client_cfg) = configs();
+        let (issuance, shares) = issue(&cfgs, &client_cfg);
+
+        // Configs created before clients combined shares lack the peers' keys and the threshold
+        let mut json = serde_json::to_value(&client_cfg).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("peer_tbs_pks").unwrap();
+        fields.remove("threshold").unwrap();
+        let old_client_cfg: MintClientConfig = serde_json::from_value(json).unwrap();
+
+        assert!(matches!(
+            issuance.combine_shares(shares, &old_client_cfg),
+            Err(CoinFinalizationError::OutdatedConfig)
+        ));
+    }
+}
@@ -1,10 +1,37 @@
 use bitcoin::Script;
-use minimint_api::db::DatabaseKeyPrefixConst;
+use minimint_api::db::migration::{ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry_with, DbPrefix, DecodedEntry};
+use minimint_api::db::{DatabaseKeyPrefixConst, DecodingError};
 use minimint_api::encoding::{Decodable, Encodable};
+use secp256k1_zkp::{schnorrsig, Secp256k1};
+use serde::Serialize;
 
 pub const DB_PREFIX_PEG_IN: u8 = 0x22;
 
-#[derive(Debug, Clone, Encodable, Decodable)]
+/// Key prefixes of the client's peg-in data
+pub const DB_PREFIXES: &[DbPrefix] = &[DbPrefix {
+    prefix: DB_PREFIX_PEG_IN,
+    name: "Peg-Ins",
+    key: "peg-in script",
+    value: "tweak secret key",
+    decode: decode_peg_in,
+}];
+
+/// Only shows the public tweak key when inspecting the database
+fn decode_peg_in(key: &[u8], value: &[u8]) -> Result<DecodedEntry, DecodingError> {
+    decode_entry_with::<PegInKey, [u8; 32], _, _>(key, value, |tweak_secret| {
+        schnorrsig::KeyPair::from_seckey_slice(&Secp256k1::signing_only(), &tweak_secret)
+            .map(|key_pair| {
+                schnorrsig::PublicKey::from_keypair(&Secp256k1::signing_only(), &key_pair)
+            })
+            .ok()
+    })
+}
+
+/// Version of the client's peg-in database layout
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(0);
+
+#[derive(Debug, Clone, Serialize, Encodable, Decodable)]
 pub struct PegInKey {
     pub peg_in_script: Script,
 }
@@ -19,3 +46,7 @@ pub struct PegInPrefixKey;
 impl DatabaseKeyPrefixConst for PegInPrefixKey {
     const DB_PREFIX: u8 = DB_PREFIX_PEG_IN;
 }
+
+pub fn migrations() -> ModuleMigrations {
+    ModuleMigrations::new("client-wallet", DB_SCHEMA_VERSION)
+}
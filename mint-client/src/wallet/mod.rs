@@ -17,7 +17,7 @@ use std::sync::Arc;
 use thiserror::Error;
 use tracing::debug;
 
-mod db;
+pub mod db;
 
 /// Federation module client for the Wallet module. It can both create transaction inputs and
 /// outputs of the wallet (on-chain) type.
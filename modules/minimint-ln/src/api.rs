@@ -0,0 +1,39 @@
+use crate::contracts::incoming::IncomingContractOffer;
+use crate::contracts::ContractId;
+use crate::{ContractAccount, LightningModule};
+use minimint_api::module::api::{ApiError, TypedApiEndpoint};
+
+/// Lists all offers for incoming contracts
+pub struct OffersEndpoint;
+
+impl TypedApiEndpoint for OffersEndpoint {
+    type State = LightningModule;
+    type Request = ();
+    type Response = Vec<IncomingContractOffer>;
+
+    const PATH: &'static str = "/offers";
+
+    fn handle(module: &LightningModule, _request: ()) -> Result<Self::Response, ApiError> {
+        Ok(module.get_offers())
+    }
+}
+
+/// Fetches the account of a contract, fails with [`ApiError::NotFound`] if it doesn't exist
+pub struct ContractAccountEndpoint;
+
+impl TypedApiEndpoint for ContractAccountEndpoint {
+    type State = LightningModule;
+    type Request = ContractId;
+    type Response = ContractAccount;
+
+    const PATH: &'static str = "/account";
+
+    fn handle(
+        module: &LightningModule,
+        contract_id: ContractId,
+    ) -> Result<ContractAccount, ApiError> {
+        module
+            .get_contract_account(contract_id)
+            .ok_or(ApiError::NotFound)
+    }
+}
@@ -1,18 +1,27 @@
 use minimint_api::config::GenerateConfig;
+use minimint_api::secret::{Erasable, Secret};
 use minimint_api::PeerId;
 use secp256k1::rand::{CryptoRng, RngCore};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::BTreeMap;
+use threshold_crypto::poly::{Commitment, Poly};
+use threshold_crypto::serde_impl::FieldWrap;
+use threshold_crypto::{Fr, IntoFr, PublicKey, PublicKeySet, SecretKeyShare};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightningModuleConfig {
     pub threshold_pub_keys: threshold_crypto::PublicKeySet,
-    // TODO: propose serde(with = "…") based protection upstream instead
-    pub threshold_sec_key:
-        threshold_crypto::serde_impl::SerdeSecret<threshold_crypto::SecretKeyShare>,
+    #[serde(with = "minimint_api::secret::exposed")]
+    pub threshold_sec_key: Secret<ThresholdKeyShare>,
     pub threshold: usize,
 }
 
+/// Key share of a `threshold_crypto` key set. [`SecretKeyShare`] doesn't expose its scalar, which
+/// resharing needs, so the scalar is kept instead. It's serialized as a field element, the same
+/// way `SerdeSecret<SecretKeyShare>` is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ThresholdKeyShare(Fr);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightningModuleClientConfig {
     pub threshold_pub_key: threshold_crypto::PublicKey,
@@ -29,19 +38,21 @@ impl GenerateConfig for LightningModuleConfig {
         mut rng: impl RngCore + CryptoRng,
     ) -> (BTreeMap<PeerId, Self>, Self::ClientConfig) {
         let threshold = peers.len() - max_evil;
-        let sks = threshold_crypto::SecretKeySet::random(threshold, &mut rng);
-        let pks = sks.public_keys();
+        // Any `threshold` shares have to be able to decrypt, which needs a polynomial of one degree
+        // less
+        let poly = Poly::random(threshold - 1, &mut rng);
+        let pks = PublicKeySet::from(poly.commitment());
 
         let server_cfg = peers
             .iter()
             .map(|&peer| {
-                let sk = sks.secret_key_share(peer.to_usize());
+                let sk = ThresholdKeyShare::from_poly(&poly, peer.to_usize());
 
                 (
                     peer,
                     LightningModuleConfig {
                         threshold_pub_keys: pks.clone(),
-                        threshold_sec_key: threshold_crypto::serde_impl::SerdeSecret(sk),
+                        threshold_sec_key: Secret::new(sk),
                         threshold,
                     },
                 )
@@ -55,3 +66,315 @@ impl GenerateConfig for LightningModuleConfig {
         (server_cfg, client_cfg)
     }
 }
+
+impl LightningModuleConfig {
+    /// Moves the threshold key of the lightning module to `new_peers`, of which all but
+    /// `new_max_evil` are needed to decrypt, using [`trusted_dealer_reshare_key_set`]. The public
+    /// key stays the same, so preimages encrypted for outstanding offers can still be decrypted and
+    /// the client config doesn't change. Key shares are indexed by peer id.
+    ///
+    /// # Panics
+    /// * If `cfgs` contains fewer configs than the current threshold
+    pub fn trusted_dealer_reshare(
+        cfgs: &BTreeMap<PeerId, LightningModuleConfig>,
+        new_peers: &[PeerId],
+        new_max_evil: usize,
+        rng: impl RngCore + CryptoRng,
+    ) -> BTreeMap<PeerId, LightningModuleConfig> {
+        let template = cfgs.values().next().expect("No lightning configs supplied");
+        let new_threshold = new_peers.len() - new_max_evil;
+
+        let shares = cfgs
+            .iter()
+            .map(|(peer, cfg)| (peer.to_usize(), *cfg.threshold_sec_key.expose()))
+            .collect();
+        let new_keys = new_peers
+            .iter()
+            .map(|peer| peer.to_usize())
+            .collect::<Vec<_>>();
+        let (pks, sks) = trusted_dealer_reshare_key_set(
+            &template.threshold_pub_keys,
+            &shares,
+            &new_keys,
+            new_threshold - 1,
+            rng,
+        );
+
+        sks.into_iter()
+            .map(|(idx, sk)| {
+                let config = LightningModuleConfig {
+                    threshold_pub_keys: pks.clone(),
+                    threshold_sec_key: Secret::new(sk),
+                    threshold: new_threshold,
+                };
+                (PeerId::from(idx as u16), config)
+            })
+            .collect()
+    }
+}
+
+impl ThresholdKeyShare {
+    /// Key share of the key holder with index `idx` of the key set defined by `poly`, see
+    /// [`threshold_crypto::SecretKeySet::secret_key_share`]
+    pub fn from_poly(poly: &Poly, idx: usize) -> ThresholdKeyShare {
+        ThresholdKeyShare(poly.evaluate((idx as u64) + 1))
+    }
+
+    /// Converts the key share for signing or decrypting. The returned key share erases itself when
+    /// dropped.
+    pub fn to_secret_key_share(&self) -> SecretKeyShare {
+        let mut scalar = self.0;
+        SecretKeyShare::from_mut(&mut scalar)
+    }
+}
+
+impl Erasable for ThresholdKeyShare {
+    fn erased() -> Self {
+        ThresholdKeyShare(0u64.into_fr())
+    }
+}
+
+impl Serialize for ThresholdKeyShare {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FieldWrap(self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ThresholdKeyShare {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let scalar = FieldWrap::<Fr>::deserialize(deserializer)?.into_inner();
+        Ok(ThresholdKeyShare(scalar))
+    }
+}
+
+/// Moves a `threshold_crypto` key set to new key holders without changing its public key. Every
+/// old key holder splits its key share into sub-shares using a random polynomial of degree
+/// `new_threshold` whose constant term is its key share and publishes commitments to it. Each new
+/// key holder verifies its sub-shares and interpolates its new key share from them, so the secret
+/// key is never reconstructed. Like with [`threshold_crypto::SecretKeySet::random`]
+/// `new_threshold + 1` of the new key shares are needed to decrypt or sign. Like the mint's key
+/// resharing the protocol is run for all key holders locally. Key shares are indexed like
+/// [`threshold_crypto::SecretKeySet::secret_key_share`], which allows both key sets indexed by peer
+/// id and by peer position.
+///
+/// * `pk_set`: the current public key set, the old key shares are checked against it
+/// * `shares`: pairs of old key indices and the key share of said key holder, all of them deal
+///   sub-shares
+/// * `new_keys`: indices of the new key holders
+///
+/// # Panics
+/// * If fewer shares than needed to reconstruct the key are supplied
+/// * If a share doesn't belong to `pk_set`
+pub fn trusted_dealer_reshare_key_set(
+    pk_set: &PublicKeySet,
+    shares: &BTreeMap<usize, ThresholdKeyShare>,
+    new_keys: &[usize],
+    new_threshold: usize,
+    mut rng: impl RngCore + CryptoRng,
+) -> (PublicKeySet, BTreeMap<usize, ThresholdKeyShare>) {
+    assert!(
+        shares.len() > pk_set.threshold(),
+        "Not enough key shares to reshare the key set"
+    );
+
+    let dealers = shares.keys().copied().collect::<Vec<_>>();
+    let deals = shares
+        .iter()
+        .map(|(&idx, share)| {
+            let deal = ReshareDeal::new(idx, share, &dealers, new_keys, new_threshold, &mut rng);
+            assert_eq!(
+                deal.dealer_pub_key().to_bytes(),
+                pk_set.public_key_share(idx).to_bytes(),
+                "Key share doesn't belong to the key set"
+            );
+            (idx, deal)
+        })
+        .collect::<Vec<_>>();
+
+    let mut commitment = Poly::zero().commitment();
+    for (_, deal) in &deals {
+        commitment += &deal.weighted_commitment;
+    }
+    let new_pk_set = PublicKeySet::from(commitment);
+    assert_eq!(
+        new_pk_set.public_key(),
+        pk_set.public_key(),
+        "Resharing changed the public key"
+    );
+
+    let new_shares = new_keys
+        .iter()
+        .enumerate()
+        .map(|(pos, &new_idx)| {
+            let samples = deals.iter().map(|(idx, deal)| {
+                let sub_share = &deal.sub_shares[pos];
+                assert!(
+                    deal.verify_sub_share(new_idx, sub_share),
+                    "Invalid sub-share"
+                );
+                ((*idx as u64) + 1, sub_share.0)
+            });
+            let share = ThresholdKeyShare(Poly::interpolate(samples).evaluate(0u64));
+            assert_eq!(
+                share.to_secret_key_share().public_key_share(),
+                new_pk_set.public_key_share(new_idx)
+            );
+            (new_idx, share)
+        })
+        .collect();
+    (new_pk_set, new_shares)
+}
+
+/// Sub-shares an old key holder dealt to the new key holders and the commitments needed to check
+/// them
+struct ReshareDeal {
+    /// Commitment to the polynomial the key share was split with
+    commitment: Commitment,
+    /// Commitment to the same polynomial multiplied with the dealer's Lagrange coefficient, these
+    /// add up to the new public key set
+    weighted_commitment: Commitment,
+    /// Sub-shares in the order of the new key holders
+    sub_shares: Vec<ThresholdKeyShare>,
+}
+
+impl ReshareDeal {
+    fn new(
+        idx: usize,
+        share: &ThresholdKeyShare,
+        dealers: &[usize],
+        new_keys: &[usize],
+        new_threshold: usize,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> ReshareDeal {
+        // Random polynomial with the key share as constant term
+        let mut poly = Poly::random(new_threshold, rng);
+        let random_constant = poly.evaluate(0u64);
+        poly -= Poly::constant(random_constant);
+        poly += Poly::constant(share.0);
+
+        let weighted_poly = &poly * Poly::constant(lagrange_coefficient(idx, dealers));
+        let sub_shares = new_keys
+            .iter()
+            .map(|&new_idx| ThresholdKeyShare(poly.evaluate((new_idx as u64) + 1)))
+            .collect();
+
+        ReshareDeal {
+            commitment: poly.commitment(),
+            weighted_commitment: weighted_poly.commitment(),
+            sub_shares,
+        }
+    }
+
+    /// Public key share of the dealer, the constant term of its polynomial
+    fn dealer_pub_key(&self) -> PublicKey {
+        PublicKeySet::from(self.commitment.clone()).public_key()
+    }
+
+    /// Checks that `sub_share` was dealt to the new key holder with index `idx`
+    fn verify_sub_share(&self, idx: usize, sub_share: &ThresholdKeyShare) -> bool {
+        PublicKeySet::from(self.commitment.clone()).public_key_share(idx)
+            == sub_share.to_secret_key_share().public_key_share()
+    }
+}
+
+/// Lagrange coefficient of the key holder `idx` for interpolating the value at zero from the key
+/// holders `indices`
+fn lagrange_coefficient(idx: usize, indices: &[usize]) -> Fr {
+    let samples = indices
+        .iter()
+        .map(|&other| ((other as u64) + 1, u64::from(other == idx)));
+    Poly::interpolate(samples).evaluate(0u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::LightningModuleConfig;
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::PeerId;
+
+    #[test]
+    fn test_reshare_keys() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let (mut cfgs, client_cfg) = LightningModuleConfig::trusted_dealer_gen(
+            &peers,
+            1,
+            &(),
+            secp256k1::rand::rngs::OsRng::new().unwrap(),
+        );
+        let ciphertext = client_cfg.threshold_pub_key.encrypt(b"preimage");
+
+        // Peer 3 leaves and peers 5 and 7 join, so the new peer ids aren't contiguous
+        cfgs.remove(&PeerId::from(3));
+        let new_peers = [0u16, 1, 2, 5, 7]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        let new_cfgs = LightningModuleConfig::trusted_dealer_reshare(
+            &cfgs,
+            &new_peers,
+            1,
+            secp256k1::rand::rngs::OsRng::new().unwrap(),
+        );
+        assert_eq!(new_cfgs.keys().copied().collect::<Vec<_>>(), new_peers);
+
+        // Any 4 of the new peers can decrypt preimages encrypted before resharing
+        let dec_shares = new_cfgs
+            .iter()
+            .skip(1)
+            .map(|(peer, cfg)| {
+                assert_eq!(cfg.threshold, 4);
+                assert_eq!(
+                    cfg.threshold_pub_keys.public_key(),
+                    client_cfg.threshold_pub_key
+                );
+                let share = cfg
+                    .threshold_sec_key
+                    .expose()
+                    .to_secret_key_share()
+                    .decrypt_share(&ciphertext)
+                    .unwrap();
+                (peer.to_usize(), share)
+            })
+            .collect::<Vec<_>>();
+        let pks = &new_cfgs[&PeerId::from(0)].threshold_pub_keys;
+        assert_eq!(
+            pks.decrypt(
+                dec_shares.iter().map(|(idx, share)| (*idx, share)),
+                &ciphertext
+            )
+            .unwrap(),
+            b"preimage".to_vec()
+        );
+        assert!(pks
+            .decrypt(
+                dec_shares.iter().take(3).map(|(idx, share)| (*idx, share)),
+                &ciphertext
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_reshare_single_peer() {
+        // A federation of a single peer has only one dealer
+        let (cfgs, client_cfg) = LightningModuleConfig::trusted_dealer_gen(
+            &[PeerId::from(0)],
+            0,
+            &(),
+            secp256k1::rand::rngs::OsRng::new().unwrap(),
+        );
+        let new_peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let new_cfgs = LightningModuleConfig::trusted_dealer_reshare(
+            &cfgs,
+            &new_peers,
+            1,
+            secp256k1::rand::rngs::OsRng::new().unwrap(),
+        );
+        for cfg in new_cfgs.values() {
+            assert_eq!(cfg.threshold, 3);
+            assert_eq!(
+                cfg.threshold_pub_keys.public_key(),
+                client_cfg.threshold_pub_key
+            );
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use crate::contracts::ContractId;
+use minimint_api::db::migration::{ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry, DbPrefix};
 use minimint_api::db::DatabaseKeyPrefixConst;
 use minimint_api::encoding::{Decodable, Encodable};
 use minimint_api::{OutPoint, PeerId};
+use serde::Serialize;
 
 const DB_PREFIX_CONTRACT: u8 = 0x40;
 const DB_PREFIX_OFFER: u8 = 0x41;
@@ -10,9 +13,64 @@ const DB_PREFIX_AGREED_DECRYPTION_SHARE: u8 = 0x43;
 const DB_PREFIX_CONTRACT_UPDATE: u8 = 0x44;
 
 // TODO: should this be its own thing or part of the contract
-const DB_PREFIX_DECRYPTED_PREIMAGE_KEY: u8 = 0x44;
-
-#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+const DB_PREFIX_DECRYPTED_PREIMAGE_KEY: u8 = 0x45;
+
+/// Key prefixes of the lightning module
+pub const DB_PREFIXES: &[DbPrefix] = &[
+    DbPrefix {
+        prefix: DB_PREFIX_CONTRACT,
+        name: "Contract Accounts",
+        key: "contract id",
+        value: "contract account",
+        decode: decode_entry::<ContractKey, crate::ContractAccount>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_OFFER,
+        name: "Offers",
+        key: "payment hash",
+        value: "incoming contract offer",
+        decode: decode_entry::<OfferKey, crate::contracts::incoming::IncomingContractOffer>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PROPOSE_DECRYPTION_SHARE,
+        name: "Proposed Decryption Shares",
+        key: "contract id",
+        value: "our preimage decryption share",
+        decode: decode_entry::<
+            ProposeDecryptionShareKey,
+            crate::contracts::incoming::PreimageDecryptionShare,
+        >,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_AGREED_DECRYPTION_SHARE,
+        name: "Agreed Decryption Shares",
+        key: "contract id, peer",
+        value: "preimage decryption share",
+        decode: decode_entry::<
+            AgreedDecryptionShareKey,
+            crate::contracts::incoming::PreimageDecryptionShare,
+        >,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_CONTRACT_UPDATE,
+        name: "Contract Updates",
+        key: "mint outpoint",
+        value: "output outcome",
+        decode: decode_entry::<ContractUpdateKey, crate::OutputOutcome>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_DECRYPTED_PREIMAGE_KEY,
+        name: "Decrypted Preimages",
+        key: "contract id",
+        value: "decrypted preimage",
+        decode: decode_entry::<DecryptedPreimageKey, crate::contracts::incoming::DecryptedPreimage>,
+    },
+];
+
+/// Version of the lightning module's database layout
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(0);
+
+#[derive(Debug, Clone, Copy, Serialize, Encodable, Decodable)]
 pub struct ContractKey(pub ContractId);
 
 impl DatabaseKeyPrefixConst for ContractKey {
@@ -26,14 +84,14 @@ impl DatabaseKeyPrefixConst for ContractKeyPrefix {
     const DB_PREFIX: u8 = DB_PREFIX_CONTRACT;
 }
 
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct ContractUpdateKey(pub OutPoint);
 
 impl DatabaseKeyPrefixConst for ContractUpdateKey {
     const DB_PREFIX: u8 = DB_PREFIX_CONTRACT_UPDATE;
 }
 
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct OfferKey(pub bitcoin_hashes::sha256::Hash);
 
 impl DatabaseKeyPrefixConst for OfferKey {
@@ -48,7 +106,7 @@ impl DatabaseKeyPrefixConst for OfferKeyPrefix {
 }
 
 // TODO: remove redundancy
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct ProposeDecryptionShareKey(pub ContractId);
 
 impl DatabaseKeyPrefixConst for ProposeDecryptionShareKey {
@@ -64,7 +122,7 @@ impl DatabaseKeyPrefixConst for ProposeDecryptionShareKeyPrefix {
 }
 
 /// Preimage decryption shares we received
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct AgreedDecryptionShareKey(pub ContractId, pub PeerId);
 
 impl DatabaseKeyPrefixConst for AgreedDecryptionShareKey {
@@ -79,9 +137,13 @@ impl DatabaseKeyPrefixConst for AgreedDecryptionShareKeyPrefix {
     const DB_PREFIX: u8 = DB_PREFIX_AGREED_DECRYPTION_SHARE;
 }
 
-#[derive(Debug, Encodable, Decodable)]
+#[derive(Debug, Serialize, Encodable, Decodable)]
 pub struct DecryptedPreimageKey(pub ContractId);
 
 impl DatabaseKeyPrefixConst for DecryptedPreimageKey {
     const DB_PREFIX: u8 = DB_PREFIX_DECRYPTED_PREIMAGE_KEY;
 }
+
+pub fn migrations() -> ModuleMigrations {
+    ModuleMigrations::new("ln", DB_SCHEMA_VERSION)
+}
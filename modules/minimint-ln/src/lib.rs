@@ -4,9 +4,10 @@
 //! the Lightning network through a Lightning gateway. See [`LightningModule`] for a high level
 //! overview.
 
+pub mod api;
 pub mod config;
 pub mod contracts;
-mod db;
+pub mod db;
 
 use crate::config::LightningModuleConfig;
 use crate::contracts::incoming::{
@@ -16,8 +17,9 @@ use crate::contracts::{
     Contract, ContractId, ContractOutcome, FundedContract, IdentifyableContract,
 };
 use crate::db::{
-    AgreedDecryptionShareKey, AgreedDecryptionShareKeyPrefix, ContractKey, ContractUpdateKey,
-    OfferKey, OfferKeyPrefix, ProposeDecryptionShareKey, ProposeDecryptionShareKeyPrefix,
+    AgreedDecryptionShareKey, AgreedDecryptionShareKeyPrefix, ContractKey, ContractKeyPrefix,
+    ContractUpdateKey, OfferKey, OfferKeyPrefix, ProposeDecryptionShareKey,
+    ProposeDecryptionShareKeyPrefix,
 };
 use async_trait::async_trait;
 use bitcoin_hashes::Hash as BitcoinHash;
@@ -25,7 +27,9 @@ use itertools::Itertools;
 use minimint_api::db::batch::{BatchItem, BatchTx};
 use minimint_api::db::{Database, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{Amount, FederationModule, PeerId};
+use minimint_api::module::api::ApiEndpoint;
+use minimint_api::module::audit::ModuleAudit;
+use minimint_api::{Amount, ConsensusContext, FederationModule, PeerId};
 use minimint_api::{InputMeta, OutPoint};
 use secp256k1::rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
@@ -122,6 +126,8 @@ impl FederationModule for LightningModule {
     type TxOutputOutcome = OutputOutcome;
     type ConsensusItem = DecryptionShareCI;
 
+    const API_BASE_NAME: &'static str = "ln";
+
     async fn consensus_proposal<'a>(
         &'a self,
         _rng: impl RngCore + CryptoRng + 'a,
@@ -137,6 +143,7 @@ impl FederationModule for LightningModule {
 
     async fn begin_consensus_epoch<'a>(
         &'a self,
+        _context: &'a ConsensusContext,
         mut batch: BatchTx<'a>,
         consensus_items: Vec<(PeerId, Self::ConsensusItem)>,
         _rng: impl RngCore + CryptoRng + 'a,
@@ -178,68 +185,22 @@ impl FederationModule for LightningModule {
         batch.commit();
     }
 
-    fn validate_input<'a>(&self, input: &'a Self::TxInput) -> Result<InputMeta<'a>, Self::Error> {
-        let account: ContractAccount = self
-            .get_contract_account(input.crontract_id)
-            .ok_or(LightningModuleError::UnknownContract(input.crontract_id))?;
-
-        if account.amount < input.amount {
-            return Err(LightningModuleError::InsufficientFunds(
-                account.amount,
-                input.amount,
-            ));
-        }
-
-        let pub_key = match account.contract {
-            FundedContract::Outgoing(outgoing) => {
-                // TODO: properly define semantics, same as LN (> vs >=)
-                if outgoing.timelock > self.block_height() {
-                    // If the timelock hasn't expired yet …
-                    let preimage_hash = bitcoin_hashes::sha256::Hash::hash(
-                        &input
-                            .witness
-                            .as_ref()
-                            .ok_or(LightningModuleError::MissingPreimage)?
-                            .0[..],
-                    );
-
-                    // … and the spender provides a valid preimage …
-                    if preimage_hash != outgoing.hash {
-                        return Err(LightningModuleError::InvalidPreimage);
-                    }
-
-                    // … then the contract account can be spent using the gateway key,
-                    outgoing.gateway_key
-                } else {
-                    // otherwise the user can claim the funds back.
-                    outgoing.user_key
-                }
-            }
-            FundedContract::Account(acc_contract) => acc_contract.key,
-            FundedContract::Incoming(incoming) => match incoming.contract.decrypted_preimage {
-                // Once the preimage has been decrypted …
-                DecryptedPreimage::Pending => {
-                    return Err(LightningModuleError::ContractNotReady);
-                }
-                // … either the user may spend the funds since they sold a valid preimage …
-                DecryptedPreimage::Some(preimage) => preimage.0,
-                // … or the gateway may claim back funds for not receiving the advertised preimage.
-                DecryptedPreimage::Invalid => incoming.contract.gateway_key,
-            },
-        };
-
-        Ok(InputMeta {
-            amount: input.amount,
-            puk_keys: Box::new(std::iter::once(pub_key)),
-        })
+    fn validate_input<'a>(
+        &self,
+        context: &ConsensusContext,
+        input: &'a Self::TxInput,
+    ) -> Result<InputMeta<'a>, Self::Error> {
+        self.validate_input_in(context, self.db.as_ref(), input)
     }
 
     fn apply_input<'a, 'b>(
         &'a self,
+        context: &ConsensusContext,
         mut batch: BatchTx<'a>,
         input: &'b Self::TxInput,
     ) -> Result<InputMeta<'b>, Self::Error> {
-        let meta = self.validate_input(input)?;
+        // Validate against the batch to take earlier spends from the same contract into account
+        let meta = self.validate_input_in(context, &batch.overlay(self.db.as_ref()), input)?;
         let amount = meta.amount;
 
         batch.append_maybe_update(
@@ -257,48 +218,19 @@ impl FederationModule for LightningModule {
     }
 
     fn validate_output(&self, output: &Self::TxOutput) -> Result<Amount, Self::Error> {
-        match output {
-            ContractOrOfferOutput::Contract(contract) => {
-                // Incoming contracts are special, they need to match an offer
-                if let Contract::Incoming(incoming) = &contract.contract {
-                    let offer = self
-                        .db
-                        .get_value::<_, IncomingContractOffer>(&OfferKey(incoming.hash))
-                        .expect("DB error")
-                        .ok_or(LightningModuleError::NoOffer(incoming.hash))?;
-
-                    if contract.amount < offer.amount {
-                        // If the account is not sufficiently funded fail the output
-                        return Err(LightningModuleError::InsufficientIncomingFunding(
-                            offer.amount,
-                            contract.amount,
-                        ));
-                    }
-                }
-
-                if contract.amount == Amount::ZERO {
-                    Err(LightningModuleError::ZeroOutput)
-                } else {
-                    Ok(contract.amount)
-                }
-            }
-            ContractOrOfferOutput::Offer(offer) => {
-                if !offer.encrypted_preimage.0.verify() {
-                    Err(LightningModuleError::InvalidEncryptedPreimage)
-                } else {
-                    Ok(Amount::ZERO)
-                }
-            }
-        }
+        self.validate_output_in(self.db.as_ref(), output)
     }
 
     fn apply_output<'a>(
         &'a self,
+        _context: &ConsensusContext,
         mut batch: BatchTx<'a>,
         output: &'a Self::TxOutput,
         out_point: OutPoint,
     ) -> Result<Amount, Self::Error> {
-        let amount = self.validate_output(output)?;
+        // Validate against the batch so offers created or used up earlier in the same epoch are
+        // taken into account
+        let amount = self.validate_output_in(&batch.overlay(self.db.as_ref()), output)?;
 
         match output {
             ContractOrOfferOutput::Contract(contract) => {
@@ -328,22 +260,18 @@ impl FederationModule for LightningModule {
                 );
 
                 if let Contract::Incoming(incoming) = &contract.contract {
-                    let offer = self
-                        .db
-                        .get_value::<_, IncomingContractOffer>(&OfferKey(incoming.hash))
-                        .expect("DB error")
-                        .expect("offer exists if output is valid");
-
                     let deryption_share = self
                         .cfg
                         .threshold_sec_key
+                        .expose()
+                        .to_secret_key_share()
                         .decrypt_share(&incoming.encrypted_preimage.0)
                         .expect("We checked for decryption share validity on contract creation");
                     batch.append_insert_new(
                         ProposeDecryptionShareKey(contract.contract.contract_id()),
                         PreimageDecryptionShare(deryption_share),
                     );
-                    batch.append_delete(OfferKey(offer.hash));
+                    batch.append_delete(OfferKey(incoming.hash));
                 }
             }
             ContractOrOfferOutput::Offer(offer) => {
@@ -474,6 +402,28 @@ impl FederationModule for LightningModule {
             .get_value(&ContractUpdateKey(out_point))
             .expect("DB error")
     }
+
+    fn audit(&self) -> ModuleAudit {
+        // Funds locked in contracts are owed to whoever can claim them, the module holds no bitcoin
+        let locked = self
+            .db
+            .find_by_prefix::<_, ContractKey, ContractAccount>(&ContractKeyPrefix)
+            .map(|res| res.expect("DB error").1.amount)
+            .sum();
+
+        ModuleAudit {
+            assets: Amount::ZERO,
+            liabilities: locked,
+            shortfall: Amount::ZERO,
+        }
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            ApiEndpoint::from_typed::<api::OffersEndpoint>(),
+            ApiEndpoint::from_typed::<api::ContractAccountEndpoint>(),
+        ]
+    }
 }
 
 impl LightningModule {
@@ -493,29 +443,108 @@ impl LightningModule {
             .verify_decryption_share(&share.0, &message.0)
     }
 
-    fn block_height(&self) -> u32 {
-        // FIXME: duplicate round consensus logic or define proper interface
-        const DB_PREFIX_ROUND_CONSENSUS: u8 = 0x32;
-
-        #[derive(Clone, Debug, Encodable, Decodable)]
-        pub struct RoundConsensusKey;
+    /// Validates `input` against the state of `db`, which is either the committed database or an
+    /// overlay containing the pending changes of the current epoch
+    fn validate_input_in<'a>(
+        &self,
+        context: &ConsensusContext,
+        db: &dyn RawDatabase,
+        input: &'a ContractInput,
+    ) -> Result<InputMeta<'a>, LightningModuleError> {
+        let account: ContractAccount = db
+            .get_value(&ContractKey(input.crontract_id))
+            .expect("DB error")
+            .ok_or(LightningModuleError::UnknownContract(input.crontract_id))?;
 
-        impl minimint_api::db::DatabaseKeyPrefixConst for RoundConsensusKey {
-            const DB_PREFIX: u8 = DB_PREFIX_ROUND_CONSENSUS;
+        if account.amount < input.amount {
+            return Err(LightningModuleError::InsufficientFunds(
+                account.amount,
+                input.amount,
+            ));
         }
 
-        #[derive(Debug, Encodable, Decodable)]
-        pub struct RoundConsensus {
-            block_height: u32,
-            fee_rate: u64,
-            randomness_beacon: [u8; 32],
-        }
+        let pub_key = match account.contract {
+            FundedContract::Outgoing(outgoing) => {
+                // TODO: properly define semantics, same as LN (> vs >=)
+                if outgoing.timelock > context.block_height.unwrap_or(0) {
+                    // If the timelock hasn't expired yet …
+                    let preimage_hash = bitcoin_hashes::sha256::Hash::hash(
+                        &input
+                            .witness
+                            .as_ref()
+                            .ok_or(LightningModuleError::MissingPreimage)?
+                            .0[..],
+                    );
 
-        self.db
-            .get_value::<_, RoundConsensus>(&RoundConsensusKey)
-            .expect("DB error")
-            .map(|rc| rc.block_height)
-            .unwrap_or(0)
+                    // … and the spender provides a valid preimage …
+                    if preimage_hash != outgoing.hash {
+                        return Err(LightningModuleError::InvalidPreimage);
+                    }
+
+                    // … then the contract account can be spent using the gateway key,
+                    outgoing.gateway_key
+                } else {
+                    // otherwise the user can claim the funds back.
+                    outgoing.user_key
+                }
+            }
+            FundedContract::Account(acc_contract) => acc_contract.key,
+            FundedContract::Incoming(incoming) => match incoming.contract.decrypted_preimage {
+                // Once the preimage has been decrypted …
+                DecryptedPreimage::Pending => {
+                    return Err(LightningModuleError::ContractNotReady);
+                }
+                // … either the user may spend the funds since they sold a valid preimage …
+                DecryptedPreimage::Some(preimage) => preimage.0,
+                // … or the gateway may claim back funds for not receiving the advertised preimage.
+                DecryptedPreimage::Invalid => incoming.contract.gateway_key,
+            },
+        };
+
+        Ok(InputMeta {
+            amount: input.amount,
+            puk_keys: Box::new(std::iter::once(pub_key)),
+        })
+    }
+
+    /// Validates `output` against the state of `db`, see [`LightningModule::validate_input_in`]
+    fn validate_output_in(
+        &self,
+        db: &dyn RawDatabase,
+        output: &ContractOrOfferOutput,
+    ) -> Result<Amount, LightningModuleError> {
+        match output {
+            ContractOrOfferOutput::Contract(contract) => {
+                // Incoming contracts are special, they need to match an offer
+                if let Contract::Incoming(incoming) = &contract.contract {
+                    let offer = db
+                        .get_value::<_, IncomingContractOffer>(&OfferKey(incoming.hash))
+                        .expect("DB error")
+                        .ok_or(LightningModuleError::NoOffer(incoming.hash))?;
+
+                    if contract.amount < offer.amount {
+                        // If the account is not sufficiently funded fail the output
+                        return Err(LightningModuleError::InsufficientIncomingFunding(
+                            offer.amount,
+                            contract.amount,
+                        ));
+                    }
+                }
+
+                if contract.amount == Amount::ZERO {
+                    Err(LightningModuleError::ZeroOutput)
+                } else {
+                    Ok(contract.amount)
+                }
+            }
+            ContractOrOfferOutput::Offer(offer) => {
+                if !offer.encrypted_preimage.0.verify() {
+                    Err(LightningModuleError::InvalidEncryptedPreimage)
+                } else {
+                    Ok(Amount::ZERO)
+                }
+            }
+        }
     }
 
     pub fn get_offers(&self) -> Vec<IncomingContractOffer> {
@@ -1,17 +1,18 @@
 use bitcoin_hashes::Hash as BitcoinHash;
-use minimint_api::db::{Database, RawDatabase};
+use minimint_api::config::GenerateConfig;
 use minimint_api::module::testing::FakeFed;
-use minimint_api::{Amount, OutPoint};
-use minimint_ln::config::LightningModuleClientConfig;
+use minimint_api::{Amount, OutPoint, PeerId};
+use minimint_ln::config::{LightningModuleClientConfig, LightningModuleConfig};
 use minimint_ln::contracts::account::AccountContract;
 use minimint_ln::contracts::incoming::{
     DecryptedPreimage, EncryptedPreimage, IncomingContract, IncomingContractOffer,
+    PreimageDecryptionShare,
 };
 use minimint_ln::contracts::outgoing::{OutgoingContract, Preimage};
 use minimint_ln::contracts::{Contract, ContractOutcome, IdentifyableContract};
 use minimint_ln::{
-    ContractInput, ContractOrOfferOutput, ContractOutput, LightningModule, LightningModuleError,
-    OutputOutcome,
+    ContractInput, ContractOrOfferOutput, ContractOutput, DecryptionShareCI, LightningModule,
+    LightningModuleError, OutputOutcome,
 };
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -55,10 +56,23 @@ async fn test_account() {
     };
     let meta = fed.verify_input(&account_input).unwrap();
     assert_eq!(meta.keys, vec![pk]);
+    assert_eq!(fed.audit().liabilities, Amount::from_sat(42));
 
     fed.consensus_round(&[account_input.clone()], &[]).await;
 
     assert!(fed.verify_input(&account_input).is_err());
+    assert_eq!(fed.audit().liabilities, Amount::ZERO);
+
+    let results = fed
+        .consensus_round_with_results(&[account_input.clone()], &[])
+        .await;
+    assert_eq!(
+        results.inputs,
+        vec![Err(LightningModuleError::InsufficientFunds(
+            Amount::ZERO,
+            Amount::from_sat(42)
+        ))]
+    );
 }
 
 #[tokio::test]
@@ -104,7 +118,7 @@ async fn test_outgoing() {
     };
 
     // Test case 1: before timeout
-    fed.patch_dbs(|db| set_block_height(db, 0));
+    fed.set_block_height(0);
 
     // Error: Missing preimage
     let account_input_no_witness = ContractInput {
@@ -125,7 +139,7 @@ async fn test_outgoing() {
     assert_eq!(meta.keys, vec![gw_pk]);
 
     // Test case 2: after timeout
-    fed.patch_dbs(|db| set_block_height(db, 42));
+    fed.set_block_height(42);
     let meta = fed.verify_input(&account_input_no_witness).unwrap();
     assert_eq!(meta.keys, vec![user_pk]);
 
@@ -219,33 +233,101 @@ async fn test_incoming() {
     // TODO: test faulty encrypted preimage
 }
 
-/// Hack to set consensus height of wallet module which is being used by the LN module too for now.
-fn set_block_height(db: &mut dyn RawDatabase, block_height: u32) {
-    use minimint_api::encoding::{Decodable, Encodable};
-
-    const DB_PREFIX_ROUND_CONSENSUS: u8 = 0x32;
-
-    #[derive(Clone, Debug, Encodable, Decodable)]
-    pub struct RoundConsensusKey;
-
-    impl minimint_api::db::DatabaseKeyPrefixConst for RoundConsensusKey {
-        const DB_PREFIX: u8 = DB_PREFIX_ROUND_CONSENSUS;
-    }
-
-    #[derive(Debug, Encodable, Decodable)]
-    pub struct RoundConsensus {
-        block_height: u32,
-        fee_rate: u64,
-        randomness_beacon: [u8; 32],
-    }
-
-    db.insert_entry(
-        &RoundConsensusKey,
-        &RoundConsensus {
-            block_height,
-            fee_rate: 0,
-            randomness_beacon: [0; 32],
-        },
+#[tokio::test]
+async fn test_byzantine_decryption_share() {
+    let mut rng = secp256k1::rand::rngs::OsRng::new().unwrap();
+
+    let mut fed = FakeFed::<LightningModule, LightningModuleClientConfig>::new(
+        4,
+        1,
+        |cfg, db| LightningModule::new(cfg, Arc::new(db)),
+        &(),
+    );
+    let peers = (1..=4).map(PeerId::from).collect::<Vec<_>>();
+
+    let (_, gw_pk) = secp256k1::SECP256K1.generate_schnorrsig_keypair(&mut rng);
+    let (_, user_pk) = secp256k1::SECP256K1.generate_schnorrsig_keypair(&mut rng);
+
+    let preimage = user_pk.serialize();
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage);
+    let encrypted_preimage = EncryptedPreimage::new(preimage, &fed.client_cfg().threshold_pub_key);
+
+    let offer = IncomingContractOffer {
+        amount: Amount::from_sat(42),
+        hash,
+        encrypted_preimage: encrypted_preimage.clone(),
+    };
+    let offer_out_point = OutPoint {
+        txid: Default::default(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(offer_out_point, ContractOrOfferOutput::Offer(offer))],
     )
-    .unwrap();
+    .await;
+
+    let contract = Contract::Incoming(IncomingContract {
+        hash,
+        encrypted_preimage: encrypted_preimage.clone(),
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+    });
+    let incoming_output = ContractOrOfferOutput::Contract(ContractOutput {
+        amount: Amount::from_sat(42),
+        contract,
+    });
+    let incoming_out_point = OutPoint {
+        txid: Default::default(),
+        out_idx: 1,
+    };
+    fed.consensus_round(&[], &[(incoming_out_point, incoming_output)])
+        .await;
+
+    // The first peer proposes shares made with a key that doesn't belong to the federation
+    let (mut foreign_cfg, _) = LightningModuleConfig::trusted_dealer_gen(&peers, 1, &(), &mut rng);
+    let foreign_key = foreign_cfg
+        .remove(&peers[0])
+        .unwrap()
+        .threshold_sec_key
+        .expose()
+        .to_secret_key_share();
+    fed.make_byzantine(peers[0], move |proposal| {
+        proposal
+            .into_iter()
+            .map(|ci| DecryptionShareCI {
+                share: PreimageDecryptionShare(
+                    foreign_key.decrypt_share(&encrypted_preimage.0).unwrap(),
+                ),
+                ..ci
+            })
+            .collect()
+    });
+
+    // With the last peer offline only two valid shares are left, which isn't enough
+    fed.only_include_proposals_of(&peers[..3]);
+    fed.consensus_round(&[], &[]).await;
+    match fed.output_outcome(incoming_out_point).unwrap() {
+        OutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(
+                outcome,
+                ContractOutcome::Incoming(DecryptedPreimage::Pending)
+            );
+        }
+        _ => panic!(),
+    };
+
+    fed.include_all_proposals();
+    fed.consensus_round(&[], &[]).await;
+    match fed.output_outcome(incoming_out_point).unwrap() {
+        OutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(
+                outcome,
+                ContractOutcome::Incoming(DecryptedPreimage::Some(
+                    minimint_ln::contracts::incoming::Preimage(user_pk)
+                ))
+            );
+        }
+        _ => panic!(),
+    };
 }
@@ -0,0 +1,35 @@
+use crate::{Mint, PartialSigResponse, PeerFault};
+use minimint_api::module::api::{ApiError, TypedApiEndpoint};
+use minimint_api::OutPoint;
+
+/// Fetches our signature share for an accepted output, fails with [`ApiError::NotFound`] if the
+/// output wasn't signed yet. Clients have to query every peer and combine the shares themselves.
+pub struct SignatureShareEndpoint;
+
+impl TypedApiEndpoint for SignatureShareEndpoint {
+    type State = Mint;
+    type Request = OutPoint;
+    type Response = PartialSigResponse;
+
+    const PATH: &'static str = "/sig_share";
+
+    fn handle(module: &Mint, out_point: OutPoint) -> Result<PartialSigResponse, ApiError> {
+        module.signature_share(out_point).ok_or(ApiError::NotFound)
+    }
+}
+
+/// Lists the invalid signature shares other peers contributed through consensus, used to identify
+/// misbehaving peers
+pub struct PeerFaultsEndpoint;
+
+impl TypedApiEndpoint for PeerFaultsEndpoint {
+    type State = Mint;
+    type Request = ();
+    type Response = Vec<PeerFault>;
+
+    const PATH: &'static str = "/faults";
+
+    fn handle(module: &Mint, _request: ()) -> Result<Self::Response, ApiError> {
+        Ok(module.peer_faults())
+    }
+}
@@ -1,20 +1,70 @@
-use crate::Keys;
+use crate::{KeyGeneration, Keys};
 use minimint_api::config::GenerateConfig;
+use minimint_api::secret::Secret;
 use minimint_api::{Amount, PeerId};
 use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
-use tbs::{dealer_keygen, AggregatePublicKey};
+use tbs::reshare::{combine_commitments, combine_sub_shares, deal_reshare};
+use tbs::{dealer_keygen, Aggregatable, AggregatePublicKey, MessageDerivation};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MintConfig {
-    pub tbs_sks: Keys<tbs::SecretKeyShare>,
+    /// Key generation used for issuing new coins
+    #[serde(default)]
+    pub generation: KeyGeneration,
+    #[serde(with = "serde_secret_keys")]
+    pub tbs_sks: Keys<Secret<tbs::SecretKeyShare>>,
     pub peer_tbs_pks: BTreeMap<PeerId, Keys<tbs::PublicKeyShare>>,
+    /// Previous key generations that are not used for issuance anymore, but whose coins can still
+    /// be spent until they are deprecated
+    #[serde(default)]
+    pub retired_generations: BTreeMap<KeyGeneration, RetiredKeyGeneration>,
+    /// How coin nonces are mapped to the messages being blind signed, shared by all generations
+    #[serde(default)]
+    pub message_derivation: MessageDerivation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetiredKeyGeneration {
+    pub peer_tbs_pks: BTreeMap<PeerId, Keys<tbs::PublicKeyShare>>,
+    /// First epoch in which coins of this generation are rejected
+    pub deprecation_epoch: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MintClientConfig {
+    /// Key generation under which new coins are issued
+    #[serde(default)]
+    pub generation: KeyGeneration,
+    pub tbs_pks: Keys<AggregatePublicKey>,
+    /// Public key shares of the current generation, used to check the signature shares fetched
+    /// from every peer before combining them. Missing in configs created before clients combined
+    /// signature shares themselves, these have to be refetched from the federation.
+    #[serde(default)]
+    pub peer_tbs_pks: BTreeMap<PeerId, Keys<tbs::PublicKeyShare>>,
+    /// Number of signature shares needed to combine a signature, zero in configs created before
+    /// clients combined signature shares themselves
+    #[serde(default)]
+    pub threshold: usize,
+    /// Keys of previous generations, coins signed with these should be reissued before they get
+    /// deprecated
+    #[serde(default)]
+    pub retired_generations: BTreeMap<KeyGeneration, RetiredClientKeyGeneration>,
+    /// How coin nonces are mapped to the messages being blind signed
+    #[serde(default)]
+    pub message_derivation: MessageDerivation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetiredClientKeyGeneration {
     pub tbs_pks: Keys<AggregatePublicKey>,
+    /// Public key shares of this generation, needed to combine signature shares created before
+    /// the keys were rotated
+    #[serde(default)]
+    pub peer_tbs_pks: BTreeMap<PeerId, Keys<tbs::PublicKeyShare>>,
+    /// First epoch in which coins of this generation are rejected
+    pub deprecation_epoch: u64,
 }
 
 impl GenerateConfig for MintConfig {
@@ -25,48 +75,423 @@ impl GenerateConfig for MintConfig {
         peers: &[PeerId],
         max_evil: usize,
         params: &Self::Params,
-        _rng: impl RngCore + CryptoRng,
+        mut rng: impl RngCore + CryptoRng,
     ) -> (BTreeMap<PeerId, Self>, Self::ClientConfig) {
-        let tbs_threshold = peers.len() - max_evil;
+        let (peer_keys, tbs_pks) = dealer_gen_keys(peers, max_evil, params);
+        let peer_tbs_pks = peer_keys
+            .values()
+            .next()
+            .expect("No peers supplied")
+            .1
+            .clone();
+        // Coin messages are derived under a domain separation tag unique to this federation
+        let message_derivation = MessageDerivation::hash_to_curve(&format!(
+            "MINIMINT-{:016x}{:016x}",
+            rng.next_u64(),
+            rng.next_u64()
+        ));
 
-        let tbs_keys = params
-            .iter()
-            .map(|&amount| {
-                let (tbs_pk, tbs_pks, tbs_sks) = dealer_keygen(tbs_threshold, peers.len());
-                (amount, (tbs_pk, tbs_pks, tbs_sks))
-            })
-            .collect::<HashMap<_, _>>();
-
-        let mint_cfg = peers
-            .iter()
-            .map(|&peer| {
+        let mint_cfg = peer_keys
+            .into_iter()
+            .map(|(peer, (tbs_sks, peer_tbs_pks))| {
                 let config = MintConfig {
-                    tbs_sks: params
-                        .iter()
-                        .map(|amount| (*amount, tbs_keys[amount].2[peer.to_usize()]))
-                        .collect(),
-                    peer_tbs_pks: peers
-                        .iter()
-                        .map(|&key_peer| {
-                            let keys = params
-                                .iter()
-                                .map(|amount| (*amount, tbs_keys[amount].1[key_peer.to_usize()]))
-                                .collect();
-                            (key_peer, keys)
-                        })
-                        .collect(),
+                    generation: KeyGeneration::default(),
+                    tbs_sks,
+                    peer_tbs_pks,
+                    retired_generations: BTreeMap::new(),
+                    message_derivation: message_derivation.clone(),
                 };
                 (peer, config)
             })
             .collect();
 
         let client_cfg = MintClientConfig {
-            tbs_pks: tbs_keys
-                .into_iter()
-                .map(|(amount, (pk, _, _))| (amount, pk))
-                .collect(),
+            generation: KeyGeneration::default(),
+            tbs_pks,
+            peer_tbs_pks,
+            threshold: peers.len() - max_evil,
+            retired_generations: BTreeMap::new(),
+            message_derivation,
         };
 
         (mint_cfg, client_cfg)
     }
 }
+
+impl MintConfig {
+    /// Rotates the mint keys of all peers using a trusted dealer. New coins will be issued using a
+    /// fresh key generation with the same amount tiers while coins of the current generation stay
+    /// spendable till `deprecation_epoch`.
+    ///
+    /// # Panics
+    /// * If `cfgs` is empty
+    pub fn trusted_dealer_rotate(
+        cfgs: &mut BTreeMap<PeerId, MintConfig>,
+        client_cfg: &mut MintClientConfig,
+        max_evil: usize,
+        deprecation_epoch: u64,
+    ) {
+        let peers = cfgs.keys().copied().collect::<Vec<_>>();
+        let tiers = cfgs
+            .values()
+            .next()
+            .expect("No mint configs supplied")
+            .tbs_sks
+            .tiers()
+            .copied()
+            .collect::<Vec<_>>();
+        let (mut peer_keys, tbs_pks) = dealer_gen_keys(&peers, max_evil, &tiers);
+        let old_peer_pks =
+            std::mem::replace(&mut client_cfg.peer_tbs_pks, peer_keys[&peers[0]].1.clone());
+        client_cfg.threshold = peers.len() - max_evil;
+
+        for (peer, cfg) in cfgs.iter_mut() {
+            let (tbs_sks, peer_tbs_pks) = peer_keys.remove(peer).expect("generated for all peers");
+            let old_generation = cfg.generation;
+            let old_pks = std::mem::replace(&mut cfg.peer_tbs_pks, peer_tbs_pks);
+
+            cfg.retired_generations.insert(
+                old_generation,
+                RetiredKeyGeneration {
+                    peer_tbs_pks: old_pks,
+                    deprecation_epoch,
+                },
+            );
+            cfg.generation = KeyGeneration(old_generation.0 + 1);
+            cfg.tbs_sks = tbs_sks;
+        }
+
+        let old_generation = client_cfg.generation;
+        let old_pks = std::mem::replace(&mut client_cfg.tbs_pks, tbs_pks);
+        client_cfg.retired_generations.insert(
+            old_generation,
+            RetiredClientKeyGeneration {
+                tbs_pks: old_pks,
+                peer_tbs_pks: old_peer_pks,
+                deprecation_epoch,
+            },
+        );
+        client_cfg.generation = KeyGeneration(old_generation.0 + 1);
+    }
+
+    /// Moves the mint keys of the current generation to `new_peers`, of which all but
+    /// `new_max_evil` are needed to sign, using [`tbs::reshare`]. The aggregate public keys stay
+    /// the same, so issued coins remain valid and clients only need the new public key shares and
+    /// threshold, which are updated in `client_cfg`. Only the peers in `cfgs` deal sub-shares,
+    /// which allows excluding compromised or leaving peers as long as enough remain. Like
+    /// [`MintConfig::trusted_dealer_rotate`] the protocol is run for all peers locally.
+    ///
+    /// # Panics
+    /// * If `cfgs` contains fewer configs than the current threshold
+    /// * If the threshold would decrease while there are retired key generations, since their
+    ///   aggregate public keys couldn't be derived anymore
+    pub fn trusted_dealer_reshare(
+        cfgs: &BTreeMap<PeerId, MintConfig>,
+        client_cfg: &mut MintClientConfig,
+        max_evil: usize,
+        new_peers: &[PeerId],
+        new_max_evil: usize,
+    ) -> BTreeMap<PeerId, MintConfig> {
+        let template = cfgs.values().next().expect("No mint configs supplied");
+        let threshold = template.peer_tbs_pks.len() - max_evil;
+        let new_threshold = new_peers.len() - new_max_evil;
+        assert!(cfgs.len() >= threshold, "Not enough peers to reshare keys");
+        assert!(
+            template.retired_generations.is_empty() || new_threshold >= threshold,
+            "Can't decrease the threshold while there are retired key generations"
+        );
+
+        let new_keys = new_peers
+            .iter()
+            .map(|peer| peer.to_usize())
+            .collect::<Vec<_>>();
+        let mut sub_shares = BTreeMap::<PeerId, BTreeMap<Amount, Vec<_>>>::new();
+        let mut commitments = BTreeMap::<Amount, Vec<_>>::new();
+        for (peer, cfg) in cfgs {
+            for (amount, sk) in cfg.tbs_sks.iter() {
+                let (commitment, peer_sub_shares) =
+                    deal_reshare(sk.expose(), new_threshold, &new_keys);
+                assert_eq!(
+                    &commitment.dealer_pub_key(),
+                    template.peer_tbs_pks[peer]
+                        .tier(&amount)
+                        .expect("Inconsistent amount tiers")
+                );
+
+                for (new_idx, sub_share) in peer_sub_shares {
+                    assert!(commitment.verify_sub_share(new_idx, &sub_share));
+                    sub_shares
+                        .entry(PeerId::from(new_idx as u16))
+                        .or_default()
+                        .entry(amount)
+                        .or_default()
+                        .push((peer.to_usize(), sub_share));
+                }
+                commitments
+                    .entry(amount)
+                    .or_default()
+                    .push((peer.to_usize(), commitment));
+            }
+        }
+
+        let mut peer_tbs_pks = BTreeMap::<PeerId, BTreeMap<Amount, _>>::new();
+        for (amount, amount_commitments) in &commitments {
+            let (pub_key, pub_key_shares) = combine_commitments(
+                amount_commitments.iter().map(|(idx, c)| (*idx, c)),
+                &new_keys,
+            );
+            let old_pub_key = template
+                .peer_tbs_pks
+                .iter()
+                .map(|(peer, keys)| {
+                    let key = *keys.tier(amount).expect("Inconsistent amount tiers");
+                    (peer.to_usize(), key)
+                })
+                .collect::<Vec<_>>()
+                .aggregate(threshold);
+            assert_eq!(pub_key, old_pub_key, "Resharing changed the public key");
+
+            for (idx, pub_key_share) in pub_key_shares {
+                peer_tbs_pks
+                    .entry(PeerId::from(idx as u16))
+                    .or_default()
+                    .insert(*amount, pub_key_share);
+            }
+        }
+        let peer_tbs_pks = peer_tbs_pks
+            .into_iter()
+            .map(|(peer, keys)| (peer, keys.into_iter().collect()))
+            .collect::<BTreeMap<_, _>>();
+        client_cfg.peer_tbs_pks = peer_tbs_pks.clone();
+        client_cfg.threshold = new_threshold;
+
+        sub_shares
+            .into_iter()
+            .map(|(peer, tier_sub_shares)| {
+                let tbs_sks = tier_sub_shares
+                    .into_iter()
+                    .map(|(amount, shares)| (amount, Secret::new(combine_sub_shares(shares))))
+                    .collect();
+                let config = MintConfig {
+                    generation: template.generation,
+                    tbs_sks,
+                    peer_tbs_pks: peer_tbs_pks.clone(),
+                    retired_generations: template.retired_generations.clone(),
+                    message_derivation: template.message_derivation.clone(),
+                };
+                (peer, config)
+            })
+            .collect()
+    }
+}
+
+impl MintClientConfig {
+    /// Returns the public key shares of all peers for `generation` if they are known to the client
+    pub fn generation_peer_keys(
+        &self,
+        generation: KeyGeneration,
+    ) -> Option<&BTreeMap<PeerId, Keys<tbs::PublicKeyShare>>> {
+        if generation == self.generation {
+            Some(&self.peer_tbs_pks)
+        } else {
+            self.retired_generations
+                .get(&generation)
+                .map(|retired| &retired.peer_tbs_pks)
+        }
+    }
+
+    /// Configs created before clients combined signature shares lack the public key shares and
+    /// threshold needed to do so
+    pub fn can_combine_shares(&self) -> bool {
+        self.threshold != 0 && !self.peer_tbs_pks.is_empty()
+    }
+
+    /// Returns the aggregate public keys of `generation` if it is known to the client
+    pub fn generation_keys(&self, generation: KeyGeneration) -> Option<&Keys<AggregatePublicKey>> {
+        if generation == self.generation {
+            Some(&self.tbs_pks)
+        } else {
+            self.retired_generations
+                .get(&generation)
+                .map(|retired| &retired.tbs_pks)
+        }
+    }
+}
+
+/// Serializes the secret key shares of all tiers using [`tbs::serde_secret`]
+mod serde_secret_keys {
+    use crate::Keys;
+    use minimint_api::secret::Secret;
+    use minimint_api::Amount;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    struct ExposedKey<'a>(&'a tbs::SecretKeyShare);
+
+    impl Serialize for ExposedKey<'_> {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            tbs::serde_secret::serialize(self.0, s)
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(transparent)]
+    struct OwnedKey(#[serde(with = "tbs::serde_secret")] tbs::SecretKeyShare);
+
+    pub fn serialize<S: Serializer>(
+        keys: &Keys<Secret<tbs::SecretKeyShare>>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        s.collect_map(
+            keys.iter()
+                .map(|(amount, key)| (amount, ExposedKey(key.expose()))),
+        )
+    }
+
+    pub fn deserialize<'d, D: Deserializer<'d>>(
+        d: D,
+    ) -> Result<Keys<Secret<tbs::SecretKeyShare>>, D::Error> {
+        let keys = BTreeMap::<Amount, OwnedKey>::deserialize(d)?;
+        Ok(keys
+            .into_iter()
+            .map(|(amount, key)| (amount, Secret::new(key.0)))
+            .collect())
+    }
+}
+
+type PeerKeys = (
+    Keys<Secret<tbs::SecretKeyShare>>,
+    BTreeMap<PeerId, Keys<tbs::PublicKeyShare>>,
+);
+
+/// Generates one key set for all `tiers` and returns every peer's secret and public key shares as
+/// well as the aggregate public keys.
+fn dealer_gen_keys(
+    peers: &[PeerId],
+    max_evil: usize,
+    tiers: &[Amount],
+) -> (BTreeMap<PeerId, PeerKeys>, Keys<AggregatePublicKey>) {
+    let tbs_threshold = peers.len() - max_evil;
+    // Key shares are indexed by peer id, so keys are generated for all ids up to the highest one
+    let keys = peers.iter().max().expect("No peers supplied").to_usize() + 1;
+
+    let tbs_keys = tiers
+        .iter()
+        .map(|&amount| {
+            let (tbs_pk, tbs_pks, tbs_sks) = dealer_keygen(tbs_threshold, keys);
+            (amount, (tbs_pk, tbs_pks, tbs_sks))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let peer_keys = peers
+        .iter()
+        .map(|&peer| {
+            let tbs_sks = tiers
+                .iter()
+                .map(|amount| (*amount, Secret::new(tbs_keys[amount].2[peer.to_usize()])))
+                .collect();
+            let peer_tbs_pks = peers
+                .iter()
+                .map(|&key_peer| {
+                    let keys = tiers
+                        .iter()
+                        .map(|amount| (*amount, tbs_keys[amount].1[key_peer.to_usize()]))
+                        .collect();
+                    (key_peer, keys)
+                })
+                .collect();
+            (peer, (tbs_sks, peer_tbs_pks))
+        })
+        .collect();
+
+    let tbs_pks = tbs_keys
+        .into_iter()
+        .map(|(amount, (pk, _, _))| (amount, pk))
+        .collect();
+
+    (peer_keys, tbs_pks)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::MintConfig;
+    use crate::{KeyGeneration, Mint};
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::{Amount, PeerId};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_rotate_keys() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let tiers = [Amount::from_sat(1), Amount::from_sat(10)];
+        let (mut cfgs, mut client_cfg) = MintConfig::trusted_dealer_gen(
+            &peers,
+            1,
+            &tiers[..],
+            rand::rngs::OsRng::new().unwrap(),
+        );
+        let old_client_pks = client_cfg.tbs_pks.clone();
+        let old_peer_pks = client_cfg.peer_tbs_pks.clone();
+
+        MintConfig::trusted_dealer_rotate(&mut cfgs, &mut client_cfg, 1, 42);
+
+        assert_eq!(client_cfg.generation, KeyGeneration(1));
+        assert_ne!(client_cfg.tbs_pks, old_client_pks);
+        assert_eq!(
+            client_cfg.generation_keys(KeyGeneration(0)),
+            Some(&old_client_pks)
+        );
+        assert!(client_cfg.generation_keys(KeyGeneration(2)).is_none());
+        assert_eq!(
+            client_cfg.generation_peer_keys(KeyGeneration(0)),
+            Some(&old_peer_pks)
+        );
+        assert_ne!(client_cfg.peer_tbs_pks, old_peer_pks);
+
+        for (_, cfg) in cfgs {
+            assert_eq!(cfg.generation, KeyGeneration(1));
+            assert_eq!(
+                cfg.retired_generations[&KeyGeneration(0)].deprecation_epoch,
+                42
+            );
+            // Makes sure the rotated config is internally consistent
+            Mint::new(cfg, 3, Arc::new(MemDatabase::new()));
+        }
+    }
+
+    #[test]
+    fn test_reshare_keys() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let tiers = [Amount::from_sat(1), Amount::from_sat(10)];
+        let (mut cfgs, mut client_cfg) = MintConfig::trusted_dealer_gen(
+            &peers,
+            1,
+            &tiers[..],
+            rand::rngs::OsRng::new().unwrap(),
+        );
+
+        // Peer 3 leaves and peers 5 and 7 join, so the new peer ids aren't contiguous
+        cfgs.remove(&PeerId::from(3));
+        let new_peers = [0u16, 1, 2, 5, 7]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        let new_cfgs = MintConfig::trusted_dealer_reshare(&cfgs, &mut client_cfg, 1, &new_peers, 1);
+
+        assert_eq!(new_cfgs.keys().copied().collect::<Vec<_>>(), new_peers);
+        assert_eq!(client_cfg.threshold, 4);
+        for (peer, cfg) in new_cfgs {
+            assert_eq!(cfg.peer_tbs_pks, client_cfg.peer_tbs_pks);
+            assert_eq!(cfg.peer_tbs_pks[&peer], cfg.tbs_sks.to_public());
+            assert_ne!(cfg.tbs_sks, cfgs[&PeerId::from(0)].tbs_sks);
+            let mint = Mint::new(cfg, 4, Arc::new(MemDatabase::new()));
+            for amount in &tiers {
+                assert_eq!(
+                    mint.pub_key[&KeyGeneration(0)][amount],
+                    *client_cfg.tbs_pks.tier(amount).unwrap()
+                );
+            }
+        }
+    }
+}
@@ -1,14 +1,82 @@
-use crate::CoinNonce;
-use minimint_api::db::DatabaseKeyPrefixConst;
+use crate::tiered::coins::Coins;
+use crate::{Coin, CoinNonce, KeyGeneration, PartialSigResponse, SigResponse};
+use minimint_api::db::batch::BatchTx;
+use minimint_api::db::migration::{ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry, DbPrefix};
+use minimint_api::db::{Database, DatabaseError, DatabaseKeyPrefixConst, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{OutPoint, PeerId};
+use minimint_api::{Amount, OutPoint, PeerId};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
 
 const DB_PREFIX_COIN_NONCE: u8 = 0x10;
-const DB_PREFIX_PROPOSED_PARTIAL_SIG: u8 = 0x11;
+const DB_PREFIX_SIGNATURE_SHARE: u8 = 0x11;
+/// Signature shares exchanged through consensus before schema version 1
 const DB_PREFIX_RECEIVED_PARTIAL_SIG: u8 = 0x12;
+/// Combined signatures, which are only stored for outputs issued before schema version 1
 const DB_PREFIX_OUTPUT_OUTCOME: u8 = 0x13;
+const DB_PREFIX_PEER_FAULT: u8 = 0x15;
+const DB_PREFIX_PENDING_ISSUANCE: u8 = 0x16;
+const DB_PREFIX_LEGACY_ISSUANCE: u8 = 0x17;
 
-#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+/// Key prefixes of the mint, registered in the server's prefix registry
+pub const DB_PREFIXES: &[DbPrefix] = &[
+    DbPrefix {
+        prefix: DB_PREFIX_COIN_NONCE,
+        name: "Used Coins",
+        key: "coin nonce",
+        value: "amount",
+        decode: decode_entry::<NonceKey, Amount>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_SIGNATURE_SHARE,
+        name: "Signature Shares",
+        key: "mint outpoint",
+        value: "our blind signature share",
+        decode: decode_entry::<SignatureShareKey, PartialSigResponse>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_RECEIVED_PARTIAL_SIG,
+        name: "Received Signature Shares",
+        key: "mint outpoint, peer",
+        value: "blind signature share",
+        decode: decode_entry::<ReceivedPartialSignatureKey, PartialSigResponse>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_OUTPUT_OUTCOME,
+        name: "Finalized (still blind) Signatures",
+        key: "mint outpoint",
+        value: "blind signature",
+        decode: decode_entry::<OutputOutcomeKey, SigResponse>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PEER_FAULT,
+        name: "Peer Faults",
+        key: "peer, mint outpoint",
+        value: "fault",
+        decode: decode_entry::<PeerFaultKey, crate::PeerErrorType>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PENDING_ISSUANCE,
+        name: "Pending Issuances",
+        key: "mint outpoint",
+        value: "blinded messages",
+        decode: decode_entry::<PendingIssuanceKey, crate::SignRequest>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_LEGACY_ISSUANCE,
+        name: "Legacy Issuance",
+        key: "none",
+        value: "bound of e-cash redeemed by coins of unknown amount",
+        decode: decode_entry::<LegacyIssuanceKey, Amount>,
+    },
+];
+
+/// Version of the mint's database layout, bump it and register a migration when changing it
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(1);
+
+#[derive(Debug, Clone, Serialize, Encodable, Decodable, Eq, PartialEq, Hash)]
 pub struct NonceKey(pub CoinNonce);
 
 impl DatabaseKeyPrefixConst for NonceKey {
@@ -16,37 +84,53 @@ impl DatabaseKeyPrefixConst for NonceKey {
 }
 
 #[derive(Debug, Encodable, Decodable)]
-pub struct ProposedPartialSignatureKey {
-    pub request_id: OutPoint, // tx + output idx
+pub struct NonceKeyPrefix;
+
+impl DatabaseKeyPrefixConst for NonceKeyPrefix {
+    const DB_PREFIX: u8 = DB_PREFIX_COIN_NONCE;
 }
 
-impl DatabaseKeyPrefixConst for ProposedPartialSignatureKey {
-    const DB_PREFIX: u8 = DB_PREFIX_PROPOSED_PARTIAL_SIG;
+/// Output that was accepted by consensus but not signed by us yet
+#[derive(Debug, Clone, Copy, Serialize, Encodable, Decodable)]
+pub struct PendingIssuanceKey(pub OutPoint);
+
+impl DatabaseKeyPrefixConst for PendingIssuanceKey {
+    const DB_PREFIX: u8 = DB_PREFIX_PENDING_ISSUANCE;
 }
 
 #[derive(Debug, Encodable, Decodable)]
-pub struct ProposedPartialSignaturesKeyPrefix;
+pub struct PendingIssuanceKeyPrefix;
 
-impl DatabaseKeyPrefixConst for ProposedPartialSignaturesKeyPrefix {
-    const DB_PREFIX: u8 = DB_PREFIX_PROPOSED_PARTIAL_SIG;
+impl DatabaseKeyPrefixConst for PendingIssuanceKeyPrefix {
+    const DB_PREFIX: u8 = DB_PREFIX_PENDING_ISSUANCE;
 }
 
-#[derive(Debug, Encodable, Decodable)]
-pub struct ReceivedPartialSignatureKey {
+/// Our signature share for an output, which clients fetch and combine with the other peers' ones
+#[derive(Debug, Serialize, Encodable, Decodable)]
+pub struct SignatureShareKey {
     pub request_id: OutPoint, // tx + output idx
-    pub peer_id: PeerId,
 }
 
-impl DatabaseKeyPrefixConst for ReceivedPartialSignatureKey {
-    const DB_PREFIX: u8 = DB_PREFIX_RECEIVED_PARTIAL_SIG;
+impl DatabaseKeyPrefixConst for SignatureShareKey {
+    const DB_PREFIX: u8 = DB_PREFIX_SIGNATURE_SHARE;
 }
 
 #[derive(Debug, Encodable, Decodable)]
-pub struct ReceivedPartialSignatureKeyOutputPrefix {
+pub struct SignatureShareKeyPrefix;
+
+impl DatabaseKeyPrefixConst for SignatureShareKeyPrefix {
+    const DB_PREFIX: u8 = DB_PREFIX_SIGNATURE_SHARE;
+}
+
+/// Signature share a peer contributed through consensus. Only our own shares of outputs that
+/// weren't combined before upgrading to schema version 1 are still read.
+#[derive(Debug, Serialize, Encodable, Decodable)]
+pub struct ReceivedPartialSignatureKey {
     pub request_id: OutPoint, // tx + output idx
+    pub peer_id: PeerId,
 }
 
-impl DatabaseKeyPrefixConst for ReceivedPartialSignatureKeyOutputPrefix {
+impl DatabaseKeyPrefixConst for ReceivedPartialSignatureKey {
     const DB_PREFIX: u8 = DB_PREFIX_RECEIVED_PARTIAL_SIG;
 }
 
@@ -58,9 +142,169 @@ impl DatabaseKeyPrefixConst for ReceivedPartialSignaturesKeyPrefix {
 }
 
 /// Transaction id and output index identifying an output outcome
-#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+#[derive(Debug, Clone, Copy, Serialize, Encodable, Decodable)]
 pub struct OutputOutcomeKey(pub OutPoint);
 
 impl DatabaseKeyPrefixConst for OutputOutcomeKey {
     const DB_PREFIX: u8 = DB_PREFIX_OUTPUT_OUTCOME;
 }
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OutputOutcomeKeyPrefix;
+
+impl DatabaseKeyPrefixConst for OutputOutcomeKeyPrefix {
+    const DB_PREFIX: u8 = DB_PREFIX_OUTPUT_OUTCOME;
+}
+
+/// Upper bound of the e-cash redeemed by coins that were spent before schema version 1 and are
+/// recorded as redeeming nothing since their amounts couldn't be recovered, see
+/// [`recover_spent_amounts`]. The audit can't tell how much of it is still outstanding.
+#[derive(Debug, Clone, Copy, Serialize, Encodable, Decodable)]
+pub struct LegacyIssuanceKey;
+
+impl DatabaseKeyPrefixConst for LegacyIssuanceKey {
+    const DB_PREFIX: u8 = DB_PREFIX_LEGACY_ISSUANCE;
+}
+
+/// Coin as encoded by schema version 0, before coins referenced the [`KeyGeneration`] they were
+/// signed with. Databases of other modules and clients contain such coins as part of spent
+/// transactions or wallets.
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct CoinV0(pub CoinNonce, pub tbs::Signature);
+
+impl From<CoinV0> for Coin {
+    /// Schema version 0 only knew the keys of the first generation
+    fn from(coin: CoinV0) -> Coin {
+        Coin(coin.0, coin.1, KeyGeneration::default())
+    }
+}
+
+/// Combined signature as stored by schema version 0, without its [`KeyGeneration`]
+#[derive(Debug, Encodable, Decodable)]
+struct SigResponseV0(Coins<tbs::BlindedSignature>);
+
+/// Signature share as stored by schema version 0, without its [`KeyGeneration`]
+#[derive(Debug, Encodable, Decodable)]
+struct PartialSigResponseV0(Coins<(tbs::BlindedMessage, tbs::BlindedSignatureShare)>);
+
+/// Invalid signature share a peer contributed through consensus for an issuance
+#[derive(Debug, Clone, Copy, Serialize, Encodable, Decodable)]
+pub struct PeerFaultKey {
+    pub peer_id: PeerId,
+    pub request_id: OutPoint,
+}
+
+impl DatabaseKeyPrefixConst for PeerFaultKey {
+    const DB_PREFIX: u8 = DB_PREFIX_PEER_FAULT;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerFaultKeyPrefix;
+
+impl DatabaseKeyPrefixConst for PeerFaultKeyPrefix {
+    const DB_PREFIX: u8 = DB_PREFIX_PEER_FAULT;
+}
+
+pub fn migrations() -> ModuleMigrations {
+    ModuleMigrations::new("mint", DB_SCHEMA_VERSION).with_migration(SchemaVersion(0), migrate_v0)
+}
+
+/// Schema version 1 differs from the first release in two ways:
+///
+/// * Signatures and signature shares are stored together with their [`KeyGeneration`], which is
+///   the first one for all entries of schema version 0.
+/// * Spent coins are stored with their amount, which the audit needs to know how much e-cash was
+///   redeemed. It can't be recovered, so these coins are recorded as zero and the e-cash issued so
+///   far is stored as [`LegacyIssuanceKey`] for the audit to offset the unknown redeemed amount.
+///
+/// The migration doesn't know our peer id, so outputs only known from other peers' shares are
+/// included in the legacy issuance too. Overestimating it only makes the audit more lenient.
+fn migrate_v0(db: &dyn RawDatabase, batch: &mut BatchTx) -> Result<(), DatabaseError> {
+    let mut issued = HashMap::new();
+    for res in db.find_by_prefix::<_, OutputOutcomeKey, SigResponseV0>(&OutputOutcomeKeyPrefix) {
+        let (key, SigResponseV0(sigs)) = res?;
+        issued.insert(key.0, sigs.amount());
+        batch.append_insert(key, SigResponse(sigs, KeyGeneration::default()));
+    }
+    // Our own shares were stored under the prefix of the current signature shares until they
+    // were exchanged through consensus
+    for res in
+        db.find_by_prefix::<_, SignatureShareKey, PartialSigResponseV0>(&SignatureShareKeyPrefix)
+    {
+        let (key, PartialSigResponseV0(shares)) = res?;
+        issued
+            .entry(key.request_id)
+            .or_insert_with(|| shares.amount());
+        batch.append_insert(key, PartialSigResponse(shares, KeyGeneration::default()));
+    }
+    for res in db.find_by_prefix::<_, ReceivedPartialSignatureKey, PartialSigResponseV0>(
+        &ReceivedPartialSignaturesKeyPrefix,
+    ) {
+        let (key, PartialSigResponseV0(shares)) = res?;
+        issued
+            .entry(key.request_id)
+            .or_insert_with(|| shares.amount());
+        batch.append_insert(key, PartialSigResponse(shares, KeyGeneration::default()));
+    }
+
+    let spent = db
+        .find_by_prefix::<_, NonceKey, ()>(&NonceKeyPrefix)
+        .collect::<Result<Vec<_>, _>>()?;
+    if spent.is_empty() {
+        return Ok(());
+    }
+
+    let legacy_issuance: Amount = issued.into_values().sum();
+
+    warn!(
+        "{} coins were spent before their amounts were recorded, the audit can't account for up to {} of e-cash issued until now",
+        spent.len(),
+        legacy_issuance
+    );
+    batch.append_insert(LegacyIssuanceKey, legacy_issuance);
+    for (key, ()) in spent {
+        batch.append_insert(key, Amount::ZERO);
+    }
+    Ok(())
+}
+
+/// Records the amounts of coins that were spent before schema version 1, which the server recovers
+/// from the transactions spending them. Each recovered coin is deducted from the
+/// [`LegacyIssuanceKey`] offset, which is dropped once no coin of unknown amount remains.
+pub fn recover_spent_amounts(
+    db: &dyn RawDatabase,
+    batch: &mut BatchTx,
+    spent: impl IntoIterator<Item = (CoinNonce, Amount)>,
+) -> Result<(), DatabaseError> {
+    let legacy_issuance = match db.get_value::<_, Amount>(&LegacyIssuanceKey)? {
+        Some(legacy_issuance) => legacy_issuance,
+        None => return Ok(()),
+    };
+
+    let mut unknown = db
+        .find_by_prefix::<_, NonceKey, Amount>(&NonceKeyPrefix)
+        .filter_map(|res| match res {
+            Ok((key, amount)) => (amount == Amount::ZERO).then(|| Ok(key)),
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<Result<HashSet<_>, _>>()?;
+    let mut recovered = Amount::ZERO;
+    for (nonce, amount) in spent {
+        let key = NonceKey(nonce);
+        if unknown.remove(&key) {
+            recovered += amount;
+            batch.append_insert(key, amount);
+        }
+    }
+
+    if unknown.is_empty() {
+        batch.append_delete(LegacyIssuanceKey);
+    } else {
+        warn!(
+            "Could not recover the amounts of {} coins spent before schema version 1",
+            unknown.len()
+        );
+        batch.append_insert(LegacyIssuanceKey, legacy_issuance.saturating_sub(recovered));
+    }
+    Ok(())
+}
@@ -1,23 +1,26 @@
 use crate::config::MintConfig;
 use crate::db::{
-    NonceKey, OutputOutcomeKey, ProposedPartialSignatureKey, ProposedPartialSignaturesKeyPrefix,
-    ReceivedPartialSignatureKey, ReceivedPartialSignatureKeyOutputPrefix,
-    ReceivedPartialSignaturesKeyPrefix,
+    LegacyIssuanceKey, NonceKey, NonceKeyPrefix, OutputOutcomeKey, OutputOutcomeKeyPrefix,
+    PeerFaultKey, PeerFaultKeyPrefix, PendingIssuanceKey, PendingIssuanceKeyPrefix,
+    ReceivedPartialSignatureKey, ReceivedPartialSignaturesKeyPrefix, SignatureShareKey,
+    SignatureShareKeyPrefix,
 };
 use async_trait::async_trait;
-use itertools::Itertools;
 use minimint_api::db::batch::{BatchItem, BatchTx, DbBatch};
 use minimint_api::db::{Database, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{Amount, FederationModule, InputMeta, OutPoint, PeerId};
+use minimint_api::module::api::ApiEndpoint;
+use minimint_api::module::audit::ModuleAudit;
+use minimint_api::secret::Secret;
+use minimint_api::{Amount, ConsensusContext, FederationModule, InputMeta, OutPoint, PeerId};
 use rand::{CryptoRng, RngCore};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::Hash;
 use std::sync::Arc;
 use tbs::{
-    combine_valid_shares, sign_blinded_msg, verify_blind_share, Aggregatable, AggregatePublicKey,
+    sign_blinded_msg, verify_blind_share, Aggregatable, AggregatePublicKey, MessageDerivation,
     PublicKeyShare, SecretKeyShare,
 };
 use thiserror::Error;
@@ -26,23 +29,52 @@ use tiered::coins::TieredMultiZip;
 pub use tiered::keys::Keys;
 use tracing::{debug, error, warn};
 
+pub mod api;
 pub mod config;
 
-mod db;
+pub mod db;
 /// Data structures taking into account different amount tiers
 pub mod tiered;
 
 /// Federated mint member mint
 pub struct Mint {
     key_id: PeerId,
-    sec_key: Keys<SecretKeyShare>,
-    pub_key_shares: BTreeMap<PeerId, Keys<PublicKeyShare>>,
-    pub_key: HashMap<Amount, AggregatePublicKey>,
-    threshold: usize, // TODO: move to cfg
+    /// Key generation used for issuing new coins
+    generation: KeyGeneration,
+    sec_key: Keys<Secret<SecretKeyShare>>,
+    pub_key: BTreeMap<KeyGeneration, HashMap<Amount, AggregatePublicKey>>,
+    /// Public key shares of all peers, used to validate the signature shares they contribute
+    pub_key_shares: BTreeMap<KeyGeneration, BTreeMap<PeerId, Keys<PublicKeyShare>>>,
+    /// Epochs from which on coins of retired key generations are no longer accepted
+    deprecation_epochs: BTreeMap<KeyGeneration, u64>,
+    message_derivation: MessageDerivation,
     db: Arc<dyn RawDatabase>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+/// Identifies a set of mint keys. Guardians can rotate keys (e.g. after a suspected compromise) by
+/// issuing under a new generation while coins signed under older generations stay spendable until
+/// their deprecation epoch.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Deserialize,
+    Serialize,
+    Encodable,
+    Decodable,
+)]
+pub struct KeyGeneration(pub u32);
+
+/// Signature share that used to be exchanged through consensus. Shares are served to clients
+/// directly now, but the type remains the mint's consensus item so that contributions of peers
+/// still proposing them can be decoded and checked.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct PartiallySignedRequest {
     out_point: OutPoint,
     partial_signature: PartialSigResponse,
@@ -53,19 +85,22 @@ pub struct PartiallySignedRequest {
 pub struct SignRequest(pub Coins<tbs::BlindedMessage>);
 
 // FIXME: optimize out blinded msg by making the mint remember it
-/// Blind signature share for a [`SignRequest`]
+/// Blind signature share for a [`SignRequest`] created using the keys of the given generation
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct PartialSigResponse(pub Coins<(tbs::BlindedMessage, tbs::BlindedSignatureShare)>);
+pub struct PartialSigResponse(
+    pub Coins<(tbs::BlindedMessage, tbs::BlindedSignatureShare)>,
+    pub KeyGeneration,
+);
 
-/// Blind signature for a [`SignRequest`]
+/// Blind signature for a [`SignRequest`] created using the keys of the given generation
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct SigResponse(pub Coins<tbs::BlindedSignature>);
+pub struct SigResponse(pub Coins<tbs::BlindedSignature>, pub KeyGeneration);
 
 /// A cryptographic coin consisting of a token and a threshold signature by the federated mint. In
 /// this form it can oly be validated, not spent since for that the corresponding [`musig::SecKey`]
-/// is required.
+/// is required. The [`KeyGeneration`] identifies the mint keys the coin was signed with.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct Coin(pub CoinNonce, pub tbs::Signature);
+pub struct Coin(pub CoinNonce, pub tbs::Signature, pub KeyGeneration);
 
 /// A unique coin nonce which is also a MuSig pub key so that transactions can be signed by the
 /// spent coin's spending keys to avoid mint frontrunning.
@@ -83,26 +118,18 @@ impl FederationModule for Mint {
     type TxOutputOutcome = Option<SigResponse>; // TODO: make newtype
     type ConsensusItem = PartiallySignedRequest;
 
+    const API_BASE_NAME: &'static str = "mint";
+
     async fn consensus_proposal<'a>(
         &'a self,
         _rng: impl RngCore + CryptoRng + 'a,
     ) -> Vec<Self::ConsensusItem> {
-        self.db
-            .find_by_prefix::<_, ProposedPartialSignatureKey, PartialSigResponse>(
-                &ProposedPartialSignaturesKeyPrefix,
-            )
-            .map(|res| {
-                let (key, partial_signature) = res.expect("DB error");
-                PartiallySignedRequest {
-                    out_point: key.request_id,
-                    partial_signature,
-                }
-            })
-            .collect()
+        vec![]
     }
 
     async fn begin_consensus_epoch<'a>(
         &'a self,
+        _context: &'a ConsensusContext,
         mut batch: BatchTx<'a>,
         consensus_items: Vec<(PeerId, Self::ConsensusItem)>,
         _rng: impl RngCore + CryptoRng + 'a,
@@ -112,52 +139,33 @@ impl FederationModule for Mint {
                 batch.subtransaction(),
                 peer,
                 partial_sig.out_point,
-                partial_sig.partial_signature,
+                &partial_sig.partial_signature,
             )
         }
         batch.commit();
     }
 
-    fn validate_input<'a>(&self, input: &'a Self::TxInput) -> Result<InputMeta<'a>, Self::Error> {
-        input.iter().try_for_each(|(amount, coin)| {
-            if !coin.verify(
-                *self
-                    .pub_key
-                    .get(&amount)
-                    .ok_or(MintError::InvalidAmountTier(amount))?,
-            ) {
-                return Err(MintError::InvalidSignature);
-            }
-
-            if self
-                .db
-                .get_value::<_, ()>(&NonceKey(coin.0.clone()))
-                .expect("DB error")
-                .is_some()
-            {
-                return Err(MintError::SpentCoin);
-            }
-
-            Ok(())
-        })?;
-
-        Ok(InputMeta {
-            amount: input.amount(),
-            puk_keys: Box::new(input.iter().map(|(_, coin)| *coin.spend_key())),
-        })
+    fn validate_input<'a>(
+        &self,
+        context: &ConsensusContext,
+        input: &'a Self::TxInput,
+    ) -> Result<InputMeta<'a>, Self::Error> {
+        self.validate_input_in(context, self.db.as_ref(), input)
     }
 
     fn apply_input<'a, 'b>(
         &'a self,
+        context: &ConsensusContext,
         mut batch: BatchTx<'a>,
         input: &'b Self::TxInput,
     ) -> Result<InputMeta<'b>, Self::Error> {
-        let meta = self.validate_input(input)?;
+        // Validate against the batch so coins spent earlier in the same epoch are detected
+        let meta = self.validate_input_in(context, &batch.overlay(self.db.as_ref()), input)?;
 
         batch.append_from_iter(
             input
                 .iter()
-                .map(|(_, coin)| BatchItem::insert_new(NonceKey(coin.0.clone()), ())),
+                .map(|(amount, coin)| BatchItem::insert_new(NonceKey(coin.0.clone()), amount)),
         );
         batch.commit();
 
@@ -165,8 +173,9 @@ impl FederationModule for Mint {
     }
 
     fn validate_output(&self, output: &Self::TxOutput) -> Result<Amount, Self::Error> {
+        let pub_keys = &self.pub_key[&self.generation];
         if let Some(amount) = output.iter().find_map(|(amount, _)| {
-            if self.pub_key.get(&amount).is_none() {
+            if pub_keys.get(&amount).is_none() {
                 Some(amount)
             } else {
                 None
@@ -180,26 +189,23 @@ impl FederationModule for Mint {
 
     fn apply_output<'a>(
         &'a self,
+        _context: &ConsensusContext,
         mut batch: BatchTx<'a>,
         output: &'a Self::TxOutput,
         out_point: OutPoint,
     ) -> Result<Amount, Self::Error> {
-        // TODO: move actual signing to worker thread
+        // Signing is expensive, so it's left to `sign_pending_issuances` which runs outside of
+        // consensus. We only make sure that we have keys for all amount tiers.
         // TODO: get rid of clone
-        let partial_sig = output
-            .clone()
-            .map(|amt, msg| -> Result<_, InvalidAmountTierError> {
-                let sec_key = self.sec_key.tier(&amt)?;
-                let blind_signature = sign_blinded_msg(msg.0, *sec_key);
-                Ok((msg.0, blind_signature))
-            })?;
-
-        batch.append_insert_new(
-            ProposedPartialSignatureKey {
-                request_id: out_point,
-            },
-            PartialSigResponse(partial_sig),
-        );
+        let blinded_msgs =
+            output
+                .clone()
+                .map(|amt, token| -> Result<_, InvalidAmountTierError> {
+                    self.sec_key.tier(&amt)?;
+                    Ok(token.0)
+                })?;
+
+        batch.append_insert_new(PendingIssuanceKey(out_point), SignRequest(blinded_msgs));
 
         batch.commit();
         Ok(output.amount())
@@ -207,101 +213,118 @@ impl FederationModule for Mint {
 
     async fn end_consensus_epoch<'a>(
         &'a self,
-        mut batch: BatchTx<'a>,
+        batch: BatchTx<'a>,
         _rng: impl RngCore + CryptoRng + 'a,
     ) {
-        // Finalize partial signatures for which we now have enough shares
-        let req_psigs = self
-            .db
-            .find_by_prefix::<_, ReceivedPartialSignatureKey, PartialSigResponse>(
-                &ReceivedPartialSignaturesKeyPrefix,
-            )
-            .map(|entry_res| {
-                let (key, partial_sig) = entry_res.expect("DB error");
-                (key.request_id, (key.peer_id, partial_sig))
-            })
-            .into_group_map();
-
-        // TODO: use own par iter impl that allows efficient use of accumulators or just decouple it entirely (doesn't need consensus)
-        let par_batches = req_psigs
-            .into_par_iter()
-            .filter_map(|(issuance_id, shares)| {
-                let mut batch = DbBatch::new();
-                let mut batch_tx = batch.transaction();
-
-                if shares.len() > self.threshold {
-                    debug!(
-                        "Trying to combine sig shares for issuance request {}",
-                        issuance_id
-                    );
-                    let (bsig, errors) = self.combine(shares.clone());
-                    // FIXME: validate shares before writing to DB to make combine infallible
-                    if !errors.0.is_empty() {
-                        warn!("Peer sent faulty share: {:?}", errors);
-                    }
-
-                    match bsig {
-                        Ok(blind_signature) => {
-                            debug!(
-                                "Successfully combined signature shares for issuance request {}",
-                                issuance_id
-                            );
-
-                            batch_tx.append_from_iter(shares.into_iter().map(|(peer, _)| {
-                                BatchItem::delete(ReceivedPartialSignatureKey {
-                                    request_id: issuance_id,
-                                    peer_id: peer,
-                                })
-                            }));
-
-                            batch_tx.append_insert(OutputOutcomeKey(issuance_id), blind_signature);
-                            batch_tx.commit();
-                            Some(batch)
-                        }
-                        Err(e) => {
-                            error!("Could not combine shares: {}", e);
-                            None
-                        }
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-        batch.append_from_accumulators(par_batches.into_iter());
         batch.commit();
     }
 
     fn output_status(&self, out_point: OutPoint) -> Option<Self::TxOutputOutcome> {
-        let we_proposed = self
-            .db
-            .get_value::<_, PartialSigResponse>(&ProposedPartialSignatureKey {
-                request_id: out_point,
-            })
-            .expect("DB error")
-            .is_some();
-        let was_consensus_outcome = self
-            .db
-            .find_by_prefix::<_, ReceivedPartialSignatureKey, PartialSigResponse>(
-                &ReceivedPartialSignatureKeyOutputPrefix {
-                    request_id: out_point,
-                },
-            )
-            .any(|res| res.is_ok());
-
+        // Signatures are only combined by the mint for outputs issued before clients did it
         let final_sig = self
             .db
             .get_value(&OutputOutcomeKey(out_point))
             .expect("DB error");
-
         if final_sig.is_some() {
-            Some(final_sig)
-        } else if we_proposed || was_consensus_outcome {
+            return Some(final_sig);
+        }
+
+        let pending = self
+            .db
+            .get_value::<_, SignRequest>(&PendingIssuanceKey(out_point))
+            .expect("DB error")
+            .is_some();
+        if pending || self.signature_share(out_point).is_some() {
             Some(None)
         } else {
             None
         }
     }
+
+    fn audit(&self) -> ModuleAudit {
+        // E-cash counts as issued as soon as its output was accepted, even if we didn't sign it
+        // yet. Shares of other peers received by older versions aren't trusted to refer to actual
+        // outputs. Pending issuances are read before signature shares since
+        // `sign_pending_issuances` may move them concurrently.
+        let finalized = self
+            .db
+            .find_by_prefix::<_, OutputOutcomeKey, SigResponse>(&OutputOutcomeKeyPrefix)
+            .map(|res| {
+                let (key, SigResponse(sigs, _)) = res.expect("DB error");
+                (key.0, sigs.amount())
+            });
+        let pending = self
+            .db
+            .find_by_prefix::<_, PendingIssuanceKey, SignRequest>(&PendingIssuanceKeyPrefix)
+            .map(|res| {
+                let (key, SignRequest(msgs)) = res.expect("DB error");
+                (key.0, msgs.amount())
+            })
+            .collect::<Vec<_>>();
+        let signed = self
+            .db
+            .find_by_prefix::<_, SignatureShareKey, PartialSigResponse>(&SignatureShareKeyPrefix)
+            .map(|res| {
+                let (key, PartialSigResponse(shares, _)) = res.expect("DB error");
+                (key.request_id, shares.amount())
+            });
+        let received = self
+            .db
+            .find_by_prefix::<_, ReceivedPartialSignatureKey, PartialSigResponse>(
+                &ReceivedPartialSignaturesKeyPrefix,
+            )
+            .filter_map(|res| {
+                let (key, PartialSigResponse(shares, _)) = res.expect("DB error");
+                (key.peer_id == self.key_id).then(|| (key.request_id, shares.amount()))
+            });
+        let issued: Amount = finalized
+            .chain(pending)
+            .chain(signed)
+            .chain(received)
+            .collect::<HashMap<_, _>>()
+            .into_values()
+            .sum();
+
+        let redeemed: Amount = self
+            .db
+            .find_by_prefix::<_, NonceKey, Amount>(&NonceKeyPrefix)
+            .map(|res| res.expect("DB error").1)
+            .sum();
+        if redeemed > issued {
+            error!(
+                "More e-cash was redeemed ({}) than issued ({})",
+                redeemed, issued
+            );
+            return ModuleAudit {
+                assets: Amount::ZERO,
+                liabilities: Amount::ZERO,
+                shortfall: redeemed - issued,
+            };
+        }
+
+        // Coins spent before redeemed amounts were recorded and whose amounts couldn't be
+        // recovered count as redeeming nothing, so we assume the remaining legacy e-cash to be
+        // redeemed instead of halting consensus on a shortfall that might not exist. Legacy coins
+        // redeemed since can't be told apart from new ones, but the server recovers all amounts
+        // when migrating, so this offset is normally gone.
+        let legacy_issuance = self
+            .db
+            .get_value::<_, Amount>(&LegacyIssuanceKey)
+            .expect("DB error")
+            .unwrap_or(Amount::ZERO);
+        ModuleAudit {
+            assets: Amount::ZERO,
+            liabilities: (issued - redeemed).saturating_sub(legacy_issuance),
+            shortfall: Amount::ZERO,
+        }
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            ApiEndpoint::from_typed::<api::SignatureShareEndpoint>(),
+            ApiEndpoint::from_typed::<api::PeerFaultsEndpoint>(),
+        ]
+    }
 }
 
 impl Mint {
@@ -321,6 +344,11 @@ impl Mint {
             .values()
             .all(|pk| pk.structural_eq(&cfg.tbs_sks)));
 
+        assert!(
+            !cfg.retired_generations.contains_key(&cfg.generation),
+            "The current key generation may not be retired"
+        );
+
         let ref_pub_key = cfg.tbs_sks.to_public();
 
         // Find our key index and make sure we know the private key for all our public key shares
@@ -334,197 +362,297 @@ impl Mint {
             cfg.peer_tbs_pks[&our_id],
             cfg.tbs_sks
                 .iter()
-                .map(|(amount, sk)| (amount, sk.to_pub_key_share()))
+                .map(|(amount, sk)| (amount, sk.expose().to_pub_key_share()))
                 .collect()
         );
 
-        let aggregate_pub_keys = TieredMultiZip::new(
-            cfg.peer_tbs_pks
-                .iter()
-                .map(|(_, keys)| keys.iter())
-                .collect(),
-        )
-        .map(|(amt, keys)| {
-            // TODO: avoid this through better aggregation API allowing references or
-            let keys = keys.into_iter().copied().collect::<Vec<_>>();
-            (amt, keys.aggregate(threshold))
-        })
-        .collect();
+        let deprecation_epochs = cfg
+            .retired_generations
+            .iter()
+            .map(|(generation, retired)| (*generation, retired.deprecation_epoch))
+            .collect();
+
+        let pub_key_shares = cfg
+            .retired_generations
+            .into_iter()
+            .map(|(generation, retired)| (generation, retired.peer_tbs_pks))
+            .chain(std::iter::once((cfg.generation, cfg.peer_tbs_pks)))
+            .collect::<BTreeMap<_, _>>();
+
+        let aggregate_pub_keys = pub_key_shares
+            .iter()
+            .map(|(generation, peer_pks)| (*generation, aggregate_pub_keys(peer_pks, threshold)))
+            .collect();
 
         Mint {
             key_id: our_id,
+            generation: cfg.generation,
             sec_key: cfg.tbs_sks,
-            pub_key_shares: cfg.peer_tbs_pks,
             pub_key: aggregate_pub_keys,
-            threshold,
+            pub_key_shares,
+            deprecation_epochs,
+            message_derivation: cfg.message_derivation,
             db,
         }
     }
-}
 
-impl Mint {
-    fn combine(
+    /// Validates `input` against the state of `db`, which is either the committed database or an
+    /// overlay containing the pending changes of the current epoch. Whether retired key
+    /// generations are still spendable is decided by the epoch of the `context`.
+    fn validate_input_in<'a>(
         &self,
-        partial_sigs: Vec<(PeerId, PartialSigResponse)>,
-    ) -> (Result<SigResponse, CombineError>, MintShareErrors) {
-        // FIXME: decide on right boundary place for this invariant
-        // Filter out duplicate contributions, they make share combinations fail
-        let peer_contrib_counts = partial_sigs
+        context: &ConsensusContext,
+        db: &dyn RawDatabase,
+        input: &'a Coins<Coin>,
+    ) -> Result<InputMeta<'a>, MintError> {
+        let coin_keys = input
             .iter()
-            .map(|(idx, _)| *idx)
-            .collect::<counter::Counter<_>>();
-        if let Some((peer, count)) = peer_contrib_counts.into_iter().find(|(_, cnt)| **cnt > 1) {
-            return (
-                Err(CombineError::MultiplePeerContributions(*peer, *count)),
-                MintShareErrors(vec![]),
-            );
+            .map(|(amount, coin)| {
+                let pub_keys = self.spendable_generation_keys(coin.2, context.epoch)?;
+                let pub_key = *pub_keys
+                    .get(&amount)
+                    .ok_or(MintError::InvalidAmountTier(amount))?;
+                Ok((coin, pub_key))
+            })
+            .collect::<Result<Vec<_>, MintError>>()?;
+
+        if !Coin::verify_batch(coin_keys, &self.message_derivation) {
+            return Err(MintError::InvalidSignature);
         }
 
-        // Determine the reference response to check against
-        let our_contribution = match &partial_sigs.iter().find(|(peer, _)| *peer == self.key_id) {
-            Some((_, psigs)) => psigs,
-            None => {
-                return (
-                    Err(CombineError::NoOwnContribution),
-                    MintShareErrors(vec![]),
-                )
+        // Listing a coin twice would count its amount twice
+        let mut nonces = HashSet::new();
+        input.iter().try_for_each(|(_, coin)| {
+            if !nonces.insert(&coin.0) {
+                return Err(MintError::DuplicateCoin);
             }
-        };
 
-        let reference_msgs = our_contribution.0.iter().map(|(_amt, (msg, _sig))| msg);
+            if db
+                .get_value::<_, Amount>(&NonceKey(coin.0.clone()))
+                .expect("DB error")
+                .is_some()
+            {
+                return Err(MintError::SpentCoin);
+            }
 
-        let mut peer_errors = vec![];
+            Ok(())
+        })?;
 
-        let partial_sigs = partial_sigs
-            .iter()
-            .filter(|(peer, sigs)| {
-                if !sigs.0.structural_eq(&our_contribution.0) {
-                    warn!(
-                        "Peer {} proposed a sig share of wrong structure (different than ours)",
-                        peer,
-                    );
-                    peer_errors.push((*peer, PeerErrorType::DifferentStructureSigShare));
-                    false
-                } else {
-                    true
-                }
-            })
-            .collect::<Vec<_>>();
-        debug!(
-            "After length filtering {} sig shares are left.",
-            partial_sigs.len()
-        );
+        Ok(InputMeta {
+            amount: input.amount(),
+            puk_keys: Box::new(input.iter().map(|(_, coin)| *coin.spend_key())),
+        })
+    }
 
-        let bsigs = TieredMultiZip::new(
-            partial_sigs
-                .iter()
-                .map(|(_peer, sig_share)| sig_share.0.iter())
-                .collect(),
-        )
-        .zip(reference_msgs)
-        .map(|((amt, sig_shares), ref_msg)| {
-            let peer_ids = partial_sigs.iter().map(|(peer, _)| *peer);
+    /// Returns the aggregate public keys of `generation` if coins of that generation may still be
+    /// spent in `epoch`.
+    fn spendable_generation_keys(
+        &self,
+        generation: KeyGeneration,
+        epoch: u64,
+    ) -> Result<&HashMap<Amount, AggregatePublicKey>, MintError> {
+        let pub_keys = self
+            .pub_key
+            .get(&generation)
+            .ok_or(MintError::UnknownKeyGeneration(generation))?;
+
+        match self.deprecation_epochs.get(&generation) {
+            Some(&deprecation_epoch) if epoch >= deprecation_epoch => Err(
+                MintError::DeprecatedKeyGeneration(generation, deprecation_epoch),
+            ),
+            _ => Ok(pub_keys),
+        }
+    }
+}
 
-            // Filter out invalid peer contributions
-            let valid_sigs = sig_shares
-                .into_iter()
-                .zip(peer_ids)
-                .filter_map(|((msg, sig), peer)| {
-                    let amount_key = match self.pub_key_shares[&peer].tier(&amt) {
-                        Ok(key) => key,
-                        Err(_) => {
-                            peer_errors.push((peer, PeerErrorType::InvalidAmountTier));
-                            return None;
-                        }
-                    };
+impl Mint {
+    /// Blind signs all outputs that were accepted by consensus since the last call and stores the
+    /// signature shares for clients to fetch. Returns the number of signed outputs.
+    pub fn sign_pending_issuances(&self) -> usize {
+        let pending = self
+            .db
+            .find_by_prefix::<_, PendingIssuanceKey, SignRequest>(&PendingIssuanceKeyPrefix)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("DB error");
+        let count = pending.len();
+        if count == 0 {
+            return 0;
+        }
 
-                    if msg != ref_msg {
-                        peer_errors.push((peer, PeerErrorType::DifferentNonce));
-                        None
-                    } else if !verify_blind_share(*msg, *sig, *amount_key) {
-                        peer_errors.push((peer, PeerErrorType::InvalidSignature));
-                        None
-                    } else {
-                        Some((peer, *sig))
-                    }
-                })
-                .collect::<Vec<_>>();
+        let signed = pending
+            .into_par_iter()
+            .map(|(key, SignRequest(msgs))| {
+                let shares = msgs
+                    .map(|amt, msg| -> Result<_, InvalidAmountTierError> {
+                        let sec_key = self.sec_key.tier(&amt)?;
+                        Ok((msg, sign_blinded_msg(msg, sec_key.expose())))
+                    })
+                    .expect("Amount tiers are checked when accepting outputs");
+                (key, PartialSigResponse(shares, self.generation))
+            })
+            .collect::<Vec<_>>();
 
-            // Check that there are still sufficient
-            if valid_sigs.len() < self.threshold {
-                return Err(CombineError::TooFewValidShares(
-                    valid_sigs.len(),
-                    partial_sigs.len(),
-                    self.threshold,
-                ));
+        let mut batch = DbBatch::new();
+        batch.autocommit(|batch_tx| {
+            for (key, partial_sig) in signed {
+                debug!("Signed issuance {}", key.0);
+                batch_tx.append_insert_new(SignatureShareKey { request_id: key.0 }, partial_sig);
+                batch_tx.append_delete(key);
             }
+        });
+        self.db.apply_batch(batch).expect("DB error");
 
-            let sig = combine_valid_shares(
-                valid_sigs
-                    .into_iter()
-                    .map(|(peer, share)| (peer.to_usize(), share)),
-                self.threshold,
-            );
+        count
+    }
 
-            Ok((amt, sig))
-        })
-        .collect::<Result<Coins<_>, CombineError>>();
+    /// Returns our signature share for the output `out_point` if we signed it already
+    pub fn signature_share(&self, out_point: OutPoint) -> Option<PartialSigResponse> {
+        self.db
+            .get_value(&SignatureShareKey {
+                request_id: out_point,
+            })
+            .expect("DB error")
+            .or_else(|| {
+                // Outputs that weren't finalized before upgrading only have our share stored as
+                // received through consensus
+                self.db
+                    .get_value(&ReceivedPartialSignatureKey {
+                        request_id: out_point,
+                        peer_id: self.key_id,
+                    })
+                    .expect("DB error")
+            })
+    }
+}
 
-        let bsigs = match bsigs {
-            Ok(bs) => bs,
-            Err(e) => return (Err(e), MintShareErrors(peer_errors)),
+impl Mint {
+    /// Checks a signature share that `peer` contributed through consensus for `output_id` against
+    /// the blinded messages of the output and the peer's public key shares. Peers running older
+    /// versions still propose shares, honest ones sign every accepted output with the same key
+    /// generation as we do.
+    fn validate_partial_signature(
+        &self,
+        peer: PeerId,
+        output_id: OutPoint,
+        partial_sig: &PartialSigResponse,
+    ) -> Result<(), PeerErrorType> {
+        let (our_msgs, generation) = match self.signature_share(output_id) {
+            Some(PartialSigResponse(shares, generation)) => {
+                let msgs = shares
+                    .into_iter()
+                    .map(|(amt, (msg, _))| (amt, msg))
+                    .collect::<Coins<_>>();
+                (msgs, generation)
+            }
+            None => {
+                let SignRequest(msgs) = self
+                    .db
+                    .get_value(&PendingIssuanceKey(output_id))
+                    .expect("DB error")
+                    .ok_or(PeerErrorType::UnknownOutput)?;
+                (msgs, self.generation)
+            }
         };
 
-        (Ok(SigResponse(bsigs)), MintShareErrors(peer_errors))
+        if partial_sig.1 != generation {
+            return Err(PeerErrorType::DifferentKeyGeneration);
+        }
+        if !partial_sig.0.structural_eq(&our_msgs) {
+            return Err(PeerErrorType::DifferentStructureSigShare);
+        }
+
+        // Peers that joined through resharing have no keys of generations retired before
+        let peer_pub_keys = self
+            .pub_key_shares
+            .get(&generation)
+            .and_then(|peer_pks| peer_pks.get(&peer))
+            .ok_or(PeerErrorType::UnknownPeer)?;
+        partial_sig.0.iter().zip(our_msgs.iter()).try_for_each(
+            |((amt, (msg, sig)), (_, ref_msg))| {
+                let amount_key = peer_pub_keys
+                    .tier(&amt)
+                    .map_err(|_| PeerErrorType::InvalidAmountTier)?;
+
+                if msg != ref_msg {
+                    Err(PeerErrorType::DifferentNonce)
+                } else if !verify_blind_share(*msg, *sig, *amount_key) {
+                    Err(PeerErrorType::InvalidSignature)
+                } else {
+                    Ok(())
+                }
+            },
+        )
     }
 
+    /// Validates a signature share `peer` contributed through consensus and records a fault if
+    /// it's invalid. Valid shares aren't stored either since clients fetch them from every peer
+    /// directly.
     fn process_partial_signature(
         &self,
         mut batch: BatchTx,
         peer: PeerId,
         output_id: OutPoint,
-        partial_sig: PartialSigResponse,
+        partial_sig: &PartialSigResponse,
     ) {
-        if self
-            .db
-            .get_value::<_, SigResponse>(&OutputOutcomeKey(output_id))
-            .expect("DB error")
-            .is_some()
-        {
-            debug!(
-                "Received sig share for finalized issuance {}, ignoring",
-                output_id
-            );
+        if peer == self.key_id {
             return;
         }
 
-        debug!(
-            "Received sig share from peer {} for issuance {}",
-            peer, output_id
-        );
-        batch.append_insert_new(
-            ReceivedPartialSignatureKey {
-                request_id: output_id,
-                peer_id: peer,
-            },
-            partial_sig,
-        );
-
-        // FIXME: add own id to cfg
-        if peer == self.key_id {
-            batch.append_delete(ProposedPartialSignatureKey {
-                request_id: output_id,
-            });
+        match self.validate_partial_signature(peer, output_id, partial_sig) {
+            Ok(()) => debug!(
+                "Ignoring sig share of peer {} for issuance {}, clients combine shares now",
+                peer, output_id
+            ),
+            Err(error) => {
+                warn!(
+                    "Peer {} sent faulty sig share for issuance {}: {:?}",
+                    peer, output_id, error
+                );
+                batch.append_insert(
+                    PeerFaultKey {
+                        peer_id: peer,
+                        request_id: output_id,
+                    },
+                    error,
+                );
+            }
         }
-
         batch.commit();
     }
+
+    /// Returns all invalid signature shares contributed by other peers
+    pub fn peer_faults(&self) -> Vec<PeerFault> {
+        self.db
+            .find_by_prefix::<_, PeerFaultKey, PeerErrorType>(&PeerFaultKeyPrefix)
+            .map(|res| {
+                let (key, error) = res.expect("DB error");
+                PeerFault {
+                    peer: key.peer_id,
+                    out_point: key.request_id,
+                    error,
+                }
+            })
+            .collect()
+    }
 }
 
 impl Coin {
-    /// Verify the coin's validity under a mit key `pk`
-    pub fn verify(&self, pk: tbs::AggregatePublicKey) -> bool {
-        tbs::verify(self.0.to_message(), self.1, pk)
+    /// Verify the coin's validity under a mint key `pk` and the mint's message `derivation`
+    pub fn verify(&self, pk: tbs::AggregatePublicKey, derivation: &MessageDerivation) -> bool {
+        tbs::verify(self.0.to_message(derivation), self.1, pk)
+    }
+
+    /// Verify many coins, each under the given mint key, at once. This is a lot faster than
+    /// verifying them one by one, but doesn't tell which coin is invalid.
+    pub fn verify_batch<'a, I>(coins: I, derivation: &MessageDerivation) -> bool
+    where
+        I: IntoIterator<Item = (&'a Coin, tbs::AggregatePublicKey)>,
+    {
+        tbs::verify_batch(
+            coins
+                .into_iter()
+                .map(|(coin, pk)| (coin.0.to_message(derivation), coin.1, pk)),
+        )
     }
 
     /// Access the nonce as the public key to the spend key
@@ -545,8 +673,32 @@ impl CoinNonce {
         bincode::deserialize(bytes).unwrap()
     }
 
-    pub fn to_message(&self) -> tbs::Message {
-        tbs::Message::from_bytes(&self.0.serialize()[..])
+    pub fn to_message(&self, derivation: &MessageDerivation) -> tbs::Message {
+        tbs::Message::derive(&self.0.serialize()[..], derivation)
+    }
+}
+
+/// Computes the aggregate public key for every amount tier from the peers' public key shares
+fn aggregate_pub_keys(
+    peer_pks: &BTreeMap<PeerId, Keys<PublicKeyShare>>,
+    threshold: usize,
+) -> HashMap<Amount, AggregatePublicKey> {
+    TieredMultiZip::new(peer_pks.iter().map(|(_, keys)| keys.iter()).collect())
+        .map(|(amt, keys)| {
+            // Key shares are indexed by peer id, which isn't necessarily the position of the peer
+            let keys = peer_pks
+                .keys()
+                .zip(keys)
+                .map(|(peer, key)| (peer.to_usize(), *key))
+                .collect::<Vec<_>>();
+            (amt, keys.aggregate(threshold))
+        })
+        .collect()
+}
+
+impl std::fmt::Display for KeyGeneration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
@@ -569,28 +721,24 @@ impl std::fmt::Display for InvalidAmountTierError {
     }
 }
 
-/// Represents an array of mint indexes that delivered faulty shares
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub struct MintShareErrors(pub Vec<(PeerId, PeerErrorType)>);
-
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// Reason why a signature share contributed by a peer was rejected
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub enum PeerErrorType {
     InvalidSignature,
     DifferentStructureSigShare,
     DifferentNonce,
     InvalidAmountTier,
+    DifferentKeyGeneration,
+    UnknownOutput,
+    UnknownPeer,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
-pub enum CombineError {
-    #[error(
-        "Too few valid shares, only {0} of {1} (required minimum {2}) provided shares were valid"
-    )]
-    TooFewValidShares(usize, usize, usize),
-    #[error("We could not find our own contribution in the provided shares, so we have no validation reference")]
-    NoOwnContribution,
-    #[error("Peer {0} contributed {1} shares, 1 expected")]
-    MultiplePeerContributions(PeerId, usize),
+/// Invalid signature share contributed by `peer` for the issuance `out_point`
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct PeerFault {
+    pub peer: PeerId,
+    pub out_point: OutPoint,
+    pub error: PeerErrorType,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
@@ -601,10 +749,16 @@ pub enum MintError {
     TooFewCoins(Amount, Amount),
     #[error("One of the supplied coins was already spent previously")]
     SpentCoin,
+    #[error("One of the supplied coins was listed more than once")]
+    DuplicateCoin,
     #[error("One of the coins had an invalid amount not issued by the mint: {0:?}")]
     InvalidAmountTier(Amount),
     #[error("One of the coins had an invalid signature")]
     InvalidSignature,
+    #[error("One of the coins was signed with unknown key generation {0}")]
+    UnknownKeyGeneration(KeyGeneration),
+    #[error("One of the coins was signed with key generation {0}, deprecated since epoch {1}")]
+    DeprecatedKeyGeneration(KeyGeneration, u64),
 }
 
 impl From<InvalidAmountTierError> for MintError {
@@ -615,198 +769,459 @@ impl From<InvalidAmountTierError> for MintError {
 
 #[cfg(test)]
 mod test {
-    // TODO: reactivate
-    /*
-    use crate::{CombineError, Mint, MintError, MintShareErrors, PeerErrorType};
-    use ::database::mem_impl::MemDatabase;
-    use minimint_api::{Amount, Coin, Coins, Keys, PartialSigResponse, SigResponse, SignRequest};
+    use crate::api::PeerFaultsEndpoint;
+    use crate::config::{MintClientConfig, MintConfig};
+    use crate::db::{
+        LegacyIssuanceKey, NonceKey, NonceKeyPrefix, OutputOutcomeKey, ReceivedPartialSignatureKey,
+        ReceivedPartialSignaturesKeyPrefix, SignatureShareKey,
+    };
+    use crate::tiered::coins::Coins;
+    use crate::{
+        BlindToken, Coin, CoinNonce, KeyGeneration, Mint, MintError, PartialSigResponse,
+        PeerErrorType, PeerFault, SigResponse,
+    };
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::db::batch::DbBatch;
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::db::migration::SchemaVersionKey;
+    use minimint_api::db::{Database, DatabaseKeyPrefix, RawDatabase};
+    use minimint_api::encoding::Encodable;
+    use minimint_api::module::api::TypedApiEndpoint;
+    use minimint_api::{Amount, ConsensusContext, FederationModule, OutPoint, PeerId};
+    use std::collections::BTreeMap;
     use std::sync::Arc;
-    use tbs::{blind_message, unblind_signature, verify, AggregatePublicKey, Message};
+    use tbs::{combine_valid_shares, unblind_signature, BlindingKey};
+
+    const PEERS: u16 = 4;
+    const MAX_EVIL: usize = 1;
+
+    /// Outputs requesting coins and the secrets needed to unblind their signatures
+    struct Issuance {
+        out_point: OutPoint,
+        output: Coins<BlindToken>,
+        nonces: Coins<(CoinNonce, BlindingKey)>,
+    }
 
-    const THRESHOLD: usize = 1;
-    const MINTS: usize = 5;
+    fn context() -> ConsensusContext {
+        ConsensusContext {
+            epoch: 0,
+            block_height: None,
+            randomness_beacon: None,
+        }
+    }
 
-    fn build_mints() -> (AggregatePublicKey, Vec<Mint>) {
-        let (pk, pks, sks) = tbs::dealer_keygen(THRESHOLD, MINTS);
+    fn configs() -> (BTreeMap<PeerId, MintConfig>, MintClientConfig) {
+        let peers = (0..PEERS).map(PeerId::from).collect::<Vec<_>>();
+        MintConfig::trusted_dealer_gen(
+            &peers,
+            MAX_EVIL,
+            &[Amount::from_sat(1), Amount::from_sat(2)],
+            rand::rngs::OsRng::new().unwrap(),
+        )
+    }
 
-        let mints = sks
+    fn build_mints() -> (MintClientConfig, Vec<(Mint, MemDatabase)>) {
+        let (cfgs, client_cfg) = configs();
+        let mints = cfgs
             .into_iter()
-            .map(|sk| {
-                Mint::new(
-                    [(Amount::from_sat(1), sk)].iter().cloned().collect(),
-                    pks.iter()
-                        .map(|pk| Keys {
-                            keys: vec![(Amount::from_sat(1), *pk)].into_iter().collect(),
-                        })
-                        .collect(),
-                    THRESHOLD,
-                    Arc::new(MemDatabase::new()),
-                )
+            .map(|(_, cfg)| {
+                let db = MemDatabase::new();
+                let mint = Mint::new(cfg, client_cfg.threshold, Arc::new(db.clone()));
+                (mint, db)
             })
-            .collect::<Vec<_>>();
+            .collect();
+        (client_cfg, mints)
+    }
 
-        (pk, mints)
+    fn out_point(out_idx: u64) -> OutPoint {
+        OutPoint {
+            txid: Default::default(),
+            out_idx,
+        }
     }
 
-    #[test]
-    fn test_issuance() {
-        let (pk, mut mints) = build_mints();
+    fn coin_nonce() -> CoinNonce {
+        let ctx = secp256k1_zkp::Secp256k1::new();
+        let key_pair = secp256k1_zkp::schnorrsig::KeyPair::from_seckey_slice(
+            &ctx,
+            &rand::random::<[u8; 32]>(),
+        )
+        .unwrap();
+        CoinNonce(secp256k1_zkp::schnorrsig::PublicKey::from_keypair(
+            &ctx, &key_pair,
+        ))
+    }
 
-        let nonce = Message::from_bytes(&b"test coin"[..]);
-        let (bkey, bmsg) = blind_message(nonce);
-        let req = SignRequest(Coins {
-            coins: vec![(Amount::from_sat(1), vec![bmsg, bmsg])]
-                .into_iter()
-                .collect(),
-        });
+    fn request_coins(client_cfg: &MintClientConfig, out_idx: u64, amounts: &[u64]) -> Issuance {
+        let nonces = amounts
+            .iter()
+            .map(|&amount| {
+                (
+                    Amount::from_sat(amount),
+                    (coin_nonce(), BlindingKey::random()),
+                )
+            })
+            .collect::<Coins<_>>();
+        let output = nonces
+            .iter()
+            .map(|(amount, (nonce, blinding_key))| {
+                let msg = nonce.to_message(&client_cfg.message_derivation);
+                (amount, BlindToken(blinding_key.blind(msg)))
+            })
+            .collect();
+        Issuance {
+            out_point: out_point(out_idx),
+            output,
+            nonces,
+        }
+    }
+
+    fn accept_output(mints: &[(Mint, MemDatabase)], issuance: &Issuance) {
+        for (mint, db) in mints {
+            let mut batch = DbBatch::new();
+            mint.apply_output(
+                &context(),
+                batch.transaction(),
+                &issuance.output,
+                issuance.out_point,
+            )
+            .unwrap();
+            db.apply_batch(batch).unwrap();
+        }
+    }
 
-        let psigs = mints
+    /// Combines the first `threshold` of `shares` the way clients do and unblinds the coins
+    fn finalize(
+        client_cfg: &MintClientConfig,
+        issuance: &Issuance,
+        shares: &[(PeerId, PartialSigResponse)],
+    ) -> Coins<Coin> {
+        issuance
+            .nonces
             .iter()
-            .map(move |m| m.issue(req.clone()).unwrap())
             .enumerate()
-            .collect::<Vec<_>>();
-
-        let mint = &mut mints[0];
+            .map(|(idx, (amount, (nonce, blinding_key)))| {
+                let sig_shares = shares
+                    .iter()
+                    .map(move |(peer, PartialSigResponse(sigs, _))| {
+                        (peer.to_usize(), sigs.iter().nth(idx).unwrap().1 .1)
+                    });
+                let bsig = combine_valid_shares(sig_shares, client_cfg.threshold);
+                let sig = unblind_signature(blinding_key, bsig);
+                (amount, Coin(nonce.clone(), sig, client_cfg.generation))
+            })
+            .collect()
+    }
 
-        // Test happy path
-        let (bsig_res, errors) = mint.combine(psigs.clone());
-        assert!(errors.0.is_empty());
+    fn signature_shares(
+        mints: &[(Mint, MemDatabase)],
+        out_point: OutPoint,
+    ) -> Vec<(PeerId, PartialSigResponse)> {
+        mints
+            .iter()
+            .map(|(mint, _)| (mint.key_id, mint.signature_share(out_point).unwrap()))
+            .collect()
+    }
 
-        let bsig = bsig_res.unwrap();
-        assert_eq!(bsig.0.amount(), Amount::from_sat(2));
+    fn spend(mints: &[(Mint, MemDatabase)], coins: &Coins<Coin>) {
+        for (mint, db) in mints {
+            let mut batch = DbBatch::new();
+            mint.apply_input(&context(), batch.transaction(), coins)
+                .unwrap();
+            db.apply_batch(batch).unwrap();
+        }
+    }
 
-        bsig.0.iter().for_each(|(_, bs)| {
-            let sig = unblind_signature(bkey, *bs);
-            assert!(verify(nonce, sig, pk));
-        });
+    fn liabilities(mints: &[(Mint, MemDatabase)]) -> Amount {
+        let audits = mints
+            .iter()
+            .map(|(mint, _)| mint.audit())
+            .collect::<Vec<_>>();
+        assert!(audits.iter().all(|audit| audit == &audits[0]));
+        assert_eq!(audits[0].assets, Amount::ZERO);
+        audits[0].liabilities
+    }
 
-        // Test threshold sig shares
-        let (bsig_res, errors) = mint.combine(psigs[..(MINTS - THRESHOLD)].to_vec());
-        assert!(bsig_res.is_ok());
-        assert!(errors.0.is_empty());
+    #[test]
+    fn test_audit() {
+        let (client_cfg, mints) = build_mints();
+        assert_eq!(liabilities(&mints), Amount::ZERO);
 
-        bsig_res.unwrap().0.iter().for_each(|(_, bs)| {
-            let sig = unblind_signature(bkey, *bs);
-            assert!(verify(nonce, sig, pk));
-        });
+        let issuance = request_coins(&client_cfg, 0, &[1, 2, 2]);
+        accept_output(&mints, &issuance);
+        assert_eq!(liabilities(&mints), Amount::from_sat(5));
 
-        // Test too few sig shares
-        let (bsig_res, errors) = mint.combine(psigs[..(MINTS - THRESHOLD - 1)].to_vec());
-        assert_eq!(bsig_res, Err(CombineError::TooFewValidShares(3, 3, 4)));
-        assert!(errors.0.is_empty());
+        for (mint, _) in &mints {
+            assert_eq!(mint.sign_pending_issuances(), 1);
+        }
+        assert_eq!(liabilities(&mints), Amount::from_sat(5));
 
-        // Test no own share
-        let (bsig_res, errors) = mint.combine(psigs[1..].to_vec());
-        assert_eq!(bsig_res, Err(CombineError::NoOwnContribution));
-        assert!(errors.0.is_empty());
+        let shares = signature_shares(&mints, issuance.out_point);
+        let coins = finalize(&client_cfg, &issuance, &shares);
+        spend(&mints, &coins);
+        assert_eq!(liabilities(&mints), Amount::ZERO);
+    }
 
-        // Test multiple peer contributions
-        let (bsig_res, errors) = mint.combine(
-            psigs
-                .iter()
-                .cloned()
-                .chain(std::iter::once(psigs[0].clone()))
-                .collect(),
+    #[test]
+    fn test_duplicate_coin() {
+        let (client_cfg, mints) = build_mints();
+        let issuance = request_coins(&client_cfg, 0, &[1, 2]);
+        accept_output(&mints, &issuance);
+        for (mint, _) in &mints {
+            assert_eq!(mint.sign_pending_issuances(), 1);
+        }
+        let coins = finalize(
+            &client_cfg,
+            &issuance,
+            &signature_shares(&mints, issuance.out_point),
         );
-        assert_eq!(bsig_res, Err(CombineError::MultiplePeerContributions(0, 2)));
-        assert!(errors.0.is_empty());
 
-        // Test wrong length response
-        let (bsig_res, errors) = mint.combine(
-            psigs
-                .iter()
-                .cloned()
-                .map(|(peer, mut psigs)| {
-                    if peer == 1 {
-                        psigs.0.coins.get_mut(&Amount::from_sat(1)).unwrap().pop();
-                    }
-                    (peer, psigs)
-                })
-                .collect(),
-        );
-        assert!(bsig_res.is_ok());
-        assert!(errors
-            .0
-            .contains(&(1, PeerErrorType::DifferentStructureSigShare)));
+        let (amount, coin) = coins.iter().next().unwrap();
+        let duplicated = coins
+            .iter()
+            .chain(std::iter::once((amount, coin)))
+            .map(|(amount, coin)| (amount, coin.clone()))
+            .collect::<Coins<_>>();
+
+        let (mint, _) = &mints[0];
+        assert!(matches!(
+            mint.validate_input(&context(), &duplicated),
+            Err(MintError::DuplicateCoin)
+        ));
+        let mut batch = DbBatch::new();
+        assert!(matches!(
+            mint.apply_input(&context(), batch.transaction(), &duplicated),
+            Err(MintError::DuplicateCoin)
+        ));
+        assert!(mint.validate_input(&context(), &coins).is_ok());
+    }
 
-        let (bsig_res, errors) = mint.combine(
-            psigs
-                .iter()
-                .cloned()
-                .map(|(peer, mut psig)| {
-                    if peer == 2 {
-                        psig.0.coins.get_mut(&Amount::from_sat(1)).unwrap()[0].1 =
-                            psigs[0].1 .0.coins[&Amount::from_sat(1)][0].1;
-                    }
-                    (peer, psig)
-                })
-                .collect(),
-        );
-        assert!(bsig_res.is_ok());
-        assert!(errors.0.contains(&(2, PeerErrorType::InvalidSignature)));
+    #[test]
+    fn test_audit_migrated_db() {
+        let (cfgs, client_cfg) = configs();
+        let cfg = cfgs.into_iter().next().unwrap().1;
 
-        let (_bk, bmsg) = blind_message(Message::from_bytes(b"test"));
-        let (bsig_res, errors) = mint.combine(
-            psigs
-                .iter()
-                .cloned()
-                .map(|(peer, mut psig)| {
-                    if peer == 3 {
-                        psig.0.coins.get_mut(&Amount::from_sat(1)).unwrap()[0].0 = bmsg;
-                    }
-                    (peer, psig)
-                })
-                .collect(),
+        let db = MemDatabase::from_dump(include_str!("fixtures/db_v0.dump")).unwrap();
+        crate::db::migrations().apply(&db).unwrap();
+        assert_eq!(
+            db.get_value(&SchemaVersionKey("mint".to_owned())).unwrap(),
+            Some(crate::db::DB_SCHEMA_VERSION)
         );
-        assert!(bsig_res.is_ok());
-        assert!(errors.0.contains(&(3, PeerErrorType::DifferentNonce)));
+        let SigResponse(sigs, generation) = db
+            .get_value(&OutputOutcomeKey(out_point(0)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(sigs.amount(), Amount::from_sat(4));
+        assert_eq!(generation, KeyGeneration::default());
+        let PartialSigResponse(shares, generation) = db
+            .get_value(&SignatureShareKey {
+                request_id: out_point(1),
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(shares.amount(), Amount::from_sat(1));
+        assert_eq!(generation, KeyGeneration::default());
+        assert_eq!(
+            db.find_by_prefix::<_, ReceivedPartialSignatureKey, PartialSigResponse>(
+                &ReceivedPartialSignaturesKeyPrefix
+            )
+            .count(),
+            2
+        );
+        // Schema version 0 stored spent coins without their amount
+        assert!(db
+            .find_by_prefix::<_, NonceKey, Amount>(&NonceKeyPrefix)
+            .all(|res| res.unwrap().1 == Amount::ZERO));
+        assert_eq!(
+            db.get_value::<_, Amount>(&LegacyIssuanceKey).unwrap(),
+            Some(Amount::from_sat(6))
+        );
+
+        // The coin spent before migrating may have redeemed all legacy e-cash, so we can't count
+        // it as outstanding without risking to halt consensus
+        let mints = vec![(
+            Mint::new(cfg, client_cfg.threshold, Arc::new(db.clone())),
+            db,
+        )];
+        assert_eq!(liabilities(&mints), Amount::ZERO);
+
+        let issuance = request_coins(&client_cfg, 3, &[1, 2]);
+        accept_output(&mints, &issuance);
+        assert_eq!(liabilities(&mints), Amount::from_sat(3));
     }
 
     #[test]
-    #[should_panic(expected = "Own key not found among pub keys.")]
-    fn test_new_panic_without_own_pub_key() {
-        let (_pk, pks, sks) = tbs::dealer_keygen(THRESHOLD, MINTS);
-        let db = MemDatabase::new();
-
-        Mint::new(
-            vec![(Amount::from_sat(1), sks[0])].into_iter().collect(),
-            pks[1..]
-                .iter()
-                .map(|pk| Keys {
-                    keys: vec![(Amount::from_sat(1), *pk)].into_iter().collect(),
-                })
-                .collect(),
-            THRESHOLD,
-            Arc::new(db),
-        );
+    fn test_audit_shortfall() {
+        let (client_cfg, mints) = build_mints();
+        let issuance = request_coins(&client_cfg, 0, &[1]);
+        accept_output(&mints, &issuance);
+
+        // A coin redeemed without having been issued, as a bug in validating inputs might allow
+        for (_, db) in &mints {
+            db.insert_entry(&NonceKey(coin_nonce()), &Amount::from_sat(2))
+                .unwrap();
+        }
+        for (mint, _) in &mints {
+            let audit = mint.audit();
+            assert_eq!(audit.liabilities, Amount::ZERO);
+            assert_eq!(audit.shortfall, Amount::from_sat(1));
+        }
     }
 
+    #[test]
+    fn test_shares_received_before_upgrade() {
+        let (cfgs, client_cfg) = configs();
+        let issuance = request_coins(&client_cfg, 0, &[1, 2]);
+
+        // Older versions exchanged shares through consensus and stored them all, including ours
+        let signers = cfgs
+            .values()
+            .map(|cfg| {
+                let db = MemDatabase::new();
+                let mint = Mint::new(cfg.clone(), client_cfg.threshold, Arc::new(db.clone()));
+                (mint, db)
+            })
+            .collect::<Vec<_>>();
+        accept_output(&signers, &issuance);
+        for (mint, _) in &signers {
+            assert_eq!(mint.sign_pending_issuances(), 1);
+        }
+        let shares = signature_shares(&signers, issuance.out_point);
+
+        let mints = cfgs
+            .into_iter()
+            .map(|(_, cfg)| {
+                let db = MemDatabase::new();
+                for (peer, share) in &shares {
+                    let key = ReceivedPartialSignatureKey {
+                        request_id: issuance.out_point,
+                        peer_id: *peer,
+                    };
+                    // Schema version 0 didn't append the key generation to the shares
+                    let mut value = Vec::new();
+                    share.consensus_encode(&mut value).unwrap();
+                    value.truncate(value.len() - 4);
+                    db.raw_insert_entry(&DatabaseKeyPrefix::to_bytes(&key), value)
+                        .unwrap();
+                }
+                crate::db::migrations().apply(&db).unwrap();
+                let mint = Mint::new(cfg, client_cfg.threshold, Arc::new(db.clone()));
+                (mint, db)
+            })
+            .collect::<Vec<_>>();
+
+        for (mint, _) in &mints {
+            assert_eq!(mint.output_status(issuance.out_point), Some(None));
+            assert_eq!(mint.sign_pending_issuances(), 0);
+        }
+        assert_eq!(signature_shares(&mints, issuance.out_point), shares);
+        assert_eq!(liabilities(&mints), Amount::from_sat(3));
+
+        let coins = finalize(&client_cfg, &issuance, &shares);
+        spend(&mints, &coins);
+        assert_eq!(liabilities(&mints), Amount::ZERO);
+    }
 
-    // FIXME: possibly make this an error
     #[test]
-    #[should_panic(expected = "index out of bounds: the len is 5 but the index is 42")]
-    fn test_combine_panic_with_unknown_mint_id() {
-        let (_pk, mints) = build_mints();
+    fn test_faulty_shares() {
+        let (client_cfg, mints) = build_mints();
+        let issuance = request_coins(&client_cfg, 0, &[1, 2]);
+        accept_output(&mints, &issuance);
+        for (mint, _) in &mints[1..] {
+            assert_eq!(mint.sign_pending_issuances(), 1);
+        }
+        let shares = signature_shares(&mints[1..], issuance.out_point);
+
+        // Peer 0 didn't sign yet, so shares are checked against the pending issuance
+        let (mint, db) = &mints[0];
+        let mut batch = DbBatch::new();
+        let (peer, share) = &shares[0];
+        mint.process_partial_signature(batch.transaction(), *peer, issuance.out_point, share);
+        // Peer 2 passes off the share of peer 1 as its own
+        let forger = PeerId::from(2);
+        mint.process_partial_signature(batch.transaction(), forger, issuance.out_point, share);
+        mint.process_partial_signature(batch.transaction(), forger, out_point(1), share);
+        db.apply_batch(batch).unwrap();
+
+        let faults = vec![
+            PeerFault {
+                peer: forger,
+                out_point: issuance.out_point,
+                error: PeerErrorType::InvalidSignature,
+            },
+            PeerFault {
+                peer: forger,
+                out_point: out_point(1),
+                error: PeerErrorType::UnknownOutput,
+            },
+        ];
+        assert_eq!(mint.peer_faults(), faults);
+        assert_eq!(PeerFaultsEndpoint::handle(mint, ()).unwrap(), faults);
 
-        let nonce = Message::from_bytes(&b"test coin"[..]);
-        let (_bkey, bmsg) = blind_message(nonce);
-        let req = SignRequest(vec![(Amount::from_sat(1), bmsg)].into_iter().collect());
+        // Neither valid nor invalid shares are stored
+        assert_eq!(
+            db.find_by_prefix::<_, ReceivedPartialSignatureKey, PartialSigResponse>(
+                &ReceivedPartialSignaturesKeyPrefix
+            )
+            .count(),
+            0
+        );
+    }
 
-        let psigs = mints
+    #[test]
+    fn test_share_of_peer_outside_retired_generation() {
+        let (mut cfgs, mut client_cfg) = configs();
+        let issuance = request_coins(&client_cfg, 0, &[1, 2]);
+        let mints = cfgs
+            .values()
+            .map(|cfg| {
+                let db = MemDatabase::new();
+                let mint = Mint::new(cfg.clone(), client_cfg.threshold, Arc::new(db.clone()));
+                (mint, db)
+            })
+            .collect::<Vec<_>>();
+        accept_output(&mints, &issuance);
+        for (mint, _) in &mints {
+            assert_eq!(mint.sign_pending_issuances(), 1);
+        }
+        let shares = signature_shares(&mints, issuance.out_point);
+
+        // After rotating the keys peer 3 is replaced by peer 5, which has no keys of generation 0
+        MintConfig::trusted_dealer_rotate(&mut cfgs, &mut client_cfg, MAX_EVIL, 42);
+        cfgs.remove(&PeerId::from(3));
+        let new_peers = [0u16, 1, 2, 5]
             .iter()
-            .map(move |m| m.issue(req.clone()).unwrap())
-            .enumerate()
-            .map(
-                |(mint, sig)| {
-                    if mint == 2 {
-                        (42, sig)
-                    } else {
-                        (mint, sig)
-                    }
-                },
-            )
+            .map(|&id| PeerId::from(id))
             .collect::<Vec<_>>();
-        let _ = mints[0].combine(psigs);
+        let mut new_cfgs = MintConfig::trusted_dealer_reshare(
+            &cfgs,
+            &mut client_cfg,
+            MAX_EVIL,
+            &new_peers,
+            MAX_EVIL,
+        );
+        let (_, db) = &mints[0];
+        let mint = Mint::new(
+            new_cfgs.remove(&PeerId::from(0)).unwrap(),
+            client_cfg.threshold,
+            Arc::new(db.clone()),
+        );
+
+        let mut batch = DbBatch::new();
+        let (peer, share) = &shares[1];
+        assert_eq!(share.1, KeyGeneration(0));
+        mint.process_partial_signature(batch.transaction(), *peer, issuance.out_point, share);
+        let newcomer = PeerId::from(5);
+        mint.process_partial_signature(batch.transaction(), newcomer, issuance.out_point, share);
+        db.apply_batch(batch).unwrap();
+
+        assert_eq!(
+            mint.peer_faults(),
+            vec![PeerFault {
+                peer: newcomer,
+                out_point: issuance.out_point,
+                error: PeerErrorType::UnknownPeer,
+            }]
+        );
     }
-     */
 }
@@ -1,5 +1,5 @@
 use crate::{Amount, InvalidAmountTierError, Keys};
-use minimint_api::encoding::{Decodable, DecodeError, Encodable};
+use minimint_api::encoding::{decode_collection_len, Decodable, DecodeError, Encodable};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::iter::FromIterator;
@@ -188,7 +188,7 @@ where
 {
     fn consensus_decode<D: std::io::Read>(mut d: D) -> Result<Self, DecodeError> {
         let mut coins = BTreeMap::new();
-        let len = u64::consensus_decode(&mut d)?;
+        let len = decode_collection_len(&mut d)?;
         for _ in 0..len {
             let amt = Amount::consensus_decode(&mut d)?;
             let coin = C::consensus_decode(&mut d)?;
@@ -1,4 +1,5 @@
 use crate::InvalidAmountTierError;
+use minimint_api::secret::Secret;
 use minimint_api::Amount;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -31,13 +32,13 @@ impl<K> Keys<K> {
     }
 }
 
-impl Keys<SecretKeyShare> {
+impl Keys<Secret<SecretKeyShare>> {
     pub fn to_public(&self) -> Keys<PublicKeyShare> {
         Keys {
             keys: self
                 .keys
                 .iter()
-                .map(|(amt, key)| (*amt, key.to_pub_key_share()))
+                .map(|(amt, key)| (*amt, key.expose().to_pub_key_share()))
                 .collect(),
         }
     }
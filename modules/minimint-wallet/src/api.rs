@@ -0,0 +1,17 @@
+use crate::Wallet;
+use minimint_api::module::api::{ApiError, TypedApiEndpoint};
+
+/// Block height the federation agreed on, `None` before the first round consensus
+pub struct ConsensusHeightEndpoint;
+
+impl TypedApiEndpoint for ConsensusHeightEndpoint {
+    type State = Wallet;
+    type Request = ();
+    type Response = Option<u32>;
+
+    const PATH: &'static str = "/block_height";
+
+    fn handle(wallet: &Wallet, _request: ()) -> Result<Option<u32>, ApiError> {
+        Ok(wallet.consensus_height())
+    }
+}
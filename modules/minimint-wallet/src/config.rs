@@ -3,17 +3,19 @@ use crate::{Feerate, PegInDescriptor};
 use bitcoin::secp256k1::rand::{CryptoRng, RngCore};
 use bitcoin::Network;
 use minimint_api::config::GenerateConfig;
+use minimint_api::secret::Secret;
 use minimint_api::PeerId;
-use miniscript::descriptor::Wsh;
+use miniscript::descriptor::{Wsh, WshInner};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WalletConfig {
     pub network: Network,
     pub peg_in_descriptor: PegInDescriptor,
     pub peer_peg_in_keys: BTreeMap<PeerId, CompressedPublicKey>,
-    pub peg_in_key: secp256k1::SecretKey,
+    #[serde(with = "minimint_api::secret::exposed")]
+    pub peg_in_key: Secret<secp256k1::SecretKey>,
     pub finalty_delay: u32,
     pub default_fee: Feerate,
     pub btc_rpc_address: String,
@@ -65,7 +67,7 @@ impl GenerateConfig for WalletConfig {
                         .iter()
                         .map(|(peer_id, (_, pk))| (*peer_id, CompressedPublicKey { key: *pk }))
                         .collect(),
-                    peg_in_key: *sk,
+                    peg_in_key: Secret::new(*sk),
                     finalty_delay: 10,
                     default_fee: Feerate { sats_per_kvb: 2000 },
                     btc_rpc_address: "127.0.0.1:18443".to_string(),
@@ -85,3 +87,126 @@ impl GenerateConfig for WalletConfig {
         (wallet_cfg, client_cfg)
     }
 }
+
+impl WalletConfig {
+    /// Number of peers whose signatures are needed to spend from the peg-in descriptor, `None` if
+    /// the descriptor isn't a sorted multisig
+    pub fn peg_in_threshold(&self) -> Option<usize> {
+        match &self.peg_in_descriptor {
+            PegInDescriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(multi) => Some(multi.k),
+                WshInner::Ms(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Peers whose peg-in keys are part of the peg-in descriptor and can thus sign peg-outs
+    pub fn peg_in_signers(&self) -> BTreeSet<PeerId> {
+        let descriptor_keys = match &self.peg_in_descriptor {
+            PegInDescriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(multi) => multi.pks.clone(),
+                WshInner::Ms(_) => vec![],
+            },
+            _ => vec![],
+        };
+        self.peer_peg_in_keys
+            .iter()
+            .filter(|(_, key)| descriptor_keys.contains(key))
+            .map(|(&peer, _)| peer)
+            .collect()
+    }
+
+    /// Adds `new_peers` to the wallet configs `cfgs` of the remaining peers and returns the configs
+    /// of both. The peg-in descriptor can't change without moving all funds of the federation, so
+    /// joining peers get peg-in keys that aren't part of it: their peg-out signatures are accepted
+    /// but don't count towards the descriptor's threshold.
+    pub fn trusted_dealer_add_peers(
+        cfgs: &BTreeMap<PeerId, WalletConfig>,
+        new_peers: &[PeerId],
+        mut rng: impl RngCore + CryptoRng,
+    ) -> BTreeMap<PeerId, WalletConfig> {
+        let template = cfgs.values().next().expect("No wallet configs supplied");
+        let secp = secp256k1::Secp256k1::new();
+
+        let new_keys = new_peers
+            .iter()
+            .map(|&peer| (peer, secp.generate_keypair(&mut rng)))
+            .collect::<Vec<_>>();
+        let mut peer_peg_in_keys = template.peer_peg_in_keys.clone();
+        peer_peg_in_keys.extend(
+            new_keys
+                .iter()
+                .map(|(peer, (_, pk))| (*peer, CompressedPublicKey { key: *pk })),
+        );
+
+        let remaining = cfgs.iter().map(|(&peer, cfg)| (peer, cfg.clone()));
+        let joining = new_keys.iter().map(|(peer, (sk, _))| {
+            let cfg = WalletConfig {
+                peg_in_key: Secret::new(*sk),
+                ..template.clone()
+            };
+            (*peer, cfg)
+        });
+        remaining
+            .chain(joining)
+            .map(|(peer, cfg)| {
+                let cfg = WalletConfig {
+                    peer_peg_in_keys: peer_peg_in_keys.clone(),
+                    ..cfg
+                };
+                (peer, cfg)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::WalletConfig;
+    use minimint_api::config::GenerateConfig;
+    use minimint_api::PeerId;
+
+    #[test]
+    fn test_add_peers() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let (mut cfgs, client_cfg) = WalletConfig::trusted_dealer_gen(
+            &peers,
+            1,
+            &(),
+            secp256k1::rand::rngs::OsRng::new().unwrap(),
+        );
+        cfgs.remove(&PeerId::from(1));
+
+        let new_cfgs = WalletConfig::trusted_dealer_add_peers(
+            &cfgs,
+            &[PeerId::from(5)],
+            secp256k1::rand::rngs::OsRng::new().unwrap(),
+        );
+        let expected_peers = [0u16, 1, 2, 3, 5]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        let remaining_peers = [0u16, 2, 3, 5]
+            .iter()
+            .map(|&id| PeerId::from(id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            new_cfgs.keys().copied().collect::<Vec<_>>(),
+            remaining_peers
+        );
+        for (peer, cfg) in &new_cfgs {
+            assert_eq!(cfg.peg_in_descriptor, client_cfg.peg_in_descriptor);
+            assert_eq!(
+                cfg.peer_peg_in_keys.keys().copied().collect::<Vec<_>>(),
+                expected_peers
+            );
+            let secp = secp256k1::Secp256k1::new();
+            assert_eq!(
+                cfg.peer_peg_in_keys[peer].key,
+                secp256k1::PublicKey::from_secret_key(&secp, cfg.peg_in_key.expose())
+            );
+            assert_eq!(cfg.peg_in_signers(), peers.iter().copied().collect());
+        }
+    }
+}
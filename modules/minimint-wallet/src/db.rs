@@ -1,6 +1,9 @@
 use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+use minimint_api::db::migration::{ModuleMigrations, SchemaVersion};
+use minimint_api::db::registry::{decode_entry, DbPrefix};
 use minimint_api::db::DatabaseKeyPrefixConst;
 use minimint_api::encoding::{Decodable, Encodable};
+use serde::Serialize;
 
 const DB_PREFIX_BLOCK_HASH: u8 = 0x30;
 const DB_PREFIX_UTXO: u8 = 0x31;
@@ -10,14 +13,73 @@ const DB_PREFIX_UNSIGNED_TRANSACTION: u8 = 0x34;
 const DB_PREFIX_PENDING_TRANSACTION: u8 = 0x35;
 const DB_PREFIX_PEG_OUT_TX_SIG_CI: u8 = 0x36;
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+/// Key prefixes of the wallet
+pub const DB_PREFIXES: &[DbPrefix] = &[
+    DbPrefix {
+        prefix: DB_PREFIX_BLOCK_HASH,
+        name: "Blocks",
+        key: "block hash",
+        value: "none",
+        decode: decode_entry::<BlockHashKey, ()>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_UTXO,
+        name: "Our UTXOs",
+        key: "bitcoin outpoint",
+        value: "data necessary for spending",
+        decode: decode_entry::<UTXOKey, crate::SpendableUTXO>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_ROUND_CONSENSUS,
+        name: "Round Consensus",
+        key: "none",
+        value: "block height, fee rate, randomness beacon",
+        decode: decode_entry::<RoundConsensusKey, crate::RoundConsensus>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PEDNING_PEGOUT,
+        name: "Queued Peg-Outs",
+        key: "mint outpoint",
+        value: "address, amount, pending since block",
+        decode: decode_entry::<PendingPegOutKey, crate::PendingPegOut>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_UNSIGNED_TRANSACTION,
+        name: "Unsigned Transactions",
+        key: "bitcoin tx id",
+        value: "PSBT",
+        decode: decode_entry::<
+            UnsignedTransactionKey,
+            bitcoin::util::psbt::PartiallySignedTransaction,
+        >,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PENDING_TRANSACTION,
+        name: "Pending Transactions",
+        key: "bitcoin tx id",
+        value: "consensus encoded tx, change tweak",
+        decode: decode_entry::<PendingTransactionKey, PendingTransaction>,
+    },
+    DbPrefix {
+        prefix: DB_PREFIX_PEG_OUT_TX_SIG_CI,
+        name: "Pending Peg-Out Signatures",
+        key: "bitcoin tx id",
+        value: "list of signatures (1 per input)",
+        decode: decode_entry::<PegOutTxSignatureCI, Vec<secp256k1::Signature>>,
+    },
+];
+
+/// Version of the wallet's database layout, changing any key or value requires a migration
+pub const DB_SCHEMA_VERSION: SchemaVersion = SchemaVersion(0);
+
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct BlockHashKey(pub BlockHash);
 
 impl DatabaseKeyPrefixConst for BlockHashKey {
     const DB_PREFIX: u8 = DB_PREFIX_BLOCK_HASH;
 }
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct UTXOKey(pub OutPoint);
 
 impl DatabaseKeyPrefixConst for UTXOKey {
@@ -31,14 +93,14 @@ impl DatabaseKeyPrefixConst for UTXOPrefixKey {
     const DB_PREFIX: u8 = DB_PREFIX_UTXO;
 }
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct RoundConsensusKey;
 
 impl DatabaseKeyPrefixConst for RoundConsensusKey {
     const DB_PREFIX: u8 = DB_PREFIX_ROUND_CONSENSUS;
 }
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct PendingPegOutKey(pub minimint_api::OutPoint);
 
 impl DatabaseKeyPrefixConst for PendingPegOutKey {
@@ -52,7 +114,7 @@ impl DatabaseKeyPrefixConst for PendingPegOutPrefixKey {
     const DB_PREFIX: u8 = DB_PREFIX_PEDNING_PEGOUT;
 }
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct UnsignedTransactionKey(pub Txid);
 
 impl DatabaseKeyPrefixConst for UnsignedTransactionKey {
@@ -60,6 +122,13 @@ impl DatabaseKeyPrefixConst for UnsignedTransactionKey {
 }
 
 #[derive(Clone, Debug, Encodable, Decodable)]
+pub struct UnsignedTransactionPrefixKey;
+
+impl DatabaseKeyPrefixConst for UnsignedTransactionPrefixKey {
+    const DB_PREFIX: u8 = DB_PREFIX_UNSIGNED_TRANSACTION;
+}
+
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct PendingTransactionKey(pub Txid);
 
 impl DatabaseKeyPrefixConst for PendingTransactionKey {
@@ -73,7 +142,7 @@ impl DatabaseKeyPrefixConst for PendingTransactionPrefixKey {
     const DB_PREFIX: u8 = DB_PREFIX_PENDING_TRANSACTION;
 }
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct PegOutTxSignatureCI(pub Txid);
 
 impl DatabaseKeyPrefixConst for PegOutTxSignatureCI {
@@ -87,8 +156,12 @@ impl DatabaseKeyPrefixConst for PegOutTxSignatureCIPrefix {
     const DB_PREFIX: u8 = DB_PREFIX_PEG_OUT_TX_SIG_CI;
 }
 
-#[derive(Clone, Debug, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Encodable, Decodable)]
 pub struct PendingTransaction {
     pub tx: Transaction,
     pub tweak: Option<Vec<u8>>,
 }
+
+pub fn migrations() -> ModuleMigrations {
+    ModuleMigrations::new("wallet", DB_SCHEMA_VERSION)
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::hash::Hasher;
 use std::sync::Arc;
 
@@ -6,6 +7,7 @@ use crate::db::{
     BlockHashKey, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix, PendingPegOutKey,
     PendingPegOutPrefixKey, PendingTransaction, PendingTransactionKey, PendingTransactionPrefixKey,
     RoundConsensusKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
+    UnsignedTransactionPrefixKey,
 };
 use crate::keys::CompressedPublicKey;
 use crate::tweakable::Tweakable;
@@ -25,7 +27,9 @@ use itertools::Itertools;
 use minimint_api::db::batch::{BatchItem, BatchTx};
 use minimint_api::db::{Database, RawDatabase};
 use minimint_api::encoding::{Decodable, Encodable};
-use minimint_api::{FederationModule, InputMeta, OutPoint, PeerId};
+use minimint_api::module::api::ApiEndpoint;
+use minimint_api::module::audit::ModuleAudit;
+use minimint_api::{ConsensusContext, FederationModule, InputMeta, OutPoint, PeerId};
 use minimint_derive::UnzipConsensus;
 use miniscript::{Descriptor, DescriptorTrait, TranslatePk2};
 use rand::{CryptoRng, Rng, RngCore};
@@ -35,8 +39,9 @@ use thiserror::Error;
 use tokio::time::Duration;
 use tracing::{debug, error, info, trace, warn};
 
+pub mod api;
 pub mod config;
-mod db;
+pub mod db;
 pub mod keys;
 pub mod tweakable;
 pub mod txoproof;
@@ -52,20 +57,22 @@ pub type PartialSig = Vec<u8>;
 
 pub type PegInDescriptor = Descriptor<CompressedPublicKey>;
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, UnzipConsensus)]
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, UnzipConsensus, Encodable, Decodable,
+)]
 pub enum WalletConsensusItem {
     RoundConsensus(RoundConsensusItem),
     PegOutSignature(PegOutSignatureItem),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct RoundConsensusItem {
     block_height: u32, // FIXME: use block hash instead, but needs more complicated verification logic
     fee_rate: Feerate,
     randomness: [u8; 32],
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
 pub struct PegOutSignatureItem {
     txid: Txid,
     signature: Vec<secp256k1::Signature>,
@@ -78,6 +85,16 @@ pub struct RoundConsensus {
     randomness_beacon: [u8; 32],
 }
 
+impl RoundConsensus {
+    pub fn block_height(&self) -> u32 {
+        self.block_height
+    }
+
+    pub fn randomness_beacon(&self) -> [u8; 32] {
+        self.randomness_beacon
+    }
+}
+
 pub struct Wallet {
     cfg: WalletConfig,
     secp: Secp256k1<All>,
@@ -143,6 +160,8 @@ impl FederationModule for Wallet {
     type TxOutputOutcome = ();
     type ConsensusItem = WalletConsensusItem;
 
+    const API_BASE_NAME: &'static str = "wallet";
+
     async fn consensus_proposal<'a>(
         &'a self,
         mut rng: impl RngCore + CryptoRng + 'a,
@@ -198,6 +217,7 @@ impl FederationModule for Wallet {
 
     async fn begin_consensus_epoch<'a>(
         &'a self,
+        _context: &'a ConsensusContext,
         mut batch: BatchTx<'a>,
         consensus_items: Vec<(PeerId, Self::ConsensusItem)>,
         _rng: impl RngCore + CryptoRng + 'a,
@@ -251,34 +271,22 @@ impl FederationModule for Wallet {
         batch.commit();
     }
 
-    fn validate_input<'a>(&self, input: &'a Self::TxInput) -> Result<InputMeta<'a>, Self::Error> {
-        if !self.block_is_known(input.proof_block()) {
-            return Err(WalletError::UnknownPegInProofBlock(input.proof_block()));
-        }
-
-        input.verify(&self.secp, &self.cfg.peg_in_descriptor)?;
-
-        if self
-            .db
-            .get_value::<_, SpendableUTXO>(&UTXOKey(input.outpoint()))
-            .expect("DB error")
-            .is_some()
-        {
-            return Err(WalletError::PegInAlreadyClaimed);
-        }
-
-        Ok(InputMeta {
-            amount: minimint_api::Amount::from_sat(input.tx_output().value),
-            puk_keys: Box::new(std::iter::once(*input.tweak_contract_key())),
-        })
+    fn validate_input<'a>(
+        &self,
+        _context: &ConsensusContext,
+        input: &'a Self::TxInput,
+    ) -> Result<InputMeta<'a>, Self::Error> {
+        self.validate_input_in(self.db.as_ref(), input)
     }
 
     fn apply_input<'a, 'b>(
         &'a self,
+        _context: &ConsensusContext,
         mut batch: BatchTx<'a>,
         input: &'b Self::TxInput,
     ) -> Result<InputMeta<'b>, Self::Error> {
-        let meta = self.validate_input(input)?;
+        // Validate against the batch so the same peg-in can't be claimed twice in one epoch
+        let meta = self.validate_input_in(&batch.overlay(self.db.as_ref()), input)?;
         debug!("Claiming peg-in {} worth {}", input.outpoint(), meta.amount);
 
         batch.append_insert_new(
@@ -309,6 +317,7 @@ impl FederationModule for Wallet {
 
     fn apply_output<'a>(
         &'a self,
+        _context: &ConsensusContext,
         mut batch: BatchTx<'a>,
         output: &'a Self::TxOutput,
         out_point: minimint_api::OutPoint,
@@ -410,6 +419,89 @@ impl FederationModule for Wallet {
         // TODO: return BTC tx id once included in peg-out tx
         Some(())
     }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![ApiEndpoint::from_typed::<api::ConsensusHeightEndpoint>()]
+    }
+
+    fn audit(&self) -> ModuleAudit {
+        audit_wallet(self.db.as_ref())
+    }
+}
+
+/// Compares our UTXOs to the peg-outs we still have to pay out, see [`Wallet::audit`]
+fn audit_wallet(db: &dyn RawDatabase) -> ModuleAudit {
+    let utxos = db
+        .find_by_prefix::<_, UTXOKey, SpendableUTXO>(&UTXOPrefixKey)
+        .map(|res| res.expect("DB error"))
+        .map(|(key, utxo)| (key.0, utxo.amount))
+        .collect::<HashMap<_, _>>();
+    let assets = utxos
+        .values()
+        .fold(bitcoin::Amount::from_sat(0), |sum, amount| sum + *amount);
+
+    let queued_peg_outs = db
+        .find_by_prefix::<_, PendingPegOutKey, PendingPegOut>(&PendingPegOutPrefixKey)
+        .map(|res| res.expect("DB error").1)
+        .fold(bitcoin::Amount::from_sat(0), |sum, peg_out| {
+            sum + peg_out.amount
+        });
+
+    // Spent UTXOs stay in our UTXO set and change isn't added to it yet, so everything a peg-out
+    // transaction spends apart from the change is still owed
+    let unsigned_peg_outs = db
+        .find_by_prefix::<_, UnsignedTransactionKey, PartiallySignedTransaction>(
+            &UnsignedTransactionPrefixKey,
+        )
+        .map(|res| {
+            let (_, psbt) = res.expect("DB error");
+            let change = psbt
+                .outputs
+                .iter()
+                .zip(psbt.global.unsigned_tx.output.iter())
+                .find(|(output, _)| output.proprietary.contains_key(&proprietary_tweak_key()))
+                .map(|(_, tx_out)| bitcoin::Amount::from_sat(tx_out.value));
+            peg_out_tx_outflow(&utxos, &psbt.global.unsigned_tx, change)
+        });
+    let pending_peg_outs = db
+        .find_by_prefix::<_, PendingTransactionKey, PendingTransaction>(
+            &PendingTransactionPrefixKey,
+        )
+        .map(|res| {
+            let (_, PendingTransaction { tx, tweak }) = res.expect("DB error");
+            // The change output is always the last one
+            let change = tweak
+                .and(tx.output.last())
+                .map(|tx_out| bitcoin::Amount::from_sat(tx_out.value));
+            peg_out_tx_outflow(&utxos, &tx, change)
+        });
+    let liabilities = unsigned_peg_outs
+        .chain(pending_peg_outs)
+        .fold(queued_peg_outs, |sum, amount| sum + amount);
+
+    ModuleAudit {
+        assets: assets.into(),
+        liabilities: liabilities.into(),
+        shortfall: minimint_api::Amount::ZERO,
+    }
+}
+
+/// Value leaving the wallet through peg-out transaction `tx` including fees, which is everything
+/// it spends except for the `change`
+fn peg_out_tx_outflow(
+    utxos: &HashMap<bitcoin::OutPoint, bitcoin::Amount>,
+    tx: &Transaction,
+    change: Option<bitcoin::Amount>,
+) -> bitcoin::Amount {
+    let spent = tx
+        .input
+        .iter()
+        .fold(bitcoin::Amount::from_sat(0), |sum, input| {
+            sum + *utxos
+                .get(&input.previous_output)
+                .expect("Peg-out transactions only spend our UTXOs")
+        });
+    spent - change.unwrap_or(bitcoin::Amount::from_sat(0))
 }
 
 impl Wallet {
@@ -678,6 +770,33 @@ impl Wallet {
         batch.commit();
     }
 
+    /// Validates `input` against the state of `db`, which is either the committed database or an
+    /// overlay containing the pending changes of the current epoch
+    fn validate_input_in<'a>(
+        &self,
+        db: &dyn RawDatabase,
+        input: &'a PegInProof,
+    ) -> Result<InputMeta<'a>, WalletError> {
+        if !self.block_is_known(input.proof_block()) {
+            return Err(WalletError::UnknownPegInProofBlock(input.proof_block()));
+        }
+
+        input.verify(&self.secp, &self.cfg.peg_in_descriptor)?;
+
+        if db
+            .get_value::<_, SpendableUTXO>(&UTXOKey(input.outpoint()))
+            .expect("DB error")
+            .is_some()
+        {
+            return Err(WalletError::PegInAlreadyClaimed);
+        }
+
+        Ok(InputMeta {
+            amount: minimint_api::Amount::from_sat(input.tx_output().value),
+            puk_keys: Box::new(std::iter::once(*input.tweak_contract_key())),
+        })
+    }
+
     fn block_is_known(&self, block_hash: BlockHash) -> bool {
         self.db
             .get_value::<_, ()>(&BlockHashKey(block_hash))
@@ -720,7 +839,7 @@ impl Wallet {
     fn offline_wallet(&self) -> StatelessWallet {
         StatelessWallet {
             descriptor: &self.cfg.peg_in_descriptor,
-            secret_key: &self.cfg.peg_in_key,
+            secret_key: self.cfg.peg_in_key.expose(),
             secp: &self.secp,
         }
     }
@@ -1101,15 +1220,18 @@ mod tests {
     use std::str::FromStr;
 
     use bitcoin::hashes::Hash as BitcoinHash;
+    use bitcoin::util::psbt::PartiallySignedTransaction;
     use bitcoin::{Address, Amount, OutPoint, TxOut};
     use miniscript::descriptor::Wsh;
     use miniscript::policy::Concrete;
     use miniscript::{Descriptor, DescriptorTrait, Segwitv0};
 
-    use crate::db::UTXOKey;
+    use crate::db::{PendingPegOutKey, PendingTransactionKey, UTXOKey, UnsignedTransactionKey};
     use crate::keys::CompressedPublicKey;
     use crate::tweakable::Tweakable;
-    use crate::{PendingPegOut, SpendableUTXO, StatelessWallet};
+    use crate::{audit_wallet, PendingPegOut, PendingTransaction, SpendableUTXO, StatelessWallet};
+    use minimint_api::db::mem_impl::MemDatabase;
+    use minimint_api::db::Database;
 
     use super::Feerate;
 
@@ -1176,4 +1298,93 @@ mod tests {
         })
         .unwrap()
     }
+
+    #[test]
+    fn test_audit() {
+        const CHANGE_TWEAK: [u8; 32] = [42u8; 32];
+
+        let ctx = secp256k1::Secp256k1::new();
+        let mut rng = rand::rngs::OsRng::new().unwrap();
+        let (sec_key, pub_key) = ctx.generate_keypair(&mut rng);
+        let descriptor = Descriptor::Wsh(
+            Wsh::new(
+                Concrete::Key(CompressedPublicKey::new(pub_key))
+                    .compile::<Segwitv0>()
+                    .unwrap(),
+            )
+            .unwrap(),
+        );
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &sec_key,
+            secp: &ctx,
+        };
+
+        let db = MemDatabase::new();
+        let audit_sat = |db: &MemDatabase| {
+            let audit = audit_wallet(db);
+            (
+                audit.assets.milli_sat / 1000,
+                audit.liabilities.milli_sat / 1000,
+            )
+        };
+
+        let tweak = secp256k1::schnorrsig::PublicKey::from_slice(&[0x02; 32][..]).unwrap();
+        let utxo = (
+            UTXOKey(OutPoint::new(
+                BitcoinHash::from_slice(&[1u8; 32]).unwrap(),
+                1,
+            )),
+            SpendableUTXO {
+                tweak,
+                amount: Amount::from_sat(42000),
+                script_pubkey: descriptor.tweak(&tweak, &ctx).script_pubkey(),
+            },
+        );
+        db.insert_entry(&utxo.0, &utxo.1).unwrap();
+        assert_eq!(audit_sat(&db), (42000, 0));
+
+        // Queued peg-outs are owed in full
+        let peg_out = PendingPegOut {
+            destination: Address::from_str("bc1qkuzm3093vc7t9q80ul4p5sydkg39sk8gm0park")
+                .unwrap()
+                .script_pubkey(),
+            amount: Amount::from_sat(42),
+            pending_since_block: 0,
+        };
+        let peg_out_key = PendingPegOutKey(minimint_api::OutPoint {
+            txid: Default::default(),
+            out_idx: 0,
+        });
+        db.insert_entry(&peg_out_key, &peg_out).unwrap();
+        assert_eq!(audit_sat(&db), (42000, 42));
+
+        // Once a transaction is created everything it spends except for the change is owed,
+        // including the fees
+        let psbt = wallet.create_tx(
+            vec![peg_out],
+            vec![utxo],
+            Feerate { sats_per_kvb: 4000 },
+            &CHANGE_TWEAK,
+        );
+        let tx = psbt.global.unsigned_tx.clone();
+        let change = tx.output.last().unwrap().value;
+        assert!(change < 42000 - 42);
+        db.remove_entry::<_, PendingPegOut>(&peg_out_key).unwrap();
+        db.insert_entry(&UnsignedTransactionKey(tx.txid()), &psbt)
+            .unwrap();
+        assert_eq!(audit_sat(&db), (42000, 42000 - change));
+
+        db.remove_entry::<_, PartiallySignedTransaction>(&UnsignedTransactionKey(tx.txid()))
+            .unwrap();
+        db.insert_entry(
+            &PendingTransactionKey(tx.txid()),
+            &PendingTransaction {
+                tx,
+                tweak: Some(CHANGE_TWEAK.to_vec()),
+            },
+        )
+        .unwrap();
+        assert_eq!(audit_sat(&db), (42000, 42000 - change));
+    }
 }
@@ -198,12 +198,17 @@ impl<'de> Deserialize<'de> for TxOutProof {
         if deserializer.is_human_readable() {
             let hex_str: &str = Deserialize::deserialize(deserializer)?;
             let bytes = hex::decode(hex_str).map_err(<D as Deserializer<'de>>::Error::custom)?;
-            Ok(TxOutProof::consensus_decode(Cursor::new(bytes))
-                .map_err(<D as Deserializer<'de>>::Error::custom)?)
+            let len = bytes.len() as u64;
+            Ok(
+                TxOutProof::consensus_decode_limited(Cursor::new(bytes), len)
+                    .map_err(<D as Deserializer<'de>>::Error::custom)?,
+            )
         } else {
             let bytes: &[u8] = Deserialize::deserialize(deserializer)?;
-            Ok(TxOutProof::consensus_decode(Cursor::new(bytes))
-                .map_err(<D as Deserializer<'de>>::Error::custom)?)
+            Ok(
+                TxOutProof::consensus_decode_limited(Cursor::new(bytes), bytes.len() as u64)
+                    .map_err(<D as Deserializer<'de>>::Error::custom)?,
+            )
         }
     }
 }
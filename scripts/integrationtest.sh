@@ -40,4 +40,18 @@ sleep 20
 $BTC_CLIENT generatetoaddress 10 $ADDR
 sleep 5
 RECEIVED=$($BTC_CLIENT getreceivedbyaddress $PEG_OUT_ADDR)
-[[ "$RECEIVED" = "0.00000500" ]]
\ No newline at end of file
+[[ "$RECEIVED" = "0.00000500" ]]
+
+# reshare the keys among peers 0-2 and spend coins issued before resharing
+TOKENS=$($MINT_CLIENT spend 42000)
+pkill -f target/release/server
+sleep 5
+cargo run --release --bin reshare -- cfg cfg-reshared 0 1 2
+cp cfg-reshared/client.json cfg/client.json
+for ((ID=0; ID<3; ID++)); do
+  echo "starting mint $ID with reshared keys"
+  (RUST_LOG=info target/release/server cfg-reshared/server-$ID.json 2>&1 | sed -e "s/^/mint $ID: /" ) &
+done
+sleep 10
+$MINT_CLIENT reissue $TOKENS
+$MINT_CLIENT fetch